@@ -0,0 +1,453 @@
+//! Commandes de l'outil d'administration `lab02-admin`.
+//! Elles utilisent les mêmes bases que le serveur (module `database`). Le serveur garde les
+//! bases en mémoire et les réécrit entièrement : l'outil prend donc le verrou du dossier de
+//! données et refuse de travailler pendant que le serveur tourne. L'option `--online`
+//! permet tout de même les commandes en lecture seule, sans verrou ni écriture.
+
+use std::{
+    fs,
+    io::Write,
+    path::{Path, PathBuf},
+};
+use anyhow::{anyhow, bail, Context, Result};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use serde::de::DeserializeOwned;
+use uuid::Uuid;
+use crate::config::CONFIG;
+use crate::consts;
+use crate::database::{self, audit, email, follow, lock::DataDirLock, migrations::{self, Store}, notification, post, report, token, user};
+
+pub const USAGE: &str = "\
+Usage: lab02-admin [--online] <command>
+
+Commands:
+  users list                 List all accounts
+  users search <text>        Search accounts by email or name
+  users show <email>         Show an account and its pending links
+  users verify <email>       Mark an account as verified
+  users revoke <email>       Remove the passkey of an account and close its sessions
+  users role <email> <role>  Set the role of an account (user, moderator or admin)
+  emails list [<to>]         List sent emails, optionally for one recipient
+  emails resend <id>         Send an email again
+  tokens purge               Delete expired validation and recovery tokens
+  posts delete <id>          Delete a post and its image
+  search rebuild             Rebuild the search index from the posts
+  data export <dir>          Copy the data directory to an empty directory
+  data import <dir>          Replace the stores and uploads with an exported copy
+
+Options:
+  --online                   Run a read-only command while the server is running";
+
+/// Auteur inscrit dans le journal de modération pour les actions de l'outil
+const ADMIN_ACTOR: &str = "lab02-admin";
+
+#[derive(Debug, PartialEq)]
+pub enum Command {
+    ListUsers,
+    SearchUsers(String),
+    ShowUser(String),
+    VerifyUser(String),
+    RevokePasskey(String),
+    SetRole(String, user::Role),
+    ListEmails(Option<String>),
+    ResendEmail(u64),
+    PurgeTokens,
+    DeletePost(Uuid),
+    RebuildSearchIndex,
+    Export(PathBuf),
+    Import(PathBuf),
+}
+
+impl Command {
+    /// Commandes sans écriture dans le dossier de données
+    fn read_only(&self) -> bool {
+        matches!(
+            self,
+            Command::ListUsers | Command::SearchUsers(_) | Command::ShowUser(_) | Command::ListEmails(_) | Command::Export(_)
+        )
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Options {
+    pub online: bool,
+    pub command: Command,
+}
+
+/// Analyse les arguments (sans le nom du programme)
+pub fn parse(args: &[String]) -> Result<Options> {
+    let online = args.iter().any(|arg| arg == "--online");
+    let words: Vec<&str> = args.iter().map(String::as_str).filter(|arg| *arg != "--online").collect();
+
+    let command = match words.as_slice() {
+        ["users", "list"] => Command::ListUsers,
+        ["users", "search", text] => Command::SearchUsers(text.to_string()),
+        ["users", "show", email] => Command::ShowUser(email.to_string()),
+        ["users", "verify", email] => Command::VerifyUser(email.to_string()),
+        ["users", "revoke", email] => Command::RevokePasskey(email.to_string()),
+        ["users", "role", email, role] => Command::SetRole(email.to_string(), role.parse()?),
+        ["emails", "list"] => Command::ListEmails(None),
+        ["emails", "list", to] => Command::ListEmails(Some(to.to_string())),
+        ["emails", "resend", id] => Command::ResendEmail(id.parse().map_err(|_| anyhow!("Invalid email id {:?}", id))?),
+        ["tokens", "purge"] => Command::PurgeTokens,
+        ["posts", "delete", id] => Command::DeletePost(id.parse().map_err(|_| anyhow!("Invalid post id {:?}", id))?),
+        ["search", "rebuild"] => Command::RebuildSearchIndex,
+        ["data", "export", dir] => Command::Export(PathBuf::from(dir)),
+        ["data", "import", dir] => Command::Import(PathBuf::from(dir)),
+        _ => bail!("Unknown command"),
+    };
+    Ok(Options { online, command })
+}
+
+/// Exécute une commande après avoir pris le verrou (sauf en mode `--online`)
+pub fn run(options: Options, out: &mut impl Write) -> Result<()> {
+    let _lock = if options.online {
+        if !options.command.read_only() {
+            bail!("--online only allows read-only commands");
+        }
+        None
+    } else {
+        let lock = DataDirLock::acquire()
+            .context("Stop the server first, or use --online for read-only commands")?;
+        Some(lock)
+    };
+
+    if let Command::Import(source) = &options.command {
+        import(source)?;
+        writeln!(out, "Imported {} into {}", source.display(), CONFIG.data_dir.display())?;
+        return Ok(());
+    }
+
+    // En ligne, les fichiers ne sont pas réécrits : la mise à niveau reste en mémoire
+    if !options.online {
+        migrations::run(false)?;
+    }
+    database::load_all()?;
+    execute(options.command, out)
+}
+
+fn execute(command: Command, out: &mut impl Write) -> Result<()> {
+    match command {
+        Command::ListUsers => {
+            for user in user::all()? {
+                print_user_line(out, &user)?;
+            }
+        }
+        Command::SearchUsers(text) => {
+            let text = text.to_lowercase();
+            for user in user::all()? {
+                let haystack = format!("{} {} {}", user.email, user.first_name, user.last_name).to_lowercase();
+                if haystack.contains(&text) {
+                    print_user_line(out, &user)?;
+                }
+            }
+        }
+        Command::ShowUser(email) => {
+            let user = user::get(&email).ok_or_else(|| anyhow!("User {} not found", email))?;
+            writeln!(out, "email:    {}", user.email)?;
+            writeln!(out, "name:     {} {}", user.first_name, user.last_name)?;
+            writeln!(out, "verified: {}", user.verified)?;
+            match &user.passkey {
+                Some(passkey) => writeln!(out, "passkey:  {}", URL_SAFE_NO_PAD.encode(passkey.cred_id()))?,
+                None => writeln!(out, "passkey:  none")?,
+            }
+            writeln!(out, "recovery codes left: {}", user.recovery_codes.len())?;
+            writeln!(out, "role:     {}{}", user.role.as_str(), if user.suspended { " (suspended)" } else { "" })?;
+            writeln!(out, "magic link: {}", if user.magic_link { "enabled" } else { "disabled" })?;
+            writeln!(out, "posts liked: {}", user.liked_posts.values().filter(|reaction| reaction.vote == 1).count())?;
+            for (token, stored) in token::for_email(&email)? {
+                let state = if stored.is_expired() { "expired" } else { "pending" };
+                writeln!(out, "link token: {} ({:?}, {}, expires at {})", token, stored.purpose, state, stored.expires_at)?;
+            }
+        }
+        Command::VerifyUser(email) => {
+            user::verify(&email).with_context(|| format!("Cannot verify {}", email))?;
+            writeln!(out, "{} is verified", email)?;
+        }
+        Command::RevokePasskey(email) => {
+            if user::revoke_passkey(&email).with_context(|| format!("Cannot revoke the passkey of {}", email))? {
+                writeln!(out, "Passkey of {} revoked, its sessions are closed", email)?;
+            } else {
+                writeln!(out, "{} has no passkey", email)?;
+            }
+        }
+        Command::SetRole(email, role) => {
+            let previous = user::get(&email).ok_or_else(|| anyhow!("User {} not found", email))?.role;
+            user::set_role(&email, role)?;
+            // Un changement qui ne peut pas être inscrit au journal est annulé
+            if let Err(e) = audit::record(ADMIN_ACTOR, audit::Action::SetRole, &email, Some(role.as_str()), "Set with lab02-admin") {
+                user::set_role(&email, previous)?;
+                return Err(e);
+            }
+            writeln!(out, "{} is now {}", email, role.as_str())?;
+        }
+        Command::ListEmails(to) => {
+            let emails = match to {
+                Some(to) => email::sent_to(&to)?,
+                None => email::all()?,
+            };
+            for email in emails {
+                writeln!(out, "#{}\t{}\t{}\n\t{}", email.pk, email.to, email.subject, email.body)?;
+            }
+        }
+        Command::ResendEmail(pk) => {
+            let email = email::get(pk)?.ok_or_else(|| anyhow!("Email #{} not found", pk))?;
+            crate::email::send_mail(&email.to, &email.subject, &email.body)?;
+            writeln!(out, "Email #{} sent again to {}", pk, email.to)?;
+        }
+        Command::PurgeTokens => {
+            writeln!(out, "{} expired tokens purged", token::purge_expired()?)?;
+        }
+        Command::DeletePost(id) => {
+            let post = post::delete(id)?.ok_or_else(|| anyhow!("Post {} not found", id))?;
+            user::remove_bookmarks(&[id.to_string()])?;
+            if let Some(image) = post.image_path.map(PathBuf::from) {
+                // Seules les images du dossier d'uploads sont supprimées
+                if image.starts_with(CONFIG.uploads_dir()) {
+                    fs::remove_file(&image).with_context(|| format!("Failed to delete {}", image.display()))?;
+                }
+            }
+            writeln!(out, "Post {} deleted", id)?;
+        }
+        Command::RebuildSearchIndex => {
+            writeln!(out, "Search index rebuilt from {} posts", post::rebuild_search_index()?)?;
+        }
+        Command::Export(target) => {
+            export(&target)?;
+            writeln!(out, "Exported {} to {}", CONFIG.data_dir.display(), target.display())?;
+        }
+        Command::Import(_) => unreachable!("import runs before the stores are loaded"),
+    }
+    Ok(())
+}
+
+fn print_user_line(out: &mut impl Write, user: &user::User) -> Result<()> {
+    writeln!(
+        out,
+        "{}\t{}\t{}\t{} {}",
+        user.email,
+        if user.verified { "verified" } else { "unverified" },
+        if user.passkey.is_some() { "passkey" } else { "no-passkey" },
+        user.first_name,
+        user.last_name,
+    )?;
+    Ok(())
+}
+
+/// Copie les bases et les uploads dans un dossier vide
+fn export(target: &Path) -> Result<()> {
+    if target.exists() && fs::read_dir(target)?.next().is_some() {
+        bail!("{} is not empty", target.display());
+    }
+    fs::create_dir_all(target)?;
+
+    for store in Store::ALL {
+        let source = store.path();
+        if source.exists() {
+            fs::copy(&source, target.join(source.file_name().unwrap_or_default()))?;
+        }
+    }
+    copy_dir(&CONFIG.uploads_dir(), &target.join(consts::UPLOADS_DIR))
+}
+
+/// Remplace les bases et les uploads par ceux d'un export, après validation de chaque base
+fn import(source: &Path) -> Result<()> {
+    import_into(source, &CONFIG.data_dir)
+}
+
+/// Fichiers et dossiers du dossier de données remplacés par un import. L'index de recherche,
+/// absent des exports, est retiré et reconstruit au chargement.
+fn replaced_entries() -> Vec<PathBuf> {
+    Store::ALL
+        .into_iter()
+        .map(|store| PathBuf::from(store.path().file_name().unwrap_or_default()))
+        .chain([PathBuf::from(consts::SEARCH_INDEX_FILE), PathBuf::from(consts::UPLOADS_DIR)])
+        .collect()
+}
+
+/// Prépare l'export dans un dossier temporaire du dossier de données, puis le met en place
+/// par renommages : une base ou des uploads absents de l'export sont supprimés, et un échec
+/// rétablit les fichiers précédents. Le verrou et les clés du serveur ne sont pas touchés.
+fn import_into(source: &Path, data_dir: &Path) -> Result<()> {
+    if !source.is_dir() {
+        bail!("{} is not a directory", source.display());
+    }
+    if source.canonicalize()? == data_dir.canonicalize()? {
+        bail!("Cannot import the data directory into itself");
+    }
+
+    let staging = data_dir.join(format!(".import-{}", Uuid::new_v4()));
+    let result = stage(source, &staging.join("new")).and_then(|()| swap(data_dir, &staging));
+    if let Err(e) = fs::remove_dir_all(&staging) {
+        eprintln!("Failed to remove {}: {}", staging.display(), e);
+    }
+    result
+}
+
+/// Copie l'export dans `staged`, valide chaque base et la met à niveau
+fn stage(source: &Path, staged: &Path) -> Result<()> {
+    fs::create_dir_all(staged)?;
+    for store in Store::ALL {
+        let file_name = store.path().file_name().unwrap_or_default().to_owned();
+        let path = source.join(&file_name);
+        if !path.exists() {
+            continue;
+        }
+        let copy = staged.join(&file_name);
+        fs::copy(&path, &copy).with_context(|| format!("Failed to copy {}", path.display()))?;
+        match store {
+            Store::Users => validate::<user::Db>(store, &copy)?,
+            Store::Emails => validate::<email::Db>(store, &copy)?,
+            Store::Posts => validate::<post::Db>(store, &copy)?,
+            Store::Tokens => validate::<token::Db>(store, &copy)?,
+            Store::Audit => validate::<audit::Db>(store, &copy)?,
+            Store::Reports => validate::<report::Db>(store, &copy)?,
+            Store::Follows => validate::<follow::Db>(store, &copy)?,
+            Store::Notifications => validate::<notification::Db>(store, &copy)?,
+        }
+        // Les bases importées sont écrites dans la version courante
+        migrations::migrate_file(store, &copy, false)?;
+    }
+    copy_dir(&source.join(consts::UPLOADS_DIR), &staged.join(consts::UPLOADS_DIR))
+}
+
+/// Met en place les entrées préparées dans `staging/new`, les précédentes passant dans
+/// `staging/previous` jusqu'à la fin de l'opération
+fn swap(data_dir: &Path, staging: &Path) -> Result<()> {
+    let previous = staging.join("previous");
+    fs::create_dir_all(&previous)?;
+    let mut moved = vec![];
+    for entry in replaced_entries() {
+        let target = data_dir.join(&entry);
+        let staged = staging.join("new").join(&entry);
+        let result = (|| -> Result<()> {
+            if target.exists() {
+                fs::rename(&target, previous.join(&entry))?;
+            }
+            moved.push(entry.clone());
+            if staged.exists() {
+                fs::rename(&staged, &target)?;
+            }
+            Ok(())
+        })();
+        if let Err(e) = result {
+            restore(data_dir, &previous, &moved);
+            return Err(e.context(format!("Failed to replace {}", target.display())));
+        }
+    }
+    Ok(())
+}
+
+/// Rétablit les entrées déplacées par un import interrompu
+fn restore(data_dir: &Path, previous: &Path, moved: &[PathBuf]) {
+    for entry in moved {
+        let target = data_dir.join(entry);
+        let _ = fs::remove_dir_all(&target).or_else(|_| fs::remove_file(&target));
+        let saved = previous.join(entry);
+        if saved.exists() {
+            if let Err(e) = fs::rename(&saved, &target) {
+                eprintln!("Failed to restore {}: {}", target.display(), e);
+            }
+        }
+    }
+}
+
+/// Vérifie qu'un fichier exporté se lit et se met à niveau
+fn validate<T: DeserializeOwned>(store: Store, path: &Path) -> Result<()> {
+    let raw: serde_yaml::Value = serde_yaml::from_reader(fs::File::open(path)?)
+        .with_context(|| format!("Failed to parse {}", path.display()))?;
+    let (_, data) = migrations::upgrade(store, raw)?;
+    serde_yaml::from_value::<T>(data).with_context(|| format!("Invalid {} store in {}", store.name(), path.display()))?;
+    Ok(())
+}
+
+fn copy_dir(from: &Path, to: &Path) -> Result<()> {
+    if !from.is_dir() {
+        return Ok(());
+    }
+    fs::create_dir_all(to)?;
+    for entry in fs::read_dir(from)? {
+        let entry = entry?;
+        if entry.file_type()?.is_file() {
+            fs::copy(entry.path(), to.join(entry.file_name()))?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(line: &str) -> Vec<String> {
+        line.split_whitespace().map(str::to_string).collect()
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            parse(&args("--online users show alice@example.com")).unwrap(),
+            Options { online: true, command: Command::ShowUser("alice@example.com".to_string()) }
+        );
+        assert_eq!(parse(&args("emails list")).unwrap().command, Command::ListEmails(None));
+        assert_eq!(parse(&args("search rebuild")).unwrap().command, Command::RebuildSearchIndex);
+        assert!(parse(&args("posts delete not-a-uuid")).is_err());
+        assert!(parse(&args("users role alice@example.com root")).is_err());
+        assert!(parse(&args("users")).is_err());
+    }
+
+    #[test]
+    fn test_online_mode_is_read_only() {
+        let options = parse(&args("--online users verify alice@example.com")).unwrap();
+        let error = run(options, &mut vec![]).unwrap_err();
+        assert!(error.to_string().contains("read-only"));
+    }
+
+    #[test]
+    fn test_verify_and_revoke() {
+        let email = format!("{}@example.com", Uuid::new_v4());
+        user::create(&email, "Alice", "Martin").unwrap();
+
+        let mut out = vec![];
+        execute(Command::VerifyUser(email.clone()), &mut out).unwrap();
+        execute(Command::RevokePasskey(email.clone()), &mut out).unwrap();
+        execute(Command::SearchUsers(email[..8].to_uppercase()), &mut out).unwrap();
+
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.contains(&format!("{} has no passkey", email)));
+        assert!(out.contains(&format!("{}\tverified\tno-passkey\tAlice Martin", email)));
+        assert!(user::get(&email).unwrap().verified);
+    }
+
+    #[test]
+    fn test_import_replaces_stores_and_uploads() {
+        let root = std::env::temp_dir().join(format!("lab02-import-{}", Uuid::new_v4()));
+        let (export, data_dir) = (root.join("export"), root.join("data"));
+        let users = Store::Users.path().file_name().unwrap().to_owned();
+        let posts = Store::Posts.path().file_name().unwrap().to_owned();
+        fs::create_dir_all(export.join(consts::UPLOADS_DIR)).unwrap();
+        fs::create_dir_all(data_dir.join(consts::UPLOADS_DIR)).unwrap();
+        fs::write(export.join(&users), "{}").unwrap();
+        fs::write(export.join(consts::UPLOADS_DIR).join("kept.png"), "new").unwrap();
+        fs::write(data_dir.join(&posts), "{}").unwrap();
+        fs::write(data_dir.join(consts::UPLOADS_DIR).join("stale.png"), "old").unwrap();
+        fs::write(data_dir.join(consts::LOCK_FILE), "").unwrap();
+
+        import_into(&export, &data_dir).unwrap();
+
+        assert!(data_dir.join(&users).exists());
+        assert!(!data_dir.join(&posts).exists());
+        assert!(data_dir.join(consts::UPLOADS_DIR).join("kept.png").exists());
+        assert!(!data_dir.join(consts::UPLOADS_DIR).join("stale.png").exists());
+        assert!(data_dir.join(consts::LOCK_FILE).exists());
+        assert_eq!(fs::read_dir(&data_dir).unwrap().count(), 3);
+
+        // Une base invalide ne touche pas aux données en place
+        fs::write(export.join(&posts), "not: [a, post store").unwrap();
+        assert!(import_into(&export, &data_dir).is_err());
+        assert!(data_dir.join(consts::UPLOADS_DIR).join("kept.png").exists());
+        assert_eq!(fs::read_dir(&data_dir).unwrap().count(), 3);
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -1,8 +1,30 @@
 //! Module principal pour le backend de l'application.
 //! Contient les gestionnaires pour les routes, les modèles de données, 
 //! le routeur, et les middlewares.
+mod bookmarks;
+pub mod ceremonies;
+mod csrf;
+pub mod drafts;
+mod follows;
 pub mod handlers_auth;
-mod models;
+mod handlers_health;
+mod live;
+mod magic_link;
+mod media;
 mod middlewares;
+mod moderation;
+mod passkeys;
+pub mod notifications;
 pub mod router;
 pub mod handlers_unauth;
+mod rate_limit;
+mod recovery_codes;
+mod reports;
+mod search;
+mod security_headers;
+pub mod session_store;
+pub mod sessions;
+mod step_up;
+pub mod verification;
+#[cfg(test)]
+mod e2e_tests;
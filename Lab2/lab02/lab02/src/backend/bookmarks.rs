@@ -0,0 +1,95 @@
+//! Posts enregistrés par un compte, conservés dans `User.stash` du plus récent au plus ancien.
+//! Un post masqué par la modération ou devenu invisible pour le compte reste enregistré mais
+//! n'est plus affiché ; les entrées dont le post a été supprimé sont retirées (à la suppression
+//! et à l'affichage de la page).
+
+use std::collections::HashMap;
+use axum::{
+    extract::{Json, Query},
+    http::StatusCode,
+    response::Html,
+    Extension,
+};
+use serde_json::json;
+use uuid::Uuid;
+use crate::backend::csrf::CsrfToken;
+use crate::backend::handlers_auth::feed_entries;
+use crate::backend::middlewares::LoggedIn;
+use crate::backend::security_headers::CspNonce;
+use crate::consts;
+use crate::database::{post::{self, Post}, user};
+use crate::utils::pagination::paginate;
+use crate::HBS;
+
+/// Enregistre un post du fil ou l'en retire
+pub async fn bookmark_post(LoggedIn(email): LoggedIn, Json(payload): Json<serde_json::Value>) -> axum::response::Result<StatusCode> {
+    let post_id = payload
+        .get("post_id")
+        .and_then(|value| value.as_str())
+        .and_then(|value| Uuid::parse_str(value).ok())
+        .ok_or((StatusCode::BAD_REQUEST, "Invalid Post ID"))?;
+    let saved = payload
+        .get("saved")
+        .and_then(|value| value.as_bool())
+        .ok_or((StatusCode::BAD_REQUEST, "Invalid saved flag"))?;
+
+    // Un post peut toujours être retiré, seuls les posts du fil du compte peuvent être enregistrés
+    if saved {
+        let post = post::get(post_id).map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to read posts"))?;
+        if !post.is_some_and(|post| post.in_feed_of(&email)) {
+            return Err((StatusCode::NOT_FOUND, "Post not found").into());
+        }
+    }
+
+    user::set_bookmark(&email, &post_id.to_string(), saved)
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to update bookmarks"))?;
+    Ok(StatusCode::OK)
+}
+
+/// Affiche les posts enregistrés, par pages de `consts::SAVED_POSTS_PAGE_SIZE`
+pub async fn saved_page(
+    LoggedIn(email): LoggedIn,
+    Extension(nonce): Extension<CspNonce>,
+    CsrfToken(csrf_token): CsrfToken,
+    Query(params): Query<HashMap<String, String>>,
+) -> axum::response::Result<Html<String>> {
+    let page = params
+        .get("page")
+        .map(|page| page.parse::<usize>())
+        .transpose()
+        .ok()
+        .flatten()
+        .unwrap_or(1)
+        .max(1);
+
+    let stash = user::get(&email).map(|user| user.stash).unwrap_or_default();
+    let mut posts: HashMap<String, Post> = post::all()
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to read posts"))?
+        .into_iter()
+        .map(|post| (post.id.to_string(), post))
+        .collect();
+
+    let deleted: Vec<String> = stash.iter().filter(|id| !posts.contains_key(*id)).cloned().collect();
+    if !deleted.is_empty() {
+        user::remove_bookmarks(&deleted).map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to update bookmarks"))?;
+    }
+
+    let saved: Vec<Post> = stash
+        .iter()
+        .filter_map(|id| posts.remove(id))
+        .filter(|post| post.in_feed_of(&email))
+        .collect();
+    let (saved, has_next) = paginate(saved, page, consts::SAVED_POSTS_PAGE_SIZE);
+
+    let data = json!({
+        "posts": feed_entries(saved, Some(&email)),
+        "page": page,
+        "previous_page": (page > 1).then(|| page - 1),
+        "next_page": has_next.then(|| page + 1),
+        "csp_nonce": nonce.0,
+        "csrf_token": csrf_token,
+    });
+    HBS.render("saved", &data)
+        .map(Html)
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Internal server error while rendering the page").into())
+}
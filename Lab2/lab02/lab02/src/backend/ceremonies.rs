@@ -0,0 +1,278 @@
+//! États des cérémonies WebAuthn en attente (enregistrement, connexion, step-up).
+//! Chaque état est horodaté et refusé après le délai WebAuthn. Le nombre d'états est
+//! borné globalement et par propriétaire : au-delà de la limite d'un propriétaire, son état le
+//! plus ancien est évincé ; au-delà de la limite globale, le nouvel état est refusé.
+//! Une cérémonie anonyme appartient à l'adresse IP qui la demande, jamais au compte visé, pour
+//! qu'un tiers ne puisse pas évincer les cérémonies d'une victime ; les connexions par passkey
+//! découvrable ont leur propre limite par adresse. Seul le step-up, demandé par une session
+//! ouverte, appartient au compte. Une tâche de fond retire régulièrement les états expirés.
+
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    time::{Duration, Instant},
+};
+use once_cell::sync::Lazy;
+use tokio::sync::RwLock;
+use webauthn_rs::DEFAULT_AUTHENTICATOR_TIMEOUT;
+use crate::consts;
+use crate::metrics::{self, Ceremony};
+use crate::utils::webauthn::{PendingAuthentication, StoredRegistrationState};
+
+/// Cérémonies d'enregistrement en attente, par identifiant d'état
+pub(crate) static REGISTRATION_STATES: Lazy<CeremonyStore<StoredRegistrationState>> =
+    Lazy::new(|| CeremonyStore::new(Ceremony::Registration, DEFAULT_AUTHENTICATOR_TIMEOUT));
+/// Cérémonies de connexion et de step-up en attente, par identifiant d'état
+pub(crate) static AUTHENTICATION_STATES: Lazy<CeremonyStore<PendingAuthentication>> =
+    Lazy::new(|| CeremonyStore::new(Ceremony::Authentication, DEFAULT_AUTHENTICATOR_TIMEOUT));
+
+/// Structure pour gérer un état temporaire avec un challenge
+pub struct TimedStoredState<T> {
+    pub(crate) state: T,
+    pub(crate) server_challenge: String,
+    created: Instant,
+    owners: Vec<Owner>,
+}
+
+/// Nouvel état refusé : la limite globale est atteinte
+#[derive(Debug)]
+pub struct CapacityReached;
+
+/// Propriétaire d'un état, dont le nombre d'états en attente est limité
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Owner {
+    /// Compte d'une session ouverte (step-up)
+    Account(String),
+    /// Adresse IP qui demande une inscription ou une connexion
+    Ip(IpAddr),
+    /// Adresse IP qui demande une connexion par passkey découvrable
+    Discoverable(IpAddr),
+}
+
+/// Propriétaires d'une cérémonie anonyme : l'adresse IP du client, si elle est connue
+pub fn owners(ip: Option<IpAddr>) -> Vec<Owner> {
+    ip.map(Owner::Ip).into_iter().collect()
+}
+
+/// Propriétaires d'une connexion par passkey découvrable
+pub fn discoverable_owners(ip: Option<IpAddr>) -> Vec<Owner> {
+    ip.map(Owner::Discoverable).into_iter().collect()
+}
+
+struct Inner<T> {
+    states: HashMap<String, TimedStoredState<T>>,
+    /// Nombre d'états par propriétaire
+    owners: HashMap<Owner, usize>,
+}
+
+pub struct CeremonyStore<T> {
+    ceremony: Ceremony,
+    ttl: Duration,
+    max_states: usize,
+    max_per_owner: usize,
+    max_discoverable_per_ip: usize,
+    inner: RwLock<Inner<T>>,
+}
+
+impl<T> CeremonyStore<T> {
+    pub fn new(ceremony: Ceremony, ttl: Duration) -> Self {
+        Self::with_limits(
+            ceremony,
+            ttl,
+            consts::MAX_PENDING_CEREMONIES,
+            consts::MAX_PENDING_CEREMONIES_PER_OWNER,
+            consts::MAX_PENDING_DISCOVERABLE_PER_IP,
+        )
+    }
+
+    pub fn with_limits(ceremony: Ceremony, ttl: Duration, max_states: usize, max_per_owner: usize, max_discoverable_per_ip: usize) -> Self {
+        CeremonyStore {
+            ceremony,
+            ttl,
+            max_states,
+            max_per_owner,
+            max_discoverable_per_ip,
+            inner: RwLock::new(Inner { states: HashMap::new(), owners: HashMap::new() }),
+        }
+    }
+
+    /// Conserve un nouvel état pour ses propriétaires
+    pub async fn insert(
+        &self,
+        id: String,
+        state: T,
+        server_challenge: String,
+        owners: Vec<Owner>,
+    ) -> Result<(), CapacityReached> {
+        let mut inner = self.inner.write().await;
+
+        // Un propriétaire à sa limite perd son état le plus ancien
+        for owner in &owners {
+            if inner.owners.get(owner).copied().unwrap_or_default() >= self.limit_of(owner) {
+                let oldest = inner
+                    .states
+                    .iter()
+                    .filter(|(_, stored)| stored.owners.contains(owner))
+                    .min_by_key(|(_, stored)| stored.created)
+                    .map(|(id, _)| id.clone());
+                if let Some(oldest) = oldest {
+                    inner.remove(&oldest);
+                    metrics::record_state_eviction(self.ceremony, "owner_limit");
+                }
+            }
+        }
+
+        if inner.states.len() >= self.max_states {
+            self.sweep_locked(&mut inner);
+            if inner.states.len() >= self.max_states {
+                metrics::record_state_eviction(self.ceremony, "capacity");
+                return Err(CapacityReached);
+            }
+        }
+
+        for owner in &owners {
+            *inner.owners.entry(owner.clone()).or_default() += 1;
+        }
+        inner.states.insert(id, TimedStoredState { state, server_challenge, created: Instant::now(), owners });
+        Ok(())
+    }
+
+    fn limit_of(&self, owner: &Owner) -> usize {
+        match owner {
+            Owner::Account(_) | Owner::Ip(_) => self.max_per_owner,
+            Owner::Discoverable(_) => self.max_discoverable_per_ip,
+        }
+    }
+
+    /// Retire un état ; un état expiré n'est pas retourné
+    pub async fn take(&self, id: &str) -> Option<TimedStoredState<T>> {
+        let stored = self.inner.write().await.remove(id)?;
+        if stored.created.elapsed() > self.ttl {
+            metrics::record_state_eviction(self.ceremony, "expired");
+            return None;
+        }
+        Some(stored)
+    }
+
+    /// Retire les états expirés et retourne leur nombre
+    pub async fn sweep(&self) -> usize {
+        let mut inner = self.inner.write().await;
+        self.sweep_locked(&mut inner)
+    }
+
+    fn sweep_locked(&self, inner: &mut Inner<T>) -> usize {
+        let expired: Vec<String> = inner
+            .states
+            .iter()
+            .filter(|(_, stored)| stored.created.elapsed() > self.ttl)
+            .map(|(id, _)| id.clone())
+            .collect();
+        for id in &expired {
+            inner.remove(id);
+            metrics::record_state_eviction(self.ceremony, "expired");
+        }
+        expired.len()
+    }
+
+    pub async fn len(&self) -> usize {
+        self.inner.read().await.states.len()
+    }
+
+    pub async fn is_empty(&self) -> bool {
+        self.len().await == 0
+    }
+}
+
+impl<T> Inner<T> {
+    fn remove(&mut self, id: &str) -> Option<TimedStoredState<T>> {
+        let stored = self.states.remove(id)?;
+        for owner in &stored.owners {
+            if let Some(count) = self.owners.get_mut(owner) {
+                *count -= 1;
+                if *count == 0 {
+                    self.owners.remove(owner);
+                }
+            }
+        }
+        Some(stored)
+    }
+}
+
+/// Tâche de fond qui retire les états expirés
+pub async fn sweep_periodically() {
+    let mut interval = tokio::time::interval(Duration::from_secs(consts::CEREMONY_SWEEP_INTERVAL_SECS));
+    loop {
+        interval.tick().await;
+        REGISTRATION_STATES.sweep().await;
+        AUTHENTICATION_STATES.sweep().await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn store(ttl: Duration) -> CeremonyStore<u32> {
+        CeremonyStore::with_limits(Ceremony::Authentication, ttl, 4, 2, 1)
+    }
+
+    fn ip(last: u8) -> IpAddr {
+        IpAddr::from([203, 0, 113, last])
+    }
+
+    async fn insert(store: &CeremonyStore<u32>, id: &str, owners: &[Owner]) -> Result<(), CapacityReached> {
+        store.insert(id.to_string(), 0, String::new(), owners.to_vec()).await
+    }
+
+    #[tokio::test]
+    async fn test_expired_states_are_rejected_and_swept() {
+        let store = store(Duration::ZERO);
+        insert(&store, "a", &[Owner::Ip(ip(1))]).await.unwrap();
+        insert(&store, "b", &[Owner::Ip(ip(1))]).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(5)).await;
+
+        assert!(store.take("a").await.is_none());
+        assert_eq!(store.sweep().await, 1);
+        assert_eq!(store.len().await, 0);
+    }
+
+    #[tokio::test]
+    async fn test_owner_limit_evicts_oldest() {
+        let store = store(Duration::from_secs(60));
+        insert(&store, "first", &[Owner::Ip(ip(1))]).await.unwrap();
+        insert(&store, "second", &[Owner::Ip(ip(1))]).await.unwrap();
+        insert(&store, "other", &[Owner::Ip(ip(2))]).await.unwrap();
+        insert(&store, "third", &[Owner::Ip(ip(1))]).await.unwrap();
+
+        assert!(store.take("first").await.is_none());
+        assert!(store.take("second").await.is_some());
+        assert!(store.take("third").await.is_some());
+        assert!(store.take("other").await.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_discoverable_ceremonies_have_their_own_limit() {
+        let store = store(Duration::from_secs(60));
+        insert(&store, "login", &owners(Some(ip(1)))).await.unwrap();
+        insert(&store, "first", &discoverable_owners(Some(ip(1)))).await.unwrap();
+        insert(&store, "second", &discoverable_owners(Some(ip(1)))).await.unwrap();
+
+        assert!(store.take("first").await.is_none());
+        assert!(store.take("second").await.is_some());
+        assert!(store.take("login").await.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_global_limit_rejects_new_states() {
+        let store = store(Duration::from_secs(60));
+        for (last, id) in ["a", "b", "c", "d"].into_iter().enumerate() {
+            insert(&store, id, &owners(Some(ip(last as u8)))).await.unwrap();
+        }
+        assert!(insert(&store, "e", &owners(Some(ip(9)))).await.is_err());
+
+        // Une place libérée peut être reprise
+        store.take("a").await.unwrap();
+        insert(&store, "e", &owners(Some(ip(9)))).await.unwrap();
+        assert_eq!(store.len().await, 4);
+    }
+}
@@ -0,0 +1,151 @@
+//! Protection CSRF des requêtes qui modifient l'état.
+//! Un jeton aléatoire (synchronizer token) est conservé dans la session et injecté dans les
+//! templates par l'extracteur `CsrfToken`. Le script `partials/csrf` le renvoie dans l'en-tête
+//! `X-CSRF-Token` de chaque requête non-GET, vérifiée par le middleware `verify_csrf`.
+//! En défense en profondeur, l'origine annoncée (`Origin`, sinon `Referer`) doit être celle
+//! du service.
+
+use axum::{
+    extract::{FromRequestParts, Request},
+    http::{request::Parts, HeaderMap, StatusCode},
+    middleware::Next,
+    response::{Html, IntoResponse, Response},
+};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use http::{header, Method};
+use log::warn;
+use serde_json::json;
+use tower_sessions::Session;
+use url::Url;
+use crate::backend::handlers_unauth::SESSION_EMAIL;
+use crate::backend::session_store::anonymous_expiry;
+use crate::config::CONFIG;
+use crate::HBS;
+
+/// Clé de session contenant le jeton CSRF
+const SESSION_CSRF_TOKEN: &str = "csrf_token";
+/// En-tête portant le jeton dans les requêtes
+pub const CSRF_HEADER: &str = "x-csrf-token";
+
+/// Jeton CSRF de la session, créé au premier affichage d'une page
+pub struct CsrfToken(pub String);
+
+#[async_trait::async_trait]
+impl <S> FromRequestParts<S> for CsrfToken
+where
+    S: Send + Sync,
+{
+    type Rejection = (StatusCode, String);
+
+    async fn from_request_parts(parts: &mut Parts, _: &S) -> Result<Self, Self::Rejection> {
+        let session = parts
+            .extensions
+            .get::<Session>()
+            .ok_or((StatusCode::INTERNAL_SERVER_ERROR, "Session layer missing".to_string()))?;
+
+        token(session)
+            .map(CsrfToken)
+            .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to store CSRF token".to_string()))
+    }
+}
+
+/// Jeton de la session, créé s'il n'existe pas encore. Une session anonyme créée pour
+/// porter le jeton expire vite (voir `session_store`).
+pub(crate) fn token(session: &Session) -> Result<String, tower_sessions::session::Error> {
+    if let Some(token) = session.get::<String>(SESSION_CSRF_TOKEN)? {
+        return Ok(token);
+    }
+    if session.get::<String>(SESSION_EMAIL)?.is_none() {
+        session.set_expiry(Some(anonymous_expiry()));
+    }
+    rotate(session)
+}
+
+/// Remplace le jeton de la session, à l'ouverture d'une session authentifiée : un jeton
+/// obtenu avant la connexion ne sert plus ensuite
+pub(crate) fn rotate(session: &Session) -> Result<String, tower_sessions::session::Error> {
+    let mut bytes = uuid::Uuid::new_v4().as_bytes().to_vec();
+    bytes.extend(uuid::Uuid::new_v4().as_bytes());
+    let token = URL_SAFE_NO_PAD.encode(bytes);
+    session.insert(SESSION_CSRF_TOKEN, &token)?;
+    Ok(token)
+}
+
+/// Comparaison en temps constant
+fn tokens_match(expected: &str, provided: &str) -> bool {
+    expected.len() == provided.len()
+        && expected.bytes().zip(provided.bytes()).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+/// Vérifie l'origine annoncée ; une requête sans `Origin` ni `Referer` n'est jugée que sur son jeton
+fn same_origin(headers: &HeaderMap) -> bool {
+    let expected = CONFIG.public_url.origin();
+    let announced = headers
+        .get(header::ORIGIN)
+        .or_else(|| headers.get(header::REFERER))
+        .map(|value| value.to_str().ok().and_then(|value| Url::parse(value).ok()));
+
+    match announced {
+        None => true,
+        Some(Some(url)) => url.origin() == expected,
+        Some(None) => false,
+    }
+}
+
+/// Page d'erreur affichée lorsque la vérification échoue
+fn rejected(reason: &str) -> Response {
+    let body = HBS
+        .render("csrf_error", &json!({ "reason": reason }))
+        .unwrap_or_else(|_| "<h1>Forbidden</h1>".to_string());
+    (StatusCode::FORBIDDEN, Html(body)).into_response()
+}
+
+/// Middleware appliqué aux routes avec session
+pub async fn verify_csrf(session: Session, request: Request, next: Next) -> Response {
+    if matches!(*request.method(), Method::GET | Method::HEAD | Method::OPTIONS) {
+        return next.run(request).await;
+    }
+
+    let path = request.uri().path().to_string();
+    if !same_origin(request.headers()) {
+        warn!("CSRF: cross-origin {} {} rejected", request.method(), path);
+        return rejected("The request did not come from this site.");
+    }
+
+    let expected = session.get::<String>(SESSION_CSRF_TOKEN).ok().flatten();
+    let provided = request.headers().get(CSRF_HEADER).and_then(|value| value.to_str().ok());
+    match (expected, provided) {
+        (Some(expected), Some(provided)) if tokens_match(&expected, provided) => next.run(request).await,
+        _ => {
+            warn!("CSRF: missing or invalid token for {} {}", request.method(), path);
+            rejected("Your session has expired or the form is out of date. Reload the page and try again.")
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tokens_match() {
+        assert!(tokens_match("abc", "abc"));
+        assert!(!tokens_match("abc", "abd"));
+        assert!(!tokens_match("abc", "ab"));
+    }
+
+    #[test]
+    fn test_same_origin() {
+        let mut headers = HeaderMap::new();
+        assert!(same_origin(&headers));
+
+        headers.insert(header::REFERER, CONFIG.link("/home").parse().unwrap());
+        assert!(same_origin(&headers));
+
+        headers.insert(header::ORIGIN, "https://evil.example".parse().unwrap());
+        assert!(!same_origin(&headers));
+
+        headers.insert(header::ORIGIN, "null".parse().unwrap());
+        assert!(!same_origin(&headers));
+    }
+}
@@ -0,0 +1,82 @@
+//! Brouillons et publication programmée.
+//! Un brouillon n'est visible que de son auteur jusqu'à sa publication. Un post programmé est
+//! publié par `publish_scheduled_periodically` une fois sa date passée. Le fil, la recherche et
+//! le flux en direct suivent l'ordre de publication : un post publié rejoint la fin du fil.
+
+use std::time::Duration;
+use axum::{
+    extract::Json,
+    http::StatusCode,
+    response::Html,
+    Extension,
+};
+use log::{error, info};
+use serde_json::json;
+use uuid::Uuid;
+use crate::backend::csrf::CsrfToken;
+use crate::backend::handlers_auth::feed_entries;
+use crate::backend::middlewares::LoggedIn;
+use crate::backend::live::{self, Update};
+use crate::backend::notifications::notify_mentions;
+use crate::backend::security_headers::CspNonce;
+use crate::consts;
+use crate::database::{post, unix_now};
+use crate::HBS;
+
+/// Affiche les brouillons et les posts programmés du compte connecté
+pub async fn drafts_page(
+    LoggedIn(email): LoggedIn,
+    Extension(nonce): Extension<CspNonce>,
+    CsrfToken(csrf_token): CsrfToken,
+) -> axum::response::Result<Html<String>> {
+    let posts = post::unpublished(&email).map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to read posts"))?;
+
+    let data = json!({
+        "posts": feed_entries(posts, Some(&email)),
+        "csp_nonce": nonce.0,
+        "csrf_token": csrf_token,
+    });
+    HBS.render("drafts", &data)
+        .map(Html)
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Internal server error while rendering the page").into())
+}
+
+/// Publie immédiatement un brouillon ou un post programmé de l'auteur
+pub async fn publish_post(LoggedIn(email): LoggedIn, Json(payload): Json<serde_json::Value>) -> axum::response::Result<StatusCode> {
+    let post_id = payload
+        .get("post_id")
+        .and_then(|value| value.as_str())
+        .and_then(|value| Uuid::parse_str(value).ok())
+        .ok_or((StatusCode::BAD_REQUEST, "Invalid Post ID"))?;
+
+    let published = post::publish(post_id, &email, unix_now())
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to write posts"))?;
+    if !published {
+        return Err((StatusCode::NOT_FOUND, "Draft not found").into());
+    }
+    if let Ok(Some(post)) = post::get(post_id) {
+        notify_mentions(&post);
+    }
+    live::publish(Update::Post(post_id));
+    Ok(StatusCode::OK)
+}
+
+/// Tâche de fond qui publie les posts programmés arrivés à échéance
+pub async fn publish_scheduled_periodically() {
+    let mut interval = tokio::time::interval(Duration::from_secs(consts::PUBLISH_SCHEDULED_INTERVAL_SECS));
+    loop {
+        interval.tick().await;
+        match post::publish_due(unix_now()) {
+            Ok(published) => {
+                for post_id in published {
+                    info!("Scheduled post {} published", post_id);
+                    if let Ok(Some(post)) = post::get(post_id) {
+                        notify_mentions(&post);
+                    }
+                    live::publish(Update::Post(post_id));
+                }
+            }
+            Err(e) => error!("Failed to publish scheduled posts: {}", e),
+        }
+    }
+}
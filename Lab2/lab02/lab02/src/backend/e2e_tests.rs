@@ -0,0 +1,1294 @@
+//! Tests de bout en bout du parcours inscription → validation → connexion → publication.
+//! Le routeur complet est piloté en mémoire avec un authentificateur logiciel qui produit
+//! de vraies réponses d'attestation et d'assertion. Les données sont écrites dans le dossier
+//! temporaire de test (voir `test_support`).
+
+use axum::{
+    body::Body,
+    http::{header, HeaderMap, Method, Request, StatusCode},
+    Router,
+};
+use regex::Regex;
+use serde_json::{json, Value};
+use tower::ServiceExt;
+use webauthn_authenticator_rs::{softpasskey::SoftPasskey, WebauthnAuthenticator};
+use webauthn_rs::prelude::{CreationChallengeResponse, RequestChallengeResponse};
+use crate::backend::csrf::CSRF_HEADER;
+use crate::config::CONFIG;
+use crate::database::{audit, email, notification::Kind, user};
+
+/// Réponse simplifiée renvoyée par le client de test
+struct TestResponse {
+    status: StatusCode,
+    headers: HeaderMap,
+    body: String,
+}
+
+impl TestResponse {
+    fn json(&self) -> Value {
+        serde_json::from_str(&self.body).unwrap_or_else(|_| panic!("not JSON: {}", self.body))
+    }
+
+    fn location(&self) -> &str {
+        self.headers[header::LOCATION].to_str().unwrap()
+    }
+}
+
+/// Navigateur simulé : une instance de l'application, son cookie de session et le jeton CSRF
+/// de la dernière page affichée, renvoyé comme le fait `partials/csrf`
+struct Browser {
+    app: Router,
+    cookie: Option<String>,
+    csrf_token: Option<String>,
+}
+
+impl Browser {
+    fn new() -> Self {
+        Browser { app: crate::app(), cookie: None, csrf_token: None }
+    }
+
+    /// Envoie une requête. Comme un navigateur, suit une redirection vers le site pour
+    /// recevoir le jeton CSRF de la page suivante, mais retourne la réponse d'origine.
+    async fn send(&mut self, method: Method, uri: &str, content_type: Option<&str>, body: Body) -> TestResponse {
+        let response = self.send_once(method, uri, content_type, body).await;
+        if response.status.is_redirection() {
+            if let Some(location) = response.headers.get(header::LOCATION).and_then(|value| value.to_str().ok()) {
+                if location.starts_with('/') {
+                    self.send_once(Method::GET, location, None, Body::empty()).await;
+                }
+            }
+        }
+        response
+    }
+
+    async fn send_once(&mut self, method: Method, uri: &str, content_type: Option<&str>, body: Body) -> TestResponse {
+        let mut request = Request::builder().method(method.clone()).uri(uri);
+        if let (Some(token), false) = (&self.csrf_token, method == Method::GET) {
+            request = request.header(CSRF_HEADER, token);
+        }
+        if let Some(content_type) = content_type {
+            request = request.header(header::CONTENT_TYPE, content_type);
+        }
+        if let Some(cookie) = &self.cookie {
+            request = request.header(header::COOKIE, cookie);
+        }
+
+        let response = self.app.clone().oneshot(request.body(body).unwrap()).await.unwrap();
+
+        if let Some(set_cookie) = response.headers().get(header::SET_COOKIE) {
+            let set_cookie = set_cookie.to_str().unwrap();
+            self.cookie = if set_cookie.contains("Max-Age=0") {
+                self.csrf_token = None;
+                None
+            } else {
+                set_cookie.split(';').next().map(str::to_string)
+            };
+        }
+
+        let status = response.status();
+        let headers = response.headers().clone();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let body = String::from_utf8_lossy(&body).to_string();
+        let csrf_meta = Regex::new(r#"name="csrf-token" content="([^"]+)""#).unwrap();
+        if let Some(token) = csrf_meta.captures(&body) {
+            self.csrf_token = Some(token[1].to_string());
+        }
+        TestResponse { status, headers, body }
+    }
+
+    async fn get(&mut self, uri: &str) -> TestResponse {
+        self.send(Method::GET, uri, None, Body::empty()).await
+    }
+
+    async fn post_json(&mut self, uri: &str, body: Value) -> TestResponse {
+        self.send(Method::POST, uri, Some("application/json"), Body::from(body.to_string())).await
+    }
+
+    async fn post_multipart(&mut self, uri: &str, text: &str, jpeg: Option<Vec<u8>>) -> TestResponse {
+        self.post_form(uri, &[("text", text)], jpeg).await
+    }
+
+    /// Envoie un formulaire multipart avec des champs texte et une image facultative
+    async fn post_form(&mut self, uri: &str, fields: &[(&str, &str)], jpeg: Option<Vec<u8>>) -> TestResponse {
+        let boundary = "lab02-test-boundary";
+        let mut body = vec![];
+        for (name, value) in fields {
+            body.extend(format!(
+                "--{boundary}\r\nContent-Disposition: form-data; name=\"{name}\"\r\n\r\n{value}\r\n"
+            ).as_bytes());
+        }
+        if let Some(jpeg) = jpeg {
+            body.extend(format!(
+                "--{boundary}\r\nContent-Disposition: form-data; name=\"file\"; filename=\"photo.jpg\"\r\n\
+                 Content-Type: image/jpeg\r\n\r\n"
+            ).as_bytes());
+            body.extend(jpeg);
+            body.extend(b"\r\n");
+        }
+        body.extend(format!("--{boundary}--\r\n").as_bytes());
+
+        let content_type = format!("multipart/form-data; boundary={}", boundary);
+        self.send(Method::POST, uri, Some(&content_type), Body::from(body)).await
+    }
+
+    /// Démarre et termine un enregistrement WebAuthn avec l'appareil donné
+    async fn register(&mut self, device: &mut Device, email: &str, reset_mode: bool) -> TestResponse {
+        self.get("/register").await;
+        let begin = self.post_json("/register", json!({ "email": email, "reset_mode": reset_mode })).await;
+        if begin.status != StatusCode::OK {
+            return begin;
+        }
+        let begin = begin.json();
+        let credential = device.create(&begin["publicKey"]);
+
+        self.post_json("/register/complete", json!({
+            "email": email,
+            "first_name": "Alice",
+            "last_name": "Martin",
+            "response": credential,
+            "state_id": begin["state_id"],
+            "reset_mode": reset_mode,
+        }))
+        .await
+    }
+
+    /// Authentification sans email : l'appareil choisit lui-même son credential
+    async fn login_without_email(&mut self, device: &mut Device) -> TestResponse {
+        self.get("/login").await;
+        let begin = self.post_json("/login/discoverable", json!({})).await.json();
+        let Some(assertion) = device.get_discoverable(&begin["publicKey"]) else {
+            return TestResponse { status: StatusCode::NOT_ACCEPTABLE, headers: HeaderMap::new(), body: String::new() };
+        };
+
+        self.post_json("/login/complete", json!({ "response": assertion, "state_id": begin["state_id"] }))
+            .await
+    }
+
+    /// Ré-authentification (step-up) de l'utilisateur connecté
+    async fn step_up(&mut self, device: &mut Device) -> TestResponse {
+        let begin = self.post_json("/reauth", json!({})).await;
+        if begin.status != StatusCode::OK {
+            return begin;
+        }
+        let Some(assertion) = device.get(&begin.json()["publicKey"]) else {
+            return TestResponse { status: StatusCode::NOT_ACCEPTABLE, headers: HeaderMap::new(), body: String::new() };
+        };
+
+        self.post_json("/reauth/complete", json!({ "response": assertion })).await
+    }
+
+    /// Démarre et termine une authentification WebAuthn avec l'appareil donné
+    async fn login(&mut self, device: &mut Device, email: &str) -> TestResponse {
+        self.get("/login").await;
+        let begin = self.post_json("/login", json!({ "email": email })).await;
+        if begin.status != StatusCode::OK {
+            return begin;
+        }
+        let begin = begin.json();
+        let Some(assertion) = device.get(&begin["publicKey"]) else {
+            return TestResponse { status: StatusCode::NOT_ACCEPTABLE, headers: HeaderMap::new(), body: String::new() };
+        };
+
+        self.post_json("/login/complete", json!({ "response": assertion, "state_id": begin["state_id"] }))
+            .await
+    }
+}
+
+/// Authentificateur logiciel jouant le rôle d'une passkey.
+/// SoftPasskey ne gère pas les resident keys : l'appareil garde la liste de ses credentials
+/// et la fournit lui-même lors d'une connexion sans email, comme le ferait un navigateur.
+struct Device {
+    authenticator: WebauthnAuthenticator<SoftPasskey>,
+    credentials: Vec<Value>,
+}
+
+impl Device {
+    fn new() -> Self {
+        Device { authenticator: WebauthnAuthenticator::new(SoftPasskey::new(true)), credentials: vec![] }
+    }
+
+    fn create(&mut self, public_key: &Value) -> Value {
+        self.create_with_resident_key(public_key, public_key["authenticatorSelection"]["residentKey"] == "required")
+    }
+
+    /// Le navigateur rapporte via credProps si une resident key a été créée ; la clé
+    /// logicielle n'en crée pas, les credentials découvrables sont simulés par `get_discoverable`
+    fn create_with_resident_key(&mut self, public_key: &Value, resident_key: bool) -> Value {
+        let mut public_key = public_key.clone();
+        public_key["authenticatorSelection"]["residentKey"] = json!("discouraged");
+        public_key["authenticatorSelection"]["requireResidentKey"] = json!(false);
+
+        let options: CreationChallengeResponse =
+            serde_json::from_value(json!({ "publicKey": public_key })).unwrap();
+        let credential = self.authenticator.do_registration(CONFIG.public_url.clone(), options).unwrap();
+        let mut credential = serde_json::to_value(credential).unwrap();
+        credential["extensions"]["cred_props"] = json!({ "rk": resident_key });
+        self.credentials.push(json!({ "type": "public-key", "id": credential["id"] }));
+        credential
+    }
+
+    fn get_discoverable(&mut self, public_key: &Value) -> Option<Value> {
+        assert_eq!(public_key["allowCredentials"], json!([]));
+        let mut public_key = public_key.clone();
+        public_key["allowCredentials"] = json!(self.credentials);
+        self.get(&public_key)
+    }
+
+    /// Retourne `None` si l'appareil ne possède aucun des identifiants demandés
+    fn get(&mut self, public_key: &Value) -> Option<Value> {
+        let options: RequestChallengeResponse =
+            serde_json::from_value(json!({ "publicKey": public_key })).unwrap();
+        let assertion = self.authenticator.do_authentication(CONFIG.public_url.clone(), options).ok()?;
+        Some(serde_json::to_value(assertion).unwrap())
+    }
+}
+
+fn unique_email() -> String {
+    format!("{}@example.com", uuid::Uuid::new_v4())
+}
+
+/// Dernier lien de l'outbox correspondant à `path` (ex. `/validate/`) envoyé à `to`
+fn last_link(to: &str, path: &str) -> String {
+    let pattern = Regex::new(&format!("{}[0-9a-f-]{{36}}", regex::escape(path))).unwrap();
+    let emails = email::sent_to(to).unwrap();
+    let body = &emails.last().expect("no email sent").body;
+    pattern.find(body).expect("no link in email").as_str().to_string()
+}
+
+fn sample_jpeg() -> Vec<u8> {
+    let image = image::RgbImage::from_pixel(8, 8, image::Rgb([200, 30, 30]));
+    let mut bytes = std::io::Cursor::new(vec![]);
+    image.write_to(&mut bytes, image::ImageFormat::Jpeg).unwrap();
+    bytes.into_inner()
+}
+
+/// Inscrit et valide un nouveau compte, puis ouvre une session
+async fn signed_in(browser: &mut Browser, device: &mut Device) -> String {
+    let email = unique_email();
+    assert_eq!(browser.register(device, &email, false).await.status, StatusCode::OK);
+    browser.get(&last_link(&email, "/validate/")).await;
+    assert_eq!(browser.login(device, &email).await.status, StatusCode::SEE_OTHER);
+    email
+}
+
+#[tokio::test]
+async fn test_register_validate_login_and_post() {
+    let mut browser = Browser::new();
+    let mut device = Device::new();
+    let email = unique_email();
+
+    // Inscription
+    let registered = browser.register(&mut device, &email, false).await;
+    assert_eq!(registered.status, StatusCode::OK, "{}", registered.body);
+    assert!(!user::get(&email).unwrap().verified);
+
+    // Validation via le lien reçu par email
+    let validated = browser.get(&last_link(&email, "/validate/")).await;
+    assert_eq!(validated.location(), "/login?validated=true");
+    assert!(user::get(&email).unwrap().verified);
+
+    // Connexion
+    let logged_in = browser.login(&mut device, &email).await;
+    assert_eq!(logged_in.status, StatusCode::SEE_OTHER, "{}", logged_in.body);
+    assert_eq!(logged_in.location(), "/home");
+
+    // Publication et like
+    let created = browser.post_multipart("/post/create", "Hello from the harness", Some(sample_jpeg())).await;
+    assert_eq!(created.status, StatusCode::OK, "{}", created.body);
+    let post_id = created.json()["post_id"].as_str().unwrap().to_string();
+
+    let home = browser.get("/home").await;
+    assert_eq!(home.status, StatusCode::OK);
+    assert!(home.body.contains("Hello from the harness"));
+
+    let liked = browser.post_json("/post/like", json!({ "post_id": post_id, "action": "like" })).await;
+    assert_eq!(liked.status, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn test_protected_routes_require_session() {
+    let mut anonymous = Browser::new();
+    anonymous.get("/login").await;
+    assert_eq!(anonymous.get("/home").await.status, StatusCode::UNAUTHORIZED);
+    assert_eq!(anonymous.post_multipart("/post/create", "spam", None).await.status, StatusCode::UNAUTHORIZED);
+    assert_eq!(
+        anonymous.post_json("/post/like", json!({ "post_id": uuid::Uuid::new_v4(), "action": "like" })).await.status,
+        StatusCode::UNAUTHORIZED
+    );
+
+    let mut browser = Browser::new();
+    signed_in(&mut browser, &mut Device::new()).await;
+    assert_eq!(browser.get("/home").await.status, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn test_recovery_enrols_a_new_passkey() {
+    let mut browser = Browser::new();
+    let mut lost_device = Device::new();
+    let email = signed_in(&mut browser, &mut lost_device).await;
+
+    // Demande de récupération, le lien est ouvert depuis un autre navigateur
+    assert_eq!(browser.post_json("/recover", json!({ "email": email })).await.status, StatusCode::OK);
+    let mut new_browser = Browser::new();
+    let mut new_device = Device::new();
+    let opened = new_browser.get(&last_link(&email, "/recover/")).await;
+    assert!(opened.body.contains("reset_mode=true"));
+
+    let reset = new_browser.register(&mut new_device, &email, true).await;
+    assert_eq!(reset.status, StatusCode::OK, "{}", reset.body);
+
+    // Les sessions ouvertes avant la récupération sont fermées
+    assert_eq!(browser.get("/home").await.status, StatusCode::UNAUTHORIZED);
+
+    // Le nouvel appareil fonctionne, l'ancien est remplacé
+    assert_eq!(new_browser.login(&mut new_device, &email).await.status, StatusCode::SEE_OTHER);
+    assert_ne!(Browser::new().login(&mut lost_device, &email).await.status, StatusCode::SEE_OTHER);
+
+    // Le lien de récupération ne sert qu'une fois
+    let again = new_browser.register(&mut Device::new(), &email, true).await;
+    assert_eq!(again.status, StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn test_recovery_code_enrols_a_new_passkey() {
+    let mut browser = Browser::new();
+    let mut lost_device = Device::new();
+    let email = signed_in(&mut browser, &mut lost_device).await;
+
+    // Les codes ne sont générés qu'après un step-up
+    assert_eq!(browser.post_json("/account/recovery-codes", json!({})).await.status, StatusCode::FORBIDDEN);
+    assert_eq!(browser.step_up(&mut lost_device).await.status, StatusCode::OK);
+    let issued = browser.post_json("/account/recovery-codes", json!({})).await;
+    assert_eq!(issued.status, StatusCode::OK, "{}", issued.body);
+    let codes: Vec<String> = serde_json::from_value(issued.json()["recovery_codes"].clone()).unwrap();
+    assert!(browser.get("/account/recovery-codes").await.body.contains(&format!("<strong>{}</strong>", codes.len())));
+
+    // Un code invalide ou d'un autre compte est refusé
+    let mut new_browser = Browser::new();
+    new_browser.get("/recover").await;
+    let wrong = new_browser.post_json("/recover/code", json!({ "email": email, "code": "0000-0000-0000" })).await;
+    assert_eq!(wrong.status, StatusCode::BAD_REQUEST);
+    let foreign = new_browser.post_json("/recover/code", json!({ "email": unique_email(), "code": codes[0] })).await;
+    assert_eq!(foreign.status, StatusCode::BAD_REQUEST);
+
+    // Un code valide permet d'enrôler une nouvelle passkey, une seule fois
+    let redeemed = new_browser.post_json("/recover/code", json!({ "email": email, "code": codes[0] })).await;
+    assert_eq!(redeemed.status, StatusCode::OK, "{}", redeemed.body);
+    assert!(redeemed.json()["redirect"].as_str().unwrap().contains("reset_mode=true"));
+    let mut new_device = Device::new();
+    assert_eq!(new_browser.register(&mut new_device, &email, true).await.status, StatusCode::OK);
+    assert_eq!(new_browser.login(&mut new_device, &email).await.status, StatusCode::SEE_OTHER);
+
+    let reused = new_browser.post_json("/recover/code", json!({ "email": email, "code": codes[0] })).await;
+    assert_eq!(reused.status, StatusCode::BAD_REQUEST);
+    assert_eq!(user::get(&email).unwrap().recovery_codes.len(), codes.len() - 1);
+}
+
+#[tokio::test]
+async fn test_existing_account_cannot_be_reset_without_recovery_link() {
+    let mut owner = Browser::new();
+    let email = signed_in(&mut owner, &mut Device::new()).await;
+
+    let mut attacker = Browser::new();
+    let mut attacker_device = Device::new();
+    for reset_mode in [false, true] {
+        let attempt = attacker.register(&mut attacker_device, &email, reset_mode).await;
+        assert_eq!(attempt.status, StatusCode::BAD_REQUEST);
+    }
+
+    // Un état d'enregistrement ne peut pas être terminé pour un autre email
+    let begin = attacker.post_json("/register", json!({ "email": unique_email() })).await.json();
+    let credential = attacker_device.create(&begin["publicKey"]);
+    let hijack = attacker.post_json("/register/complete", json!({
+        "email": email,
+        "first_name": "Eve",
+        "last_name": "Attacker",
+        "response": credential,
+        "state_id": begin["state_id"],
+    })).await;
+    assert_eq!(hijack.status, StatusCode::BAD_REQUEST);
+    assert_eq!(user::get(&email).unwrap().first_name, "Alice");
+}
+
+#[tokio::test]
+async fn test_login_failure_paths() {
+    let mut browser = Browser::new();
+    let mut device = Device::new();
+    let email = unique_email();
+    browser.register(&mut device, &email, false).await;
+
+    // Compte inconnu : même forme de réponse, avec un credential factice stable pour cet email
+    let known = browser.post_json("/login", json!({ "email": email })).await.json();
+    let unknown_email = unique_email();
+    let unknown_account = browser.post_json("/login", json!({ "email": unknown_email })).await;
+    assert_eq!(unknown_account.status, StatusCode::OK);
+    let allowed = |response: &Value| response["publicKey"]["allowCredentials"].clone();
+    let decoy = allowed(&unknown_account.json());
+    assert_eq!(decoy.as_array().unwrap().len(), 1);
+    assert_eq!(
+        decoy[0].as_object().unwrap().keys().collect::<Vec<_>>(),
+        allowed(&known)[0].as_object().unwrap().keys().collect::<Vec<_>>()
+    );
+    let keys = |response: &Value| response["publicKey"].as_object().unwrap().keys().cloned().collect::<Vec<_>>();
+    assert_eq!(keys(&unknown_account.json()), keys(&known));
+    let again = browser.post_json("/login", json!({ "email": unknown_email })).await.json();
+    assert_eq!(allowed(&again), decoy);
+    let other = browser.post_json("/login", json!({ "email": unique_email() })).await.json();
+    assert_ne!(allowed(&other), decoy);
+
+    // Appareil qui ne possède pas la passkey du compte
+    assert_ne!(browser.login(&mut Device::new(), &email).await.status, StatusCode::SEE_OTHER);
+
+    // Identifiant d'état inconnu puis rejeu d'un état déjà consommé
+    let begin = browser.post_json("/login", json!({ "email": email })).await.json();
+    let assertion = device.get(&begin["publicKey"]).unwrap();
+    let unknown = browser
+        .post_json("/login/complete", json!({ "response": assertion, "state_id": uuid::Uuid::new_v4() }))
+        .await;
+    assert_eq!(unknown.status, StatusCode::BAD_REQUEST);
+
+    let body = json!({ "response": assertion, "state_id": begin["state_id"] });
+    assert_eq!(browser.post_json("/login/complete", body.clone()).await.status, StatusCode::SEE_OTHER);
+    assert_eq!(browser.post_json("/login/complete", body).await.status, StatusCode::BAD_REQUEST);
+
+    // Lien de validation invalide
+    let invalid = browser.get(&format!("/validate/{}", uuid::Uuid::new_v4())).await;
+    assert_eq!(invalid.location(), "/register?error=invalid_token");
+}
+
+#[tokio::test]
+async fn test_usernameless_login() {
+    let mut browser = Browser::new();
+    let mut device = Device::new();
+    let email = unique_email();
+
+    browser.get("/register").await;
+    let begin = browser.post_json("/register", json!({ "email": email, "discoverable": true })).await.json();
+    assert_eq!(begin["publicKey"]["authenticatorSelection"]["residentKey"], "required");
+    assert_eq!(begin["publicKey"]["extensions"]["credProps"], true);
+    let credential = device.create(&begin["publicKey"]);
+    let registered = browser.post_json("/register/complete", json!({
+        "email": email,
+        "first_name": "Alice",
+        "last_name": "Martin",
+        "response": credential,
+        "state_id": begin["state_id"],
+    })).await;
+    assert_eq!(registered.status, StatusCode::OK);
+    browser.get(&last_link(&email, "/validate/")).await;
+
+    // Le compte est retrouvé depuis le credential, sans saisir d'email
+    let logged_in = browser.login_without_email(&mut device).await;
+    assert_eq!(logged_in.status, StatusCode::SEE_OTHER, "{}", logged_in.body);
+    assert_eq!(browser.get("/home").await.status, StatusCode::OK);
+
+    // L'email saisi reste possible en repli
+    assert_eq!(Browser::new().login(&mut device, &email).await.status, StatusCode::SEE_OTHER);
+
+    // Un credential inconnu du serveur est refusé
+    let mut stranger = Device::new();
+    stranger.create(&browser.post_json("/register", json!({ "email": unique_email() })).await.json()["publicKey"]);
+    let rejected = Browser::new().login_without_email(&mut stranger).await;
+    assert_eq!(rejected.status, StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn test_required_resident_key_must_be_created() {
+    let mut browser = Browser::new();
+    let email = unique_email();
+
+    browser.get("/register").await;
+    let begin = browser.post_json("/register", json!({ "email": email, "discoverable": true })).await.json();
+    let credential = Device::new().create_with_resident_key(&begin["publicKey"], false);
+    let rejected = browser.post_json("/register/complete", json!({
+        "email": email,
+        "first_name": "Alice",
+        "last_name": "Martin",
+        "response": credential,
+        "state_id": begin["state_id"],
+    })).await;
+    assert_eq!(rejected.status, StatusCode::FORBIDDEN);
+    assert!(rejected.body.contains("resident key"));
+    assert!(!user::exists(&email).unwrap());
+}
+
+#[tokio::test]
+async fn test_sensitive_operation_requires_step_up() {
+    let mut browser = Browser::new();
+    let mut device = Device::new();
+    let email = signed_in(&mut browser, &mut device).await;
+    assert!(browser.get("/home").await.body.contains("withStepUp"));
+
+    // La session seule ne suffit pas
+    assert_eq!(browser.post_json("/account/delete", json!({})).await.status, StatusCode::FORBIDDEN);
+
+    // Un autre appareil ne peut pas ré-authentifier la session
+    assert_eq!(browser.step_up(&mut Device::new()).await.status, StatusCode::NOT_ACCEPTABLE);
+
+    // La cérémonie est liée à la session qui l'a démarrée
+    let begin = browser.post_json("/reauth", json!({})).await.json();
+    let assertion = device.get(&begin["publicKey"]).unwrap();
+    let mut other = Browser::new();
+    signed_in(&mut other, &mut Device::new()).await;
+    let stolen = other.post_json("/reauth/complete", json!({ "response": assertion })).await;
+    assert_eq!(stolen.status, StatusCode::BAD_REQUEST);
+    assert_eq!(other.post_json("/account/delete", json!({})).await.status, StatusCode::FORBIDDEN);
+
+    // Avec un step-up récent, l'opération est permise et la session fermée
+    assert_eq!(browser.step_up(&mut device).await.status, StatusCode::OK);
+    assert_eq!(browser.post_json("/account/delete", json!({})).await.status, StatusCode::OK);
+    assert!(user::get(&email).is_none());
+    assert_eq!(browser.get("/home").await.status, StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn test_deleted_account_leaves_nothing_to_a_new_account_with_the_same_email() {
+    let mut browser = Browser::new();
+    let mut device = Device::new();
+    let email = signed_in(&mut browser, &mut device).await;
+    let (public_text, public_id) = create_post(&mut browser, &[], Some(sample_jpeg())).await;
+    let (private_text, _) = create_post(&mut browser, &[("visibility", "private")], None).await;
+    let (draft_text, _) = create_post(&mut browser, &[("draft", "true")], None).await;
+    let image = crate::database::post::get(public_id.parse().unwrap()).unwrap().unwrap().image_path.unwrap();
+
+    // Un autre compte enregistre et signale le post public
+    let mut other = Browser::new();
+    let other_email = signed_in(&mut other, &mut Device::new()).await;
+    other.get("/home").await;
+    assert_eq!(other.post_json("/post/bookmark", json!({ "post_id": public_id, "saved": true })).await.status, StatusCode::OK);
+    let report = json!({ "post_id": public_id, "category": "spam", "details": "Same link everywhere" });
+    assert_eq!(other.post_json("/post/report", report).await.status, StatusCode::OK);
+
+    assert_eq!(browser.step_up(&mut device).await.status, StatusCode::OK);
+    assert_eq!(browser.post_json("/account/delete", json!({})).await.status, StatusCode::OK);
+    assert!(user::get(&other_email).unwrap().stash.is_empty());
+    assert!(crate::database::report::open().unwrap().iter().all(|report| report.post_id.to_string() != public_id));
+    assert!(!std::path::Path::new(&image).exists());
+
+    // Le nouveau compte ne voit aucun post de l'ancien, même privé ou en brouillon
+    let mut newcomer = Browser::new();
+    let mut new_device = Device::new();
+    assert_eq!(newcomer.register(&mut new_device, &email, false).await.status, StatusCode::OK);
+    newcomer.get(&last_link(&email, "/validate/")).await;
+    assert_eq!(newcomer.login(&mut new_device, &email).await.status, StatusCode::SEE_OTHER);
+    let home = newcomer.get("/home").await.body;
+    let drafts = newcomer.get("/drafts").await.body;
+    for text in [&public_text, &private_text, &draft_text] {
+        assert!(!home.contains(text.as_str()) && !drafts.contains(text.as_str()));
+    }
+}
+
+#[tokio::test]
+async fn test_passkey_replacement_requires_step_up_and_revokes_the_old_one() {
+    let mut laptop = Browser::new();
+    let mut old_device = Device::new();
+    let email = signed_in(&mut laptop, &mut old_device).await;
+    let mut phone = Browser::new();
+    assert_eq!(phone.login(&mut old_device, &email).await.status, StatusCode::SEE_OTHER);
+
+    // La session seule ne suffit pas
+    assert_eq!(laptop.post_json("/account/passkey", json!({})).await.status, StatusCode::FORBIDDEN);
+
+    // Après un step-up, une nouvelle passkey remplace l'ancienne
+    assert_eq!(laptop.step_up(&mut old_device).await.status, StatusCode::OK);
+    let begin = laptop.post_json("/account/passkey", json!({})).await;
+    assert_eq!(begin.status, StatusCode::OK);
+    let mut new_device = Device::new();
+    let credential = new_device.create(&begin.json()["publicKey"]);
+    let complete = laptop.post_json("/account/passkey/complete", json!({ "response": credential })).await;
+    assert_eq!(complete.status, StatusCode::OK);
+
+    // L'enrôlement ne peut pas être terminé une seconde fois
+    let replay = laptop.post_json("/account/passkey/complete", json!({ "response": credential })).await;
+    assert_eq!(replay.status, StatusCode::BAD_REQUEST);
+
+    // Les autres sessions sont fermées, la session courante reste ouverte
+    assert_eq!(laptop.get("/home").await.status, StatusCode::OK);
+    assert_eq!(phone.get("/home").await.status, StatusCode::UNAUTHORIZED);
+    assert_eq!(Browser::new().login(&mut old_device, &email).await.status, StatusCode::NOT_ACCEPTABLE);
+    assert_eq!(Browser::new().login(&mut new_device, &email).await.status, StatusCode::SEE_OTHER);
+}
+
+#[tokio::test]
+async fn test_sessions_can_be_listed_and_revoked() {
+    let mut laptop = Browser::new();
+    let mut device = Device::new();
+    let email = signed_in(&mut laptop, &mut device).await;
+    let mut phone = Browser::new();
+    let mut tablet = Browser::new();
+    assert_eq!(phone.login(&mut device, &email).await.status, StatusCode::SEE_OTHER);
+    assert_eq!(tablet.login(&mut device, &email).await.status, StatusCode::SEE_OTHER);
+
+    // Les trois sessions sont listées, seule la courante ne peut pas être révoquée depuis la liste
+    let page = laptop.get("/account/sessions").await;
+    assert_eq!(page.status, StatusCode::OK);
+    let ids: Vec<String> = Regex::new(r#"data-session-id="([^"]+)""#).unwrap()
+        .captures_iter(&page.body)
+        .map(|captures| captures[1].to_string())
+        .collect();
+    assert_eq!(ids.len(), 2);
+    assert_eq!(page.body.matches("This device").count(), 1);
+
+    // Une session d'un autre compte ne peut pas être révoquée
+    let mut stranger = Browser::new();
+    signed_in(&mut stranger, &mut Device::new()).await;
+    let foreign = stranger.post_json("/account/sessions/revoke", json!({ "id": ids[0] })).await;
+    assert_eq!(foreign.status, StatusCode::NOT_FOUND);
+
+    // Révocation individuelle puis de toutes les autres sessions
+    assert_eq!(laptop.post_json("/account/sessions/revoke", json!({ "id": ids[0] })).await.status, StatusCode::OK);
+    let revoked = laptop.post_json("/account/sessions/revoke-others", json!({})).await;
+    assert_eq!(revoked.json()["revoked"], 1);
+    assert_eq!(phone.get("/home").await.status, StatusCode::UNAUTHORIZED);
+    assert_eq!(tablet.get("/home").await.status, StatusCode::UNAUTHORIZED);
+    assert_eq!(laptop.get("/home").await.status, StatusCode::OK);
+
+    // La déconnexion est un POST protégé, qui ferme la session et efface le cookie
+    assert_eq!(laptop.get("/logout").await.status, StatusCode::METHOD_NOT_ALLOWED);
+    let token = laptop.csrf_token.take();
+    assert_eq!(laptop.post_json("/logout", json!({})).await.status, StatusCode::FORBIDDEN);
+    laptop.csrf_token = token;
+    let logout = laptop.post_json("/logout", json!({})).await;
+    assert!(logout.headers[header::SET_COOKIE].to_str().unwrap().contains("Max-Age=0"));
+    assert_eq!(laptop.get("/home").await.status, StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn test_magic_link_is_opt_in_and_disabled_by_default() {
+    let mut browser = Browser::new();
+    let mut device = Device::new();
+    let email = signed_in(&mut browser, &mut device).await;
+
+    // L'activation par le titulaire exige un step-up
+    let enable = json!({ "enabled": true });
+    assert_eq!(browser.post_json("/account/magic-link", enable.clone()).await.status, StatusCode::FORBIDDEN);
+    assert_eq!(browser.step_up(&mut device).await.status, StatusCode::OK);
+    assert_eq!(browser.post_json("/account/magic-link", enable).await.status, StatusCode::OK);
+    assert!(user::get(&email).unwrap().magic_link);
+
+    // Sans `LAB02_MAGIC_LINK_LOGIN`, aucun lien n'est envoyé ni accepté
+    let mut kiosk = Browser::new();
+    kiosk.get("/login").await;
+    assert_eq!(kiosk.post_json("/login/magic", json!({ "email": email })).await.status, StatusCode::NOT_FOUND);
+    let opened = kiosk.get(&format!("/login/magic/{}", uuid::Uuid::new_v4())).await;
+    assert_eq!(opened.location(), "/login?error=magic_link_failed");
+    assert_eq!(kiosk.get("/home").await.status, StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn test_moderation_console() {
+    let mut author = Browser::new();
+    let mut author_device = Device::new();
+    let author_email = signed_in(&mut author, &mut author_device).await;
+    let mut moderator = Browser::new();
+    let moderator_email = signed_in(&mut moderator, &mut Device::new()).await;
+    user::set_role(&moderator_email, user::Role::Moderator).unwrap();
+    let mut admin = Browser::new();
+    let admin_email = signed_in(&mut admin, &mut Device::new()).await;
+    user::set_role(&admin_email, user::Role::Admin).unwrap();
+
+    let text = format!("Abusive post {}", uuid::Uuid::new_v4());
+    let created = author.post_multipart("/post/create", &text, None).await;
+    let post_id = created.json()["post_id"].as_str().unwrap().to_string();
+
+    // La console est réservée aux modérateurs
+    assert_eq!(author.get("/moderation").await.status, StatusCode::FORBIDDEN);
+    assert_eq!(author.post_json("/moderation/post", json!({ "post_id": post_id, "hidden": true, "reason": "x" })).await.status, StatusCode::FORBIDDEN);
+    assert!(moderator.get("/moderation").await.body.contains(&text));
+
+    // Masquage d'un post, avec une raison obligatoire
+    let hide = json!({ "post_id": post_id, "hidden": true, "reason": "" });
+    assert_eq!(moderator.post_json("/moderation/post", hide).await.status, StatusCode::BAD_REQUEST);
+    let hide = json!({ "post_id": post_id, "hidden": true, "reason": "Harassment" });
+    assert_eq!(moderator.post_json("/moderation/post", hide).await.status, StatusCode::OK);
+    assert!(!author.get("/home").await.body.contains(&text));
+    let like = json!({ "post_id": post_id, "action": "like" });
+    assert_eq!(author.post_json("/post/like", like).await.status, StatusCode::NOT_FOUND);
+
+    // Suspension : les sessions sont fermées et la connexion refusée
+    let suspend = json!({ "email": author_email, "suspended": true, "reason": "Repeated harassment" });
+    assert_eq!(moderator.post_json("/moderation/user", suspend).await.status, StatusCode::OK);
+    assert_eq!(author.get("/home").await.status, StatusCode::UNAUTHORIZED);
+    assert_eq!(Browser::new().login(&mut author_device, &author_email).await.status, StatusCode::FORBIDDEN);
+
+    // Un modérateur ne touche ni aux comptes de rang égal ou supérieur, ni aux rôles
+    let suspend_admin = json!({ "email": admin_email, "suspended": true, "reason": "Coup" });
+    assert_eq!(moderator.post_json("/moderation/user", suspend_admin).await.status, StatusCode::FORBIDDEN);
+    let promote = json!({ "email": moderator_email, "role": "admin", "reason": "Coup" });
+    assert_eq!(moderator.post_json("/moderation/role", promote).await.status, StatusCode::FORBIDDEN);
+
+    // Un administrateur gère les rôles, sauf le sien
+    let demote = json!({ "email": moderator_email, "role": "user", "reason": "Left the team" });
+    assert_eq!(admin.post_json("/moderation/role", demote).await.status, StatusCode::OK);
+    assert_eq!(user::get(&moderator_email).unwrap().role, user::Role::User);
+    let own = json!({ "email": admin_email, "role": "user", "reason": "Oops" });
+    assert_eq!(admin.post_json("/moderation/role", own).await.status, StatusCode::BAD_REQUEST);
+    assert_eq!(moderator.get("/moderation").await.status, StatusCode::FORBIDDEN);
+
+    // Chaque action est journalisée avec son auteur et sa raison
+    let console = admin.get("/moderation").await.body;
+    assert!(console.contains(r#"<option value="moderator">"#));
+    let targets = [&post_id, &author_email, &moderator_email];
+    let log: Vec<_> = audit::recent(usize::MAX)
+        .unwrap()
+        .into_iter()
+        .filter(|entry| targets.contains(&&entry.target))
+        .collect();
+    assert_eq!(log.len(), 3);
+    assert!(log.iter().any(|entry| entry.action == audit::Action::HidePost && entry.actor == moderator_email && entry.reason == "Harassment"));
+    assert!(log.iter().any(|entry| entry.action == audit::Action::SetRole && entry.actor == admin_email && entry.detail.as_deref() == Some("user")));
+}
+
+#[tokio::test]
+async fn test_reported_posts_reach_the_moderation_queue() {
+    let mut author = Browser::new();
+    signed_in(&mut author, &mut Device::new()).await;
+    let mut moderator = Browser::new();
+    let moderator_email = signed_in(&mut moderator, &mut Device::new()).await;
+    user::set_role(&moderator_email, user::Role::Moderator).unwrap();
+
+    let text = format!("Reported post {}", uuid::Uuid::new_v4());
+    let created = author.post_multipart("/post/create", &text, None).await;
+    let post_id = created.json()["post_id"].as_str().unwrap().to_string();
+
+    let mut reporters = vec![];
+    for _ in 0..CONFIG.report_hide_threshold {
+        let mut reporter = Browser::new();
+        let email = signed_in(&mut reporter, &mut Device::new()).await;
+        reporters.push((reporter, email));
+    }
+    let report = json!({ "post_id": post_id, "category": "spam", "details": "Same link everywhere" });
+    let invalid = json!({ "post_id": post_id, "category": "boring" });
+    assert_eq!(reporters[0].0.post_json("/post/report", invalid).await.status, StatusCode::BAD_REQUEST);
+
+    // Un compte ne compte qu'une fois, même s'il signale plusieurs fois
+    for _ in 0..CONFIG.report_hide_threshold {
+        assert_eq!(reporters[0].0.post_json("/post/report", report.clone()).await.status, StatusCode::OK);
+    }
+    assert!(author.get("/home").await.body.contains(&text));
+    let queue = moderator.get("/moderation").await.body;
+    assert!(queue.contains("spam ×1") && queue.contains("Same link everywhere"));
+
+    // Au seuil, le post est masqué en attendant la décision
+    for (reporter, _) in reporters.iter_mut().skip(1) {
+        let report = json!({ "post_id": post_id, "category": "harassment" });
+        assert_eq!(reporter.post_json("/post/report", report).await.status, StatusCode::OK);
+    }
+    assert!(!author.get("/home").await.body.contains(&text));
+    assert!(audit::recent(usize::MAX).unwrap().iter().any(|entry| entry.target == post_id && entry.actor == "auto-moderation"));
+
+    // Réservé aux modérateurs, raison obligatoire
+    let dismiss = json!({ "post_id": post_id, "outcome": "dismissed", "reason": "Not spam" });
+    assert_eq!(author.post_json("/moderation/reports", dismiss.clone()).await.status, StatusCode::FORBIDDEN);
+    let no_reason = json!({ "post_id": post_id, "outcome": "dismissed" });
+    assert_eq!(moderator.post_json("/moderation/reports", no_reason).await.status, StatusCode::BAD_REQUEST);
+
+    // Rejet : le post redevient visible et les auteurs des signalements sont prévenus
+    assert_eq!(moderator.post_json("/moderation/reports", dismiss.clone()).await.status, StatusCode::OK);
+    assert!(author.get("/home").await.body.contains(&text));
+    for (_, email) in &reporters {
+        assert_eq!(email::sent_to(email).unwrap().last().unwrap().subject, "Your report was reviewed");
+    }
+    assert_eq!(moderator.post_json("/moderation/reports", dismiss).await.status, StatusCode::NOT_FOUND);
+
+    // Après un rejet, les mêmes comptes ne peuvent pas signaler de nouveau ce post
+    let report = json!({ "post_id": post_id, "category": "illegal" });
+    assert_eq!(reporters[0].0.post_json("/post/report", report.clone()).await.status, StatusCode::OK);
+    let remove = json!({ "post_id": post_id, "outcome": "removed", "reason": "Illegal content" });
+    assert_eq!(moderator.post_json("/moderation/reports", remove).await.status, StatusCode::NOT_FOUND);
+
+    // Un nouveau signalement d'un autre compte rouvre la file ; le retrait garde le post masqué
+    let mut newcomer = Browser::new();
+    signed_in(&mut newcomer, &mut Device::new()).await;
+    assert_eq!(newcomer.post_json("/post/report", report).await.status, StatusCode::OK);
+    let remove = json!({ "post_id": post_id, "outcome": "removed", "reason": "Illegal content" });
+    assert_eq!(moderator.post_json("/moderation/reports", remove).await.status, StatusCode::OK);
+    assert!(!author.get("/home").await.body.contains(&text));
+    let log: Vec<_> = audit::recent(usize::MAX)
+        .unwrap()
+        .into_iter()
+        .filter(|entry| entry.target == post_id && entry.action == audit::Action::ResolveReports)
+        .collect();
+    assert_eq!(log.len(), 2);
+    assert!(log.iter().all(|entry| entry.actor == moderator_email));
+}
+
+#[tokio::test]
+async fn test_bookmarks() {
+    let mut browser = Browser::new();
+    let email = signed_in(&mut browser, &mut Device::new()).await;
+    let mut other = Browser::new();
+    signed_in(&mut other, &mut Device::new()).await;
+
+    let mut post_ids = vec![];
+    for text in ["First saved", "Second saved", "Deleted later"] {
+        let text = format!("{} {}", text, uuid::Uuid::new_v4());
+        let created = browser.post_multipart("/post/create", &text, None).await;
+        post_ids.push(created.json()["post_id"].as_str().unwrap().to_string());
+    }
+    for post_id in &post_ids {
+        let save = json!({ "post_id": post_id, "saved": true });
+        assert_eq!(browser.post_json("/post/bookmark", save).await.status, StatusCode::OK);
+    }
+    let invalid = json!({ "post_id": uuid::Uuid::new_v4(), "saved": true });
+    assert_eq!(browser.post_json("/post/bookmark", invalid).await.status, StatusCode::NOT_FOUND);
+
+    // Chaque compte voit son propre état dans le fil JSON
+    let bookmarked = |feed: Value, post_id: &str| {
+        feed["posts"].as_array().unwrap().iter().find(|post| post["id"] == post_id).unwrap()["bookmarked"].as_bool().unwrap()
+    };
+    assert!(bookmarked(browser.get("/posts").await.json(), &post_ids[0]));
+    assert!(!bookmarked(other.get("/posts").await.json(), &post_ids[0]));
+
+    // Retrait, puis suppression d'un post enregistré
+    let unsave = json!({ "post_id": post_ids[0], "saved": false });
+    assert_eq!(browser.post_json("/post/bookmark", unsave).await.status, StatusCode::OK);
+    assert!(!bookmarked(browser.get("/posts").await.json(), &post_ids[0]));
+    crate::database::post::delete(post_ids[2].parse().unwrap()).unwrap();
+
+    let saved = browser.get("/saved").await.body;
+    assert!(saved.contains(&post_ids[1]));
+    assert!(!saved.contains(&post_ids[0]) && !saved.contains(&post_ids[2]));
+    assert_eq!(user::get(&email).unwrap().stash, vec![post_ids[1].clone()]);
+    assert!(browser.get("/saved?page=2").await.body.contains("No saved post on this page"));
+}
+
+#[tokio::test]
+async fn test_follow_graph_and_timelines() {
+    let mut alice = Browser::new();
+    let alice_email = signed_in(&mut alice, &mut Device::new()).await;
+    let mut bob = Browser::new();
+    let bob_email = signed_in(&mut bob, &mut Device::new()).await;
+    let mut carol = Browser::new();
+    signed_in(&mut carol, &mut Device::new()).await;
+
+    let bob_text = format!("Bob writes {}", uuid::Uuid::new_v4());
+    bob.post_multipart("/post/create", &bob_text, None).await;
+    let carol_text = format!("Carol writes {}", uuid::Uuid::new_v4());
+    carol.post_multipart("/post/create", &carol_text, None).await;
+
+    // Le fil global montre tout, le fil des abonnements est vide
+    let home = alice.get("/home").await.body;
+    assert!(home.contains(&bob_text) && home.contains(&carol_text));
+    assert!(alice.get("/home?feed=following").await.body.contains("No post yet from the accounts you follow"));
+
+    let bob_handle = user::get(&bob_email).unwrap().handle;
+    let alice_handle = user::get(&alice_email).unwrap().handle;
+    let follow = json!({ "handle": bob_handle, "follow": true });
+    assert_eq!(alice.post_json("/users/follow", follow.clone()).await.status, StatusCode::OK);
+    assert_eq!(alice.post_json("/users/follow", follow).await.status, StatusCode::OK);
+    let own = json!({ "handle": alice_handle, "follow": true });
+    assert_eq!(alice.post_json("/users/follow", own).await.status, StatusCode::BAD_REQUEST);
+    let unknown = json!({ "handle": user::new_handle(), "follow": true });
+    assert_eq!(alice.post_json("/users/follow", unknown).await.status, StatusCode::NOT_FOUND);
+
+    let timeline = alice.get("/home?feed=following").await.body;
+    assert!(timeline.contains(&bob_text) && !timeline.contains(&carol_text));
+
+    // Profil et listes d'abonnés, sans aucun email
+    let profile = alice.get(&format!("/users/{}", bob_handle)).await.body;
+    assert!(profile.contains(&bob_text) && !profile.contains(&carol_text));
+    assert!(profile.contains("1 followers") && profile.contains("Unfollow"));
+    assert!(!profile.contains(&bob_email));
+    let followers = bob.get(&format!("/users/{}/followers", bob_handle)).await.body;
+    assert!(followers.contains(&format!("/users/{}", alice_handle)) && !followers.contains(&alice_email));
+    let following = bob.get(&format!("/users/{}/following", alice_handle)).await.body;
+    assert!(following.contains(&format!("/users/{}", bob_handle)));
+    assert_eq!(alice.get(&format!("/users/{}/friends", bob_handle)).await.status, StatusCode::NOT_FOUND);
+    assert_eq!(alice.get(&format!("/users/{}", bob_email)).await.status, StatusCode::NOT_FOUND);
+
+    // Un compte inconnu et un compte non public donnent la même réponse
+    let unknown = alice.get(&format!("/users/{}", user::new_handle())).await;
+    let pending_email = unique_email();
+    assert_eq!(Browser::new().register(&mut Device::new(), &pending_email, false).await.status, StatusCode::OK);
+    let pending = alice.get(&format!("/users/{}", user::get(&pending_email).unwrap().handle)).await;
+    assert_eq!((unknown.status, &unknown.body), (StatusCode::NOT_FOUND, &pending.body));
+    assert_eq!(pending.status, StatusCode::NOT_FOUND);
+
+    let unfollow = json!({ "handle": bob_handle, "follow": false });
+    assert_eq!(alice.post_json("/users/follow", unfollow).await.status, StatusCode::OK);
+    assert!(!alice.get("/home?feed=following").await.body.contains(&bob_text));
+    assert!(crate::database::follow::followers(&bob_email).unwrap().is_empty());
+}
+
+/// Publie un post au texte unique avec les champs donnés ; retourne son texte et son identifiant
+async fn create_post(browser: &mut Browser, fields: &[(&str, &str)], jpeg: Option<Vec<u8>>) -> (String, String) {
+    let text = format!("Post {}", uuid::Uuid::new_v4());
+    let mut form = vec![("text", text.as_str())];
+    form.extend_from_slice(fields);
+    let created = browser.post_form("/post/create", &form, jpeg).await;
+    assert_eq!(created.status, StatusCode::OK, "{}", created.body);
+    let post_id = created.json()["post_id"].as_str().unwrap().to_string();
+    (text, post_id)
+}
+
+#[tokio::test]
+async fn test_post_visibility_drafts_and_scheduling() {
+    let mut author = Browser::new();
+    let author_email = signed_in(&mut author, &mut Device::new()).await;
+    let mut follower = Browser::new();
+    signed_in(&mut follower, &mut Device::new()).await;
+    follower.get("/home").await;
+    let author_handle = user::get(&author_email).unwrap().handle;
+    assert_eq!(follower.post_json("/users/follow", json!({ "handle": author_handle, "follow": true })).await.status, StatusCode::OK);
+    let mut stranger = Browser::new();
+    signed_in(&mut stranger, &mut Device::new()).await;
+
+    let later = (crate::database::unix_now() + 3600).to_string();
+    let (followers_text, followers_id) = create_post(&mut author, &[("visibility", "followers")], Some(sample_jpeg())).await;
+    let (private_text, _) = create_post(&mut author, &[("visibility", "private")], None).await;
+    let (draft_text, draft_id) = create_post(&mut author, &[("draft", "true")], None).await;
+    let (scheduled_text, _) = create_post(&mut author, &[("publish_at", &later)], None).await;
+
+    let invalid = [("text", "x"), ("visibility", "friends")];
+    assert_eq!(author.post_form("/post/create", &invalid, None).await.status, StatusCode::BAD_REQUEST);
+    let past = [("text", "x"), ("publish_at", "1")];
+    assert_eq!(author.post_form("/post/create", &past, None).await.status, StatusCode::BAD_REQUEST);
+
+    // Les mêmes règles pour le fil, l'API JSON et les images
+    let feed = follower.get("/home").await.body;
+    assert!(feed.contains(&followers_text) && !feed.contains(&private_text));
+    assert!(!feed.contains(&draft_text) && !feed.contains(&scheduled_text));
+    let stranger_feed = stranger.get("/posts").await.body;
+    assert!(!stranger_feed.contains(&followers_text) && !stranger_feed.contains(&private_text));
+    assert_eq!(follower.get(&format!("/media/{}", followers_id)).await.status, StatusCode::OK);
+    assert_eq!(stranger.get(&format!("/media/{}", followers_id)).await.status, StatusCode::NOT_FOUND);
+    let like = json!({ "post_id": followers_id, "action": "like" });
+    assert_eq!(stranger.post_json("/post/like", like).await.status, StatusCode::NOT_FOUND);
+    let author_feed = author.get("/home").await.body;
+    assert!(author_feed.contains(&followers_text) && author_feed.contains(&private_text));
+
+    // Brouillons et posts programmés restent chez l'auteur jusqu'à leur publication
+    let drafts = author.get("/drafts").await.body;
+    assert!(drafts.contains(&draft_text) && drafts.contains(&scheduled_text));
+    assert_eq!(stranger.post_json("/post/publish", json!({ "post_id": draft_id })).await.status, StatusCode::NOT_FOUND);
+    let (fresh_text, _) = create_post(&mut author, &[], None).await;
+    assert_eq!(author.post_json("/post/publish", json!({ "post_id": draft_id })).await.status, StatusCode::OK);
+    assert!(stranger.get("/home").await.body.contains(&draft_text));
+
+    crate::database::post::publish_due(later.parse().unwrap()).unwrap();
+    let feed = stranger.get("/home").await.body;
+    assert!(feed.contains(&scheduled_text));
+    assert!(!author.get("/drafts").await.body.contains(&scheduled_text));
+    // Le fil suit l'ordre de publication, pas celui de création
+    let position = |text: &str| feed.find(text).unwrap();
+    assert!(position(&fresh_text) < position(&draft_text) && position(&draft_text) < position(&scheduled_text));
+}
+
+#[tokio::test]
+async fn test_post_content_is_rendered_as_sanitised_markdown() {
+    let mut browser = Browser::new();
+    let email = signed_in(&mut browser, &mut Device::new()).await;
+    let tag = format!("tag{}", uuid::Uuid::new_v4().simple());
+    let text = format!("*{}* <script>alert(1)</script> [x](javascript:alert(1)) #{} @{}", tag, tag, email);
+    assert_eq!(browser.post_multipart("/post/create", &text, None).await.status, StatusCode::OK);
+
+    let home = browser.get("/home").await.body;
+    assert!(home.contains(&format!("<em>{}</em>", tag)));
+    assert!(!home.contains("<script>alert(1)") && home.contains("&lt;script&gt;alert(1)"));
+    assert!(!home.contains("javascript:alert"));
+    assert!(home.contains(&format!(r#"<a href="/tags/{}" rel="nofollow noopener">"#, tag)));
+    let handle = user::get(&email).unwrap().handle;
+    assert!(home.contains(&format!(r#"<a href="/users/{}" rel="nofollow noopener">@{}</a>"#, handle, email)));
+
+    let feed = browser.get("/posts").await.json();
+    let post = feed["posts"].as_array().unwrap().iter().find(|post| post["content"] == text).unwrap();
+    assert!(post["content_html"].as_str().unwrap().contains("<em>"));
+    assert!(browser.get(&format!("/tags/{}", tag.to_uppercase())).await.body.contains(&format!("<em>{}</em>", tag)));
+}
+
+#[tokio::test]
+async fn test_search_respects_visibility_and_follows_edits() {
+    let mut author = Browser::new();
+    let author_email = signed_in(&mut author, &mut Device::new()).await;
+    let mut reader = Browser::new();
+    signed_in(&mut reader, &mut Device::new()).await;
+    reader.get("/home").await;
+
+    let word = format!("mot{}", uuid::Uuid::new_v4().simple());
+    let public = format!("Les chats de {} dorment #{}", word, word);
+    let private = format!("Le {} secret", word);
+    let created = author.post_form("/post/create", &[("text", &public)], None).await;
+    let public_id: uuid::Uuid = created.json()["post_id"].as_str().unwrap().parse().unwrap();
+    let created = author.post_form("/post/create", &[("text", &private), ("visibility", "private")], None).await;
+    assert_eq!(created.status, StatusCode::OK);
+    let search = |query: &str| format!("/search.json?{}", query);
+
+    // Racines, expressions, hashtags et auteur ; le post privé reste invisible
+    let found = reader.get(&search(&format!("q={}+chat", word))).await.json();
+    assert_eq!(found["total"], 1);
+    assert_eq!(found["posts"][0]["content"], public.as_str());
+    assert_eq!(reader.get(&search(&format!("q=%22chat+de+{}%22", word))).await.json()["total"], 1);
+    assert_eq!(reader.get(&search(&format!("q=%22{}+chat%22", word))).await.json()["total"], 0);
+    assert_eq!(reader.get(&search(&format!("tag={}", word))).await.json()["total"], 1);
+    assert_eq!(reader.get(&search(&format!("q={}&author={}", word, unique_email()))).await.json()["total"], 0);
+    assert_eq!(author.get(&search(&format!("q={}&author={}", word, author_email))).await.json()["total"], 2);
+    let suggestions = reader.get(&search(&format!("q={}", word))).await.json();
+    assert_eq!(suggestions["tags"][0]["tag"], word.as_str());
+
+    // Les comptes sont trouvés par leur nom, jamais par leur email, et désignés par leur identifiant
+    user::set_name(&author_email, "Zoe", &word).unwrap();
+    let accounts = reader.get(&search(&format!("q=zoe+{}", word))).await.json();
+    assert_eq!(accounts["users"], json!([{ "handle": user::get(&author_email).unwrap().handle, "name": format!("Zoe {}", word) }]));
+    assert_eq!(reader.get(&search(&format!("q={}", &author_email[..8]))).await.json()["users"], json!([]));
+    user::set_suspended(&author_email, true).unwrap();
+    assert_eq!(reader.get(&search(&format!("q=zoe+{}", word))).await.json()["users"], json!([]));
+    user::set_suspended(&author_email, false).unwrap();
+    assert!(reader.get(&format!("/search?q={}", word)).await.body.contains(&format!("<a href=\"/tags/{}\"", word)));
+
+    // L'index suit les modifications et les suppressions
+    crate::database::post::update(public_id, |post| post.content = "Renamed".to_string()).unwrap();
+    assert_eq!(reader.get(&search(&format!("q={}", word))).await.json()["total"], 0);
+    assert_eq!(author.get(&search(&format!("q={}", word))).await.json()["total"], 1);
+    crate::database::post::delete(public_id).unwrap();
+    let renamed = crate::database::search::Query::parse("renamed");
+    assert!(crate::database::post::search(&renamed, &author_email).unwrap().iter().all(|post| post.id != public_id));
+}
+
+#[tokio::test]
+async fn test_likes_are_counted_per_account() {
+    let mut alice = Browser::new();
+    signed_in(&mut alice, &mut Device::new()).await;
+    let (_, post_id) = create_post(&mut alice, &[], None).await;
+    let mut bob = Browser::new();
+    signed_in(&mut bob, &mut Device::new()).await;
+    let mut carol = Browser::new();
+    signed_in(&mut carol, &mut Device::new()).await;
+    bob.get("/home").await;
+    carol.get("/home").await;
+
+    let vote = |action: &str| json!({ "post_id": post_id, "action": action });
+    assert_eq!(bob.post_json("/post/like", vote("like")).await.json()["likes"], 1);
+    assert_eq!(carol.post_json("/post/like", vote("like")).await.json()["likes"], 2);
+
+    // Un second clic n'annule que le vote de ce compte
+    assert_eq!(carol.post_json("/post/like", vote("like")).await.json()["likes"], 1);
+    assert_eq!(carol.post_json("/post/like", vote("dislike")).await.json()["likes"], 0);
+    let feed = alice.get("/posts").await.json();
+    let entry = feed["posts"].as_array().unwrap().iter().find(|post| post["id"] == post_id).unwrap().clone();
+    assert_eq!(entry["likes"], 0);
+
+    // L'auteur n'est notifié que du premier like de chaque compte, même une fois la notification lue
+    assert!(alice.get("/home").await.body.contains(r#"id="unread_notifications">2</span>"#));
+    assert_eq!(alice.post_json("/notifications/read", json!({})).await.status, StatusCode::OK);
+    bob.post_json("/post/like", vote("like")).await;
+    assert_eq!(bob.post_json("/post/like", vote("like")).await.json()["likes"], 0);
+    assert!(!alice.get("/home").await.body.contains(r#"id="unread_notifications">"#));
+}
+
+#[tokio::test]
+async fn test_notifications_and_digest() {
+    let mut alice = Browser::new();
+    let alice_email = signed_in(&mut alice, &mut Device::new()).await;
+    let mut bob = Browser::new();
+    let bob_email = signed_in(&mut bob, &mut Device::new()).await;
+    bob.get("/home").await;
+
+    // Abonnement, like et mention ; un like répété ne compte qu'une fois
+    let (_, post_id) = create_post(&mut alice, &[], None).await;
+    let alice_handle = user::get(&alice_email).unwrap().handle;
+    assert_eq!(bob.post_json("/users/follow", json!({ "handle": alice_handle, "follow": true })).await.status, StatusCode::OK);
+    for action in ["like", "like", "like"] {
+        assert_eq!(bob.post_json("/post/like", json!({ "post_id": post_id, "action": action })).await.status, StatusCode::OK);
+    }
+    let mention = format!("Hello @{}", alice_email);
+    assert_eq!(bob.post_multipart("/post/create", &mention, None).await.status, StatusCode::OK);
+    let (_, own_post) = create_post(&mut bob, &[], None).await;
+    bob.post_json("/post/like", json!({ "post_id": own_post, "action": "like" })).await;
+
+    assert!(alice.get("/home").await.body.contains(r#"<span class="badge bg-danger" id="unread_notifications">3</span>"#));
+    let page = alice.get("/notifications").await.body;
+    assert!(page.contains("started following you") && page.contains("liked your post") && page.contains("mentioned you in a post"));
+    assert!(!bob.get("/home").await.body.contains(r#"id="unread_notifications">"#));
+
+    // Résumé quotidien des seuls événements choisis, au plus une fois par période
+    let invalid = alice.post_json("/notifications/preferences", json!({ "digest": ["like", "comment"] })).await;
+    assert_eq!(invalid.status, StatusCode::BAD_REQUEST);
+    assert_eq!(alice.post_json("/notifications/preferences", json!({ "digest": ["mention"] })).await.status, StatusCode::OK);
+    let digests = |email: &str| -> Vec<String> {
+        email::sent_to(email)
+            .unwrap()
+            .into_iter()
+            .filter(|email| email.subject == "Your lab02 notifications")
+            .map(|email| email.body)
+            .collect()
+    };
+    let now = crate::database::unix_now();
+    crate::backend::notifications::send_digests(now).unwrap();
+    assert_eq!(digests(&alice_email).len(), 1);
+    assert!(digests(&alice_email)[0].contains("mentioned you") && !digests(&alice_email)[0].contains("liked"));
+    assert_eq!(bob.post_multipart("/post/create", &mention, None).await.status, StatusCode::OK);
+    crate::backend::notifications::send_digests(now + 60).unwrap();
+    assert_eq!(digests(&alice_email).len(), 1);
+    crate::backend::notifications::send_digests(now + crate::consts::DIGEST_PERIOD_SECS).unwrap();
+    assert_eq!(digests(&alice_email).len(), 2);
+
+    // Un compte dont l'email n'est pas vérifié ne reçoit aucun résumé, ses notifications restent dues
+    let pending = unique_email();
+    user::create(&pending, "Alice", "Martin").unwrap();
+    crate::database::notification::set_digest(&pending, [Kind::Follow].into()).unwrap();
+    crate::database::notification::add(&pending, Kind::Follow, &bob_email, None).unwrap();
+    crate::backend::notifications::send_digests(now + 2 * crate::consts::DIGEST_PERIOD_SECS).unwrap();
+    assert!(digests(&pending).is_empty());
+    assert!(!crate::database::notification::inbox(&pending).unwrap().notifications[0].emailed);
+
+    // Lecture
+    assert_eq!(alice.post_json("/notifications/read", json!({ "id": "nope" })).await.status, StatusCode::BAD_REQUEST);
+    assert_eq!(alice.post_json("/notifications/read", json!({})).await.json()["marked"], 4);
+    assert!(!alice.get("/home").await.body.contains(r#"id="unread_notifications">"#));
+    assert_eq!(crate::database::notification::unread_count(&bob_email).unwrap(), 0);
+}
+
+/// Ouvre le flux `/live` sans attendre la fin de la réponse
+async fn open_live(browser: &Browser, last_event_id: Option<&str>) -> (StatusCode, axum::body::BodyDataStream) {
+    let mut request = Request::get("/live").header(header::COOKIE, browser.cookie.clone().unwrap());
+    if let Some(id) = last_event_id {
+        request = request.header("last-event-id", id);
+    }
+    let response = browser.app.clone().oneshot(request.body(Body::empty()).unwrap()).await.unwrap();
+    (response.status(), response.into_body().into_data_stream())
+}
+
+/// Lit le flux jusqu'à ce que le texte reçu contienne `needle` ; retourne tout le texte lu
+async fn read_until(stream: &mut axum::body::BodyDataStream, needle: &str) -> String {
+    use futures_util::StreamExt;
+    let mut received = String::new();
+    while !received.contains(needle) {
+        let chunk = tokio::time::timeout(std::time::Duration::from_secs(5), stream.next())
+            .await
+            .unwrap_or_else(|_| panic!("{:?} not received in {:?}", needle, received))
+            .unwrap()
+            .unwrap();
+        received.push_str(&String::from_utf8_lossy(&chunk));
+    }
+    received
+}
+
+#[tokio::test]
+async fn test_live_feed_streams_visible_updates() {
+    let mut alice = Browser::new();
+    let alice_email = signed_in(&mut alice, &mut Device::new()).await;
+    let mut bob = Browser::new();
+    signed_in(&mut bob, &mut Device::new()).await;
+    bob.get("/home").await;
+
+    let (status, mut live) = open_live(&alice, None).await;
+    assert_eq!(status, StatusCode::OK);
+    read_until(&mut live, "retry:3000").await;
+
+    // Seuls les changements visibles par le compte lui parviennent
+    let (private_text, _) = create_post(&mut bob, &[("visibility", "private")], None).await;
+    let (public_text, public_id) = create_post(&mut bob, &[], None).await;
+    let received = read_until(&mut live, &public_text).await;
+    assert!(!received.contains(&private_text));
+    let post_event = Regex::new(&format!(r"id: ([0-9a-f]+)-(\d+)\nevent: post\ndata: [^\n]*{}", public_text)).unwrap();
+    let captures = post_event.captures(&received).unwrap();
+    let (epoch, event_id) = (captures[1].to_string(), captures[2].parse::<u64>().unwrap());
+
+    bob.post_json("/post/like", json!({ "post_id": public_id, "action": "like" })).await;
+    read_until(&mut live, &format!(r#""likes":1,"post_id":"{}""#, public_id)).await;
+    let mention = format!("Hi @{}", alice_email);
+    assert_eq!(bob.post_multipart("/post/create", &mention, None).await.status, StatusCode::OK);
+    read_until(&mut live, "event: notification").await;
+
+    // Reprise après Last-Event-ID, ou rechargement si la reprise est impossible
+    let (_, mut replay) = open_live(&alice, Some(&format!("{}-{}", epoch, event_id - 1))).await;
+    read_until(&mut replay, &public_text).await;
+    let (_, mut stale) = open_live(&alice, Some(&format!("{}-{}", epoch, u64::MAX))).await;
+    read_until(&mut stale, "event: resync").await;
+    // Même numéro, mais d'un démarrage précédent du serveur
+    let previous_boot = format!("{}-{}", uuid::Uuid::new_v4().simple(), event_id - 1);
+    let (_, mut restarted) = open_live(&alice, Some(&previous_boot)).await;
+    read_until(&mut restarted, "event: resync").await;
+
+    // Nombre de connexions limité par compte
+    let mut open = vec![];
+    while open.len() + 4 < crate::consts::LIVE_MAX_CONNECTIONS_PER_USER {
+        let (status, stream) = open_live(&alice, None).await;
+        assert_eq!(status, StatusCode::OK);
+        open.push(stream);
+    }
+    assert_eq!(open_live(&alice, None).await.0, StatusCode::TOO_MANY_REQUESTS);
+    drop(open);
+    let (status, mut revoked) = open_live(&alice, None).await;
+    assert_eq!(status, StatusCode::OK);
+    read_until(&mut revoked, "retry:3000").await;
+
+    // Une session révoquée ferme le flux au prochain envoi
+    crate::backend::sessions::revoke_all(&alice_email, None);
+    create_post(&mut bob, &[], None).await;
+    let end = tokio::time::timeout(std::time::Duration::from_secs(5), futures_util::StreamExt::next(&mut revoked)).await;
+    assert!(end.expect("the stream was not closed").is_none());
+    assert_eq!(open_live(&alice, None).await.0, StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn test_state_changing_requests_require_csrf_token() {
+    let mut browser = Browser::new();
+    browser.get("/login").await;
+    let anonymous_token = browser.csrf_token.clone().unwrap();
+    signed_in(&mut browser, &mut Device::new()).await;
+    assert!(browser.get("/home").await.body.contains("X-CSRF-Token"));
+    let token = browser.csrf_token.take().unwrap();
+
+    // Le jeton obtenu avant la connexion est remplacé à l'ouverture de la session
+    assert_ne!(token, anonymous_token);
+    browser.csrf_token = Some(anonymous_token);
+    assert_eq!(browser.post_multipart("/post/create", "stale", None).await.status, StatusCode::FORBIDDEN);
+    browser.csrf_token = None;
+
+    // Sans jeton, ou avec le jeton d'une autre session
+    let missing = browser.post_json("/post/like", json!({ "post_id": uuid::Uuid::new_v4(), "action": "like" })).await;
+    assert_eq!(missing.status, StatusCode::FORBIDDEN);
+    assert!(missing.body.contains("Request rejected"));
+
+    let mut other = Browser::new();
+    other.get("/login").await;
+    browser.csrf_token = other.csrf_token.clone();
+    assert_eq!(browser.post_multipart("/post/create", "forged", None).await.status, StatusCode::FORBIDDEN);
+
+    // Jeton valide mais origine étrangère
+    let request = Request::post("/post/create")
+        .header(header::COOKIE, browser.cookie.clone().unwrap())
+        .header(CSRF_HEADER, &token)
+        .header(header::ORIGIN, "https://evil.example")
+        .body(Body::empty())
+        .unwrap();
+    let cross_origin = browser.app.clone().oneshot(request).await.unwrap();
+    assert_eq!(cross_origin.status(), StatusCode::FORBIDDEN);
+
+    // Jeton de la session, même origine
+    browser.csrf_token = Some(token);
+    assert_eq!(browser.post_multipart("/post/create", "legit", None).await.status, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn test_unverified_session_is_restricted_until_validation() {
+    let mut browser = Browser::new();
+    let mut device = Device::new();
+    let email = unique_email();
+    browser.register(&mut device, &email, false).await;
+    let first_link = last_link(&email, "/validate/");
+
+    // Session restreinte : seule la page de vérification est accessible
+    let logged_in = browser.login(&mut device, &email).await;
+    assert_eq!(logged_in.location(), "/verify-email");
+    assert_eq!(browser.get("/home").await.location(), "/verify-email");
+    let created = browser.post_multipart("/post/create", "too early", None).await;
+    assert_eq!(created.status, StatusCode::FORBIDDEN);
+    assert!(browser.get("/verify-email").await.body.contains(&email));
+
+    // Un nouveau lien invalide le précédent ; un compte inconnu reçoit la même réponse
+    assert_eq!(browser.post_json("/verify-email/resend", json!({ "email": email })).await.status, StatusCode::OK);
+    assert_eq!(browser.post_json("/verify-email/resend", json!({ "email": unique_email() })).await.status, StatusCode::OK);
+    let second_link = last_link(&email, "/validate/");
+    assert_ne!(first_link, second_link);
+    assert_eq!(browser.get(&first_link).await.location(), "/register?error=invalid_token");
+
+    // Un lien de validation ne sert pas de lien de récupération
+    let recovery_path = second_link.replace("/validate/", "/recover/");
+    assert!(Browser::new().get(&recovery_path).await.body.contains("recovery_failed"));
+
+    assert_eq!(browser.get(&second_link).await.location(), "/login?validated=true");
+    assert_eq!(browser.get("/home").await.status, StatusCode::OK);
+}
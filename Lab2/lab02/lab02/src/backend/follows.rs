@@ -0,0 +1,121 @@
+//! Abonnements entre comptes et pages de profil.
+//! Le graphe est gardé dans `database::follow` avec son index inverse, et les posts sont
+//! indexés par auteur : le fil des comptes suivis ne parcourt que les posts de ces comptes.
+//! Les routes et les listes désignent les comptes par leur identifiant public (`handle`),
+//! jamais par leur email ; un compte inconnu et un compte non public (email non vérifié,
+//! compte suspendu) donnent la même réponse.
+
+use axum::{
+    extract::{Json, Path},
+    http::StatusCode,
+    response::Html,
+    Extension,
+};
+use serde_json::json;
+use crate::backend::csrf::CsrfToken;
+use crate::backend::handlers_auth::feed_entries;
+use crate::backend::middlewares::LoggedIn;
+use crate::backend::notifications::notify;
+use crate::backend::security_headers::CspNonce;
+use crate::database::{follow, notification::Kind, post, user};
+use crate::HBS;
+
+fn display_name(user: &user::User) -> String {
+    format!("{} {}", user.first_name, user.last_name)
+}
+
+/// Suit un autre compte ou cesse de le suivre
+pub async fn follow_user(LoggedIn(follower): LoggedIn, Json(payload): Json<serde_json::Value>) -> axum::response::Result<StatusCode> {
+    let handle = payload
+        .get("handle")
+        .and_then(|value| value.as_str())
+        .ok_or((StatusCode::BAD_REQUEST, "Handle is required"))?;
+    let follow = payload
+        .get("follow")
+        .and_then(|value| value.as_bool())
+        .ok_or((StatusCode::BAD_REQUEST, "Invalid follow flag"))?;
+
+    // Seul un compte public peut être suivi, mais un abonnement existant peut toujours être retiré
+    let followee = if follow { user::public_by_handle(handle) } else { user::by_handle(handle) }
+        .ok_or((StatusCode::NOT_FOUND, "User not found"))?
+        .email;
+    if followee == follower {
+        return Err((StatusCode::BAD_REQUEST, "You cannot follow yourself").into());
+    }
+
+    let updated = if follow {
+        follow::follow(&follower, &followee)
+    } else {
+        follow::unfollow(&follower, &followee)
+    };
+    let changed = updated.map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to update follows"))?;
+    if follow && changed {
+        notify(&followee, Kind::Follow, &follower, None);
+    }
+    Ok(StatusCode::OK)
+}
+
+/// Affiche le profil d'un compte et ses posts
+pub async fn profile_page(
+    LoggedIn(viewer): LoggedIn,
+    Extension(nonce): Extension<CspNonce>,
+    CsrfToken(csrf_token): CsrfToken,
+    Path(handle): Path<String>,
+) -> axum::response::Result<Html<String>> {
+    let profile = user::public_by_handle(&handle).ok_or((StatusCode::NOT_FOUND, "User not found"))?;
+    let email = &profile.email;
+
+    let posts = post::visible_by_authors([email], &viewer)
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to read posts"))?;
+    let followers = follow::followers(email).map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to read follows"))?;
+    let following = follow::following(email).map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to read follows"))?;
+
+    let data = json!({
+        "handle": profile.handle,
+        "name": display_name(&profile),
+        "posts": feed_entries(posts, Some(&viewer)),
+        "followers_count": followers.len(),
+        "following_count": following.len(),
+        "is_self": *email == viewer,
+        "is_following": followers.contains(&viewer),
+        "csp_nonce": nonce.0,
+        "csrf_token": csrf_token,
+    });
+    HBS.render("profile", &data)
+        .map(Html)
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Internal server error while rendering the page").into())
+}
+
+/// Affiche les abonnés (`followers`) ou les abonnements (`following`) d'un compte
+pub async fn follows_page(
+    _: LoggedIn,
+    Extension(nonce): Extension<CspNonce>,
+    CsrfToken(csrf_token): CsrfToken,
+    Path((handle, list)): Path<(String, String)>,
+) -> axum::response::Result<Html<String>> {
+    let profile = user::public_by_handle(&handle).ok_or((StatusCode::NOT_FOUND, "User not found"))?;
+    let (title, accounts) = match list.as_str() {
+        "followers" => ("Followers", follow::followers(&profile.email)),
+        "following" => ("Following", follow::following(&profile.email)),
+        _ => return Err((StatusCode::NOT_FOUND, "Not found").into()),
+    };
+    let accounts: Vec<_> = accounts
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to read follows"))?
+        .into_iter()
+        .filter_map(|email| user::get(&email))
+        .filter(user::User::is_public)
+        .map(|user| json!({ "handle": user.handle, "name": display_name(&user) }))
+        .collect();
+
+    let data = json!({
+        "title": title,
+        "handle": profile.handle,
+        "name": display_name(&profile),
+        "accounts": accounts,
+        "csp_nonce": nonce.0,
+        "csrf_token": csrf_token,
+    });
+    HBS.render("follows", &data)
+        .map(Html)
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Internal server error while rendering the page").into())
+}
@@ -1,49 +1,103 @@
 //! Gestion des routes nécessitant une authentification utilisateur.
 
 use axum::{
-    extract::{Multipart, Query},
+    extract::{Multipart, Path, Query},
     response::{Html, IntoResponse},
     Json, Extension,
 };
-use anyhow::anyhow;
 use handlebars::Handlebars;
 use http::StatusCode;
-use once_cell::sync::Lazy;
-use serde::{Deserialize, Serialize};
 use serde_json::json;
 use std::{
     collections::HashMap,
-    fs::{create_dir_all, File},
+    fs::{create_dir_all, remove_file, File},
     io::Write,
-    path::Path,
-    sync::{Arc, RwLock},
+    path::PathBuf,
+    sync::Arc,
 };
+use log::{info, warn};
+use tower_sessions::Session;
 use uuid::Uuid;
+use crate::config::CONFIG;
+use crate::backend::csrf::CsrfToken;
+use crate::backend::live::{self, Update};
+use crate::backend::middlewares::{LoggedIn, StepUp};
+use crate::backend::notifications;
+use crate::backend::security_headers::CspNonce;
+use crate::backend::sessions;
 use crate::consts;
-
-/// Modèle représentant un post avec des likes
-#[derive(Clone, Serialize, Deserialize, Debug)]
-pub struct Post {
-    pub id: Uuid,
-    pub content: String,
-    pub image_path: Option<String>,
-    pub likes: i32,
+use crate::database::{follow, notification, post::{self, Post, Visibility}, report::{self, Category}, search, token, unix_now, user::{self, Role}};
+use crate::utils::markdown;
+use crate::HBS;
+use crate::utils::webauthn::CREDENTIAL_STORE;
+
+/// Posts prêts à afficher pour le compte qui les consulte : contenu rendu en HTML sûr,
+/// nom de l'auteur et état d'enregistrement
+pub(crate) fn feed_entries(posts: Vec<Post>, viewer: Option<&str>) -> Vec<serde_json::Value> {
+    let stash = viewer.and_then(user::get).map(|user| user.stash).unwrap_or_default();
+    // Nom et identifiant public des auteurs, seulement pour les comptes publics
+    let mut authors: HashMap<String, Option<(String, String)>> = HashMap::new();
+    posts
+        .into_iter()
+        .map(|post| {
+            let author = post.author.as_ref().and_then(|author| {
+                authors
+                    .entry(author.clone())
+                    .or_insert_with(|| {
+                        user::get(author)
+                            .filter(user::User::is_public)
+                            .map(|user| (format!("{} {}", user.first_name, user.last_name), user.handle))
+                    })
+                    .clone()
+            });
+            let (author_name, author_handle) = author.unzip();
+            let bookmarked = stash.contains(&post.id.to_string());
+            let mut value = json!(post);
+            value["likes"] = json!(user::likes(post.id));
+            value["content_html"] = json!(markdown::render(&post.content));
+            value["author_name"] = json!(author_name);
+            value["author_handle"] = json!(author_handle);
+            value["image_url"] = json!(post.image_path.is_some().then(|| format!("/media/{}", post.id)));
+            value["bookmarked"] = json!(bookmarked);
+            value
+        })
+        .collect()
 }
 
-/// Base de données statique pour les posts (simulée en mémoire)
-static POSTS: Lazy<RwLock<Vec<Post>>> = Lazy::new(|| {
-    RwLock::new(vec![])
-});
-
 /// Affiche la page principale avec la liste des posts
 pub async fn home(
+    logged_in: Option<LoggedIn>,
     Extension(hbs): Extension<Arc<Handlebars<'_>>>,
+    Extension(nonce): Extension<CspNonce>,
+    CsrfToken(csrf_token): CsrfToken,
     Query(params): Query<HashMap<String, String>>,
 ) -> impl IntoResponse {
     let user = params.get("user").cloned().unwrap_or_else(|| "Guest".to_string());
+    let email = logged_in.map(|LoggedIn(email)| email);
+    // Fil global, ou fil des comptes suivis
+    let following_feed = params.get("feed").is_some_and(|feed| feed == "following");
+    let posts = match (&email, following_feed) {
+        (Some(email), true) => follow::following(email).and_then(|following| post::visible_by_authors(&following, email)),
+        (Some(email), false) => post::visible_to(email),
+        (None, _) => Ok(vec![]),
+    };
+    let posts = feed_entries(posts.unwrap_or_default(), email.as_deref());
+    let role = email
+        .as_deref()
+        .and_then(user::get)
+        .map(|user| user.role)
+        .unwrap_or_default();
+    let unread = email.as_deref().and_then(|email| notification::unread_count(email).ok()).unwrap_or_default();
     let data = json!({
         "user": user,
-        "posts": *POSTS.read().unwrap(),
+        "posts": posts,
+        "following_feed": following_feed,
+        "viewer_handle": email.as_deref().and_then(user::get).map(|user| user.handle),
+        "is_moderator": role >= Role::Moderator,
+        "unread_notifications": unread,
+        "report_categories": Category::ALL.map(Category::as_str),
+        "csp_nonce": nonce.0,
+        "csrf_token": csrf_token,
     });
 
     match hbs.render("home", &data) {
@@ -52,10 +106,59 @@ pub async fn home(
     }
 }
 
+/// Affiche les posts du fil qui portent un hashtag
+pub async fn tag_page(
+    LoggedIn(email): LoggedIn,
+    Extension(nonce): Extension<CspNonce>,
+    CsrfToken(csrf_token): CsrfToken,
+    Path(tag): Path<String>,
+) -> axum::response::Result<Html<String>> {
+    let tag = tag.to_lowercase();
+    let mut query = search::Query::default();
+    query.add_tag(&tag);
+    let posts = post::search(&query, &email).map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to read posts"))?;
+
+    let data = json!({
+        "tag": tag,
+        "posts": feed_entries(posts, Some(&email)),
+        "csp_nonce": nonce.0,
+        "csrf_token": csrf_token,
+    });
+    HBS.render("tag", &data)
+        .map(Html)
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Internal server error while rendering the page").into())
+}
+
+/// Liste des posts du fil en JSON, avec l'état d'enregistrement pour le compte connecté.
+/// Les règles de visibilité sont celles du fil HTML.
+pub async fn list_posts(LoggedIn(email): LoggedIn) -> axum::response::Result<Json<serde_json::Value>> {
+    let posts = post::visible_to(&email).map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to read posts"))?;
+    Ok(Json(json!({ "posts": feed_entries(posts, Some(&email)) })))
+}
+
+/// Date de publication d'un nouveau post : `None` pour un brouillon, maintenant par défaut,
+/// ou une date future d'au plus `consts::MAX_SCHEDULE_DAYS` jours
+fn publication_date(draft: bool, scheduled: Option<u64>) -> Result<Option<u64>, (StatusCode, &'static str)> {
+    let now = unix_now();
+    match (draft, scheduled) {
+        (true, Some(_)) => Err((StatusCode::BAD_REQUEST, "A draft cannot be scheduled")),
+        (true, None) => Ok(None),
+        (false, None) => Ok(Some(now)),
+        (false, Some(at)) if at <= now => Err((StatusCode::BAD_REQUEST, "Publication date must be in the future")),
+        (false, Some(at)) if at > now + consts::MAX_SCHEDULE_DAYS * 24 * 60 * 60 => {
+            Err((StatusCode::BAD_REQUEST, "Publication date is too far away"))
+        }
+        (false, Some(at)) => Ok(Some(at)),
+    }
+}
+
 /// Crée un nouveau post avec texte et image
-pub async fn create_post(mut multipart: Multipart) -> axum::response::Result<Json<serde_json::Value>> {
+pub async fn create_post(LoggedIn(author): LoggedIn, mut multipart: Multipart) -> axum::response::Result<Json<serde_json::Value>> {
     let mut text_content = None;
     let mut uploaded_file_path = None;
+    let mut visibility = Visibility::Public;
+    let mut draft = false;
+    let mut publish_at = None;
 
     while let Some(field) = multipart.next_field().await? {
         let field_name = field.name().unwrap_or_default().to_string();
@@ -63,6 +166,13 @@ pub async fn create_post(mut multipart: Multipart) -> axum::response::Result<Jso
         if field_name == "text" {
             let text = field.text().await.unwrap_or_default();
             text_content = Some(text);
+        } else if field_name == "visibility" {
+            visibility = field.text().await?.parse().map_err(|_| (StatusCode::BAD_REQUEST, "Invalid visibility"))?;
+        } else if field_name == "draft" {
+            draft = field.text().await? == "true";
+        } else if field_name == "publish_at" {
+            let at: u64 = field.text().await?.parse().map_err(|_| (StatusCode::BAD_REQUEST, "Invalid publication date"))?;
+            publish_at = Some(at);
         } else if field_name == "file" {
             let filename = field.file_name().unwrap_or_default().to_string();
 
@@ -99,15 +209,15 @@ pub async fn create_post(mut multipart: Multipart) -> axum::response::Result<Jso
             }
 
             // Save file to the uploads directory
-            let uploads_dir = consts::UPLOADS_DIR;
-            if !Path::new(uploads_dir).exists() {
-                create_dir_all(uploads_dir).map_err(|_| {
+            let uploads_dir = CONFIG.uploads_dir();
+            if !uploads_dir.exists() {
+                create_dir_all(&uploads_dir).map_err(|_| {
                     (StatusCode::INTERNAL_SERVER_ERROR, "Failed to create uploads directory")
                 })?;
             }
 
             let unique_filename = format!("{}.jpg", Uuid::new_v4()); // Generate a unique filename
-            let file_path = format!("{}/{}", uploads_dir, unique_filename);
+            let file_path = uploads_dir.join(unique_filename).to_string_lossy().to_string();
             let mut file = File::create(&file_path).map_err(|_| {
                 (StatusCode::INTERNAL_SERVER_ERROR, "Failed to save uploaded file")
             })?;
@@ -121,106 +231,91 @@ pub async fn create_post(mut multipart: Multipart) -> axum::response::Result<Jso
     }
 
     let text = text_content.ok_or((StatusCode::BAD_REQUEST, "Text content is required"))?;
+    let publish_at = publication_date(draft, publish_at)?;
     let image_path = uploaded_file_path;
 
     // Save the post
-    let post_id = save_post(&text, image_path.as_deref());
-
-    Ok(Json(json!({ "post_id": post_id })))
-}
-
-/// Sauvegarde des posts dans un fichier YAML
-pub fn save_posts_to_file() -> Result<(), anyhow::Error> {
-    let posts = POSTS.read().map_err(|_| anyhow!("Failed to read posts"))?; // Lecture des posts existants
-    let file_path = consts::POSTS_DB_PATH;
-    let file_dir = Path::new(file_path).parent().unwrap();
-
-    if !file_dir.exists() {
-        create_dir_all(file_dir).or(Err(anyhow!("Failed to create directory for posts.")))?;
-    }
-
-    let file = File::create(file_path).or(Err(anyhow!("Failed to create posts.yaml.")))?;
-    serde_yaml::to_writer(file, &*posts).or(Err(anyhow!("Failed to serialize posts to YAML.")))?;
-    Ok(())
-}
-
-/// Charge les posts depuis un fichier YAML
-pub fn load_posts_from_file() -> Result<(), anyhow::Error> {
-    let file_path = consts::POSTS_DB_PATH;
-
-    if Path::new(file_path).exists() {
-        let file = File::open(file_path).or(Err(anyhow!("Failed to open posts.yaml.")))?;
-        let loaded_posts: Vec<Post> = serde_yaml::from_reader(file).unwrap_or_default();
-
-        let mut posts = POSTS.write().map_err(|_| anyhow!("Failed to write posts"))?;
-        *posts = loaded_posts;
-    }
-
-    Ok(())
-}
-
-/// Simule la sauvegarde d'un post dans une base de données
-fn save_post(text: &str, image_path: Option<&str>) -> String {
-    let new_post = Post {
-        id: Uuid::new_v4(),
-        content: text.to_string(),
-        image_path: image_path.map(|path| path.to_string()), // Save the relative path
-        likes: 0,
-    };
-
-    let post_id = new_post.id.to_string();
-
-    {
-        let mut posts = POSTS.write().unwrap();
-        posts.push(new_post);
-    }
-
-    if let Err(e) = save_posts_to_file() {
-        eprintln!("Failed to save posts: {}", e);
+    let post_id = post::create(&author, &text, image_path.as_deref(), visibility, publish_at)
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to save post"))?;
+    if let Ok(Some(post)) = post::get(post_id) {
+        if post.is_published() {
+            notifications::notify_mentions(&post);
+            live::publish(Update::Post(post_id));
+        }
     }
 
-    post_id
+    Ok(Json(json!({ "post_id": post_id.to_string() })))
 }
 
-/// Permet de like un post
-pub async fn like_post(Json(body): Json<serde_json::Value>) -> axum::response::Result<StatusCode> {
+/// Like ou dislike d'un post par le compte connecté ; retourne le nouveau total du post
+pub async fn like_post(LoggedIn(viewer): LoggedIn, Json(body): Json<serde_json::Value>) -> axum::response::Result<Json<serde_json::Value>> {
     let post_id = body
         .get("post_id")
         .and_then(|v| v.as_str())
-        .ok_or_else(|| (StatusCode::BAD_REQUEST, "Post ID is required"))?;
+        .ok_or((StatusCode::BAD_REQUEST, "Post ID is required"))?;
     let post_id = Uuid::parse_str(post_id).map_err(|_| (StatusCode::BAD_REQUEST, "Invalid Post ID"))?;
 
     let action = body
         .get("action")
         .and_then(|v| v.as_str())
-        .ok_or_else(|| (StatusCode::BAD_REQUEST, "Action is required"))?;
-
-    let mut posts = POSTS.write().map_err(|_| (StatusCode::BAD_REQUEST, "Failed to write posts"))?;
-    let post = posts.iter_mut().find(|post| post.id == post_id);
-
-    if let Some(post) = post {
-        match action {
-            "like" => {
-                if post.likes == 1 {
-                    post.likes = 0;
-                } else {
-                    post.likes = 1;
-                }
-            }
-            "dislike" => {
-                if post.likes == -1 {
-                    post.likes = 0;
-                } else {
-                    post.likes = -1;
-                }
+        .ok_or((StatusCode::BAD_REQUEST, "Action is required"))?;
+
+    let target = match action {
+        "like" => 1,
+        "dislike" => -1,
+        _ => return Err((StatusCode::BAD_REQUEST, "Invalid action").into()),
+    };
+
+    // Un post masqué ou hors du fil de ce compte n'existe pas pour lui
+    let post = post::get(post_id)
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to read posts"))?
+        .filter(|post| post.in_feed_of(&viewer))
+        .ok_or((StatusCode::NOT_FOUND, "Post not found"))?;
+
+    // Chaque compte a son propre vote ; un second clic sur la même action l'annule
+    let (likes, first_like) = user::react(&viewer, post_id, target)
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to save the vote"))?;
+    live::publish(Update::Likes { post_id, likes });
+    if let (true, Some(author)) = (first_like, &post.author) {
+        notifications::notify(author, notification::Kind::Like, &viewer, Some(post_id));
+    }
+    Ok(Json(json!({ "likes": likes })))
+}
+
+/// Efface ce qu'un compte supprimé laisse derrière lui : ses posts (brouillons, posts privés
+/// et images compris), les signalements qu'il a faits ou qui visent ses posts, ses abonnements,
+/// ses notifications et ses jetons. Un compte créé ensuite avec le même email n'en hérite pas.
+pub(crate) fn remove_account_data(email: &str) -> anyhow::Result<()> {
+    let posts = post::remove_author(email)?;
+    let post_ids: Vec<Uuid> = posts.iter().map(|post| post.id).collect();
+    report::remove_account(email, &post_ids)?;
+    user::remove_bookmarks(&post_ids.iter().map(Uuid::to_string).collect::<Vec<_>>())?;
+    follow::remove_account(email)?;
+    notification::remove_account(email)?;
+    token::revoke(email, None)?;
+
+    // Seules les images du dossier d'uploads sont supprimées
+    let uploads_dir = CONFIG.uploads_dir();
+    for image in posts.into_iter().filter_map(|post| post.image_path).map(PathBuf::from) {
+        if image.starts_with(&uploads_dir) {
+            if let Err(e) = remove_file(&image) {
+                warn!("Failed to delete {}: {}", image.display(), e);
             }
-            _ => return Err((StatusCode::BAD_REQUEST, "Invalid action").into()),
         }
-        return Ok(StatusCode::OK);
     }
-
-    Err((StatusCode::NOT_FOUND, "Post not found").into())
+    Ok(())
 }
 
-
-
+/// Supprime le compte de l'utilisateur connecté et ferme sa session
+pub async fn delete_account(session: Session, step_up: StepUp) -> axum::response::Result<StatusCode> {
+    user::delete(&step_up.email)
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to delete account"))?;
+    remove_account_data(&step_up.email)
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to delete account"))?;
+    CREDENTIAL_STORE.write().await.remove(&step_up.email);
+    sessions::revoke_all(&step_up.email, None);
+    session.flush();
+
+    info!("Account {} deleted", step_up.email);
+    Ok(StatusCode::OK)
+}
@@ -0,0 +1,93 @@
+//! Endpoints d'exploitation pour l'orchestrateur : vivacité, disponibilité et métriques.
+
+use std::{fs, path::Path};
+use axum::{
+    http::{header, HeaderMap, StatusCode},
+    response::IntoResponse,
+    Json,
+};
+use ring::digest;
+use serde_json::json;
+use subtle::ConstantTimeEq;
+use crate::backend::ceremonies::{AUTHENTICATION_STATES, REGISTRATION_STATES};
+use crate::config::CONFIG;
+use crate::database::{self, email, migrations::Store};
+use crate::metrics::{self, Ceremony};
+
+/// Le processus répond
+pub async fn healthz() -> StatusCode {
+    StatusCode::OK
+}
+
+/// Vérifie qu'un dossier existe (ou peut être créé) et accepte l'écriture d'un fichier
+fn dir_writable(dir: &Path) -> bool {
+    let probe = dir.join(".readyz-probe");
+    fs::create_dir_all(dir).is_ok() && fs::write(&probe, b"ok").is_ok() && fs::remove_file(&probe).is_ok()
+}
+
+/// Le service peut traiter du trafic : bases chargées, uploads et boîte d'envoi accessibles
+pub async fn readyz() -> impl IntoResponse {
+    let stores_loaded = database::is_loaded();
+    let uploads_writable = dir_writable(&CONFIG.uploads_dir());
+    let mailer_reachable = email::count().is_ok()
+        && Store::Emails.path().parent().is_some_and(dir_writable);
+
+    let ready = stores_loaded && uploads_writable && mailer_reachable;
+    let status = if ready { StatusCode::OK } else { StatusCode::SERVICE_UNAVAILABLE };
+
+    (status, Json(json!({
+        "ready": ready,
+        "checks": {
+            "stores_loaded": stores_loaded,
+            "uploads_writable": uploads_writable,
+            "mailer_reachable": mailer_reachable,
+        },
+    })))
+}
+
+/// Compare l'en-tête `Authorization` au jeton configuré en temps constant.
+/// Les empreintes ont la même longueur quelle que soit la valeur reçue.
+fn metrics_authorized(headers: &HeaderMap, token: &str) -> bool {
+    let Some(provided) = headers.get(header::AUTHORIZATION) else {
+        return false;
+    };
+    let expected = digest::digest(&digest::SHA256, format!("Bearer {}", token).as_bytes());
+    let provided = digest::digest(&digest::SHA256, provided.as_bytes());
+    provided.as_ref().ct_eq(expected.as_ref()).into()
+}
+
+/// Exporte les métriques au format Prometheus, seulement avec le jeton configuré.
+/// Sans jeton configuré, l'accès est refusé.
+pub async fn metrics_endpoint(headers: HeaderMap) -> impl IntoResponse {
+    if !CONFIG.metrics_token.as_deref().is_some_and(|token| metrics_authorized(&headers, token)) {
+        return (StatusCode::UNAUTHORIZED, "Unauthorized").into_response();
+    }
+
+    // Les jauges sont lues au moment de l'export
+    metrics::set_pending_states(Ceremony::Registration, REGISTRATION_STATES.len().await);
+    metrics::set_pending_states(Ceremony::Authentication, AUTHENTICATION_STATES.len().await);
+    metrics::set_email_queue_depth(email::count().unwrap_or_default());
+
+    (
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        metrics::render(),
+    )
+        .into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderValue;
+
+    #[test]
+    fn test_metrics_token_check() {
+        let mut headers = HeaderMap::new();
+        assert!(!metrics_authorized(&headers, "secret"));
+        headers.insert(header::AUTHORIZATION, HeaderValue::from_static("Bearer secret"));
+        assert!(metrics_authorized(&headers, "secret"));
+        assert!(!metrics_authorized(&headers, "secre"));
+        headers.insert(header::AUTHORIZATION, HeaderValue::from_static("Bearer secret2"));
+        assert!(!metrics_authorized(&headers, "secret"));
+    }
+}
@@ -5,46 +5,62 @@
 use axum::{
     extract::{Path, Json, Query},
     response::{Redirect, IntoResponse, Html},
-    http::StatusCode,
+    http::{HeaderMap, StatusCode},
+    Extension,
 };
 
-use once_cell::sync::Lazy;
 use serde_json::json;
 use std::collections::HashMap;
-use tokio::sync::RwLock;
-use webauthn_rs::prelude::{PasskeyAuthentication, PublicKeyCredential, RegisterPublicKeyCredential};
+use tower_sessions::Session;
+use webauthn_rs::prelude::{PublicKeyCredential, RegisterPublicKeyCredential};
 use crate::HBS;
-use crate::database::{user, token};
-use crate::utils::webauthn::{begin_registration, complete_registration, begin_authentication, complete_authentication, StoredRegistrationState, CREDENTIAL_STORE};
-use crate::utils::input::{valid_email, valid_name, valid_id, valid_bool};
+use crate::config::CONFIG;
+use crate::backend::ceremonies::{discoverable_owners, owners, CapacityReached, Owner, AUTHENTICATION_STATES, REGISTRATION_STATES};
+use crate::backend::csrf::{self, CsrfToken};
+use crate::backend::middlewares::ClientIp;
+use crate::backend::moderation::is_suspended;
+use crate::backend::recovery_codes;
+use crate::backend::security_headers::CspNonce;
+use crate::backend::sessions;
+use crate::backend::verification::{after_login, is_verified, send_validation_email};
+use crate::database::{user, token::{self, Purpose}};
+use crate::utils::webauthn::{
+    begin_registration, complete_registration, begin_authentication, complete_authentication,
+    begin_decoy_authentication, begin_discoverable_authentication, complete_discoverable_authentication,
+    PendingAuthentication, CREDENTIAL_STORE,
+};
+use crate::utils::registration_policy::PolicyViolation;
+use crate::utils::input::{normalize_email, valid_email, valid_name, valid_id, valid_bool};
 use crate::email::send_mail;
 use log::error;
-/// Structure pour gérer un état temporaire avec un challenge
-pub struct TimedStoredState<T> {
-    state: T,
-    server_challenge: String,
+/// Clé de session contenant l'email de l'utilisateur connecté
+pub(crate) const SESSION_EMAIL: &str = "email";
+/// Clé de session contenant l'email dont la récupération a été validée par un lien
+const SESSION_RECOVERY_EMAIL: &str = "recovery_email";
+
+/// Autorise la session à enrôler une nouvelle passkey pour cet email et retourne la page d'enrôlement
+pub(crate) fn grant_recovery(session: &Session, email: &str) -> Result<String, tower_sessions::session::Error> {
+    session.insert(SESSION_RECOVERY_EMAIL, email)?;
+    Ok(format!("/register?reset_mode=true&email={}&success=true", email))
 }
-impl<T> TimedStoredState<T> {
-    /// Constructor for TimedStoredState
-    pub fn new(state: T, server_challenge: String) -> Self {
-        TimedStoredState {
-            state,
-            server_challenge,
-        }
-    }
+
+/// Vérifie que la session a ouvert un lien de récupération pour cet email
+fn recovery_granted(session: &Session, email: &str) -> bool {
+    matches!(session.get::<String>(SESSION_RECOVERY_EMAIL), Ok(Some(granted)) if granted == email)
 }
-/// Stockage des états d'enregistrement et d'authentification
-pub(crate) static REGISTRATION_STATES: Lazy<RwLock<HashMap<String, StoredRegistrationState>>> =
-    Lazy::new(Default::default);
-pub static AUTHENTICATION_STATES: Lazy<RwLock<HashMap<String, TimedStoredState<PasskeyAuthentication>>>> = Lazy::new(Default::default);
 
 /// Début du processus d'enregistrement WebAuthn
-pub async fn register_begin(Json(payload): Json<serde_json::Value>) -> axum::response::Result<Json<serde_json::Value>> {
+pub async fn register_begin(
+    session: Session,
+    ClientIp(ip): ClientIp,
+    Json(payload): Json<serde_json::Value>,
+) -> axum::response::Result<Json<serde_json::Value>> {
      // Extract the user email from the JSON payload
    
-     let user_email = payload
+     let user_email: &str = &payload
      .get("email")
      .and_then(|value| value.as_str())
+     .map(normalize_email)
      .ok_or((StatusCode::BAD_REQUEST, "Email is required"))?;
 
     // Validate the email format
@@ -54,13 +70,21 @@ pub async fn register_begin(Json(payload): Json<serde_json::Value>) -> axum::res
 
     // Check if the user already exists and if reset mode is enabled
     let reset_flag = payload.get("reset_mode");
+    let reset_mode = valid_bool(reset_flag) && reset_flag.and_then(|value| value.as_bool()).unwrap_or(false);
 
-    if user::exists(user_email).unwrap() && !valid_bool(reset_flag) {
+    // An existing account can only be re-registered from a valid recovery link
+    let exists = user::exists(user_email)
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to read users"))?;
+    if exists && !(reset_mode && recovery_granted(&session, user_email)) {
         return Err((StatusCode::BAD_REQUEST, "Action not permitted").into());
     }
 
+    // Optionally ask for a discoverable credential to allow login without email
+    let discoverable_flag = payload.get("discoverable");
+    let discoverable = valid_bool(discoverable_flag) && discoverable_flag.and_then(|value| value.as_bool()).unwrap_or(false);
+
     // Start the WebAuthn registration process
-    let (public_key_options, reg_state) = begin_registration(user_email, user_email)
+    let (public_key_options, stored_state) = begin_registration(user_email, user_email, discoverable)
         .await
         .map_err(|error| (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to begin registration: {}", error)))?;
 
@@ -68,12 +92,11 @@ pub async fn register_begin(Json(payload): Json<serde_json::Value>) -> axum::res
     let unique_state_id = uuid::Uuid::new_v4().to_string();
 
     // Store the registration state
-    let mut reg_state_store = REGISTRATION_STATES.write().await;
-    reg_state_store.insert(unique_state_id.clone(), StoredRegistrationState {
-        registration_state: reg_state,
-        challenge: public_key_options["challenge"].as_str().unwrap().to_string(),
-    });
-
+    let server_challenge = public_key_options["challenge"].as_str().unwrap_or_default().to_string();
+    REGISTRATION_STATES
+        .insert(unique_state_id.clone(), stored_state, server_challenge, owners(ip))
+        .await
+        .map_err(too_many_ceremonies)?;
 
     // Return the JSON response with public key options and the unique state ID
     Ok(Json(json!({
@@ -85,13 +108,17 @@ pub async fn register_begin(Json(payload): Json<serde_json::Value>) -> axum::res
 
 
 /// Fin du processus d'enregistrement WebAuthn
-pub async fn register_complete(Json(payload): Json<serde_json::Value>) -> axum::response::Result<StatusCode> {
+pub async fn register_complete(
+    session: Session,
+    Json(payload): Json<serde_json::Value>,
+) -> axum::response::Result<Json<serde_json::Value>> {
     // Extract and validate the user's email from the JSON payload
 
 
-    let user_email = payload
+    let user_email: &str = &payload
         .get("email")
         .and_then(|value| value.as_str())
+        .map(normalize_email)
         .ok_or((StatusCode::BAD_REQUEST, "Email is required"))?;
 
     if !valid_email(user_email) {
@@ -126,14 +153,27 @@ pub async fn register_complete(Json(payload): Json<serde_json::Value>) -> axum::
     }
 
     // Retrieve and remove the stored registration state
-    let mut reg_state_store = REGISTRATION_STATES.write().await;
-    let stored_reg_state = reg_state_store
-        .remove(reg_state_id)
-        .ok_or((StatusCode::BAD_REQUEST, "Invalid state ID"))?;
+    let stored_reg_state = REGISTRATION_STATES
+        .take(reg_state_id)
+        .await
+        .ok_or((StatusCode::BAD_REQUEST, "Invalid state ID"))?
+        .state;
+
+    // The state must have been started for this email, and recovery must still be granted
+    if stored_reg_state.email != user_email {
+        return Err((StatusCode::BAD_REQUEST, "Invalid state ID").into());
+    }
+    let is_recovery = user::exists(user_email)
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to read users"))?;
+    if is_recovery && !recovery_granted(&session, user_email) {
+        return Err((StatusCode::BAD_REQUEST, "Action not permitted").into());
+    }
 
     // Extract and validate the registration response
-    let reg_response: RegisterPublicKeyCredential = serde_json::from_value(payload.get("response").unwrap().clone())
-        .map_err(|_| (StatusCode::BAD_REQUEST, "Invalid response format"))?;
+    let reg_response: RegisterPublicKeyCredential = payload
+        .get("response")
+        .and_then(|response| serde_json::from_value(response.clone()).ok())
+        .ok_or((StatusCode::BAD_REQUEST, "Invalid response format"))?;
     log::info!("User email: {}", user_email);
     // Complete the WebAuthn registration process
     complete_registration(
@@ -142,84 +182,124 @@ pub async fn register_complete(Json(payload): Json<serde_json::Value>) -> axum::
         &stored_reg_state,
     )
         .await
-        .map_err(|_| (StatusCode::BAD_REQUEST, "Failed to complete registration"))?;
+        .map_err(|error| match error.downcast_ref::<PolicyViolation>() {
+            Some(violation) => (StatusCode::FORBIDDEN, violation.to_string()),
+            None => (StatusCode::BAD_REQUEST, "Failed to complete registration".to_string()),
+        })?;
 
     // Create a new user in the database
     user::create(user_email, user_first_name, user_last_name)
         .map_err(|_| (StatusCode::BAD_REQUEST, "Failed to store user details"))?;
 
     // Generate a verification token and send a verification email
-    if let Ok(verification_token) = token::generate(user_email) {
-        if send_mail(
-            user_email,
-            "Link your account",
-            &format!(
-                "Hello {}! Please link your account by following this URL: http://localhost:8080/validate/{}",
-                user_email, verification_token
-            ),
-        )
-            .is_err()
-        {
-            error!("Failed to send verification email to {}", user_email);
-        }
+    if !is_verified(user_email) && send_validation_email(user_email).is_err() {
+        error!("Failed to send verification email to {}", user_email);
     }
 
     // Associate the passkey with the user
     let user_passkey = CREDENTIAL_STORE.read().await.get(user_email).unwrap().clone();
     user::set_passkey(user_email, user_passkey).map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to set passkey"))?;
 
-    // Return OK status
-    Ok(StatusCode::OK)
+    // The recovery link can only be used once, and sessions opened before recovery are closed
+    if is_recovery {
+        session.remove_value(SESSION_RECOVERY_EMAIL);
+        sessions::revoke_all(user_email, None);
+    }
+
+    // Optional recovery codes, shown only in this response
+    let codes_flag = payload.get("recovery_codes");
+    let recovery_codes = if valid_bool(codes_flag) && codes_flag.and_then(|value| value.as_bool()).unwrap_or(false) {
+        Some(recovery_codes::issue(user_email).map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to generate recovery codes"))?)
+    } else {
+        None
+    };
+
+    Ok(Json(json!({ "recovery_codes": recovery_codes })))
 }
 
 
 
 
 /// Début du processus d'authentification WebAuthn
-pub async fn login_begin(Json(payload): Json<serde_json::Value>) -> axum::response::Result<Json<serde_json::Value>> {
+pub async fn login_begin(ClientIp(ip): ClientIp, Json(payload): Json<serde_json::Value>) -> axum::response::Result<Json<serde_json::Value>> {
    // Extract and validate the user's email from the JSON payload
-   let user_email = payload
+   let user_email: &str = &payload
    .get("email")
    .and_then(|value| value.as_str())
+   .map(normalize_email)
    .ok_or((StatusCode::BAD_REQUEST, "Email is required"))?;
     if !valid_email(user_email) {
     return Err((StatusCode::BAD_REQUEST, "Invalid email format").into());
     }
 
+    // An unknown email gets a challenge of the same shape with a decoy credential,
+    // so the response does not reveal whether the account exists
+    if !CREDENTIAL_STORE.read().await.contains_key(user_email) {
+        let (auth_challenge_response, auth_state) = begin_decoy_authentication(user_email)
+            .map_err(|_| (StatusCode::BAD_REQUEST, "Failed to initiate authentication"))?;
+        let pending = PendingAuthentication::Discoverable(auth_state);
+        return Ok(Json(store_authentication_state(auth_challenge_response, pending, owners(ip)).await?));
+    }
+
     // Start the WebAuthn authentication process
     let (auth_challenge_response, auth_state) = begin_authentication(user_email)
     .await
     .map_err(|_| (StatusCode::BAD_REQUEST, "Failed to initiate authentication"))?;
 
+    let pending = PendingAuthentication::Account { email: user_email.to_string(), state: auth_state };
+    Ok(Json(store_authentication_state(auth_challenge_response, pending, owners(ip)).await?))
+}
+
+/// Début d'une authentification sans email (credentials découvrables, autofill du navigateur)
+pub async fn login_discoverable_begin(ClientIp(ip): ClientIp) -> axum::response::Result<Json<serde_json::Value>> {
+    let (auth_challenge_response, auth_state) = begin_discoverable_authentication()
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to initiate authentication"))?;
+
+    let pending = PendingAuthentication::Discoverable(auth_state);
+    Ok(Json(store_authentication_state(auth_challenge_response, pending, discoverable_owners(ip)).await?))
+}
+
+/// Conserve l'état d'authentification et retourne les options pour le client
+async fn store_authentication_state(
+    public_key: serde_json::Value,
+    pending: PendingAuthentication,
+    owners: Vec<Owner>,
+) -> axum::response::Result<serde_json::Value> {
     // Generate a unique state identifier
     let auth_state_id = uuid::Uuid::new_v4().to_string();
+    let server_challenge = public_key["challenge"].as_str().unwrap_or_default().to_string();
 
-    // Store the authentication state
     AUTHENTICATION_STATES
-    .write()
-    .await
-    .insert(auth_state_id.clone(), TimedStoredState {
-        state: auth_state,
-        server_challenge: auth_challenge_response["challenge"].as_str().unwrap().to_string(),
-    });
+        .insert(auth_state_id.clone(), pending, server_challenge, owners)
+        .await
+        .map_err(too_many_ceremonies)?;
 
-    // Return the JSON response with the public key and state ID
-    Ok(Json(json!({
-    "publicKey": auth_challenge_response,
-    "state_id": auth_state_id,
-    })))
+    Ok(json!({
+        "publicKey": public_key,
+        "state_id": auth_state_id,
+    }))
+}
+
+/// Réponse lorsque la limite globale de cérémonies en attente est atteinte
+pub(crate) fn too_many_ceremonies(_: CapacityReached) -> (StatusCode, &'static str) {
+    (StatusCode::SERVICE_UNAVAILABLE, "Too many pending requests, please try again later")
 }
 
 /// Fin du processus d'authentification WebAuthn
-pub async fn login_complete(Json(payload): Json<serde_json::Value>) -> axum::response::Result<Redirect> {
+pub async fn login_complete(
+    session: Session,
+    ClientIp(ip): ClientIp,
+    headers: HeaderMap,
+    Json(payload): Json<serde_json::Value>,
+) -> axum::response::Result<Redirect> {
     // Extract and validate the response and state identifier from the input payload
     let auth_response = payload
         .get("response")
-        .ok_or_else(|| (StatusCode::BAD_REQUEST, "Authentication response is required"))?;
+        .ok_or((StatusCode::BAD_REQUEST, "Authentication response is required"))?;
     let auth_state_id = payload
         .get("state_id")
         .and_then(|value| value.as_str())
-        .ok_or_else(|| (StatusCode::BAD_REQUEST, "State ID is required"))?;
+        .ok_or((StatusCode::BAD_REQUEST, "State ID is required"))?;
 
     // Deserialize and validate the authentication response format
     let auth_response: PublicKeyCredential = serde_json::from_value(auth_response.clone())
@@ -231,32 +311,55 @@ pub async fn login_complete(Json(payload): Json<serde_json::Value>) -> axum::res
     }
 
     // Retrieve and validate the stored authentication state using the state ID
-    let mut authentication_states = AUTHENTICATION_STATES.write().await;
-    let stored_auth_state = authentication_states
-        .remove(auth_state_id)
+    let stored_auth_state = AUTHENTICATION_STATES
+        .take(auth_state_id)
+        .await
         .ok_or((StatusCode::BAD_REQUEST, "Invalid or expired authentication state ID"))?;
 
     // Complete the WebAuthn authentication process
-    complete_authentication(
-        &auth_response,
-        &stored_auth_state.state,
-        &stored_auth_state.server_challenge,
-    )
-    .await
-    .map_err(|error| (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to complete authentication: {}", error)))?;
+    let user_email = match stored_auth_state.state {
+        PendingAuthentication::Account { email, state } => complete_authentication(
+            &email,
+            &auth_response,
+            &state,
+            &stored_auth_state.server_challenge,
+        )
+        .await
+        .map(|_| email),
+        PendingAuthentication::Discoverable(state) => {
+            complete_discoverable_authentication(&auth_response, state).await
+        }
+        // A step-up ceremony cannot open a session
+        PendingAuthentication::StepUp { .. } => return Err((StatusCode::BAD_REQUEST, "Invalid or expired authentication state ID").into()),
+    }
+    .map_err(|error| (StatusCode::UNAUTHORIZED, format!("Failed to complete authentication: {}", error)))?;
+
+    // Suspended accounts cannot sign in
+    if is_suspended(&user_email) {
+        return Err((StatusCode::FORBIDDEN, "Account suspended").into());
+    }
+
+    // Unverified accounts are refused or restricted, depending on the policy
+    let destination = after_login(CONFIG.unverified_login, is_verified(&user_email))?;
+
+    // Open the session with a fresh identifier to prevent session fixation
+    session.cycle_id();
+    sessions::open(&session, &user_email, &headers, ip)
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to open session"))?;
 
     // Redirect the user to the home page upon successful authentication
-    Ok(Redirect::to("/home"))
+    Ok(Redirect::to(destination))
 }
 
 /// Gère la déconnexion de l'utilisateur
-pub async fn logout() -> impl IntoResponse {
+pub async fn logout(session: Session) -> impl IntoResponse {
+    sessions::close(&session);
     Redirect::to("/")
 }
 
 /// Valide un compte utilisateur via un token
 pub async fn validate_account(Path(token): Path<String>) -> impl IntoResponse {
-    match token::consume(&token) {
+    match token::consume(&token, Purpose::Validation) {
         Ok(email) => match user::verify(&email) {
             Ok(_) => Redirect::to("/login?validated=true"),
             Err(_) => Redirect::to("/register?error=validation_failed"),
@@ -266,21 +369,26 @@ pub async fn validate_account(Path(token): Path<String>) -> impl IntoResponse {
 }
 
 /// Envoie un email de récupération de compte à l'utilisateur
-pub async fn recover_account(Json(payload): Json<serde_json::Value>) -> axum::response::Result<Html<String>> {
+pub async fn recover_account(
+    Extension(nonce): Extension<CspNonce>,
+    CsrfToken(csrf_token): CsrfToken,
+    Json(payload): Json<serde_json::Value>,
+) -> axum::response::Result<Html<String>> {
     let mut response_data = HashMap::new();
 
     // Extract and validate the user's email from the input payload
-    let user_email = payload
+    let user_email: &str = &payload
         .get("email")
         .and_then(|value| value.as_str())
+        .map(normalize_email)
         .ok_or((StatusCode::BAD_REQUEST, "Email address is required"))?;
 
     // Generate a unique recovery token for the user
-    let recovery_token = token::generate(user_email)
+    let recovery_token = token::generate(user_email, Purpose::Recovery)
         .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to generate recovery token"))?;
 
     // Construct the recovery URL using the generated token
-    let recovery_link = format!("http://localhost:8080/recover/{}", recovery_token);
+    let recovery_link = CONFIG.link(&format!("/recover/{}", recovery_token));
 
     // Attempt to send the recovery email to the user
     let email_send_result = send_mail(
@@ -291,6 +399,8 @@ pub async fn recover_account(Json(payload): Json<serde_json::Value>) -> axum::re
 
     // Insert a success message into the response data
     response_data.insert("message", "Recovery email sent successfully.");
+    response_data.insert("csp_nonce", nonce.0.as_str());
+    response_data.insert("csrf_token", csrf_token.as_str());
 
     // Check if sending the email failed
     if email_send_result.is_err() {
@@ -304,13 +414,13 @@ pub async fn recover_account(Json(payload): Json<serde_json::Value>) -> axum::re
 }
 
 /// Gère la réinitialisation du compte utilisateur via un token de récupération
-pub async fn reset_account(Path(token): Path<String>) -> Html<String> {
-    match token::consume(&token) {
-        Ok(email) => {
-            let redirect_url = format!("/register?reset_mode=true&email={}&success=true", email);
-            Html(format!("<meta http-equiv='refresh' content='0;url={}'/>", redirect_url))
-        }
-        Err(_) => {
+pub async fn reset_account(session: Session, Path(token): Path<String>) -> Html<String> {
+    let granted = token::consume(&token, Purpose::Recovery)
+        .ok()
+        .and_then(|email| grant_recovery(&session, &email).ok());
+    match granted {
+        Some(redirect_url) => Html(format!("<meta http-equiv='refresh' content='0;url={}'/>", redirect_url)),
+        None => {
             let redirect_url = "/register?error=recovery_failed";
             Html(format!("<meta http-equiv='refresh' content='0;url={}'/>", redirect_url))
         }
@@ -320,10 +430,15 @@ pub async fn reset_account(Path(token): Path<String>) -> Html<String> {
 /// --- Affichage des pages ---
 ///
 /// Affiche la page d'accueil
-pub async fn index(session: tower_sessions::Session) -> impl IntoResponse {
-    let is_logged_in = session.get::<String>("email").is_ok();
-    let mut data = HashMap::new();
-    data.insert("logged_in", is_logged_in);
+pub async fn index(session: Session, Extension(nonce): Extension<CspNonce>) -> impl IntoResponse {
+    let is_logged_in = matches!(session.get::<String>(SESSION_EMAIL), Ok(Some(_)));
+    // Le jeton ne sert qu'au bouton de déconnexion : pas de session créée pour un visiteur
+    let csrf_token = if is_logged_in { csrf::token(&session).ok() } else { None };
+    let data = json!({
+        "logged_in": is_logged_in,
+        "csp_nonce": nonce.0,
+        "csrf_token": csrf_token,
+    });
 
     HBS.render("index", &data)
         .map(Html)
@@ -331,13 +446,35 @@ pub async fn index(session: tower_sessions::Session) -> impl IntoResponse {
 }
 
 /// Affiche la page de connexion
-pub async fn login_page() -> impl IntoResponse {
-    Html(include_str!("../../templates/login.hbs"))
+pub async fn login_page(
+    Extension(nonce): Extension<CspNonce>,
+    CsrfToken(csrf_token): CsrfToken,
+    Query(params): Query<HashMap<String, String>>,
+) -> impl IntoResponse {
+    let error_message = match params.get("error").map(String::as_str) {
+        Some("magic_link_failed") => Some("Invalid or expired sign-in link. Open it in the browser where you requested it."),
+        _ => None,
+    };
+    let data = json!({
+        "csp_nonce": nonce.0,
+        "csrf_token": csrf_token,
+        "magic_link": CONFIG.magic_link_login,
+        "error_message": error_message,
+    });
+    HBS.render("login", &data)
+        .map(Html)
+        .unwrap_or_else(|_| Html("<h1>Internal Server Error</h1>".to_string()))
 }
 
 /// Affiche la page d'inscription avec des messages contextuels si présents
-pub async fn register_page(Query(params): Query<HashMap<String, String>>) -> impl IntoResponse {
+pub async fn register_page(
+    Extension(nonce): Extension<CspNonce>,
+    CsrfToken(csrf_token): CsrfToken,
+    Query(params): Query<HashMap<String, String>>,
+) -> impl IntoResponse {
     let mut context = HashMap::new();
+    context.insert("csp_nonce", nonce.0.as_str());
+    context.insert("csrf_token", csrf_token.as_str());
     if let Some(success) = params.get("success") {
         if success == "true" {
             context.insert("success_message", "Account recovery successful. Please reset your passkey.");
@@ -355,6 +492,8 @@ pub async fn register_page(Query(params): Query<HashMap<String, String>>) -> imp
 }
 
 /// Affiche la page de récupération de compte
-pub async fn recover_page() -> impl IntoResponse {
-    Html(include_str!("../../templates/recover.hbs"))
+pub async fn recover_page(Extension(nonce): Extension<CspNonce>, CsrfToken(csrf_token): CsrfToken) -> impl IntoResponse {
+    HBS.render("recover", &json!({ "csp_nonce": nonce.0, "csrf_token": csrf_token }))
+        .map(Html)
+        .unwrap_or_else(|_| Html("<h1>Internal Server Error</h1>".to_string()))
 }
@@ -0,0 +1,350 @@
+//! Mises à jour du fil en direct par Server-Sent Events (`/live`).
+//!
+//! Les handlers publient dans un hub les posts publiés, les nouveaux totaux de likes et les
+//! notifications. Chaque connexion les reçoit par un canal `broadcast` borné et les filtre
+//! pour son compte au moment de l'envoi : un post est relu et n'est transmis que s'il est dans
+//! le fil du compte, une notification n'est transmise qu'à son destinataire.
+//!
+//! Un client lent ne ralentit personne : il prend du retard sur le canal, puis rattrape les
+//! événements manqués dans le journal du hub. Le même journal sert aux reconnexions
+//! (`Last-Event-ID`). Si les événements manqués n'y sont plus, le client reçoit `resync` et
+//! recharge le fil. Les identifiants d'événements portent l'époque du démarrage du serveur :
+//! après un redémarrage, la numérotation repart de 1 et un ancien identifiant demande aussi un
+//! rechargement. Le nombre de connexions simultanées par compte est limité.
+//!
+//! Une connexion ouverte ne passe plus par le middleware des sessions : avant chaque événement
+//! et chaque message de maintien, elle vérifie que sa session est toujours ouverte (voir
+//! `sessions::is_open`) et se ferme sinon.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    convert::Infallible,
+    sync::{Mutex, MutexGuard},
+    time::Duration,
+};
+use axum::{
+    http::{HeaderMap, StatusCode},
+    response::sse::{Event, Sse},
+};
+use futures_util::stream::{self, Stream, StreamExt};
+use log::warn;
+use once_cell::sync::Lazy;
+use serde_json::json;
+use tokio::sync::broadcast::{self, error::RecvError};
+use tower_sessions::Session;
+use uuid::Uuid;
+use crate::backend::handlers_auth::feed_entries;
+use crate::backend::middlewares::LoggedIn;
+use crate::backend::sessions;
+use crate::consts;
+use crate::database::{follow, notification, post};
+use crate::metrics;
+
+/// En-tête envoyé par `EventSource` à la reconnexion
+const LAST_EVENT_ID: &str = "last-event-id";
+
+/// Époque de ce démarrage du serveur, préfixe des identifiants d'événements
+static EPOCH: Lazy<String> = Lazy::new(|| Uuid::new_v4().simple().to_string());
+
+/// Identifiant SSE de l'événement `id` du journal
+fn event_id(id: u64) -> String {
+    format!("{}-{}", *EPOCH, id)
+}
+
+/// Numéro dans le journal d'un identifiant SSE, `None` s'il ne vient pas de ce démarrage
+fn parse_event_id(value: &str) -> Option<u64> {
+    let (epoch, id) = value.split_once('-')?;
+    if epoch != EPOCH.as_str() {
+        return None;
+    }
+    id.parse().ok()
+}
+
+/// Changement diffusé aux connexions
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum Update {
+    /// Post qui vient d'être publié
+    Post(Uuid),
+    /// Nouveau total de likes d'un post
+    Likes { post_id: Uuid, likes: i32 },
+    /// Nouvelle notification pour ce compte
+    Notification(String),
+}
+
+/// Derniers événements publiés, numérotés à partir de 1
+#[derive(Default)]
+struct Journal {
+    last_id: u64,
+    events: VecDeque<(u64, Update)>,
+}
+
+impl Journal {
+    /// Événements publiés après `id`, `None` si certains ne sont plus dans le journal
+    /// ou si `id` n'a jamais été publié
+    fn since(&self, id: u64) -> Option<Vec<(u64, Update)>> {
+        if id > self.last_id {
+            return None;
+        }
+        let oldest = self.events.front().map_or(self.last_id + 1, |(oldest, _)| *oldest);
+        if id + 1 < oldest {
+            return None;
+        }
+        Some(self.events.iter().filter(|(event_id, _)| *event_id > id).cloned().collect())
+    }
+}
+
+struct Hub {
+    sender: broadcast::Sender<(u64, Update)>,
+    /// Verrouillé pendant chaque envoi : le journal et le canal voient les événements dans le même ordre
+    journal: Mutex<Journal>,
+}
+
+static HUB: Lazy<Hub> = Lazy::new(|| Hub {
+    sender: broadcast::channel(consts::LIVE_CHANNEL_CAPACITY).0,
+    journal: Mutex::default(),
+});
+
+fn journal() -> MutexGuard<'static, Journal> {
+    HUB.journal.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Diffuse un changement aux connexions ouvertes
+pub(crate) fn publish(update: Update) {
+    let mut journal = journal();
+    journal.last_id += 1;
+    let event = (journal.last_id, update);
+    journal.events.push_back(event.clone());
+    if journal.events.len() > consts::LIVE_REPLAY_EVENTS {
+        journal.events.pop_front();
+    }
+    // Sans connexion ouverte, l'événement reste seulement dans le journal
+    let _ = HUB.sender.send(event);
+}
+
+/// Connexions ouvertes par compte
+static CONNECTIONS: Lazy<Mutex<HashMap<String, usize>>> = Lazy::new(Default::default);
+
+fn connections() -> MutexGuard<'static, HashMap<String, usize>> {
+    CONNECTIONS.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Place occupée par une connexion, libérée quand le flux est abandonné
+struct ConnectionSlot(String);
+
+impl ConnectionSlot {
+    fn acquire(email: &str) -> Option<ConnectionSlot> {
+        let mut connections = connections();
+        let count = connections.entry(email.to_string()).or_default();
+        if *count >= consts::LIVE_MAX_CONNECTIONS_PER_USER {
+            return None;
+        }
+        *count += 1;
+        metrics::set_live_connections(connections.values().sum());
+        Some(ConnectionSlot(email.to_string()))
+    }
+}
+
+impl Drop for ConnectionSlot {
+    fn drop(&mut self) {
+        let mut connections = connections();
+        if let Some(count) = connections.get_mut(&self.0) {
+            *count -= 1;
+            if *count == 0 {
+                connections.remove(&self.0);
+            }
+        }
+        metrics::set_live_connections(connections.values().sum());
+    }
+}
+
+/// État d'une connexion SSE
+struct Connection {
+    email: String,
+    /// Session dans le registre, vérifiée avant chaque envoi
+    session_id: String,
+    receiver: broadcast::Receiver<(u64, Update)>,
+    /// Événements à filtrer puis envoyer
+    pending: VecDeque<(u64, Update)>,
+    /// Dernier événement mis dans `pending`
+    queued_id: u64,
+    /// Le client doit recharger son fil
+    resync: bool,
+    _slot: ConnectionSlot,
+}
+
+impl Connection {
+    /// Ouvre une connexion qui reprend après l'événement `last_event_id`
+    fn open(email: &str, session_id: String, last_event_id: Option<&str>, slot: ConnectionSlot) -> Connection {
+        let journal = journal();
+        let receiver = HUB.sender.subscribe();
+        let (pending, resync) = match last_event_id.map(|id| parse_event_id(id).and_then(|id| journal.since(id))) {
+            None => (vec![], false),
+            Some(Some(missed)) => (missed, false),
+            Some(None) => (vec![], true),
+        };
+        Connection {
+            email: email.to_string(),
+            session_id,
+            receiver,
+            pending: pending.into(),
+            queued_id: journal.last_id,
+            resync,
+            _slot: slot,
+        }
+    }
+
+    /// Rattrape les événements perdus par le canal, ou demande un rechargement du fil
+    fn catch_up(&mut self) {
+        let journal = journal();
+        match journal.since(self.queued_id) {
+            Some(missed) => self.pending.extend(missed),
+            None => {
+                self.pending.clear();
+                self.resync = true;
+            }
+        }
+        self.receiver = HUB.sender.subscribe();
+        self.queued_id = journal.last_id;
+    }
+
+    /// Prochain événement à envoyer, `None` quand le hub est fermé ou la session fermée
+    async fn next_event(&mut self) -> Option<Event> {
+        let event = self.next_update().await?;
+        sessions::is_open(&self.session_id).then_some(event)
+    }
+
+    /// Prochain changement à envoyer, ou message de maintien après `LIVE_HEARTBEAT_SECS` sans changement
+    async fn next_update(&mut self) -> Option<Event> {
+        loop {
+            if self.resync {
+                self.resync = false;
+                return Some(Event::default().id(event_id(self.queued_id)).event("resync").data("{}"));
+            }
+            if let Some((id, update)) = self.pending.pop_front() {
+                match render(id, &update, &self.email) {
+                    Some(event) => return Some(event),
+                    None => continue,
+                }
+            }
+            let heartbeat = Duration::from_secs(consts::LIVE_HEARTBEAT_SECS);
+            let Ok(received) = tokio::time::timeout(heartbeat, self.receiver.recv()).await else {
+                return Some(Event::default().comment("heartbeat"));
+            };
+            match received {
+                Ok((id, update)) if id > self.queued_id => {
+                    self.queued_id = id;
+                    self.pending.push_back((id, update));
+                }
+                Ok(_) => {}
+                Err(RecvError::Lagged(skipped)) => {
+                    warn!("Live connection of {} lagged by {} events", self.email, skipped);
+                    self.catch_up();
+                }
+                Err(RecvError::Closed) => return None,
+            }
+        }
+    }
+}
+
+/// Événement SSE d'un changement, s'il concerne `viewer`
+fn render(id: u64, update: &Update, viewer: &str) -> Option<Event> {
+    let (name, data) = match update {
+        Update::Post(post_id) => {
+            let post = post::get(*post_id).ok()??;
+            if !post.in_feed_of(viewer) {
+                return None;
+            }
+            let followed = post
+                .author
+                .as_deref()
+                .is_some_and(|author| follow::is_following(viewer, author).unwrap_or(false));
+            let mut entry = feed_entries(vec![post], Some(viewer)).pop()?;
+            entry["followed"] = json!(followed);
+            ("post", entry)
+        }
+        Update::Likes { post_id, likes } => {
+            if !post::get(*post_id).ok()??.in_feed_of(viewer) {
+                return None;
+            }
+            ("likes", json!({ "post_id": post_id, "likes": likes }))
+        }
+        Update::Notification(recipient) => {
+            if recipient != viewer {
+                return None;
+            }
+            ("notification", json!({ "unread": notification::unread_count(viewer).ok()? }))
+        }
+    };
+    Some(Event::default().id(event_id(id)).event(name).data(data.to_string()))
+}
+
+/// Flux des mises à jour du fil pour le compte connecté
+pub async fn live_feed(
+    LoggedIn(email): LoggedIn,
+    session: Session,
+    headers: HeaderMap,
+) -> axum::response::Result<Sse<impl Stream<Item = Result<Event, Infallible>>>> {
+    let session_id = sessions::registry_id(&session)
+        .filter(|id| sessions::is_open(id))
+        .ok_or((StatusCode::UNAUTHORIZED, "Unauthorized"))?;
+    let slot = ConnectionSlot::acquire(&email).ok_or((StatusCode::TOO_MANY_REQUESTS, "Too many live connections"))?;
+    let last_event_id = headers.get(LAST_EVENT_ID).and_then(|value| value.to_str().ok());
+
+    let connection = Connection::open(&email, session_id, last_event_id, slot);
+    // Le premier message fixe le délai de reconnexion du client
+    let retry = Event::default().retry(Duration::from_secs(consts::LIVE_RETRY_SECS));
+    let updates = stream::unfold(connection, |mut connection| async move {
+        let event = connection.next_event().await?;
+        Some((event, connection))
+    });
+    let events = stream::once(async { retry }).chain(updates).map(Ok);
+
+    Ok(Sse::new(events))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn journal_of(ids: std::ops::RangeInclusive<u64>) -> Journal {
+        Journal {
+            last_id: *ids.end(),
+            events: ids.map(|id| (id, Update::Notification(id.to_string()))).collect(),
+        }
+    }
+
+    #[test]
+    fn test_journal_replay() {
+        let journal = journal_of(5..=8);
+        let ids = |events: Option<Vec<(u64, Update)>>| events.map(|events| events.into_iter().map(|(id, _)| id).collect::<Vec<_>>());
+        assert_eq!(ids(journal.since(6)), Some(vec![7, 8]));
+        assert_eq!(ids(journal.since(4)), Some(vec![5, 6, 7, 8]));
+        assert_eq!(ids(journal.since(8)), Some(vec![]));
+        // Événements sortis du journal, ou pas encore publiés
+        assert_eq!(ids(journal.since(3)), None);
+        assert_eq!(ids(journal.since(9)), None);
+        assert_eq!(ids(Journal::default().since(0)), Some(vec![]));
+    }
+
+    #[test]
+    fn test_event_ids_carry_the_boot_epoch() {
+        assert_eq!(parse_event_id(&event_id(42)), Some(42));
+        // Identifiants d'un démarrage précédent ou d'avant l'époque
+        assert_eq!(parse_event_id(&format!("{}-42", Uuid::new_v4().simple())), None);
+        assert_eq!(parse_event_id("42"), None);
+        assert_eq!(parse_event_id(&format!("{}-x", *EPOCH)), None);
+    }
+
+    #[test]
+    fn test_connections_are_capped_per_account() {
+        let email = format!("{}@example.com", Uuid::new_v4());
+        let slots: Vec<_> = (0..consts::LIVE_MAX_CONNECTIONS_PER_USER)
+            .map(|_| ConnectionSlot::acquire(&email).unwrap())
+            .collect();
+        assert!(ConnectionSlot::acquire(&email).is_none());
+        assert!(ConnectionSlot::acquire(&format!("other-{}", email)).is_some());
+        drop(slots);
+        assert!(ConnectionSlot::acquire(&email).is_some());
+        assert!(!connections().contains_key(&email));
+    }
+}
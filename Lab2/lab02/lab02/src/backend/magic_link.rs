@@ -0,0 +1,170 @@
+//! Connexion par lien envoyé par email, pour les postes où une passkey ne peut pas être enregistrée.
+//! La fonctionnalité est désactivée par défaut (`CONFIG.magic_link_login`) et chaque titulaire
+//! d'un compte vérifié doit l'activer après un step-up. Le lien est à usage unique, expire après
+//! `consts::MAGIC_LINK_TTL_SECS` et n'est accepté que par la session qui l'a demandé :
+//! un lien transféré ou intercepté ne s'ouvre pas dans un autre navigateur.
+
+use axum::{
+    extract::{Json, Path},
+    http::{HeaderMap, StatusCode},
+    response::Redirect,
+};
+use anyhow::Result;
+use log::{error, info};
+use tower_sessions::Session;
+use crate::backend::middlewares::{ClientIp, StepUp};
+use crate::backend::sessions;
+use crate::config::CONFIG;
+use crate::consts;
+use crate::database::{token::{self, Purpose}, user};
+use crate::email::send_mail;
+use crate::utils::input::{normalize_email, valid_bool, valid_email};
+
+/// Clé de session contenant le dernier lien demandé depuis ce navigateur
+const SESSION_MAGIC_LINK: &str = "magic_link_token";
+/// Page de connexion affichée quand un lien est refusé
+const MAGIC_LINK_FAILED: &str = "/login?error=magic_link_failed";
+
+/// Indique si le compte peut se connecter par lien
+fn allowed(email: &str) -> bool {
+    user::get(email).is_some_and(|user| user.verified && user.magic_link && !user.suspended)
+}
+
+/// Envoie un lien si le compte l'autorise et le lie à la session qui le demande.
+/// Les liens envoyés précédemment ne sont plus valables.
+fn send_link(session: &Session, email: &str) -> Result<()> {
+    if !allowed(email) {
+        return Ok(());
+    }
+
+    token::revoke(email, Some(Purpose::MagicLink))?;
+    let magic_token = token::generate(email, Purpose::MagicLink)?;
+    session.insert(SESSION_MAGIC_LINK, &magic_token)?;
+    send_mail(
+        email,
+        "Your sign-in link",
+        &format!(
+            "Follow this link to sign in, from the browser where you requested it: {} \
+             It expires in {} minutes. If you did not ask for it, ignore this email.",
+            CONFIG.link(&format!("/login/magic/{}", magic_token)),
+            consts::MAGIC_LINK_TTL_SECS / 60
+        ),
+    )
+}
+
+/// Consomme le lien s'il a été demandé par cette session et retourne le compte à connecter
+fn redeem(session: &Session, magic_token: &str) -> Option<String> {
+    let requested = session.remove::<String>(SESSION_MAGIC_LINK).ok().flatten()?;
+    if requested != magic_token {
+        return None;
+    }
+    let email = token::consume(magic_token, Purpose::MagicLink).ok()?;
+    allowed(&email).then_some(email)
+}
+
+/// Demande un lien de connexion. La réponse est identique que le compte l'autorise ou non.
+pub async fn request_magic_link(session: Session, Json(payload): Json<serde_json::Value>) -> axum::response::Result<StatusCode> {
+    if !CONFIG.magic_link_login {
+        return Err((StatusCode::NOT_FOUND, "Sign-in by email link is disabled").into());
+    }
+
+    let email: &str = &payload
+        .get("email")
+        .and_then(|value| value.as_str())
+        .map(normalize_email)
+        .ok_or((StatusCode::BAD_REQUEST, "Email address is required"))?;
+    if !valid_email(email) {
+        return Err((StatusCode::BAD_REQUEST, "Invalid email format").into());
+    }
+
+    if let Err(e) = send_link(&session, email) {
+        error!("Failed to send a sign-in link to {}: {}", email, e);
+    }
+    Ok(StatusCode::OK)
+}
+
+/// Ouvre une session à partir d'un lien reçu par email
+pub async fn open_magic_link(
+    session: Session,
+    ClientIp(ip): ClientIp,
+    headers: HeaderMap,
+    Path(magic_token): Path<String>,
+) -> Redirect {
+    if !CONFIG.magic_link_login {
+        return Redirect::to(MAGIC_LINK_FAILED);
+    }
+    let Some(email) = redeem(&session, &magic_token) else {
+        return Redirect::to(MAGIC_LINK_FAILED);
+    };
+
+    // Nouvel identifiant de session, comme après une authentification WebAuthn
+    session.cycle_id();
+    if sessions::open(&session, &email, &headers, ip).is_err() {
+        return Redirect::to(MAGIC_LINK_FAILED);
+    }
+    info!("{} signed in with an email link", email);
+    Redirect::to("/home")
+}
+
+/// Active ou désactive la connexion par lien du compte connecté (step-up requis)
+pub async fn set_magic_link(step_up: StepUp, Json(payload): Json<serde_json::Value>) -> axum::response::Result<StatusCode> {
+    let enabled = payload.get("enabled");
+    if !valid_bool(enabled) {
+        return Err((StatusCode::BAD_REQUEST, "Invalid enabled flag").into());
+    }
+    let enabled = enabled.and_then(|value| value.as_bool()).unwrap_or(false);
+
+    user::set_magic_link(&step_up.email, enabled)
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to update account"))?;
+    if !enabled {
+        token::revoke(&step_up.email, Some(Purpose::MagicLink))
+            .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to revoke pending links"))?;
+    }
+    Ok(StatusCode::OK)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::email;
+
+    fn account(verified: bool, magic_link: bool) -> String {
+        let email = format!("{}@example.com", uuid::Uuid::new_v4());
+        user::create(&email, "Alice", "Martin").unwrap();
+        if verified {
+            user::verify(&email).unwrap();
+        }
+        user::set_magic_link(&email, magic_link).unwrap();
+        email
+    }
+
+    fn sent_token(to: &str) -> String {
+        let body = email::sent_to(to).unwrap().last().unwrap().body.clone();
+        let start = body.find("/login/magic/").unwrap() + "/login/magic/".len();
+        body[start..].split_whitespace().next().unwrap().to_string()
+    }
+
+    #[test]
+    fn test_link_only_opens_in_the_requesting_session() {
+        let email = account(true, true);
+        let requester = Session::new(None);
+        send_link(&requester, &email).unwrap();
+        let magic_token = sent_token(&email);
+
+        // Un lien transféré à un autre navigateur est refusé sans être consommé
+        assert_eq!(redeem(&Session::new(None), &magic_token), None);
+        assert_eq!(redeem(&requester, &magic_token), Some(email));
+        // Usage unique
+        requester.insert(SESSION_MAGIC_LINK, &magic_token).unwrap();
+        assert_eq!(redeem(&requester, &magic_token), None);
+    }
+
+    #[test]
+    fn test_link_requires_verified_opt_in() {
+        for (verified, magic_link) in [(false, true), (true, false)] {
+            let email = account(verified, magic_link);
+            send_link(&Session::new(None), &email).unwrap();
+            assert!(email::sent_to(&email).unwrap().iter().all(|sent| !sent.body.contains("/login/magic/")));
+        }
+    }
+}
@@ -0,0 +1,35 @@
+//! Images des posts. Elles ne sont servies qu'aux comptes qui peuvent voir le post
+//! (voir `Post::can_view`), avec les mêmes règles que le fil.
+
+use axum::{
+    extract::Path,
+    http::{header, StatusCode},
+    response::IntoResponse,
+};
+use uuid::Uuid;
+use crate::backend::middlewares::LoggedIn;
+use crate::config::CONFIG;
+use crate::database::post;
+
+/// Sert l'image du post `post_id`
+pub async fn post_image(LoggedIn(viewer): LoggedIn, Path(post_id): Path<Uuid>) -> axum::response::Result<impl IntoResponse> {
+    let post = post::get(post_id)
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to read posts"))?
+        .filter(|post| post.can_view(&viewer))
+        .ok_or((StatusCode::NOT_FOUND, "Image not found"))?;
+    let path = post
+        .image_path
+        .map(std::path::PathBuf::from)
+        .filter(|path| path.starts_with(CONFIG.uploads_dir()))
+        .ok_or((StatusCode::NOT_FOUND, "Image not found"))?;
+
+    let bytes = tokio::fs::read(&path).await.map_err(|_| (StatusCode::NOT_FOUND, "Image not found"))?;
+    Ok((
+        [
+            (header::CONTENT_TYPE, "image/jpeg"),
+            // La visibilité peut changer : pas de cache partagé
+            (header::CACHE_CONTROL, "private, no-store"),
+        ],
+        bytes,
+    ))
+}
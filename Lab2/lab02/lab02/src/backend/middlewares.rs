@@ -1,9 +1,23 @@
 //! Middleware pour gérer les sessions utilisateur.
 //! Vérifie la validité d'une session utilisateur et rejette les requêtes non autorisées.
+//! Une session dont le compte n'a pas vérifié son email est renvoyée vers `/verify-email`.
+//! Un compte suspendu par la modération est refusé.
+//! `StepUp` exige en plus une ré-authentification récente pour les opérations sensibles.
+//! `LoggedIn` fournit l'email du compte connecté aux handlers qui n'exigent rien de plus.
+//! `Authorized` réserve un handler aux comptes qui ont au moins un rôle donné.
+//! `ClientIp` fournit l'adresse du client lorsque le serveur la connaît, lue dans
+//! `X-Forwarded-For` seulement derrière un reverse proxy de confiance.
 
-use axum::extract::FromRequestParts;
-use axum::http::{request::Parts, StatusCode};
+use std::{convert::Infallible, marker::PhantomData, net::{IpAddr, SocketAddr}};
+use axum::extract::{ConnectInfo, FromRequestParts};
+use axum::http::{request::Parts, HeaderMap, Method, StatusCode};
+use axum::response::{IntoResponse, Redirect, Response};
 use tower_sessions::Session;
+use crate::backend::handlers_unauth::SESSION_EMAIL;
+use crate::backend::step_up;
+use crate::backend::verification::{is_verified, VERIFY_EMAIL_PAGE};
+use crate::config::CONFIG;
+use crate::database::user::{self, Role};
 
 /// Middleware pour valider une session utilisateur
 pub struct SessionUser;
@@ -13,15 +27,188 @@ impl <S> FromRequestParts<S> for SessionUser
 where
     S: Send + Sync,
 {
-    type Rejection = (StatusCode, String);
+    type Rejection = Response;
 
     async fn from_request_parts(parts: &mut Parts, _: &S) -> Result<Self, Self::Rejection> {
-        if let Some(session) = parts.extensions.get::<Session>() {
-            if session.get::<String>("email").is_ok() {
-                return Ok(SessionUser);
+        let email = parts
+            .extensions
+            .get::<Session>()
+            .and_then(|session| session.get::<String>(SESSION_EMAIL).ok().flatten());
+        let Some(email) = email else {
+            return Err((StatusCode::UNAUTHORIZED, "Unauthorized").into_response());
+        };
+
+        if user::get(&email).is_some_and(|user| user.suspended) {
+            return Err((StatusCode::FORBIDDEN, "Account suspended").into_response());
+        }
+
+        // Session restreinte : seule la page de vérification est accessible
+        if !is_verified(&email) {
+            if parts.method == Method::GET {
+                return Err(Redirect::to(VERIFY_EMAIL_PAGE).into_response());
             }
+            return Err((StatusCode::FORBIDDEN, "Email address not verified").into_response());
+        }
+
+        Ok(SessionUser)
+    }
+}
+
+/// Extracteur pour les opérations sensibles : session valide et step-up récent
+pub struct StepUp {
+    pub email: String,
+}
+
+#[async_trait::async_trait]
+impl <S> FromRequestParts<S> for StepUp
+where
+    S: Send + Sync,
+{
+    type Rejection = (StatusCode, String);
+
+    async fn from_request_parts(parts: &mut Parts, _: &S) -> Result<Self, Self::Rejection> {
+        let Some(session) = parts.extensions.get::<Session>() else {
+            return Err((StatusCode::UNAUTHORIZED, "Unauthorized".to_string()));
+        };
+        let Ok(Some(email)) = session.get::<String>(SESSION_EMAIL) else {
+            return Err((StatusCode::UNAUTHORIZED, "Unauthorized".to_string()));
+        };
+
+        if !step_up::is_fresh(session) {
+            return Err((StatusCode::FORBIDDEN, "Re-authentication required".to_string()));
+        }
+
+        Ok(StepUp { email })
+    }
+}
+
+/// Rôle minimal exigé par `Authorized`
+pub trait RequiredRole {
+    const ROLE: Role;
+}
+
+/// Modérateurs et administrateurs
+pub struct Moderator;
+
+impl RequiredRole for Moderator {
+    const ROLE: Role = Role::Moderator;
+}
+
+/// Administrateurs uniquement
+pub struct Admin;
+
+impl RequiredRole for Admin {
+    const ROLE: Role = Role::Admin;
+}
+
+/// Extracteur de l'email du compte connecté, `401` sans session authentifiée
+pub struct LoggedIn(pub String);
+
+#[async_trait::async_trait]
+impl <S> FromRequestParts<S> for LoggedIn
+where
+    S: Send + Sync,
+{
+    type Rejection = (StatusCode, &'static str);
+
+    async fn from_request_parts(parts: &mut Parts, _: &S) -> Result<Self, Self::Rejection> {
+        parts
+            .extensions
+            .get::<Session>()
+            .and_then(|session| session.get::<String>(SESSION_EMAIL).ok().flatten())
+            .map(LoggedIn)
+            .ok_or((StatusCode::UNAUTHORIZED, "Unauthorized"))
+    }
+}
+
+/// Extracteur pour les handlers réservés à un rôle : compte connecté, vérifié, non suspendu
+/// et dont le rôle est au moins `R::ROLE`
+pub struct Authorized<R> {
+    pub email: String,
+    pub role: Role,
+    _required: PhantomData<R>,
+}
+
+#[async_trait::async_trait]
+impl <S, R> FromRequestParts<S> for Authorized<R>
+where
+    S: Send + Sync,
+    R: RequiredRole,
+{
+    type Rejection = (StatusCode, &'static str);
+
+    async fn from_request_parts(parts: &mut Parts, _: &S) -> Result<Self, Self::Rejection> {
+        let email = parts
+            .extensions
+            .get::<Session>()
+            .and_then(|session| session.get::<String>(SESSION_EMAIL).ok().flatten())
+            .ok_or((StatusCode::UNAUTHORIZED, "Unauthorized"))?;
+        let user = user::get(&email).ok_or((StatusCode::UNAUTHORIZED, "Unauthorized"))?;
+
+        if !user.verified || user.suspended || user.role < R::ROLE {
+            return Err((StatusCode::FORBIDDEN, "Insufficient role"));
         }
+        Ok(Authorized { email, role: user.role, _required: PhantomData })
+    }
+}
+
+/// Adresse IP du client, absente si le transport ne la fournit pas
+pub struct ClientIp(pub Option<IpAddr>);
+
+#[async_trait::async_trait]
+impl <S> FromRequestParts<S> for ClientIp
+where
+    S: Send + Sync,
+{
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let connect_info = ConnectInfo::<SocketAddr>::from_request_parts(parts, state).await;
+        let peer = connect_info.ok().map(|ConnectInfo(addr)| addr.ip());
+        Ok(ClientIp(peer.map(|peer| client_ip(peer, &parts.headers, &CONFIG.trusted_proxies))))
+    }
+}
+
+/// Adresse du client : en partant de la connexion, remonte `X-Forwarded-For` de droite à gauche
+/// tant que le saut précédent est un proxy de confiance. Une entrée invalide arrête la remontée.
+fn client_ip(peer: IpAddr, headers: &HeaderMap, trusted_proxies: &[IpAddr]) -> IpAddr {
+    let mut client = peer;
+    let hops = headers
+        .get_all("x-forwarded-for")
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .collect::<Vec<_>>();
+    for hop in hops.into_iter().rev() {
+        if !trusted_proxies.contains(&client) {
+            break;
+        }
+        match hop.trim().parse() {
+            Ok(ip) => client = ip,
+            Err(_) => break,
+        }
+    }
+    client
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_forwarded_for_only_from_trusted_proxies() {
+        let proxy: IpAddr = "10.0.0.1".parse().unwrap();
+        let client: IpAddr = "203.0.113.7".parse().unwrap();
+        let mut headers = HeaderMap::new();
+        headers.insert("x-forwarded-for", "198.51.100.1, 203.0.113.7".parse().unwrap());
+
+        // Sans proxy de confiance, l'en-tête est ignoré
+        assert_eq!(client_ip(proxy, &headers, &[]), proxy);
+        // Le premier saut non fiable en partant de la droite est le client, pas l'entrée forgée à gauche
+        assert_eq!(client_ip(proxy, &headers, &[proxy]), client);
+        assert_eq!(client_ip(proxy, &HeaderMap::new(), &[proxy]), proxy);
 
-        Err((StatusCode::UNAUTHORIZED, "Unauthorized".to_string()))
+        headers.insert("x-forwarded-for", "not-an-ip".parse().unwrap());
+        assert_eq!(client_ip(proxy, &headers, &[proxy]), proxy);
     }
 }
@@ -0,0 +1,205 @@
+//! Console de modération.
+//! Les modérateurs masquent ou rétablissent des posts et suspendent des comptes, les
+//! administrateurs gèrent en plus les rôles. Chaque action exige une raison et est inscrite
+//! dans le journal (`database::audit`) avec le compte qui l'a faite ; une action qui ne peut
+//! pas être inscrite est annulée. La console affiche en tête la file des posts signalés
+//! (voir `reports`).
+//! La modération des commentaires est hors périmètre : lab02 n'a pas de commentaires, seuls
+//! les posts et les comptes sont modérés. Des commentaires devront passer par cette console.
+
+use axum::{
+    extract::Json,
+    http::StatusCode,
+    response::Html,
+    Extension,
+};
+use log::{error, info};
+use serde_json::json;
+use uuid::Uuid;
+use crate::backend::csrf::CsrfToken;
+use crate::backend::middlewares::{Admin, Authorized, Moderator};
+use crate::backend::reports;
+use crate::backend::security_headers::CspNonce;
+use crate::backend::sessions;
+use crate::consts;
+use crate::database::{audit::{self, Action}, post, user::{self, Role}};
+use crate::utils::input::normalize_email;
+use crate::HBS;
+
+type Rejection = (StatusCode, &'static str);
+
+/// Indique si le compte a été suspendu par la modération
+pub fn is_suspended(email: &str) -> bool {
+    user::get(email).is_some_and(|user| user.suspended)
+}
+
+/// Raison de l'action, obligatoire
+pub(crate) fn reason(payload: &serde_json::Value) -> Result<&str, Rejection> {
+    let reason = payload
+        .get("reason")
+        .and_then(|value| value.as_str())
+        .map(str::trim)
+        .unwrap_or_default();
+    if reason.is_empty() {
+        return Err((StatusCode::BAD_REQUEST, "A reason is required"));
+    }
+    if reason.chars().count() > consts::MODERATION_REASON_MAX_LEN {
+        return Err((StatusCode::BAD_REQUEST, "Reason is too long"));
+    }
+    Ok(reason)
+}
+
+fn flag(payload: &serde_json::Value, key: &str) -> Result<bool, Rejection> {
+    payload.get(key).and_then(|value| value.as_bool()).ok_or((StatusCode::BAD_REQUEST, "Invalid flag"))
+}
+
+/// Inscrit l'action dans le journal
+fn record(actor: &str, action: Action, target: &str, detail: Option<&str>, reason: &str) -> Result<(), Rejection> {
+    audit::record(actor, action, target, detail, reason).map_err(|e| {
+        error!("Failed to record moderation action {:?} on {}: {}", action, target, e);
+        (StatusCode::INTERNAL_SERVER_ERROR, "Failed to record the action")
+    })?;
+    info!("{} applied {:?} to {}: {}", actor, action, target, reason);
+    Ok(())
+}
+
+/// Affiche les posts, les comptes et le journal de modération
+pub async fn moderation_page(
+    auth: Authorized<Moderator>,
+    Extension(nonce): Extension<CspNonce>,
+    CsrfToken(csrf_token): CsrfToken,
+) -> axum::response::Result<Html<String>> {
+    let posts: Vec<_> = post::all()
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to read posts"))?
+        .into_iter()
+        .map(|post| {
+            let mut value = json!(post);
+            value["likes"] = json!(user::likes(post.id));
+            value
+        })
+        .collect();
+    let users: Vec<_> = user::all()
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to read users"))?
+        .into_iter()
+        .map(|user| json!({
+            "email": user.email,
+            "name": format!("{} {}", user.first_name, user.last_name),
+            "role": user.role.as_str(),
+            "suspended": user.suspended,
+            "is_self": user.email == auth.email,
+        }))
+        .collect();
+    let reports = reports::queue().map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to read reports"))?;
+    let entries = audit::recent(consts::MODERATION_AUDIT_PAGE_SIZE)
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to read the audit log"))?;
+
+    let data = json!({
+        "reports": reports,
+        "posts": posts,
+        "users": users,
+        "audit": entries,
+        "is_admin": auth.role == Role::Admin,
+        "roles": Role::ALL.map(Role::as_str),
+        "csp_nonce": nonce.0,
+        "csrf_token": csrf_token,
+    });
+    HBS.render("moderation", &data)
+        .map(Html)
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Internal server error while rendering the page").into())
+}
+
+/// Masque ou rétablit un post
+pub async fn moderate_post(auth: Authorized<Moderator>, Json(payload): Json<serde_json::Value>) -> axum::response::Result<StatusCode> {
+    let post_id = payload
+        .get("post_id")
+        .and_then(|value| value.as_str())
+        .and_then(|value| Uuid::parse_str(value).ok())
+        .ok_or((StatusCode::BAD_REQUEST, "Invalid Post ID"))?;
+    let hidden = flag(&payload, "hidden")?;
+    let reason = reason(&payload)?;
+
+    let previous = post::update(post_id, |post| std::mem::replace(&mut post.hidden, hidden))
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to write posts"))?
+        .ok_or((StatusCode::NOT_FOUND, "Post not found"))?;
+    if previous == hidden {
+        return Ok(StatusCode::OK);
+    }
+
+    let action = if hidden { Action::HidePost } else { Action::RestorePost };
+    if let Err(rejection) = record(&auth.email, action, &post_id.to_string(), None, reason) {
+        if let Err(e) = post::update(post_id, |post| post.hidden = previous) {
+            error!("Failed to roll back {:?} on {}: {}", action, post_id, e);
+        }
+        return Err(rejection.into());
+    }
+    Ok(StatusCode::OK)
+}
+
+/// Suspend ou rétablit un compte de rôle inférieur. La suspension ferme ses sessions.
+pub async fn moderate_user(auth: Authorized<Moderator>, Json(payload): Json<serde_json::Value>) -> axum::response::Result<StatusCode> {
+    let email: &str = &payload
+        .get("email")
+        .and_then(|value| value.as_str())
+        .map(normalize_email)
+        .ok_or((StatusCode::BAD_REQUEST, "Email address is required"))?;
+    let suspended = flag(&payload, "suspended")?;
+    let reason = reason(&payload)?;
+
+    if email == auth.email {
+        return Err((StatusCode::BAD_REQUEST, "You cannot moderate your own account").into());
+    }
+    let target = user::get(email).ok_or((StatusCode::NOT_FOUND, "User not found"))?;
+    if target.role >= auth.role {
+        return Err((StatusCode::FORBIDDEN, "Insufficient role").into());
+    }
+    if target.suspended == suspended {
+        return Ok(StatusCode::OK);
+    }
+
+    user::set_suspended(email, suspended).map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to update account"))?;
+    let action = if suspended { Action::SuspendUser } else { Action::RestoreUser };
+    if let Err(rejection) = record(&auth.email, action, email, None, reason) {
+        if let Err(e) = user::set_suspended(email, !suspended) {
+            error!("Failed to roll back {:?} on {}: {}", action, email, e);
+        }
+        return Err(rejection.into());
+    }
+
+    if suspended {
+        sessions::revoke_all(email, None);
+    }
+    Ok(StatusCode::OK)
+}
+
+/// Change le rôle d'un autre compte (administrateurs uniquement)
+pub async fn set_role(auth: Authorized<Admin>, Json(payload): Json<serde_json::Value>) -> axum::response::Result<StatusCode> {
+    let email: &str = &payload
+        .get("email")
+        .and_then(|value| value.as_str())
+        .map(normalize_email)
+        .ok_or((StatusCode::BAD_REQUEST, "Email address is required"))?;
+    let role: Role = payload
+        .get("role")
+        .and_then(|value| value.as_str())
+        .and_then(|value| value.parse().ok())
+        .ok_or((StatusCode::BAD_REQUEST, "Invalid role"))?;
+    let reason = reason(&payload)?;
+
+    // Un administrateur ne peut pas se retirer ses propres droits par erreur
+    if email == auth.email {
+        return Err((StatusCode::BAD_REQUEST, "You cannot change your own role").into());
+    }
+    let target = user::get(email).ok_or((StatusCode::NOT_FOUND, "User not found"))?;
+    if target.role == role {
+        return Ok(StatusCode::OK);
+    }
+
+    user::set_role(email, role).map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to update account"))?;
+    if let Err(rejection) = record(&auth.email, Action::SetRole, email, Some(role.as_str()), reason) {
+        if let Err(e) = user::set_role(email, target.role) {
+            error!("Failed to roll back {:?} on {}: {}", Action::SetRole, email, e);
+        }
+        return Err(rejection.into());
+    }
+    Ok(StatusCode::OK)
+}
@@ -0,0 +1,198 @@
+//! Notifications : likes reçus, mentions dans un post et nouveaux abonnés.
+//! Les événements sont gardés par destinataire dans `database::notification` et affichés sur
+//! `/notifications`, avec un compteur des non lues sur `home`. Chaque compte choisit les
+//! événements repris dans un résumé quotidien par email, envoyé par
+//! `send_digests_periodically` ; aucun résumé n'est envoyé par défaut.
+//! Une notification ne doit jamais faire échouer l'action qui la cause : les erreurs sont
+//! seulement journalisées. Les nouvelles notifications sont aussi poussées sur `/live`.
+
+use std::{collections::BTreeSet, time::Duration};
+use axum::{
+    extract::Json,
+    http::StatusCode,
+    response::Html,
+    Extension,
+};
+use log::{error, info};
+use serde_json::json;
+use uuid::Uuid;
+use crate::backend::csrf::CsrfToken;
+use crate::backend::middlewares::LoggedIn;
+use crate::backend::live::{self, Update};
+use crate::backend::security_headers::CspNonce;
+use crate::config::CONFIG;
+use crate::consts;
+use crate::database::{notification::{self, Kind, Notification}, post::{self, Post}, unix_now, user};
+use crate::email::send_mail;
+use crate::utils::markdown;
+use crate::HBS;
+
+/// Longueur de l'extrait de post affiché avec une notification
+const EXCERPT_LEN: usize = 80;
+
+/// Notifie `recipient` sans faire échouer l'appelant
+pub(crate) fn notify(recipient: &str, kind: Kind, actor: &str, post_id: Option<Uuid>) {
+    match notification::add(recipient, kind, actor, post_id) {
+        Ok(true) => live::publish(Update::Notification(recipient.to_string())),
+        Ok(false) => {}
+        Err(e) => error!("Failed to notify {} of a {}: {}", recipient, kind.as_str(), e),
+    }
+}
+
+/// Notifie les comptes mentionnés dans un post qui vient d'être publié et qu'ils peuvent voir
+pub(crate) fn notify_mentions(post: &Post) {
+    let Some(author) = &post.author else {
+        return;
+    };
+    for email in markdown::mentions(&post.content) {
+        if user::get(&email).is_some() && post.in_feed_of(&email) {
+            notify(&email, Kind::Mention, author, Some(post.id));
+        }
+    }
+}
+
+/// Phrase décrivant une notification, avec le nom de son auteur
+fn describe(notification: &Notification) -> String {
+    let actor = user::get(&notification.actor)
+        .map(|user| format!("{} {}", user.first_name, user.last_name))
+        .unwrap_or_else(|| "Someone".to_string());
+    match notification.kind {
+        Kind::Like => format!("{} liked your post", actor),
+        Kind::Mention => format!("{} mentioned you in a post", actor),
+        Kind::Follow => format!("{} started following you", actor),
+    }
+}
+
+/// Début du post d'une notification, s'il existe encore et que le destinataire peut le voir
+fn excerpt(notification: &Notification, recipient: &str) -> Option<String> {
+    let post = post::get(notification.post_id?).ok()??;
+    if !post.can_view(recipient) {
+        return None;
+    }
+    let mut excerpt: String = post.content.chars().take(EXCERPT_LEN).collect();
+    if post.content.chars().count() > EXCERPT_LEN {
+        excerpt.push('…');
+    }
+    Some(excerpt)
+}
+
+/// Affiche les notifications du compte connecté, des plus récentes aux plus anciennes
+pub async fn notifications_page(
+    LoggedIn(email): LoggedIn,
+    Extension(nonce): Extension<CspNonce>,
+    CsrfToken(csrf_token): CsrfToken,
+) -> axum::response::Result<Html<String>> {
+    let inbox = notification::inbox(&email).map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to read notifications"))?;
+
+    let notifications: Vec<_> = inbox
+        .notifications
+        .iter()
+        .rev()
+        .map(|notification| json!({
+            "id": notification.id,
+            "kind": notification.kind.as_str(),
+            "actor_handle": user::get(&notification.actor).filter(user::User::is_public).map(|user| user.handle),
+            "text": describe(notification),
+            "excerpt": excerpt(notification, &email),
+            "created_at": notification.created_at,
+            "read": notification.read,
+        }))
+        .collect();
+    let preferences: Vec<_> = Kind::ALL
+        .into_iter()
+        .map(|kind| json!({ "kind": kind.as_str(), "enabled": inbox.digest.contains(&kind) }))
+        .collect();
+
+    let data = json!({
+        "notifications": notifications,
+        "unread": inbox.notifications.iter().filter(|n| !n.read).count(),
+        "preferences": preferences,
+        "csp_nonce": nonce.0,
+        "csrf_token": csrf_token,
+    });
+    HBS.render("notifications", &data)
+        .map(Html)
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Internal server error while rendering the page").into())
+}
+
+/// Marque une notification comme lue (`id`), ou toutes sans identifiant
+pub async fn mark_read(LoggedIn(email): LoggedIn, Json(payload): Json<serde_json::Value>) -> axum::response::Result<Json<serde_json::Value>> {
+    let id = match payload.get("id") {
+        None | Some(serde_json::Value::Null) => None,
+        Some(value) => Some(
+            value
+                .as_str()
+                .and_then(|value| Uuid::parse_str(value).ok())
+                .ok_or((StatusCode::BAD_REQUEST, "Invalid notification ID"))?,
+        ),
+    };
+    let marked = notification::mark_read(&email, id)
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to update notifications"))?;
+    Ok(Json(json!({ "marked": marked })))
+}
+
+/// Choisit les événements repris dans le résumé quotidien (`digest`, liste de types)
+pub async fn set_digest_preferences(LoggedIn(email): LoggedIn, Json(payload): Json<serde_json::Value>) -> axum::response::Result<StatusCode> {
+    let kinds = payload
+        .get("digest")
+        .and_then(|value| value.as_array())
+        .ok_or((StatusCode::BAD_REQUEST, "Digest preferences are required"))?
+        .iter()
+        .map(|kind| kind.as_str().and_then(|kind| kind.parse().ok()))
+        .collect::<Option<BTreeSet<Kind>>>()
+        .ok_or((StatusCode::BAD_REQUEST, "Unknown notification kind"))?;
+
+    notification::set_digest(&email, kinds)
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to update notifications"))?;
+    Ok(StatusCode::OK)
+}
+
+/// Texte du résumé envoyé à `recipient`
+fn digest_body(recipient: &str, notifications: &[Notification]) -> String {
+    let mut body = format!("You have {} new notifications:\n\n", notifications.len());
+    for notification in notifications {
+        body.push_str(&format!("- {}", describe(notification)));
+        if let Some(excerpt) = excerpt(notification, recipient) {
+            body.push_str(&format!(": \"{}\"", excerpt));
+        }
+        body.push('\n');
+    }
+    body.push_str(&format!(
+        "\nSee them at {}\nChoose which notifications are emailed to you on the same page.",
+        CONFIG.public_url.join("/notifications").map(|url| url.to_string()).unwrap_or_default()
+    ));
+    body
+}
+
+/// Envoie les résumés dus et retourne le nombre d'emails envoyés. Un compte dont l'email n'est
+/// pas vérifié n'en reçoit pas ; un résumé dont l'envoi échoue reste dû et sera retenté.
+pub(crate) fn send_digests(now: u64) -> anyhow::Result<usize> {
+    let digests = notification::due_digests(now, consts::DIGEST_PERIOD_SECS)?;
+    let mut sent = 0;
+    for (recipient, notifications) in digests {
+        if !user::get(&recipient).is_some_and(|user| user.verified) {
+            continue;
+        }
+        if let Err(e) = send_mail(&recipient, "Your lab02 notifications", &digest_body(&recipient, &notifications)) {
+            error!("Failed to send the notification digest of {}: {}", recipient, e);
+            continue;
+        }
+        let ids: Vec<Uuid> = notifications.iter().map(|n| n.id).collect();
+        notification::mark_emailed(&recipient, &ids, now)?;
+        sent += 1;
+    }
+    Ok(sent)
+}
+
+/// Tâche de fond qui envoie les résumés quotidiens
+pub async fn send_digests_periodically() {
+    let mut interval = tokio::time::interval(Duration::from_secs(consts::DIGEST_CHECK_INTERVAL_SECS));
+    loop {
+        interval.tick().await;
+        match send_digests(unix_now()) {
+            Ok(0) => {}
+            Ok(sent) => info!("{} notification digests sent", sent),
+            Err(e) => error!("Failed to send notification digests: {}", e),
+        }
+    }
+}
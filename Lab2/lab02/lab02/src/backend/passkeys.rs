@@ -0,0 +1,92 @@
+//! Remplacement de la passkey du compte connecté, après un step-up.
+//! Un compte n'a qu'une passkey : en enrôler une nouvelle révoque l'ancienne, et les autres
+//! sessions du compte sont fermées. Le changement d'email n'est pas proposé : l'email
+//! identifie le compte dans tous les stores (posts, abonnements, notifications, journal d'audit).
+
+use axum::{extract::Json, http::StatusCode};
+use log::info;
+use serde_json::json;
+use tower_sessions::Session;
+use webauthn_rs::prelude::RegisterPublicKeyCredential;
+use crate::backend::ceremonies::{Owner, REGISTRATION_STATES};
+use crate::backend::handlers_unauth::too_many_ceremonies;
+use crate::backend::middlewares::StepUp;
+use crate::backend::sessions;
+use crate::database::user;
+use crate::utils::input::valid_bool;
+use crate::utils::registration_policy::PolicyViolation;
+use crate::utils::webauthn::{begin_registration, complete_registration, CREDENTIAL_STORE};
+
+/// Clé de session contenant l'identifiant de l'enrôlement en cours
+const SESSION_PASSKEY_STATE: &str = "passkey_state";
+
+/// Début du remplacement : options d'enregistrement pour le compte connecté
+pub async fn replace_passkey_begin(
+    step_up: StepUp,
+    session: Session,
+    Json(payload): Json<serde_json::Value>,
+) -> axum::response::Result<Json<serde_json::Value>> {
+    let discoverable_flag = payload.get("discoverable");
+    let discoverable = valid_bool(discoverable_flag) && discoverable_flag.and_then(|value| value.as_bool()).unwrap_or(false);
+
+    let (public_key_options, stored_state) = begin_registration(&step_up.email, &step_up.email, discoverable)
+        .await
+        .map_err(|error| (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to begin registration: {}", error)))?;
+
+    // L'identifiant de l'état reste côté serveur, dans la session qui a démarré l'enrôlement
+    let state_id = uuid::Uuid::new_v4().to_string();
+    let server_challenge = public_key_options["challenge"].as_str().unwrap_or_default().to_string();
+    REGISTRATION_STATES
+        .insert(state_id.clone(), stored_state, server_challenge, vec![Owner::Account(step_up.email)])
+        .await
+        .map_err(too_many_ceremonies)?;
+    session
+        .insert(SESSION_PASSKEY_STATE, &state_id)
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to store registration state"))?;
+
+    Ok(Json(json!({ "publicKey": public_key_options })))
+}
+
+/// Fin du remplacement : enregistre la nouvelle passkey et ferme les autres sessions
+pub async fn replace_passkey_complete(
+    step_up: StepUp,
+    session: Session,
+    Json(payload): Json<serde_json::Value>,
+) -> axum::response::Result<StatusCode> {
+    let response: RegisterPublicKeyCredential = payload
+        .get("response")
+        .and_then(|response| serde_json::from_value(response.clone()).ok())
+        .ok_or((StatusCode::BAD_REQUEST, "Invalid response format"))?;
+
+    let Some(state_id) = session.remove::<String>(SESSION_PASSKEY_STATE).ok().flatten() else {
+        return Err((StatusCode::BAD_REQUEST, "No passkey enrolment in progress").into());
+    };
+    let stored_state = REGISTRATION_STATES
+        .take(&state_id)
+        .await
+        .ok_or((StatusCode::BAD_REQUEST, "Invalid or expired registration state"))?
+        .state;
+    if stored_state.email != step_up.email {
+        return Err((StatusCode::BAD_REQUEST, "Invalid or expired registration state").into());
+    }
+
+    complete_registration(&step_up.email, &response, &stored_state)
+        .await
+        .map_err(|error| match error.downcast_ref::<PolicyViolation>() {
+            Some(violation) => (StatusCode::FORBIDDEN, violation.to_string()),
+            None => (StatusCode::BAD_REQUEST, "Failed to complete registration".to_string()),
+        })?;
+    let passkey = CREDENTIAL_STORE
+        .read()
+        .await
+        .get(&step_up.email)
+        .cloned()
+        .ok_or((StatusCode::INTERNAL_SERVER_ERROR, "Failed to set passkey"))?;
+    user::set_passkey(&step_up.email, passkey).map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to set passkey"))?;
+
+    // Les sessions ouvertes avec l'ancienne passkey ne restent pas ouvertes
+    sessions::revoke_all(&step_up.email, sessions::registry_id(&session).as_deref());
+
+    info!("Passkey replaced for {}", step_up.email);
+    Ok(StatusCode::OK)
+}
@@ -0,0 +1,368 @@
+//! Limitation de débit des routes publiques.
+//! Chaque route listée dans `POLICIES` possède un seau à jetons par adresse IP et,
+//! si la requête désigne un compte (champ `email` du corps JSON), un seau par compte.
+//! Les routes qui vérifient un secret (fin de cérémonie WebAuthn, code de récupération)
+//! appliquent en plus un verrouillage progressif : au-delà de quelques échecs, chaque nouvel échec double le délai d'attente.
+//! Une requête limitée reçoit `429` avec `Retry-After`. Le nombre d'entrées en mémoire est borné :
+//! une fois plein, l'entrée qui expire le plus tôt est oubliée, trouvée par un index ordonné.
+
+use std::{
+    collections::{BTreeSet, HashMap},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+use axum::{
+    body::{to_bytes, Body},
+    extract::{MatchedPath, Request},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use http::{header, Method, StatusCode};
+use log::warn;
+use once_cell::sync::Lazy;
+use crate::backend::middlewares::ClientIp;
+use crate::config::CONFIG;
+use crate::utils::input::normalize_email;
+use crate::{consts, metrics};
+
+/// Seau à jetons : `burst` requêtes d'affilée, puis une requête toutes les `refill_every`
+#[derive(Clone, Copy)]
+pub struct Quota {
+    burst: u32,
+    refill_every: Duration,
+}
+
+impl Quota {
+    const fn new(burst: u32, refill_every_secs: u64) -> Self {
+        Quota { burst, refill_every: Duration::from_secs(refill_every_secs) }
+    }
+}
+
+/// Limites appliquées aux requêtes non-GET d'une route
+pub struct RoutePolicy {
+    /// Motif de la route axum
+    route: &'static str,
+    per_ip: Quota,
+    per_account: Option<Quota>,
+    /// Verrouillage progressif après des échecs répétés (réponses 4xx)
+    lockout: bool,
+}
+
+/// Limites par route
+const POLICIES: &[RoutePolicy] = &[
+    RoutePolicy { route: "/register", per_ip: Quota::new(10, 60), per_account: Some(Quota::new(3, 600)), lockout: false },
+    RoutePolicy { route: "/register/complete", per_ip: Quota::new(10, 60), per_account: Some(Quota::new(5, 300)), lockout: true },
+    RoutePolicy { route: "/login", per_ip: Quota::new(20, 6), per_account: Some(Quota::new(10, 60)), lockout: false },
+    RoutePolicy { route: "/login/discoverable", per_ip: Quota::new(20, 6), per_account: None, lockout: false },
+    RoutePolicy { route: "/login/complete", per_ip: Quota::new(20, 6), per_account: None, lockout: true },
+    RoutePolicy { route: "/login/magic", per_ip: Quota::new(5, 60), per_account: Some(Quota::new(3, 900)), lockout: false },
+    RoutePolicy { route: "/recover", per_ip: Quota::new(5, 60), per_account: Some(Quota::new(3, 900)), lockout: false },
+    RoutePolicy { route: "/recover/code", per_ip: Quota::new(5, 60), per_account: Some(Quota::new(5, 900)), lockout: true },
+    RoutePolicy { route: "/verify-email/resend", per_ip: Quota::new(5, 60), per_account: Some(Quota::new(3, 900)), lockout: false },
+    RoutePolicy { route: "/reauth/complete", per_ip: Quota::new(10, 30), per_account: None, lockout: true },
+];
+
+/// Échecs tolérés avant le premier délai
+const FREE_FAILURES: u32 = 3;
+/// Premier délai de verrouillage, doublé à chaque échec suivant
+const LOCKOUT_BASE: Duration = Duration::from_secs(1);
+const LOCKOUT_MAX: Duration = Duration::from_secs(15 * 60);
+/// Sans nouvel échec pendant cette durée, le compteur est oublié
+const FAILURE_MEMORY: Duration = Duration::from_secs(60 * 60);
+
+static LIMITER: Lazy<Limiter> = Lazy::new(|| Limiter::new(consts::RATE_LIMIT_MAX_ENTRIES));
+
+struct Entry {
+    tokens: f64,
+    updated: Instant,
+    failures: u32,
+    locked_until: Option<Instant>,
+    /// Date à partir de laquelle l'entrée n'a plus d'effet et peut être oubliée
+    expires: Instant,
+}
+
+impl Entry {
+    fn new(now: Instant) -> Self {
+        Entry { tokens: 0.0, updated: now, failures: 0, locked_until: None, expires: now }
+    }
+}
+
+/// Entrées indexées par clé et par date d'expiration
+struct Table {
+    max_entries: usize,
+    entries: HashMap<String, Entry>,
+    by_expiry: BTreeSet<(Instant, String)>,
+}
+
+impl Table {
+    /// Entrée de `key`, créée au besoin après avoir libéré une place
+    fn entry(&mut self, key: &str, new: impl FnOnce() -> Entry) -> &mut Entry {
+        if !self.entries.contains_key(key) {
+            self.make_room();
+            let entry = new();
+            self.by_expiry.insert((entry.expires, key.to_string()));
+            self.entries.insert(key.to_string(), entry);
+        }
+        self.entries.get_mut(key).expect("entry inserted above")
+    }
+
+    fn set_expires(&mut self, key: &str, expires: Instant) {
+        if let Some(entry) = self.entries.get_mut(key) {
+            self.by_expiry.remove(&(entry.expires, key.to_string()));
+            entry.expires = expires;
+            self.by_expiry.insert((expires, key.to_string()));
+        }
+    }
+
+    fn remove(&mut self, key: &str) {
+        if let Some(entry) = self.entries.remove(key) {
+            self.by_expiry.remove(&(entry.expires, key.to_string()));
+        }
+    }
+
+    /// Libère une place en oubliant l'entrée qui expire le plus tôt (expirée en priorité)
+    fn make_room(&mut self) {
+        if self.entries.len() < self.max_entries {
+            return;
+        }
+        if let Some((_, key)) = self.by_expiry.pop_first() {
+            self.entries.remove(&key);
+        }
+    }
+}
+
+struct Limiter {
+    table: Mutex<Table>,
+}
+
+impl Limiter {
+    fn new(max_entries: usize) -> Self {
+        Limiter { table: Mutex::new(Table { max_entries, entries: HashMap::new(), by_expiry: BTreeSet::new() }) }
+    }
+
+    /// Consomme un jeton ; retourne le délai avant le prochain jeton si le seau est vide
+    fn check(&self, key: &str, quota: Quota, now: Instant) -> Result<(), Duration> {
+        let mut table = self.table.lock().unwrap_or_else(|e| e.into_inner());
+        let burst = f64::from(quota.burst);
+        let refill = quota.refill_every.as_secs_f64();
+
+        let entry = table.entry(key, || {
+            let mut entry = Entry::new(now);
+            entry.tokens = burst;
+            entry
+        });
+
+        let elapsed = now.saturating_duration_since(entry.updated).as_secs_f64();
+        entry.tokens = (entry.tokens + elapsed / refill).min(burst);
+        entry.updated = now;
+
+        if entry.tokens < 1.0 {
+            return Err(Duration::from_secs_f64((1.0 - entry.tokens) * refill));
+        }
+        entry.tokens -= 1.0;
+        let expires = now + Duration::from_secs_f64((burst - entry.tokens) * refill);
+        table.set_expires(key, expires);
+        Ok(())
+    }
+
+    /// Délai restant si la clé est verrouillée
+    fn locked(&self, key: &str, now: Instant) -> Option<Duration> {
+        let table = self.table.lock().unwrap_or_else(|e| e.into_inner());
+        let until = table.entries.get(key)?.locked_until?;
+        (until > now).then(|| until - now)
+    }
+
+    fn record_failure(&self, key: &str, now: Instant) {
+        let mut table = self.table.lock().unwrap_or_else(|e| e.into_inner());
+        let entry = table.entry(key, || Entry::new(now));
+
+        entry.failures += 1;
+        if entry.failures > FREE_FAILURES {
+            let exponent = (entry.failures - FREE_FAILURES - 1).min(16);
+            let delay = LOCKOUT_BASE.saturating_mul(1 << exponent).min(LOCKOUT_MAX);
+            entry.locked_until = Some(now + delay);
+        }
+        table.set_expires(key, now + FAILURE_MEMORY);
+    }
+
+    fn record_success(&self, key: &str) {
+        self.table.lock().unwrap_or_else(|e| e.into_inner()).remove(key);
+    }
+}
+
+/// Compte visé par la requête, lu dans le champ `email` du corps JSON
+fn account_of(body: &[u8]) -> Option<String> {
+    let payload: serde_json::Value = serde_json::from_slice(body).ok()?;
+    let email = normalize_email(payload.get("email")?.as_str()?);
+    (!email.is_empty()).then_some(email)
+}
+
+fn too_many_requests(route: &str, retry_after: Duration) -> Response {
+    metrics::record_rate_limited(route);
+    // Arrondi à la seconde supérieure, au moins une seconde
+    let seconds = retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0);
+    (
+        StatusCode::TOO_MANY_REQUESTS,
+        [(header::RETRY_AFTER, seconds.max(1).to_string())],
+        "Too many requests, please try again later",
+    )
+        .into_response()
+}
+
+/// Middleware appliquant `POLICIES` selon la route de la requête
+pub async fn rate_limit(ClientIp(ip): ClientIp, request: Request, next: Next) -> Response {
+    let policy = request
+        .extensions()
+        .get::<MatchedPath>()
+        .and_then(|path| POLICIES.iter().find(|policy| policy.route == path.as_str()));
+    let Some(policy) = policy else {
+        return next.run(request).await;
+    };
+    if !CONFIG.rate_limit || matches!(*request.method(), Method::GET | Method::HEAD) {
+        return next.run(request).await;
+    }
+
+    // Le corps est lu pour trouver le compte visé, puis rendu au handler
+    let mut account = None;
+    let request = if policy.per_account.is_some() {
+        let (parts, body) = request.into_parts();
+        let Ok(bytes) = to_bytes(body, consts::RATE_LIMIT_BODY_LIMIT).await else {
+            return (StatusCode::PAYLOAD_TOO_LARGE, "Request body too large").into_response();
+        };
+        account = account_of(&bytes);
+        Request::from_parts(parts, Body::from(bytes))
+    } else {
+        request
+    };
+
+    // Adresse IP inconnue (transport sans adresse) : seul le compte est limité
+    let mut identities = Vec::new();
+    if let Some(ip) = ip {
+        identities.push((format!("ip:{}", ip), policy.per_ip));
+    }
+    if let (Some(account), Some(quota)) = (account, policy.per_account) {
+        identities.push((format!("account:{}", account), quota));
+    }
+
+    let now = Instant::now();
+    let failure_key = |identity: &str| format!("{}:failures:{}", policy.route, identity);
+    if policy.lockout {
+        for (identity, _) in &identities {
+            if let Some(retry_after) = LIMITER.locked(&failure_key(identity), now) {
+                warn!("Rate limit: {} locked out of {} after repeated failures", identity, policy.route);
+                return too_many_requests(policy.route, retry_after);
+            }
+        }
+    }
+    for (identity, quota) in &identities {
+        if let Err(retry_after) = LIMITER.check(&format!("{}:{}", policy.route, identity), *quota, now) {
+            warn!("Rate limit: {} exceeded the quota of {}", identity, policy.route);
+            return too_many_requests(policy.route, retry_after);
+        }
+    }
+
+    let response = next.run(request).await;
+
+    if policy.lockout {
+        let status = response.status();
+        for (identity, _) in &identities {
+            if status.is_client_error() {
+                LIMITER.record_failure(&failure_key(identity), Instant::now());
+            } else if status.is_success() || status.is_redirection() {
+                LIMITER.record_success(&failure_key(identity));
+            }
+        }
+    }
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::SocketAddr;
+    use axum::{extract::connect_info::MockConnectInfo, routing::post, Router};
+    use tower::ServiceExt;
+
+    #[test]
+    fn test_bucket_refills_over_time() {
+        let limiter = Limiter::new(10);
+        let quota = Quota::new(2, 10);
+        let start = Instant::now();
+
+        assert!(limiter.check("key", quota, start).is_ok());
+        assert!(limiter.check("key", quota, start).is_ok());
+        let retry_after = limiter.check("key", quota, start).unwrap_err();
+        assert_eq!(retry_after, Duration::from_secs(10));
+
+        assert!(limiter.check("key", quota, start + Duration::from_secs(10)).is_ok());
+        assert!(limiter.check("key", quota, start + Duration::from_secs(10)).is_err());
+    }
+
+    #[test]
+    fn test_failures_lock_out_progressively() {
+        let limiter = Limiter::new(10);
+        let now = Instant::now();
+
+        for _ in 0..FREE_FAILURES {
+            limiter.record_failure("key", now);
+        }
+        assert!(limiter.locked("key", now).is_none());
+
+        limiter.record_failure("key", now);
+        assert_eq!(limiter.locked("key", now), Some(LOCKOUT_BASE));
+        limiter.record_failure("key", now);
+        assert_eq!(limiter.locked("key", now), Some(LOCKOUT_BASE * 2));
+
+        limiter.record_success("key");
+        assert!(limiter.locked("key", now).is_none());
+    }
+
+    #[test]
+    fn test_entries_are_bounded() {
+        let limiter = Limiter::new(3);
+        let quota = Quota::new(1, 60);
+        let now = Instant::now();
+
+        for key in ["a", "b", "c", "d", "e"] {
+            limiter.check(key, quota, now).unwrap();
+        }
+        let table = limiter.table.lock().unwrap();
+        assert_eq!(table.entries.len(), 3);
+        assert_eq!(table.by_expiry.len(), 3);
+    }
+
+    #[test]
+    fn test_earliest_expiry_is_evicted_first() {
+        let limiter = Limiter::new(2);
+        let now = Instant::now();
+
+        limiter.check("short", Quota::new(1, 10), now).unwrap();
+        limiter.check("long", Quota::new(1, 600), now).unwrap();
+        limiter.check("new", Quota::new(1, 60), now).unwrap();
+
+        let table = limiter.table.lock().unwrap();
+        assert!(!table.entries.contains_key("short"));
+        assert!(table.entries.contains_key("long") && table.entries.contains_key("new"));
+    }
+
+    #[tokio::test]
+    async fn test_repeated_failures_get_retry_after() {
+        let app = Router::new()
+            .route("/login/complete", post(|| async { StatusCode::UNAUTHORIZED }))
+            .layer(axum::middleware::from_fn(rate_limit))
+            .layer(MockConnectInfo(SocketAddr::from(([203, 0, 113, 7], 4000))));
+        let attempt = || async {
+            app.clone()
+                .oneshot(Request::post("/login/complete").body(Body::empty()).unwrap())
+                .await
+                .unwrap()
+        };
+
+        for _ in 0..=FREE_FAILURES {
+            assert_eq!(attempt().await.status(), StatusCode::UNAUTHORIZED);
+        }
+        let response = attempt().await;
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(response.headers()[header::RETRY_AFTER], "1");
+    }
+}
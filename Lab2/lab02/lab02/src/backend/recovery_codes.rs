@@ -0,0 +1,162 @@
+//! Codes de récupération à usage unique, alternative au lien envoyé par email.
+//! Les codes sont générés à l'inscription (sur demande) ou depuis le compte après un step-up,
+//! affichés une seule fois et stockés sous forme d'empreinte SHA-256 : ils sont tirés
+//! aléatoirement avec assez d'entropie pour qu'un hachage lent soit inutile.
+//! Un code valide accorde à la session l'enrôlement d'une nouvelle passkey, comme le lien
+//! de récupération, et n'est plus utilisable ensuite.
+
+use axum::{
+    extract::Json,
+    http::StatusCode,
+    response::Html,
+    Extension,
+};
+use anyhow::{anyhow, Result};
+use log::{error, info};
+use ring::{digest, rand::{SecureRandom, SystemRandom}};
+use serde_json::json;
+use tower_sessions::Session;
+use crate::backend::csrf::CsrfToken;
+use crate::backend::handlers_unauth::grant_recovery;
+use crate::backend::middlewares::{LoggedIn, StepUp};
+use crate::backend::security_headers::CspNonce;
+use crate::consts;
+use crate::database::user;
+use crate::email::send_mail;
+use crate::utils::input::normalize_email;
+use crate::HBS;
+
+/// Alphabet base32 de Crockford : sans I, L, O ni U pour éviter les confusions à la saisie
+const ALPHABET: &[u8; 32] = b"0123456789abcdefghjkmnpqrstvwxyz";
+/// Nombre de caractères par groupe dans un code affiché
+const GROUP_LEN: usize = 4;
+
+/// Tire un nouveau code, affiché par groupes séparés par des tirets
+fn random_code(rng: &SystemRandom) -> Result<String> {
+    let mut bytes = [0u8; consts::RECOVERY_CODE_LEN];
+    rng.fill(&mut bytes).map_err(|_| anyhow!("System random generator unavailable"))?;
+    let chars: Vec<char> = bytes.iter().map(|byte| char::from(ALPHABET[usize::from(byte % 32)])).collect();
+    Ok(chars.chunks(GROUP_LEN).map(|group| group.iter().collect::<String>()).collect::<Vec<_>>().join("-"))
+}
+
+/// Forme canonique d'un code saisi : casse, tirets et espaces sont ignorés
+fn normalize(code: &str) -> String {
+    code.chars().filter(|c| c.is_ascii_alphanumeric()).map(|c| c.to_ascii_lowercase()).collect()
+}
+
+/// Empreinte stockée pour un code
+fn hash(code: &str) -> String {
+    digest::digest(&digest::SHA256, normalize(code).as_bytes())
+        .as_ref()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+/// Génère une nouvelle série de codes pour le compte et invalide la précédente
+pub fn issue(email: &str) -> Result<Vec<String>> {
+    let rng = SystemRandom::new();
+    let codes = (0..consts::RECOVERY_CODE_COUNT)
+        .map(|_| random_code(&rng))
+        .collect::<Result<Vec<_>>>()?;
+    user::set_recovery_codes(email, codes.iter().map(|code| hash(code)).collect())?;
+    info!("Recovery codes issued for {}", email);
+    Ok(codes)
+}
+
+/// Consomme un code du compte ; retourne `false` s'il n'est pas valable
+pub fn redeem(email: &str, code: &str) -> Result<bool> {
+    if normalize(code).len() != consts::RECOVERY_CODE_LEN {
+        return Ok(false);
+    }
+    user::redeem_recovery_code(email, &hash(code))
+}
+
+/// Affiche le nombre de codes restants et permet d'en générer de nouveaux
+pub async fn recovery_codes_page(
+    LoggedIn(email): LoggedIn,
+    Extension(nonce): Extension<CspNonce>,
+    CsrfToken(csrf_token): CsrfToken,
+) -> axum::response::Result<Html<String>> {
+    let remaining = user::get(&email)
+        .map(|user| user.recovery_codes.len())
+        .ok_or((StatusCode::UNAUTHORIZED, "Unauthorized"))?;
+
+    let data = json!({
+        "remaining": remaining,
+        "csp_nonce": nonce.0,
+        "csrf_token": csrf_token,
+    });
+    HBS.render("recovery_codes", &data)
+        .map(Html)
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Internal server error while rendering the page").into())
+}
+
+/// Génère une nouvelle série de codes (step-up requis)
+pub async fn regenerate_recovery_codes(step_up: StepUp) -> axum::response::Result<Json<serde_json::Value>> {
+    let codes = issue(&step_up.email).map_err(|e| {
+        error!("Failed to issue recovery codes for {}: {}", step_up.email, e);
+        (StatusCode::INTERNAL_SERVER_ERROR, "Failed to generate recovery codes")
+    })?;
+    Ok(Json(json!({ "recovery_codes": codes })))
+}
+
+/// Échange un code contre l'enrôlement d'une nouvelle passkey pour ce compte
+pub async fn redeem_recovery_code(session: Session, Json(payload): Json<serde_json::Value>) -> axum::response::Result<Json<serde_json::Value>> {
+    let email: &str = &payload
+        .get("email")
+        .and_then(|value| value.as_str())
+        .map(normalize_email)
+        .ok_or((StatusCode::BAD_REQUEST, "Email address is required"))?;
+    let code = payload
+        .get("code")
+        .and_then(|value| value.as_str())
+        .ok_or((StatusCode::BAD_REQUEST, "Recovery code is required"))?;
+
+    // Même réponse pour un compte inconnu et un code invalide
+    let redeemed = redeem(email, code).map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to read users"))?;
+    if !redeemed {
+        return Err((StatusCode::BAD_REQUEST, "Invalid email or recovery code").into());
+    }
+
+    let redirect = grant_recovery(&session, email)
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to store recovery state"))?;
+
+    // Le titulaire est prévenu : un code utilisé à son insu signale une fuite
+    let remaining = user::get(email).map(|user| user.recovery_codes.len()).unwrap_or_default();
+    if let Err(e) = send_mail(
+        email,
+        "Recovery code used",
+        &format!("A recovery code was used to enrol a new passkey on your account. {} codes remain.", remaining),
+    ) {
+        error!("Failed to notify {} of a recovery code use: {}", email, e);
+    }
+
+    Ok(Json(json!({ "redirect": redirect })))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_codes_are_hashed_and_single_use() {
+        let email = format!("{}@example.com", uuid::Uuid::new_v4());
+        user::create(&email, "Alice", "Martin").unwrap();
+
+        let codes = issue(&email).unwrap();
+        assert_eq!(codes.len(), consts::RECOVERY_CODE_COUNT);
+        let stored = user::get(&email).unwrap().recovery_codes;
+        assert!(codes.iter().all(|code| !stored.contains(code)));
+
+        // La saisie tolère majuscules et espaces, mais un code ne sert qu'une fois
+        let typed = codes[0].to_uppercase().replace('-', " ");
+        assert!(redeem(&email, &typed).unwrap());
+        assert!(!redeem(&email, &codes[0]).unwrap());
+        assert_eq!(user::get(&email).unwrap().recovery_codes.len(), consts::RECOVERY_CODE_COUNT - 1);
+
+        // Une nouvelle série invalide l'ancienne
+        issue(&email).unwrap();
+        assert!(!redeem(&email, &codes[1]).unwrap());
+    }
+}
@@ -0,0 +1,164 @@
+//! Signalement des posts et file de modération.
+//! Un compte ne signale un post qu'une fois, même après la décision de la modération. Au-delà de
+//! `CONFIG.report_hide_threshold` signalements ouverts, le post est masqué en attendant qu'un
+//! modérateur tranche : retrait (le post reste masqué) ou rejet (le post redevient visible).
+//! Les auteurs des signalements sont prévenus de la décision.
+
+use std::collections::HashMap;
+use axum::{extract::Json, http::StatusCode};
+use log::{error, info};
+use serde_json::json;
+use uuid::Uuid;
+use crate::backend::middlewares::{Authorized, LoggedIn, Moderator};
+use crate::backend::moderation;
+use crate::config::CONFIG;
+use crate::consts;
+use crate::database::{audit::{self, Action}, post, report::{self, Category, Outcome}};
+use crate::email::send_mail;
+
+/// Auteur inscrit dans le journal pour les masquages automatiques
+const AUTO_MODERATOR: &str = "auto-moderation";
+
+/// Signale un post du fil
+pub async fn report_post(LoggedIn(reporter): LoggedIn, Json(payload): Json<serde_json::Value>) -> axum::response::Result<StatusCode> {
+    let post_id = payload
+        .get("post_id")
+        .and_then(|value| value.as_str())
+        .and_then(|value| Uuid::parse_str(value).ok())
+        .ok_or((StatusCode::BAD_REQUEST, "Invalid Post ID"))?;
+    let category: Category = payload
+        .get("category")
+        .and_then(|value| value.as_str())
+        .and_then(|value| value.parse().ok())
+        .ok_or((StatusCode::BAD_REQUEST, "Invalid report category"))?;
+    let details = payload.get("details").and_then(|value| value.as_str()).unwrap_or_default().trim();
+    if details.chars().count() > consts::REPORT_DETAILS_MAX_LEN {
+        return Err((StatusCode::BAD_REQUEST, "Report details are too long").into());
+    }
+
+    // Seuls les posts visibles du fil peuvent être signalés
+    let post = post::get(post_id).map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to read posts"))?;
+    if !post.is_some_and(|post| post.in_feed_of(&reporter)) {
+        return Err((StatusCode::NOT_FOUND, "Post not found").into());
+    }
+
+    let open_reports = report::add(post_id, &reporter, category, details)
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to store the report"))?;
+    // Un compte qui a déjà signalé ce post ne compte pas de nouveau
+    let Some(open_reports) = open_reports else {
+        return Ok(StatusCode::OK);
+    };
+
+    if CONFIG.report_hide_threshold > 0 && open_reports >= CONFIG.report_hide_threshold {
+        auto_hide(post_id, open_reports);
+    }
+    Ok(StatusCode::OK)
+}
+
+/// Masque un post trop signalé en attendant la décision d'un modérateur
+fn auto_hide(post_id: Uuid, open_reports: usize) {
+    let hidden = post::update(post_id, |post| !std::mem::replace(&mut post.hidden, true));
+    match hidden {
+        Ok(Some(true)) => {
+            let reason = format!("{} open reports, pending review", open_reports);
+            if let Err(e) = audit::record(AUTO_MODERATOR, Action::HidePost, &post_id.to_string(), None, &reason) {
+                error!("Failed to record the automatic hiding of {}: {}", post_id, e);
+            }
+            info!("Post {} hidden after {} reports", post_id, open_reports);
+        }
+        Ok(_) => {}
+        Err(e) => error!("Failed to hide reported post {}: {}", post_id, e),
+    }
+}
+
+/// File de modération : posts signalés, du plus signalé au moins signalé
+pub fn queue() -> anyhow::Result<Vec<serde_json::Value>> {
+    let mut by_post: HashMap<Uuid, Vec<report::Report>> = HashMap::new();
+    for report in report::open()? {
+        by_post.entry(report.post_id).or_default().push(report);
+    }
+
+    let mut queue: Vec<_> = post::all()?
+        .into_iter()
+        .filter_map(|post| by_post.remove(&post.id).map(|reports| (post, reports)))
+        .collect();
+    queue.sort_by(|(a, a_reports), (b, b_reports)| {
+        b_reports.len().cmp(&a_reports.len()).then_with(|| a.id.cmp(&b.id))
+    });
+
+    Ok(queue
+        .into_iter()
+        .map(|(post, reports)| {
+            let mut categories: Vec<(&str, usize)> = vec![];
+            for category in Category::ALL {
+                let count = reports.iter().filter(|report| report.category == category).count();
+                if count > 0 {
+                    categories.push((category.as_str(), count));
+                }
+            }
+            json!({
+                "post_id": post.id,
+                "content": post.content,
+                "hidden": post.hidden,
+                "count": reports.len(),
+                "categories": categories
+                    .iter()
+                    .map(|(category, count)| format!("{} ×{}", category, count))
+                    .collect::<Vec<_>>()
+                    .join(", "),
+                "details": reports
+                    .iter()
+                    .filter(|report| !report.details.is_empty())
+                    .map(|report| &report.details)
+                    .collect::<Vec<_>>(),
+            })
+        })
+        .collect())
+}
+
+/// Tranche les signalements ouverts d'un post et prévient leurs auteurs
+pub async fn resolve_reports(auth: Authorized<Moderator>, Json(payload): Json<serde_json::Value>) -> axum::response::Result<StatusCode> {
+    let post_id = payload
+        .get("post_id")
+        .and_then(|value| value.as_str())
+        .and_then(|value| Uuid::parse_str(value).ok())
+        .ok_or((StatusCode::BAD_REQUEST, "Invalid Post ID"))?;
+    let outcome = match payload.get("outcome").and_then(|value| value.as_str()) {
+        Some("removed") => Outcome::Removed,
+        Some("dismissed") => Outcome::Dismissed,
+        _ => return Err((StatusCode::BAD_REQUEST, "Invalid outcome").into()),
+    };
+    let reason = moderation::reason(&payload)?;
+
+    // Le post est vérifié avant de clôturer ses signalements
+    post::get(post_id)
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to read posts"))?
+        .ok_or((StatusCode::NOT_FOUND, "Post not found"))?;
+    let reporters = report::resolve(post_id, outcome)
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to write reports"))?;
+    if reporters.is_empty() {
+        return Err((StatusCode::NOT_FOUND, "No open report on this post").into());
+    }
+    post::update(post_id, |post| post.hidden = outcome == Outcome::Removed)
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to write posts"))?
+        .ok_or((StatusCode::NOT_FOUND, "Post not found"))?;
+
+    let detail = match outcome {
+        Outcome::Removed => "removed",
+        Outcome::Dismissed => "dismissed",
+    };
+    audit::record(&auth.email, Action::ResolveReports, &post_id.to_string(), Some(detail), reason)
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to record the action"))?;
+
+    let decision = match outcome {
+        Outcome::Removed => "The post has been removed.",
+        Outcome::Dismissed => "The post does not break the rules and stays visible.",
+    };
+    for reporter in reporters {
+        let body = format!("Thank you for your report. A moderator reviewed it. {}", decision);
+        if let Err(e) = send_mail(&reporter, "Your report was reviewed", &body) {
+            error!("Failed to notify {} of a report resolution: {}", reporter, e);
+        }
+    }
+    Ok(StatusCode::OK)
+}
@@ -3,32 +3,46 @@
 
 use axum::{Router, routing::{get, post}, BoxError};
 use axum::error_handling::HandleErrorLayer;
-use http::StatusCode;
-use tower_sessions::{SessionManagerLayer, MemoryStore};
-use tower_http::cors::{Any, CorsLayer};
+use http::{header, HeaderValue, Method, StatusCode};
+use log::warn;
+use tower_sessions::SessionManagerLayer;
+use tower_http::cors::{AllowOrigin, Any, CorsLayer};
 use tower::{ServiceBuilder};
+use crate::config::CONFIG;
+use crate::backend::csrf::verify_csrf;
+use crate::backend::drafts::{drafts_page, publish_post};
+use crate::backend::follows::{follow_user, follows_page, profile_page};
+use crate::backend::live::live_feed;
+use crate::backend::magic_link::{open_magic_link, request_magic_link, set_magic_link};
+use crate::backend::media::post_image;
+use crate::backend::moderation::{moderate_post, moderate_user, moderation_page, set_role};
+use crate::backend::notifications::{mark_read, notifications_page, set_digest_preferences};
+use crate::backend::bookmarks::{bookmark_post, saved_page};
+use crate::backend::rate_limit::rate_limit;
+use crate::backend::reports::{report_post, resolve_reports};
+use crate::backend::search::{search_page, search_results};
+use crate::backend::passkeys::{replace_passkey_begin, replace_passkey_complete};
+use crate::backend::recovery_codes::{recovery_codes_page, redeem_recovery_code, regenerate_recovery_codes};
+use crate::backend::security_headers::security_headers;
+use crate::backend::session_store::{authenticated_expiry, SESSION_STORE};
+use crate::backend::sessions::{revoke_other_sessions, revoke_session, sessions_page, track_sessions};
 use crate::backend::handlers_unauth::{
-    register_begin, register_complete, login_begin, login_complete,
+    register_begin, register_complete, login_begin, login_discoverable_begin, login_complete,
     index, login_page, register_page, validate_account, logout,
     recover_page, recover_account, reset_account,
 };
-use crate::backend::handlers_auth::{create_post, home, like_post};
+use crate::backend::handlers_auth::{create_post, delete_account, home, like_post, list_posts, tag_page};
+use crate::backend::step_up::{step_up_begin, step_up_complete};
+use crate::backend::verification::{resend_verification, verify_email_page};
+use crate::backend::handlers_health::{healthz, metrics_endpoint, readyz};
+use crate::metrics::track_requests;
 
 /// Initialisation du routeur principal et des middlewares
 pub fn get_router() -> Router {
-    // Configuration CORS pour permettre les requêtes de n'importe quelle origine (en mode debug uniquement)
-    let router = if cfg!(debug_assertions) {
-        let cors = CorsLayer::new()
-            .allow_methods(tower_http::cors::AllowMethods::any())
-            .allow_origin(Any);
-        Router::new().layer(cors)
-    } else {
-        Router::new()
-    };
-
-    // Configuration des sessions en mémoire
-    let store = MemoryStore::default(); // Initialisation du MemoryStore
-    let session_manager = SessionManagerLayer::new(store).with_http_only(true);
+    // Sessions en mémoire, expirées après inactivité (voir `session_store`)
+    let session_manager = SessionManagerLayer::new(SESSION_STORE.clone())
+        .with_http_only(true)
+        .with_expiry(authenticated_expiry());
 
     let service = ServiceBuilder::new()
         .layer(HandleErrorLayer::new(|_e: BoxError| async move {
@@ -36,10 +50,53 @@ pub fn get_router() -> Router {
         }))
         .layer(session_manager);
 
-    router
+    Router::new()
         .merge(unauth_routes())
         .merge(auth_routes())
+        .layer(axum::middleware::from_fn(verify_csrf))
+        .layer(axum::middleware::from_fn(track_sessions))
         .layer(service)
+        .layer(axum::middleware::from_fn(rate_limit))
+        .merge(ops_routes())
+        .layer(cors_layer())
+        .layer(axum::middleware::from_fn(security_headers))
+        .layer(axum::middleware::from_fn(track_requests))
+}
+
+/// Routes d'exploitation (sondes et métriques), sans session
+fn ops_routes() -> Router {
+    Router::new()
+        .route("/healthz", get(healthz)) // Vivacité du processus
+        .route("/readyz", get(readyz)) // Disponibilité du service
+        .route("/metrics", get(metrics_endpoint)) // Métriques Prometheus
+}
+
+/// Configuration CORS : n'importe quelle origine en mode debug, liste explicite en production
+fn cors_layer() -> CorsLayer {
+    if cfg!(debug_assertions) {
+        return CorsLayer::new()
+            .allow_methods(tower_http::cors::AllowMethods::any())
+            .allow_origin(Any);
+    }
+
+    let origins: Vec<HeaderValue> = CONFIG
+        .cors_allowed_origins
+        .iter()
+        .filter_map(|origin| match HeaderValue::from_str(origin) {
+            Ok(value) => Some(value),
+            Err(_) => {
+                warn!("Ignoring invalid CORS origin {:?}", origin);
+                None
+            }
+        })
+        .collect();
+
+    // Une liste vide n'autorise aucune requête cross-origin
+    CorsLayer::new()
+        .allow_origin(AllowOrigin::list(origins))
+        .allow_methods([Method::GET, Method::POST])
+        .allow_headers([header::CONTENT_TYPE])
+        .allow_credentials(true)
 }
 
 /// Routes accessibles sans authentification
@@ -50,17 +107,55 @@ fn unauth_routes() -> Router {
         .route("/register", get(register_page).post(register_begin)) // Début de l'enregistrement WebAuthn
         .route("/register/complete", post(register_complete)) // Fin de l'enregistrement WebAuthn
         .route("/login", get(login_page).post(login_begin)) // Page de connexion
+        .route("/login/discoverable", post(login_discoverable_begin)) // Début de l'authentification sans email
         .route("/login/complete", post(login_complete)) // Fin de l'authentification WebAuthn
-        .route("/logout", get(logout)) // Déconnexion
+        .route("/login/magic", post(request_magic_link)) // Envoi d'un lien de connexion par email
+        .route("/login/magic/:token", get(open_magic_link)) // Connexion par lien
+        .route("/logout", post(logout)) // Déconnexion
         .route("/recover", get(recover_page).post(recover_account)) // Page et handler de récupération
         .route("/recover/:token", get(reset_account)) // Lien pour la récupération de compte
+        .route("/recover/code", post(redeem_recovery_code)) // Récupération avec un code à usage unique
+        .route("/verify-email", get(verify_email_page)) // Invitation à vérifier son email
+        .route("/verify-email/resend", post(resend_verification)) // Nouvel envoi du lien de validation
 }
 
 /// Routes nécessitant une authentification
 fn auth_routes() -> Router {
     Router::new()
         .route("/home", get(home)) // Page principale
+        .route("/posts", get(list_posts)) // Fil en JSON
+        .route("/live", get(live_feed)) // Mises à jour du fil en direct (Server-Sent Events)
+        .route("/tags/:tag", get(tag_page)) // Posts portant un hashtag
+        .route("/search", get(search_page)) // Recherche dans les posts, les hashtags et les comptes
+        .route("/search.json", get(search_results)) // Résultats de recherche en JSON
         .route("/post/like", post(like_post)) // Ajout d'un like à un post
         .route("/post/create", post(create_post)) // Ajout d'un post
+        .route("/post/publish", post(publish_post)) // Publication immédiate d'un brouillon
+        .route("/drafts", get(drafts_page)) // Brouillons et posts programmés
+        .route("/media/:post_id", get(post_image)) // Image d'un post, selon sa visibilité
+        .route("/post/report", post(report_post)) // Signalement d'un post
+        .route("/post/bookmark", post(bookmark_post)) // Enregistrement d'un post
+        .route("/saved", get(saved_page)) // Posts enregistrés
+        .route("/users/follow", post(follow_user)) // Abonnement à un compte
+        .route("/users/:handle", get(profile_page)) // Profil d'un compte
+        .route("/users/:handle/:list", get(follows_page)) // Abonnés et abonnements d'un compte
+        .route("/notifications", get(notifications_page)) // Notifications du compte
+        .route("/notifications/read", post(mark_read)) // Notifications marquées comme lues
+        .route("/notifications/preferences", post(set_digest_preferences)) // Choix du résumé quotidien par email
+        .route("/reauth", post(step_up_begin)) // Début d'une ré-authentification
+        .route("/reauth/complete", post(step_up_complete)) // Fin d'une ré-authentification
+        .route("/account/delete", post(delete_account)) // Suppression du compte (step-up requis)
+        .route("/account/passkey", post(replace_passkey_begin)) // Début du remplacement de la passkey (step-up requis)
+        .route("/account/passkey/complete", post(replace_passkey_complete)) // Fin du remplacement de la passkey (step-up requis)
+        .route("/account/recovery-codes", get(recovery_codes_page).post(regenerate_recovery_codes)) // Codes de récupération (step-up requis pour en générer)
+        .route("/account/magic-link", post(set_magic_link)) // Activation de la connexion par lien (step-up requis)
+        .route("/account/sessions", get(sessions_page)) // Sessions actives du compte
+        .route("/account/sessions/revoke", post(revoke_session)) // Révocation d'une session
+        .route("/account/sessions/revoke-others", post(revoke_other_sessions)) // Révocation des autres sessions
+        .route("/moderation", get(moderation_page)) // Console de modération (modérateurs)
+        .route("/moderation/post", post(moderate_post)) // Masquage d'un post
+        .route("/moderation/user", post(moderate_user)) // Suspension d'un compte
+        .route("/moderation/role", post(set_role)) // Gestion des rôles (administrateurs)
+        .route("/moderation/reports", post(resolve_reports)) // Décision sur un post signalé
         .layer(axum::middleware::from_extractor::<crate::backend::middlewares::SessionUser>()) // Middleware pour vérifier l'utilisateur connecté
 }
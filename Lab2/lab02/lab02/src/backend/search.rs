@@ -0,0 +1,132 @@
+//! Recherche dans les posts, les hashtags et les comptes (par nom affiché).
+//! Les posts viennent de l'index plein texte (`database::search`) et sont filtrés comme le
+//! fil : un post que le compte ne pourrait pas voir n'est jamais retourné. Le paramètre `q`
+//! accepte des mots, des expressions entre guillemets et des `#hashtags` ; `tag` et `author`
+//! filtrent en plus par hashtag et par email de l'auteur.
+
+use std::collections::HashMap;
+use axum::{
+    extract::{Json, Query},
+    http::StatusCode,
+    response::Html,
+    Extension,
+};
+use serde_json::json;
+use url::form_urlencoded;
+use crate::backend::csrf::CsrfToken;
+use crate::backend::handlers_auth::feed_entries;
+use crate::backend::middlewares::LoggedIn;
+use crate::backend::security_headers::CspNonce;
+use crate::consts;
+use crate::database::{post, search, user};
+use crate::utils::pagination::paginate;
+use crate::HBS;
+
+type Rejection = (StatusCode, &'static str);
+
+/// Lien vers une autre page de la même recherche
+fn page_url(params: &HashMap<String, String>, page: usize) -> String {
+    let mut query = form_urlencoded::Serializer::new(String::new());
+    for name in ["q", "tag", "author"] {
+        if let Some(value) = params.get(name).filter(|value| !value.is_empty()) {
+            query.append_pair(name, value);
+        }
+    }
+    query.append_pair("page", &page.to_string());
+    format!("/search?{}", query.finish())
+}
+
+/// Exécute la recherche décrite par les paramètres pour `viewer`
+fn run(viewer: &str, params: &HashMap<String, String>) -> Result<serde_json::Value, Rejection> {
+    let text = params.get("q").map(|q| q.trim()).unwrap_or_default();
+    let page = params
+        .get("page")
+        .and_then(|page| page.parse::<usize>().ok())
+        .unwrap_or(1)
+        .max(1);
+
+    let mut query = search::Query::parse(text);
+    if let Some(tag) = params.get("tag") {
+        query.add_tag(tag);
+    }
+    query.author = params.get("author").filter(|author| !author.is_empty()).cloned();
+
+    let posts = post::search(&query, viewer).map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to search posts"))?;
+    let total = posts.len();
+    let (posts, has_next) = paginate(posts, page, consts::SEARCH_PAGE_SIZE);
+
+    // Comptes et hashtags dont le nom commence comme la recherche, en première page seulement
+    let mut users = vec![];
+    let mut tags = vec![];
+    if page == 1 && !text.is_empty() {
+        let needle = text.to_lowercase();
+        // Seul le nom affiché est cherché, parmi les comptes publics : l'email n'est ni
+        // comparé ni retourné, pour ne pas révéler quels emails sont inscrits
+        users = user::all()
+            .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to read users"))?
+            .into_iter()
+            .filter(user::User::is_public)
+            .map(|user| (format!("{} {}", user.first_name, user.last_name), user.handle))
+            .filter(|(name, _)| name.to_lowercase().contains(&needle))
+            .take(consts::SEARCH_SUGGESTIONS_LIMIT)
+            .map(|(name, handle)| json!({ "handle": handle, "name": name }))
+            .collect();
+        let prefix = needle.trim_start_matches('#');
+        if !prefix.is_empty() && prefix.chars().all(|c| c.is_alphanumeric() || c == '_') {
+            tags = post::search_tags(prefix, viewer)
+                .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to search hashtags"))?
+                .into_iter()
+                .take(consts::SEARCH_SUGGESTIONS_LIMIT)
+                .map(|(tag, count)| json!({ "tag": tag, "count": count }))
+                .collect();
+        }
+    }
+
+    Ok(json!({
+        "q": text,
+        "tag": params.get("tag"),
+        "author": query.author,
+        "total": total,
+        "posts": feed_entries(posts, Some(viewer)),
+        "users": users,
+        "tags": tags,
+        "page": page,
+        "previous_page": (page > 1).then(|| page_url(params, page - 1)),
+        "next_page": has_next.then(|| page_url(params, page + 1)),
+    }))
+}
+
+/// Page de recherche
+pub async fn search_page(
+    LoggedIn(viewer): LoggedIn,
+    Extension(nonce): Extension<CspNonce>,
+    CsrfToken(csrf_token): CsrfToken,
+    Query(params): Query<HashMap<String, String>>,
+) -> axum::response::Result<Html<String>> {
+    let mut data = run(&viewer, &params)?;
+    data["csp_nonce"] = json!(nonce.0);
+    data["csrf_token"] = json!(csrf_token);
+    HBS.render("search", &data)
+        .map(Html)
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Internal server error while rendering the page").into())
+}
+
+/// Résultats de recherche en JSON, avec les mêmes paramètres que la page
+pub async fn search_results(LoggedIn(viewer): LoggedIn, Query(params): Query<HashMap<String, String>>) -> axum::response::Result<Json<serde_json::Value>> {
+    Ok(Json(run(&viewer, &params)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_page_url_keeps_the_search() {
+        let params = HashMap::from([
+            ("q".to_string(), "\"chat noir\" & co".to_string()),
+            ("author".to_string(), String::new()),
+            ("page".to_string(), "1".to_string()),
+        ]);
+        assert_eq!(page_url(&params, 2), "/search?q=%22chat+noir%22+%26+co&page=2");
+    }
+}
@@ -0,0 +1,127 @@
+//! Middleware ajoutant les en-têtes de sécurité aux réponses.
+//! Génère un nonce CSP par requête, exposé aux handlers via l'extension `CspNonce`
+//! pour autoriser uniquement les scripts inline des templates.
+
+use axum::{extract::Request, middleware::Next, response::Response};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use http::{header, HeaderName, HeaderValue};
+use crate::config::{SecurityHeaders, CONFIG};
+
+/// Nonce CSP de la requête courante, à injecter dans les balises `<script>`
+#[derive(Clone)]
+pub struct CspNonce(pub String);
+
+impl CspNonce {
+    fn generate() -> Self {
+        CspNonce(URL_SAFE_NO_PAD.encode(uuid::Uuid::new_v4().as_bytes()))
+    }
+}
+
+/// Construit la Content-Security-Policy pour un nonce donné
+fn content_security_policy(nonce: &str, config: &SecurityHeaders) -> String {
+    format!(
+        "default-src 'self'; \
+         script-src 'nonce-{nonce}' https://cdn.jsdelivr.net; \
+         style-src 'self' 'unsafe-inline' https://cdn.jsdelivr.net; \
+         img-src 'self' data:; \
+         connect-src 'self'; \
+         object-src 'none'; \
+         base-uri 'none'; \
+         form-action 'self'; \
+         frame-ancestors {}",
+        config.frame_ancestors
+    )
+}
+
+/// Équivalent `X-Frame-Options` de `frame-ancestors`, pour les anciens navigateurs
+fn x_frame_options(frame_ancestors: &str) -> Option<&'static str> {
+    match frame_ancestors.trim() {
+        "'none'" => Some("DENY"),
+        "'self'" => Some("SAMEORIGIN"),
+        _ => None,
+    }
+}
+
+/// HSTS, seulement quand le service est servi en HTTPS : sur HTTP l'en-tête est ignoré
+/// par les navigateurs et annoncerait un HTTPS qui n'existe pas
+fn strict_transport_security(config: &SecurityHeaders, tls_enabled: bool) -> Option<String> {
+    (tls_enabled && config.hsts_max_age > 0).then(|| format!("max-age={}; includeSubDomains", config.hsts_max_age))
+}
+
+/// Middleware appliqué à toutes les routes
+pub async fn security_headers(mut request: Request, next: Next) -> Response {
+    let nonce = CspNonce::generate();
+    request.extensions_mut().insert(nonce.clone());
+
+    let mut response = next.run(request).await;
+    let config = &CONFIG.security_headers;
+    let headers = response.headers_mut();
+
+    let mut set = |name: HeaderName, value: String| {
+        if let Ok(value) = HeaderValue::from_str(&value) {
+            headers.insert(name, value);
+        }
+    };
+
+    set(header::CONTENT_SECURITY_POLICY, content_security_policy(&nonce.0, config));
+    set(header::X_CONTENT_TYPE_OPTIONS, "nosniff".to_string());
+    set(header::REFERRER_POLICY, config.referrer_policy.clone());
+    set(HeaderName::from_static("permissions-policy"), config.permissions_policy.clone());
+    set(HeaderName::from_static("cross-origin-opener-policy"), config.cross_origin_opener_policy.clone());
+    if let Some(value) = x_frame_options(&config.frame_ancestors) {
+        set(header::X_FRAME_OPTIONS, value.to_string());
+    }
+    if let Some(value) = strict_transport_security(config, CONFIG.tls.is_some()) {
+        set(header::STRICT_TRANSPORT_SECURITY, value);
+    }
+
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{body::Body, routing::get, Extension, Router};
+    use tower::ServiceExt;
+
+    #[tokio::test]
+    async fn test_headers_and_nonce_match() {
+        let app = Router::new()
+            .route("/", get(|Extension(nonce): Extension<CspNonce>| async move { nonce.0 }))
+            .layer(axum::middleware::from_fn(security_headers));
+
+        let response = app
+            .oneshot(Request::builder().uri("/").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+
+        let csp = response.headers()[header::CONTENT_SECURITY_POLICY].to_str().unwrap().to_string();
+        assert_eq!(response.headers()[header::X_FRAME_OPTIONS], "DENY");
+        // Les tests servent en HTTP
+        assert!(!response.headers().contains_key(header::STRICT_TRANSPORT_SECURITY));
+        assert!(response.headers().contains_key("permissions-policy"));
+
+        // Le nonce de l'en-tête est celui transmis au handler
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let nonce = String::from_utf8(body.to_vec()).unwrap();
+        assert!(csp.contains(&format!("'nonce-{}'", nonce)));
+        assert!(!csp.split(';').any(|d| d.trim().starts_with("script-src") && d.contains("unsafe-inline")));
+    }
+
+    #[test]
+    fn test_hsts_only_over_tls() {
+        let config = &CONFIG.security_headers;
+        assert_eq!(strict_transport_security(config, false), None);
+        assert_eq!(
+            strict_transport_security(config, true),
+            Some(format!("max-age={}; includeSubDomains", config.hsts_max_age))
+        );
+    }
+
+    #[test]
+    fn test_x_frame_options_mapping() {
+        assert_eq!(x_frame_options("'none'"), Some("DENY"));
+        assert_eq!(x_frame_options("'self'"), Some("SAMEORIGIN"));
+        assert_eq!(x_frame_options("https://example.com"), None);
+    }
+}
@@ -0,0 +1,113 @@
+//! Store des sessions en mémoire, avec expiration.
+//! Chaque session est gardée jusqu'à sa date d'expiration : le layer fixe une expiration
+//! après inactivité, plus courte tant que la session est anonyme (elle ne porte alors qu'un
+//! jeton CSRF). Une tâche de fond retire régulièrement les sessions expirées, pour que les
+//! visites anonymes ne fassent pas grossir la mémoire sans limite.
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use async_trait::async_trait;
+use log::info;
+use once_cell::sync::Lazy;
+use tower_sessions::{
+    cookie::time::{self, OffsetDateTime},
+    session::{Id, Session},
+    Expiry, SessionStore,
+};
+use crate::consts;
+
+/// Store partagé par le layer des sessions et la tâche de nettoyage
+pub static SESSION_STORE: Lazy<MemorySessionStore> = Lazy::new(MemorySessionStore::default);
+
+/// Expiration d'une session connectée
+pub fn authenticated_expiry() -> Expiry {
+    Expiry::OnInactivity(time::Duration::seconds(consts::SESSION_IDLE_TIMEOUT_SECS as i64))
+}
+
+/// Expiration d'une session anonyme
+pub fn anonymous_expiry() -> Expiry {
+    Expiry::OnInactivity(time::Duration::seconds(consts::ANONYMOUS_SESSION_IDLE_TIMEOUT_SECS as i64))
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct MemorySessionStore(Arc<Mutex<HashMap<Id, (Session, OffsetDateTime)>>>);
+
+impl MemorySessionStore {
+    fn sessions(&self) -> std::sync::MutexGuard<'_, HashMap<Id, (Session, OffsetDateTime)>> {
+        self.0.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Retire les sessions expirées et retourne leur nombre
+    pub fn delete_expired(&self) -> usize {
+        let now = OffsetDateTime::now_utc();
+        let mut sessions = self.sessions();
+        let before = sessions.len();
+        sessions.retain(|_, (_, expiry_date)| *expiry_date > now);
+        before - sessions.len()
+    }
+
+    pub fn len(&self) -> usize {
+        self.sessions().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[async_trait]
+impl SessionStore for MemorySessionStore {
+    type Error = std::convert::Infallible;
+
+    async fn save(&self, session: &Session) -> Result<(), Self::Error> {
+        self.sessions().insert(*session.id(), (session.clone(), session.expiry_date()));
+        Ok(())
+    }
+
+    async fn load(&self, session_id: &Id) -> Result<Option<Session>, Self::Error> {
+        Ok(self
+            .sessions()
+            .get(session_id)
+            .filter(|(_, expiry_date)| *expiry_date > OffsetDateTime::now_utc())
+            .map(|(session, _)| session.clone()))
+    }
+
+    async fn delete(&self, session_id: &Id) -> Result<(), Self::Error> {
+        self.sessions().remove(session_id);
+        Ok(())
+    }
+}
+
+/// Tâche de fond qui retire les sessions expirées
+pub async fn delete_expired_periodically() {
+    let mut interval = tokio::time::interval(Duration::from_secs(consts::SESSION_SWEEP_INTERVAL_SECS));
+    loop {
+        interval.tick().await;
+        let deleted = SESSION_STORE.delete_expired();
+        if deleted > 0 {
+            info!("{} expired sessions deleted", deleted);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_expired_sessions_are_deleted() {
+        let store = MemorySessionStore::default();
+        let expired = Session::new(Some(Expiry::AtDateTime(OffsetDateTime::now_utc() - time::Duration::seconds(1))));
+        let active = Session::new(Some(anonymous_expiry()));
+        store.save(&expired).await.unwrap();
+        store.save(&active).await.unwrap();
+
+        assert!(store.load(expired.id()).await.unwrap().is_none());
+        assert_eq!(store.delete_expired(), 1);
+        assert_eq!(store.len(), 1);
+        assert!(store.load(active.id()).await.unwrap().is_some());
+    }
+}
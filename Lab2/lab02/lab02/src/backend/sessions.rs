@@ -0,0 +1,326 @@
+//! Sessions actives des utilisateurs connectés.
+//! Chaque connexion enregistre un identifiant opaque dans la session et une entrée dans le
+//! registre (appareil, adresse IP, création, dernière activité). Le middleware
+//! `track_sessions` ferme toute session dont l'entrée a disparu : c'est ainsi qu'une session
+//! est révoquée depuis un autre appareil, ou lorsque la passkey du compte change. Il ferme
+//! aussi les sessions ouvertes avant la dernière génération de sessions du compte, persistée
+//! avec lui : l'outil d'administration révoque ainsi les sessions sans accès au registre.
+//! Le registre est en mémoire, comme le store des sessions : un redémarrage ferme tout. Il est
+//! borné par compte et au total, et une tâche de fond en retire les sessions inactives.
+
+use std::{collections::HashMap, sync::Mutex, time::Duration};
+use axum::{
+    extract::{Json, Request},
+    http::{header, HeaderMap, StatusCode},
+    middleware::Next,
+    response::{Html, Response},
+    Extension,
+};
+use log::{error, info};
+use once_cell::sync::Lazy;
+use serde::Serialize;
+use serde_json::json;
+use tower_sessions::Session;
+use crate::backend::csrf::{self, CsrfToken};
+use crate::backend::handlers_unauth::SESSION_EMAIL;
+use crate::backend::middlewares::ClientIp;
+use crate::backend::security_headers::CspNonce;
+use crate::backend::session_store::authenticated_expiry;
+use crate::config::CONFIG;
+use crate::consts;
+use crate::database::{unix_now, user};
+use crate::HBS;
+
+/// Clé de session contenant l'identifiant de la session dans le registre
+const SESSION_ID: &str = "session_id";
+/// Longueur conservée du User-Agent
+const USER_AGENT_MAX_LEN: usize = 200;
+
+#[derive(Clone, Serialize)]
+struct ActiveSession {
+    email: String,
+    user_agent: String,
+    ip: Option<String>,
+    created_at: u64,
+    last_seen: u64,
+    /// Génération de sessions du compte à l'ouverture
+    generation: u64,
+}
+
+static SESSIONS: Lazy<Mutex<HashMap<String, ActiveSession>>> = Lazy::new(Default::default);
+
+fn registry() -> std::sync::MutexGuard<'static, HashMap<String, ActiveSession>> {
+    SESSIONS.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+fn user_agent(headers: &HeaderMap) -> String {
+    let agent = headers.get(header::USER_AGENT).and_then(|value| value.to_str().ok()).unwrap_or("Unknown device");
+    agent.chars().take(USER_AGENT_MAX_LEN).collect()
+}
+
+/// Enregistre la session qui vient de s'authentifier
+pub fn open(session: &Session, email: &str, headers: &HeaderMap, ip: Option<std::net::IpAddr>) -> anyhow::Result<()> {
+    let id = uuid::Uuid::new_v4().to_string();
+    let now = unix_now();
+    let record = ActiveSession {
+        email: email.to_string(),
+        user_agent: user_agent(headers),
+        ip: ip.map(|ip| ip.to_string()),
+        created_at: now,
+        last_seen: now,
+        generation: user::get(email).map(|user| user.session_generation).unwrap_or_default(),
+    };
+
+    session.set_expiry(Some(authenticated_expiry()));
+    session.insert(SESSION_EMAIL, email)?;
+    session.insert(SESSION_ID, &id)?;
+    csrf::rotate(session)?;
+
+    let mut sessions = registry();
+    make_room(&mut sessions, email, now);
+    sessions.insert(id, record);
+    Ok(())
+}
+
+/// Libère une place pour une nouvelle session de `email` : au-delà de la limite du compte,
+/// sa session la moins récemment utilisée est fermée ; le registre plein est d'abord purgé
+/// des sessions inactives, puis perd la session la moins récemment utilisée
+fn make_room(sessions: &mut HashMap<String, ActiveSession>, email: &str, now: u64) {
+    let least_recent = |sessions: &HashMap<String, ActiveSession>, email: Option<&str>| {
+        sessions
+            .iter()
+            .filter(|(_, record)| email.is_none_or(|email| record.email == email))
+            .min_by_key(|(_, record)| record.last_seen)
+            .map(|(id, _)| id.clone())
+    };
+
+    if sessions.values().filter(|record| record.email == email).count() >= consts::MAX_SESSIONS_PER_USER {
+        if let Some(id) = least_recent(sessions, Some(email)) {
+            sessions.remove(&id);
+        }
+    }
+    if sessions.len() >= consts::MAX_ACTIVE_SESSIONS {
+        sweep(sessions, now);
+        if sessions.len() >= consts::MAX_ACTIVE_SESSIONS {
+            if let Some(id) = least_recent(sessions, None) {
+                sessions.remove(&id);
+            }
+        }
+    }
+}
+
+/// Oublie les sessions inactives trop longtemps et retourne leur nombre
+fn sweep(sessions: &mut HashMap<String, ActiveSession>, now: u64) -> usize {
+    let before = sessions.len();
+    sessions.retain(|_, record| now.saturating_sub(record.last_seen) < consts::SESSION_IDLE_TIMEOUT_SECS);
+    before - sessions.len()
+}
+
+/// Tâche de fond qui retire les sessions inactives du registre
+pub async fn sweep_periodically() {
+    let mut interval = tokio::time::interval(Duration::from_secs(consts::SESSION_SWEEP_INTERVAL_SECS));
+    loop {
+        interval.tick().await;
+        sweep(&mut registry(), unix_now());
+    }
+}
+
+/// Ferme la session courante : registre, données et cookie
+pub fn close(session: &Session) {
+    if let Ok(Some(id)) = session.get::<String>(SESSION_ID) {
+        registry().remove(&id);
+    }
+    session.flush();
+}
+
+/// Révoque les sessions d'un compte, sauf éventuellement la session `except`. Sans exception,
+/// une nouvelle génération de sessions est aussi persistée avec le compte.
+pub fn revoke_all(email: &str, except: Option<&str>) -> usize {
+    if except.is_none() && user::exists(email).unwrap_or(false) {
+        if let Err(e) = user::new_session_generation(email) {
+            error!("Failed to start a new session generation for {}: {}", email, e);
+        }
+    }
+    let mut sessions = registry();
+    let before = sessions.len();
+    sessions.retain(|id, record| record.email != email || Some(id.as_str()) == except);
+    let revoked = before - sessions.len();
+    if revoked > 0 {
+        info!("{} sessions of {} revoked", revoked, email);
+    }
+    revoked
+}
+
+/// Met à jour la dernière activité ; retourne `false` si la session a été révoquée, si son
+/// compte n'existe plus ou si une génération de sessions plus récente l'a fermée
+fn touch(id: &str, ip: Option<std::net::IpAddr>) -> bool {
+    let mut sessions = registry();
+    let Some(record) = sessions.get_mut(id) else {
+        return false;
+    };
+    let now = unix_now();
+    let current = user::get(&record.email).is_some_and(|user| user.session_generation == record.generation);
+    if !current || now.saturating_sub(record.last_seen) >= consts::SESSION_IDLE_TIMEOUT_SECS {
+        sessions.remove(id);
+        return false;
+    }
+    record.last_seen = now;
+    if let Some(ip) = ip {
+        record.ip = Some(ip.to_string());
+    }
+    true
+}
+
+/// Identifiant dans le registre de la session courante
+pub(crate) fn registry_id(session: &Session) -> Option<String> {
+    session.get::<String>(SESSION_ID).ok().flatten()
+}
+
+/// Indique, sans la marquer active, si la session `id` est toujours ouverte : présente dans
+/// le registre, de la génération courante d'un compte qui existe et n'est pas suspendu
+pub(crate) fn is_open(id: &str) -> bool {
+    registry().get(id).is_some_and(|record| {
+        user::get(&record.email).is_some_and(|user| user.session_generation == record.generation && !user.suspended)
+    })
+}
+
+/// Middleware appliqué aux routes avec session : une session révoquée redevient anonyme
+pub async fn track_sessions(ClientIp(ip): ClientIp, session: Session, request: Request, next: Next) -> Response {
+    if let Ok(Some(_)) = session.get::<String>(SESSION_EMAIL) {
+        let active = match session.get::<String>(SESSION_ID) {
+            Ok(Some(id)) => touch(&id, ip),
+            _ => false,
+        };
+        if !active {
+            session.flush();
+        }
+    }
+    next.run(request).await
+}
+
+/// Session du compte connecté, avec son identifiant
+fn current(session: &Session) -> Result<(String, String), (StatusCode, &'static str)> {
+    let email = session.get::<String>(SESSION_EMAIL).ok().flatten();
+    let id = session.get::<String>(SESSION_ID).ok().flatten();
+    match (email, id) {
+        (Some(email), Some(id)) => Ok((email, id)),
+        _ => Err((StatusCode::UNAUTHORIZED, "Unauthorized")),
+    }
+}
+
+/// Liste les sessions actives du compte connecté
+pub async fn sessions_page(
+    session: Session,
+    Extension(nonce): Extension<CspNonce>,
+    CsrfToken(csrf_token): CsrfToken,
+) -> axum::response::Result<Html<String>> {
+    let (email, current_id) = current(&session)?;
+
+    let mut sessions: Vec<_> = registry()
+        .iter()
+        .filter(|(_, record)| record.email == email)
+        .map(|(id, record)| json!({
+            "id": id,
+            "current": *id == current_id,
+            "user_agent": record.user_agent,
+            "ip": record.ip,
+            "created_at": record.created_at,
+            "last_seen": record.last_seen,
+        }))
+        .collect();
+    sessions.sort_by_key(|record| std::cmp::Reverse(record["last_seen"].as_u64()));
+
+    let data = json!({
+        "sessions": sessions,
+        "magic_link_available": CONFIG.magic_link_login,
+        "magic_link_enabled": user::get(&email).is_some_and(|user| user.magic_link),
+        "csp_nonce": nonce.0,
+        "csrf_token": csrf_token,
+    });
+    HBS.render("sessions", &data)
+        .map(Html)
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Internal server error while rendering the page").into())
+}
+
+/// Révoque une autre session du compte connecté
+pub async fn revoke_session(session: Session, Json(payload): Json<serde_json::Value>) -> axum::response::Result<StatusCode> {
+    let (email, current_id) = current(&session)?;
+    let id = payload
+        .get("id")
+        .and_then(|value| value.as_str())
+        .ok_or((StatusCode::BAD_REQUEST, "Session ID is required"))?;
+    if id == current_id {
+        return Err((StatusCode::BAD_REQUEST, "Use logout to close the current session").into());
+    }
+
+    let mut sessions = registry();
+    match sessions.get(id) {
+        Some(record) if record.email == email => {
+            sessions.remove(id);
+            Ok(StatusCode::OK)
+        }
+        _ => Err((StatusCode::NOT_FOUND, "Session not found").into()),
+    }
+}
+
+/// Révoque toutes les sessions du compte connecté sauf la session courante
+pub async fn revoke_other_sessions(session: Session) -> axum::response::Result<Json<serde_json::Value>> {
+    let (email, current_id) = current(&session)?;
+    let revoked = revoke_all(&email, Some(&current_id));
+    Ok(Json(json!({ "revoked": revoked })))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(email: &str) -> ActiveSession {
+        ActiveSession { email: email.to_string(), user_agent: String::new(), ip: None, created_at: 0, last_seen: unix_now(), generation: 0 }
+    }
+
+    fn account() -> String {
+        let email = format!("{}@example.com", uuid::Uuid::new_v4());
+        user::create(&email, "Alice", "Martin").unwrap();
+        email
+    }
+
+    #[test]
+    fn test_revoke_all_keeps_excepted_session_and_other_accounts() {
+        let email = account();
+        let other = account();
+        let ids: Vec<String> = (0..3).map(|_| uuid::Uuid::new_v4().to_string()).collect();
+        registry().insert(ids[0].clone(), record(&email));
+        registry().insert(ids[1].clone(), record(&email));
+        registry().insert(ids[2].clone(), record(&other));
+
+        assert_eq!(revoke_all(&email, Some(&ids[0])), 1);
+        assert!(touch(&ids[0], None));
+        assert!(!touch(&ids[1], None));
+        assert!(touch(&ids[2], None));
+    }
+
+    #[test]
+    fn test_new_session_generation_closes_sessions() {
+        let email = account();
+        let id = uuid::Uuid::new_v4().to_string();
+        registry().insert(id.clone(), record(&email));
+        assert!(touch(&id, None));
+
+        user::new_session_generation(&email).unwrap();
+        assert!(!touch(&id, None));
+    }
+
+    #[test]
+    fn test_sessions_per_account_are_bounded() {
+        let email = account();
+        let mut sessions = HashMap::new();
+        for index in 0..consts::MAX_SESSIONS_PER_USER {
+            let mut record = record(&email);
+            record.last_seen = index as u64;
+            sessions.insert(index.to_string(), record);
+        }
+        make_room(&mut sessions, &email, unix_now());
+        assert_eq!(sessions.len(), consts::MAX_SESSIONS_PER_USER - 1);
+        assert!(!sessions.contains_key("0"));
+    }
+}
@@ -0,0 +1,91 @@
+//! Ré-authentification (step-up) avant les opérations sensibles.
+//! L'utilisateur connecté refait une assertion WebAuthn avec vérification de l'utilisateur ;
+//! la cérémonie est liée à sa session et le succès reste valable pendant
+//! `consts::STEP_UP_WINDOW_SECS`. Les handlers sensibles l'exigent avec l'extracteur
+//! `middlewares::StepUp` : suppression du compte, remplacement de la passkey, codes de
+//! récupération et connexion par lien. L'email d'un compte ne peut pas être changé.
+
+use axum::{extract::Json, http::StatusCode};
+use serde_json::json;
+use tower_sessions::Session;
+use webauthn_rs::prelude::PublicKeyCredential;
+use crate::backend::ceremonies::{Owner, AUTHENTICATION_STATES};
+use crate::backend::handlers_unauth::too_many_ceremonies;
+use crate::backend::middlewares::LoggedIn;
+use crate::consts;
+use crate::database::unix_now;
+use crate::utils::webauthn::{begin_authentication, complete_authentication, PendingAuthentication};
+
+/// Clé de session contenant l'identifiant de la cérémonie de step-up en cours
+const SESSION_STEP_UP_STATE: &str = "step_up_state";
+/// Clé de session contenant la date (secondes Unix) du dernier step-up réussi
+const SESSION_STEP_UP_AT: &str = "step_up_at";
+
+/// Indique si la session a réussi un step-up dans la fenêtre de validité
+pub fn is_fresh(session: &Session) -> bool {
+    match session.get::<u64>(SESSION_STEP_UP_AT) {
+        Ok(Some(at)) => unix_now().saturating_sub(at) <= consts::STEP_UP_WINDOW_SECS,
+        _ => false,
+    }
+}
+
+/// Début du step-up : challenge pour la passkey de l'utilisateur connecté
+pub async fn step_up_begin(LoggedIn(user_email): LoggedIn, session: Session) -> axum::response::Result<Json<serde_json::Value>> {
+    let (auth_challenge_response, auth_state) = begin_authentication(&user_email)
+        .await
+        .map_err(|_| (StatusCode::BAD_REQUEST, "Failed to initiate re-authentication"))?;
+
+    // L'identifiant de l'état reste côté serveur, dans la session qui a démarré la cérémonie
+    let state_id = uuid::Uuid::new_v4().to_string();
+    let server_challenge = auth_challenge_response["challenge"].as_str().unwrap_or_default().to_string();
+    let owners = vec![Owner::Account(user_email.clone())];
+    AUTHENTICATION_STATES
+        .insert(state_id.clone(), PendingAuthentication::StepUp { email: user_email, state: auth_state }, server_challenge, owners)
+        .await
+        .map_err(too_many_ceremonies)?;
+    session
+        .insert(SESSION_STEP_UP_STATE, &state_id)
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to store re-authentication state"))?;
+
+    Ok(Json(json!({ "publicKey": auth_challenge_response })))
+}
+
+/// Fin du step-up : vérifie l'assertion et marque la session comme ré-authentifiée
+pub async fn step_up_complete(
+    LoggedIn(user_email): LoggedIn,
+    session: Session,
+    Json(payload): Json<serde_json::Value>,
+) -> axum::response::Result<StatusCode> {
+    let auth_response: PublicKeyCredential = payload
+        .get("response")
+        .and_then(|response| serde_json::from_value(response.clone()).ok())
+        .ok_or((StatusCode::BAD_REQUEST, "Invalid authentication response format"))?;
+
+    let Some(state_id) = session.remove::<String>(SESSION_STEP_UP_STATE).ok().flatten() else {
+        return Err((StatusCode::BAD_REQUEST, "No re-authentication in progress").into());
+    };
+
+    let stored = AUTHENTICATION_STATES
+        .take(&state_id)
+        .await
+        .ok_or((StatusCode::BAD_REQUEST, "Invalid or expired re-authentication state"))?;
+
+    // La cérémonie doit avoir été démarrée pour l'utilisateur de cette session
+    let PendingAuthentication::StepUp { email, state } = stored.state else {
+        return Err((StatusCode::BAD_REQUEST, "Invalid or expired re-authentication state").into());
+    };
+    if email != user_email {
+        return Err((StatusCode::BAD_REQUEST, "Invalid or expired re-authentication state").into());
+    }
+
+    // La vérification de l'utilisateur est exigée par l'état d'authentification des passkeys
+    complete_authentication(&email, &auth_response, &state, &stored.server_challenge)
+        .await
+        .map_err(|_| (StatusCode::UNAUTHORIZED, "Re-authentication failed"))?;
+
+    session
+        .insert(SESSION_STEP_UP_AT, unix_now())
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to store re-authentication"))?;
+
+    Ok(StatusCode::OK)
+}
@@ -0,0 +1,172 @@
+//! Vérification de l'adresse email des comptes.
+//! Selon `CONFIG.unverified_login`, un compte non vérifié ne peut pas se connecter ou
+//! n'obtient qu'une session limitée à la page `/verify-email` (voir `middlewares::SessionUser`).
+//! Le lien de validation peut être renvoyé, ce qui invalide le précédent. Les comptes jamais
+//! vérifiés sont supprimés après `CONFIG.unverified_purge_days` jours.
+
+use std::time::Duration;
+use axum::{
+    extract::Json,
+    http::StatusCode,
+    response::{Html, IntoResponse},
+    Extension,
+};
+use anyhow::Result;
+use log::{error, info};
+use serde_json::json;
+use tower_sessions::Session;
+use crate::backend::csrf::CsrfToken;
+use crate::backend::handlers_auth::remove_account_data;
+use crate::backend::handlers_unauth::SESSION_EMAIL;
+use crate::backend::security_headers::CspNonce;
+use crate::config::{UnverifiedLogin, CONFIG};
+use crate::consts;
+use crate::database::{token::{self, Purpose}, unix_now, user};
+use crate::email::send_mail;
+use crate::utils::input::normalize_email;
+use crate::utils::webauthn::CREDENTIAL_STORE;
+use crate::HBS;
+
+/// Page vers laquelle une session non vérifiée est redirigée
+pub const VERIFY_EMAIL_PAGE: &str = "/verify-email";
+
+/// Indique si le compte a vérifié son email
+pub fn is_verified(email: &str) -> bool {
+    user::get(email).is_some_and(|user| user.verified)
+}
+
+/// Destination après une connexion réussie, ou refus si la politique bloque les comptes non vérifiés
+pub fn after_login(policy: UnverifiedLogin, verified: bool) -> Result<&'static str, (StatusCode, &'static str)> {
+    match (verified, policy) {
+        (true, _) => Ok("/home"),
+        (false, UnverifiedLogin::Restricted) => Ok(VERIFY_EMAIL_PAGE),
+        (false, UnverifiedLogin::Block) => {
+            Err((StatusCode::FORBIDDEN, "Please verify your email address before signing in"))
+        }
+    }
+}
+
+/// Envoie un nouveau lien de validation ; les liens envoyés précédemment ne sont plus valables
+pub fn send_validation_email(email: &str) -> Result<()> {
+    token::revoke(email, Some(Purpose::Validation))?;
+    let validation_token = token::generate(email, Purpose::Validation)?;
+    send_mail(
+        email,
+        "Link your account",
+        &format!(
+            "Hello {}! Please link your account by following this URL: {}",
+            email, CONFIG.link(&format!("/validate/{}", validation_token))
+        ),
+    )
+}
+
+/// Affiche la page invitant à vérifier son email
+pub async fn verify_email_page(
+    session: Session,
+    Extension(nonce): Extension<CspNonce>,
+    CsrfToken(csrf_token): CsrfToken,
+) -> impl IntoResponse {
+    let email = session.get::<String>(SESSION_EMAIL).ok().flatten();
+    let data = json!({
+        "email": email,
+        "csp_nonce": nonce.0,
+        "csrf_token": csrf_token,
+    });
+
+    HBS.render("verify_email", &data)
+        .map(Html)
+        .unwrap_or_else(|_| Html("<h1>Internal Server Error</h1>".to_string()))
+}
+
+/// Renvoie le lien de validation. La réponse est identique que le compte existe ou non.
+pub async fn resend_verification(Json(payload): Json<serde_json::Value>) -> axum::response::Result<StatusCode> {
+    let email: &str = &payload
+        .get("email")
+        .and_then(|value| value.as_str())
+        .map(normalize_email)
+        .ok_or((StatusCode::BAD_REQUEST, "Email address is required"))?;
+
+    if user::get(email).is_some_and(|user| !user.verified) {
+        send_validation_email(email).map_err(|e| {
+            error!("Failed to resend the validation email to {}: {}", email, e);
+            (StatusCode::INTERNAL_SERVER_ERROR, "Failed to send the validation email")
+        })?;
+    }
+
+    Ok(StatusCode::OK)
+}
+
+/// Supprime les comptes non vérifiés plus anciens que le délai configuré
+pub async fn purge_unverified(max_age_days: u64) -> Result<usize> {
+    purge_created_before(unix_now().saturating_sub(max_age_days * 24 * 60 * 60)).await
+}
+
+async fn purge_created_before(created_before: u64) -> Result<usize> {
+    let purged = user::purge_unverified(created_before)?;
+
+    let mut credentials = CREDENTIAL_STORE.write().await;
+    for email in &purged {
+        credentials.remove(email);
+        remove_account_data(email)?;
+        info!("Unverified account {} purged", email);
+    }
+    Ok(purged.len())
+}
+
+/// Tâche de fond qui applique `purge_unverified`, sauf si le délai est désactivé
+pub async fn purge_unverified_periodically() {
+    if CONFIG.unverified_purge_days == 0 {
+        return;
+    }
+    let mut interval = tokio::time::interval(Duration::from_secs(consts::UNVERIFIED_PURGE_INTERVAL_SECS));
+    loop {
+        interval.tick().await;
+        if let Err(e) = purge_unverified(CONFIG.unverified_purge_days).await {
+            error!("Failed to purge unverified accounts: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::{follow, notification, post::{self, Visibility}};
+
+    #[test]
+    fn test_after_login() {
+        assert_eq!(after_login(UnverifiedLogin::Block, true), Ok("/home"));
+        assert_eq!(after_login(UnverifiedLogin::Restricted, false), Ok(VERIFY_EMAIL_PAGE));
+        assert_eq!(after_login(UnverifiedLogin::Block, false).unwrap_err().0, StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn test_purge_keeps_recent_and_verified_accounts() {
+        let stale = format!("{}@example.com", uuid::Uuid::new_v4());
+        let verified = format!("{}@example.com", uuid::Uuid::new_v4());
+        let recent = format!("{}@example.com", uuid::Uuid::new_v4());
+        for email in [&stale, &verified, &recent] {
+            user::create(email, "Alice", "Martin").unwrap();
+        }
+        user::verify(&verified).unwrap();
+        // Comptes créés bien avant la limite, sans toucher à ceux des autres tests
+        user::set_created_at(&stale, 1_000).unwrap();
+        user::set_created_at(&verified, 1_000).unwrap();
+
+        // Le compte purgé ne laisse ni abonnement, ni notification, ni post
+        let draft = post::create(&stale, "Stale draft", None, Visibility::Private, None).unwrap();
+        follow::follow(&recent, &stale).unwrap();
+        follow::follow(&stale, &verified).unwrap();
+        notification::add(&recent, notification::Kind::Follow, &stale, None).unwrap();
+        notification::add(&stale, notification::Kind::Follow, &recent, None).unwrap();
+
+        purge_created_before(2_000).await.unwrap();
+        assert!(user::get(&stale).is_none());
+        assert!(follow::following(&recent).unwrap().is_empty());
+        assert!(follow::followers(&verified).unwrap().is_empty());
+        assert!(notification::inbox(&recent).unwrap().notifications.is_empty());
+        assert!(notification::inbox(&stale).unwrap().notifications.is_empty());
+        assert!(post::get(draft).unwrap().is_none());
+        assert!(user::get(&verified).is_some());
+        assert!(user::get(&recent).is_some());
+    }
+}
@@ -0,0 +1,31 @@
+//! Outil d'administration d'une instance lab02 (voir `lab02::admin`).
+
+use std::io::stdout;
+use dotenv::dotenv;
+use lab02::{admin, config};
+
+fn main() {
+    // Même configuration que le serveur (dossier de données, URL publique)
+    dotenv().ok();
+    env_logger::builder()
+        .filter_level(log::LevelFilter::Warn)
+        .init();
+    if let Err(e) = config::validate() {
+        eprintln!("Error: {:#}", e);
+        std::process::exit(1);
+    }
+
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let options = match admin::parse(&args) {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("{}\n\n{}", e, admin::USAGE);
+            std::process::exit(2);
+        }
+    };
+
+    if let Err(e) = admin::run(options, &mut stdout()) {
+        eprintln!("Error: {:#}", e);
+        std::process::exit(1);
+    }
+}
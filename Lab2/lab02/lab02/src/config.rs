@@ -0,0 +1,225 @@
+//! Configuration de l'application lue depuis l'environnement (et le fichier `.env`).
+//! Chaque valeur possède un défaut raisonnable pour un déploiement local ; une valeur
+//! définie mais invalide empêche le démarrage au lieu d'être remplacée par le défaut.
+
+use std::{env, net::IpAddr, path::PathBuf, str::FromStr};
+use anyhow::{anyhow, Result};
+use once_cell::sync::Lazy;
+use url::Url;
+use crate::consts;
+
+/// Configuration globale, initialisée au premier accès
+#[cfg(not(test))]
+pub static CONFIG: Lazy<Config> = Lazy::new(|| Config::from_env().expect("Invalid configuration"));
+/// Configuration globale des tests, dont le dossier de données vient du harnais (`test_support`)
+#[cfg(test)]
+pub static CONFIG: Lazy<Config> = Lazy::new(crate::test_support::config);
+
+pub struct Config {
+    /// Dossier contenant les bases et les uploads (`LAB02_DATA_DIR`)
+    pub data_dir: PathBuf,
+    /// Port du listener HTTP (`LAB02_HTTP_PORT`)
+    pub http_port: u16,
+    /// URL publique du service (`LAB02_PUBLIC_URL`), utilisée comme origine WebAuthn et dans les liens envoyés
+    pub public_url: Url,
+    /// Terminaison TLS, activée lorsque `LAB02_TLS_CERT` et `LAB02_TLS_KEY` sont définis
+    pub tls: Option<TlsConfig>,
+    /// Origines autorisées par CORS en production (`LAB02_CORS_ORIGINS`, séparées par des virgules)
+    pub cors_allowed_origins: Vec<String>,
+    pub security_headers: SecurityHeaders,
+    /// Jeton bearer exigé sur `/metrics`, fermé tant qu'il n'est pas défini (`LAB02_METRICS_TOKEN`)
+    pub metrics_token: Option<String>,
+    pub webauthn: WebauthnPolicy,
+    /// Reverse proxies dont l'en-tête `X-Forwarded-For` donne l'adresse du client
+    /// (`LAB02_TRUSTED_PROXIES`, adresses IP séparées par des virgules, aucun par défaut)
+    pub trusted_proxies: Vec<IpAddr>,
+    /// Limitation de débit des routes publiques (`LAB02_RATE_LIMIT`, activée par défaut)
+    pub rate_limit: bool,
+    /// Traitement des comptes dont l'email n'est pas vérifié (`LAB02_UNVERIFIED_LOGIN`)
+    pub unverified_login: UnverifiedLogin,
+    /// Délai avant suppression d'un compte non vérifié, en jours, `0` le garde
+    /// indéfiniment (`LAB02_UNVERIFIED_PURGE_DAYS`)
+    pub unverified_purge_days: u64,
+    /// Connexion par lien envoyé par email pour les comptes qui l'ont activée
+    /// (`LAB02_MAGIC_LINK_LOGIN`, désactivée par défaut)
+    pub magic_link_login: bool,
+    /// Nombre de signalements ouverts à partir duquel un post est masqué en attendant la
+    /// modération, `0` pour ne jamais masquer automatiquement (`LAB02_REPORT_HIDE_THRESHOLD`)
+    pub report_hide_threshold: usize,
+}
+
+/// Connexion d'un compte dont l'email n'est pas vérifié
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum UnverifiedLogin {
+    /// La connexion est refusée (`block`)
+    Block,
+    /// La session ouverte ne donne accès qu'à la page de vérification (`restricted`)
+    Restricted,
+}
+
+impl FromStr for UnverifiedLogin {
+    type Err = ();
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "block" => Ok(UnverifiedLogin::Block),
+            "restricted" => Ok(UnverifiedLogin::Restricted),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Certificat et clé PEM servis en HTTPS
+#[derive(Clone)]
+pub struct TlsConfig {
+    pub cert_path: PathBuf,
+    pub key_path: PathBuf,
+    /// Port du listener HTTPS (`LAB02_HTTPS_PORT`)
+    pub https_port: u16,
+    /// Garde un listener HTTP qui redirige vers HTTPS (`LAB02_HTTP_REDIRECT`)
+    pub redirect_http: bool,
+}
+
+/// Valeurs des en-têtes de sécurité ajoutés à chaque réponse
+pub struct SecurityHeaders {
+    /// Durée HSTS en secondes, `0` désactive l'en-tête
+    pub hsts_max_age: u64,
+    /// Directive CSP `frame-ancestors`, reprise aussi dans `X-Frame-Options`
+    pub frame_ancestors: String,
+    pub referrer_policy: String,
+    pub permissions_policy: String,
+    pub cross_origin_opener_policy: String,
+}
+
+/// Politique d'enregistrement des authentificateurs, validée au démarrage
+/// (voir `utils::registration_policy`)
+#[derive(Default)]
+pub struct WebauthnPolicy {
+    /// Préférence de transmission de l'attestation (`LAB02_WEBAUTHN_ATTESTATION` : none, indirect, direct)
+    pub attestation: Option<String>,
+    /// Certificats PEM des autorités d'attestation acceptées (`LAB02_WEBAUTHN_ATTESTATION_CA`)
+    pub attestation_ca_files: Vec<PathBuf>,
+    /// AAGUID acceptés, tous si vide (`LAB02_WEBAUTHN_AAGUID_ALLOW`)
+    pub aaguid_allow: Vec<String>,
+    /// AAGUID refusés (`LAB02_WEBAUTHN_AAGUID_DENY`)
+    pub aaguid_deny: Vec<String>,
+    /// Exigence de resident key imposée à tous (`LAB02_WEBAUTHN_RESIDENT_KEY`),
+    /// sinon laissée au choix de l'utilisateur à l'inscription
+    pub resident_key: Option<String>,
+}
+
+impl Config {
+    pub fn from_env() -> Result<Self> {
+        let http_port = env_parse("LAB02_HTTP_PORT", consts::HTTP_PORT)?;
+        let tls = match (env::var("LAB02_TLS_CERT"), env::var("LAB02_TLS_KEY")) {
+            (Ok(cert), Ok(key)) => Some(TlsConfig {
+                cert_path: cert.into(),
+                key_path: key.into(),
+                https_port: env_parse("LAB02_HTTPS_PORT", consts::HTTPS_PORT)?,
+                redirect_http: env_parse("LAB02_HTTP_REDIRECT", false)?,
+            }),
+            _ => None,
+        };
+
+        let default_url = match &tls {
+            Some(tls) => format!("https://localhost:{}", tls.https_port),
+            None => format!("http://localhost:{}", http_port),
+        };
+        let public_url = env_parse("LAB02_PUBLIC_URL", Url::parse(&default_url).expect("Invalid default public URL"))?;
+
+        Ok(Config {
+            data_dir: env::var("LAB02_DATA_DIR").map(PathBuf::from).unwrap_or_else(|_| PathBuf::from(consts::DATA_DIR)),
+            http_port,
+            public_url,
+            tls,
+            cors_allowed_origins: env_list("LAB02_CORS_ORIGINS"),
+            security_headers: SecurityHeaders {
+                hsts_max_age: env_parse("LAB02_HSTS_MAX_AGE", 31_536_000)?,
+                frame_ancestors: env_or("LAB02_FRAME_ANCESTORS", "'none'"),
+                referrer_policy: env_or("LAB02_REFERRER_POLICY", "strict-origin-when-cross-origin"),
+                permissions_policy: env_or(
+                    "LAB02_PERMISSIONS_POLICY",
+                    "camera=(), microphone=(), geolocation=(), payment=(), \
+                     publickey-credentials-create=(self), publickey-credentials-get=(self)",
+                ),
+                cross_origin_opener_policy: env_or("LAB02_COOP", "same-origin"),
+            },
+            metrics_token: env::var("LAB02_METRICS_TOKEN").ok().filter(|token| !token.is_empty()),
+            webauthn: WebauthnPolicy {
+                attestation: env::var("LAB02_WEBAUTHN_ATTESTATION").ok(),
+                attestation_ca_files: env_list("LAB02_WEBAUTHN_ATTESTATION_CA").into_iter().map(PathBuf::from).collect(),
+                aaguid_allow: env_list("LAB02_WEBAUTHN_AAGUID_ALLOW"),
+                aaguid_deny: env_list("LAB02_WEBAUTHN_AAGUID_DENY"),
+                resident_key: env::var("LAB02_WEBAUTHN_RESIDENT_KEY").ok(),
+            },
+            trusted_proxies: env_list("LAB02_TRUSTED_PROXIES")
+                .iter()
+                .map(|ip| ip.parse().map_err(|_| invalid("LAB02_TRUSTED_PROXIES", ip)))
+                .collect::<Result<_>>()?,
+            rate_limit: env_parse("LAB02_RATE_LIMIT", true)?,
+            unverified_login: env_parse("LAB02_UNVERIFIED_LOGIN", UnverifiedLogin::Restricted)?,
+            unverified_purge_days: env_parse("LAB02_UNVERIFIED_PURGE_DAYS", 7)?,
+            magic_link_login: env_parse("LAB02_MAGIC_LINK_LOGIN", false)?,
+            report_hide_threshold: env_parse("LAB02_REPORT_HIDE_THRESHOLD", 3)?,
+        })
+    }
+
+    /// Dossier des fichiers uploadés
+    pub fn uploads_dir(&self) -> PathBuf {
+        self.data_dir.join(consts::UPLOADS_DIR)
+    }
+
+    /// Construit un lien absolu vers `path` à partir de l'URL publique
+    pub fn link(&self, path: &str) -> String {
+        format!("{}{}", self.public_url.as_str().trim_end_matches('/'), path)
+    }
+}
+
+fn env_or(key: &str, default: &str) -> String {
+    env::var(key).unwrap_or_else(|_| default.to_string())
+}
+
+/// Valide la configuration ; à appeler au démarrage, avant le premier accès à `CONFIG`
+pub fn validate() -> Result<()> {
+    Config::from_env().map(|_| ())
+}
+
+/// Valeur de `key`, ou `default` si la variable n'est pas définie
+fn env_parse<T: FromStr>(key: &str, default: T) -> Result<T> {
+    match env::var(key) {
+        Ok(value) => value.parse().map_err(|_| invalid(key, &value)),
+        Err(_) => Ok(default),
+    }
+}
+
+fn invalid(key: &str, value: &str) -> anyhow::Error {
+    anyhow!("Invalid value '{}' for {}", value, key)
+}
+
+fn env_list(key: &str) -> Vec<String> {
+    env::var(key)
+        .unwrap_or_default()
+        .split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_env_parse_rejects_invalid_values() {
+        assert_eq!(env_parse("LAB02_TEST_UNSET", 7u64).unwrap(), 7);
+
+        env::set_var("LAB02_TEST_THRESHOLD", "12");
+        assert_eq!(env_parse("LAB02_TEST_THRESHOLD", 3u32).unwrap(), 12);
+
+        env::set_var("LAB02_TEST_FLAG", "yes");
+        let error = env_parse("LAB02_TEST_FLAG", false).unwrap_err();
+        assert!(error.to_string().contains("LAB02_TEST_FLAG"));
+        assert!(env_parse("LAB02_TEST_FLAG", UnverifiedLogin::Block).is_err());
+    }
+}
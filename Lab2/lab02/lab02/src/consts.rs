@@ -1,8 +1,52 @@
 //! Définition des constantes globales pour l'application.
 
 pub const HTTP_PORT: u16 = 8080; // Port par défaut pour le serveur HTTP.
-pub const USERS_DB_PATH: &str = "./data/users.yaml"; // Chemin de la base de données des utilisateurs.
-pub const EMAILS_DB_PATH: &str = "./data/emails.yaml"; // Chemin de la base de données des emails.
-pub const POSTS_DB_PATH: &str = "./data/posts.yaml"; // Chemin de la base de données des posts.
-pub const UPLOADS_DIR: &str = "./data/uploads"; // Dossier pour les fichiers uploadés.
+pub const HTTPS_PORT: u16 = 8443; // Port par défaut pour le serveur HTTPS.
+pub const DATA_DIR: &str = "./data"; // Dossier de données par défaut.
+pub const USERS_DB_FILE: &str = "users.yaml"; // Base de données des utilisateurs, dans le dossier de données.
+pub const EMAILS_DB_FILE: &str = "emails.yaml"; // Base de données des emails, dans le dossier de données.
+pub const POSTS_DB_FILE: &str = "posts.yaml"; // Base de données des posts, dans le dossier de données.
+pub const TOKENS_DB_FILE: &str = "tokens.yaml"; // Tokens des liens envoyés par email, dans le dossier de données.
+pub const AUDIT_DB_FILE: &str = "audit.yaml"; // Journal des actions de modération, dans le dossier de données.
+pub const REPORTS_DB_FILE: &str = "reports.yaml"; // Signalements des posts, dans le dossier de données.
+pub const FOLLOWS_DB_FILE: &str = "follows.yaml"; // Abonnements entre comptes, dans le dossier de données.
+pub const NOTIFICATIONS_DB_FILE: &str = "notifications.yaml"; // Notifications et préférences de résumé, dans le dossier de données.
+pub const SEARCH_INDEX_FILE: &str = "search_index.yaml"; // Index de recherche des posts, dérivé de leur base, dans le dossier de données.
+pub const LOGIN_DECOY_KEY_FILE: &str = "login_decoy.key"; // Clé des identifiants de passkeys factices des emails inconnus, dans le dossier de données.
+pub const LOCK_FILE: &str = "lab02.lock"; // Verrou tenu par le serveur, dans le dossier de données.
+pub const MODERATION_REASON_MAX_LEN: usize = 500; // Longueur maximale de la raison d'une action de modération
+pub const MODERATION_AUDIT_PAGE_SIZE: usize = 100; // Actions du journal affichées dans la console
+pub const MAX_SCHEDULE_DAYS: u64 = 365; // Délai maximal de publication programmée d'un post
+pub const PUBLISH_SCHEDULED_INTERVAL_SECS: u64 = 30; // Période de publication des posts programmés
+pub const SEARCH_PAGE_SIZE: usize = 20; // Posts trouvés affichés par page de recherche
+pub const SEARCH_SUGGESTIONS_LIMIT: usize = 10; // Comptes et hashtags proposés par une recherche
+pub const NOTIFICATIONS_PER_ACCOUNT: usize = 200; // Notifications gardées par compte, les plus anciennes sont oubliées
+pub const DIGEST_PERIOD_SECS: u64 = 24 * 60 * 60; // Délai minimal entre deux résumés par email d'un compte
+pub const DIGEST_CHECK_INTERVAL_SECS: u64 = 60 * 60; // Période de la tâche d'envoi des résumés
+pub const LIVE_CHANNEL_CAPACITY: usize = 256; // Événements en attente par connexion du fil en direct avant rattrapage
+pub const LIVE_REPLAY_EVENTS: usize = 1024; // Événements gardés pour les reconnexions et les connexions en retard
+pub const LIVE_MAX_CONNECTIONS_PER_USER: usize = 5; // Connexions simultanées au fil en direct par compte
+pub const LIVE_HEARTBEAT_SECS: u64 = 15; // Période des messages de maintien des connexions du fil en direct
+pub const LIVE_RETRY_SECS: u64 = 3; // Délai de reconnexion indiqué aux clients du fil en direct
+pub const SAVED_POSTS_PAGE_SIZE: usize = 20; // Posts enregistrés affichés par page
+pub const REPORT_DETAILS_MAX_LEN: usize = 1000; // Longueur maximale du texte d'un signalement
+pub const RECOVERY_CODE_COUNT: usize = 10; // Codes de récupération générés par série
+pub const RECOVERY_CODE_LEN: usize = 12; // Caractères base32 par code (60 bits)
+pub const SESSION_IDLE_TIMEOUT_SECS: u64 = 7 * 24 * 60 * 60; // Inactivité après laquelle une session est fermée
+pub const ANONYMOUS_SESSION_IDLE_TIMEOUT_SECS: u64 = 60 * 60; // Inactivité après laquelle une session anonyme (jeton CSRF seul) est oubliée
+pub const SESSION_SWEEP_INTERVAL_SECS: u64 = 5 * 60; // Période de retrait des sessions expirées et inactives
+pub const MAX_SESSIONS_PER_USER: usize = 20; // Sessions actives par compte, la moins récemment utilisée est fermée au-delà
+pub const MAX_ACTIVE_SESSIONS: usize = 100_000; // Sessions actives gardées dans le registre
+pub const TOKEN_TTL_SECS: u64 = 24 * 60 * 60; // Validité des liens de validation et de récupération
+pub const MAGIC_LINK_TTL_SECS: u64 = 10 * 60; // Validité d'un lien de connexion
+pub const STEP_UP_WINDOW_SECS: u64 = 300; // Validité d'une ré-authentification
+pub const DECOY_CREDENTIAL_ID_LEN: usize = 16; // Octets d'un identifiant de passkey factice
+pub const MAX_PENDING_CEREMONIES: usize = 10_000; // Cérémonies WebAuthn en attente, par type
+pub const MAX_PENDING_CEREMONIES_PER_OWNER: usize = 5; // Cérémonies en attente par adresse IP, ou par compte pour le step-up
+pub const MAX_PENDING_DISCOVERABLE_PER_IP: usize = 5; // Connexions par passkey découvrable en attente par adresse IP
+pub const CEREMONY_SWEEP_INTERVAL_SECS: u64 = 60; // Période de retrait des cérémonies expirées
+pub const RATE_LIMIT_MAX_ENTRIES: usize = 100_000; // Seaux et compteurs d'échecs gardés en mémoire
+pub const RATE_LIMIT_BODY_LIMIT: usize = 64 * 1024; // Taille maximale d'un corps lu par la limitation de débit
+pub const UNVERIFIED_PURGE_INTERVAL_SECS: u64 = 60 * 60; // Période de suppression des comptes non vérifiés
+pub const UPLOADS_DIR: &str = "uploads"; // Dossier pour les fichiers uploadés, dans le dossier de données.
 
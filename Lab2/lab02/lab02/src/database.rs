@@ -1,15 +1,50 @@
-//! Gestion des bases de données pour les utilisateurs, tokens, et emails.
+//! Gestion des bases de données pour les utilisateurs, tokens, emails et posts.
+//! Chaque base persistée est écrite avec un en-tête de version (voir `migrations`).
+
+pub mod lock;
+pub mod migrations;
+pub mod search;
 
 use std::{
-    collections::HashMap,
+    collections::{BTreeMap, BTreeSet, HashMap},
     fs::{create_dir_all, File},
-    path::Path,
-    sync::RwLock,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        RwLock,
+    },
+    time::{Instant, SystemTime, UNIX_EPOCH},
 };
 use anyhow::{anyhow, Result};
 use serde::{Deserialize, Serialize};
 use serde_yaml::{self, to_writer};
-use crate::consts;
+use crate::metrics;
+use migrations::{Store, Versioned};
+
+/// Indique que toutes les bases ont été chargées depuis le disque
+static LOADED: AtomicBool = AtomicBool::new(false);
+
+/// Charge toutes les bases persistées
+pub fn load_all() -> Result<()> {
+    user::load()?;
+    email::load()?;
+    post::load()?;
+    token::load()?;
+    audit::load()?;
+    report::load()?;
+    follow::load()?;
+    notification::load()?;
+    LOADED.store(true, Ordering::SeqCst);
+    Ok(())
+}
+
+pub fn is_loaded() -> bool {
+    LOADED.load(Ordering::SeqCst)
+}
+
+/// Date courante en secondes Unix, utilisée pour les dates persistées
+pub(crate) fn unix_now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or_default()
+}
 
 // Gestion des utilisateurs
 pub mod user {
@@ -17,6 +52,48 @@ pub mod user {
     use once_cell::sync::Lazy;
     use webauthn_rs::prelude::Passkey;
 
+    /// Rôle d'un compte, du moins au plus privilégié
+    #[derive(Clone, Copy, Serialize, Deserialize, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+    #[serde(rename_all = "snake_case")]
+    pub enum Role {
+        #[default]
+        User,
+        Moderator,
+        Admin,
+    }
+
+    impl Role {
+        pub const ALL: [Role; 3] = [Role::User, Role::Moderator, Role::Admin];
+
+        pub fn as_str(self) -> &'static str {
+            match self {
+                Role::User => "user",
+                Role::Moderator => "moderator",
+                Role::Admin => "admin",
+            }
+        }
+    }
+
+    impl std::str::FromStr for Role {
+        type Err = anyhow::Error;
+
+        fn from_str(value: &str) -> Result<Self> {
+            Role::ALL
+                .into_iter()
+                .find(|role| role.as_str() == value)
+                .ok_or_else(|| anyhow!("Unknown role {:?}", value))
+        }
+    }
+
+    /// Réaction d'un compte à un post
+    #[derive(Clone, Copy, Serialize, Deserialize, Debug, Default, PartialEq, Eq)]
+    pub struct Reaction {
+        /// 1 pour un like, -1 pour un dislike, 0 une fois le vote annulé
+        pub vote: i32,
+        /// Le compte a déjà aimé ce post : l'auteur n'est notifié que du premier like
+        pub liked: bool,
+    }
+
     #[derive(Clone, Serialize, Deserialize, Debug)]
     pub struct User {
         pub first_name: String,
@@ -24,12 +101,52 @@ pub mod user {
         pub email: String,
         pub passkey: Option<Passkey>,
         pub verified: bool,
+        /// Posts enregistrés (identifiants), du plus récent au plus ancien
         pub stash: Vec<String>,
-        pub liked_posts: Vec<u64>,
+        /// Réactions du compte, par post
+        pub liked_posts: BTreeMap<uuid::Uuid, Reaction>,
+        /// Date de création du compte, en secondes Unix
+        pub created_at: u64,
+        /// Empreintes des codes de récupération encore utilisables
+        pub recovery_codes: Vec<String>,
+        /// Connexion par lien envoyé par email, activée par le titulaire
+        pub magic_link: bool,
+        pub role: Role,
+        /// Compte suspendu par la modération : aucune connexion possible
+        pub suspended: bool,
+        /// Incrémentée pour fermer toutes les sessions du compte, y compris depuis l'outil
+        /// d'administration qui n'a pas accès au registre des sessions du serveur
+        pub session_generation: u64,
+        /// Identifiant opaque du compte dans les URL et les listes publiques, à la place de l'email
+        pub handle: String,
     }
 
-    type Db = HashMap<String, User>;
+    impl User {
+        /// Le profil n'est montré qu'une fois l'email vérifié et tant que le compte n'est pas suspendu
+        pub fn is_public(&self) -> bool {
+            self.verified && !self.suspended
+        }
+    }
+
+    /// Nouvel identifiant public, aléatoire pour ne rien révéler de l'email
+    pub(crate) fn new_handle() -> String {
+        uuid::Uuid::new_v4().simple().to_string()
+    }
+
+    pub(crate) type Db = HashMap<String, User>;
     static DB: Lazy<RwLock<Db>> = Lazy::new(Default::default);
+    /// Total des votes de chaque post, calculé depuis les réactions des comptes.
+    /// Toujours verrouillé après `DB` et reconstruit quand des comptes disparaissent.
+    static LIKES: Lazy<RwLock<HashMap<uuid::Uuid, i32>>> = Lazy::new(Default::default);
+
+    fn reindex(db: &Db) -> Result<()> {
+        let mut likes: HashMap<uuid::Uuid, i32> = HashMap::new();
+        for (post_id, reaction) in db.values().flat_map(|user| &user.liked_posts) {
+            *likes.entry(*post_id).or_default() += reaction.vote;
+        }
+        *LIKES.write().or(Err(anyhow!("DB poisoned")))? = likes;
+        Ok(())
+    }
 
     pub fn create(email: &str, first_name: &str, last_name: &str) -> Result<bool> {
         let user = User {
@@ -39,7 +156,14 @@ pub mod user {
             passkey: None,
             verified: false,
             stash: Vec::new(),
-            liked_posts: Vec::new(),
+            liked_posts: BTreeMap::new(),
+            created_at: unix_now(),
+            recovery_codes: Vec::new(),
+            magic_link: false,
+            role: Role::User,
+            suspended: false,
+            session_generation: 0,
+            handle: new_handle(),
         };
 
         let mut db = DB.write().or(Err(anyhow!("DB poisoned")))?;
@@ -61,14 +185,203 @@ pub mod user {
         Ok(())
     }
 
-    pub fn get_passkey(email: &str) -> Result<Option<Passkey>> {
+    pub fn get(email: &str) -> Option<User> {
+        DB.read().ok()?.get(email).cloned()
+    }
+
+    /// Retrouve le compte public d'un identifiant ; un compte inconnu et un compte qui n'est
+    /// pas public donnent tous deux `None`
+    pub fn public_by_handle(handle: &str) -> Option<User> {
+        DB.read().ok()?.values().find(|user| user.handle == handle && user.is_public()).cloned()
+    }
+
+    /// Retrouve un compte par son identifiant, public ou non
+    pub fn by_handle(handle: &str) -> Option<User> {
+        DB.read().ok()?.values().find(|user| user.handle == handle).cloned()
+    }
+
+    /// Copie de tous les comptes, triés par email
+    pub fn all() -> Result<Vec<User>> {
+        let mut users: Vec<User> = DB.read().or(Err(anyhow!("DB poisoned")))?.values().cloned().collect();
+        users.sort_by(|a, b| a.email.cmp(&b.email));
+        Ok(users)
+    }
+
+    /// Retire la passkey d'un compte et ferme ses sessions ; retourne `false` s'il n'en avait pas
+    pub fn revoke_passkey(email: &str) -> Result<bool> {
+        let mut db = DB.write().or(Err(anyhow!("DB poisoned")))?;
+        let user = db.get_mut(email).ok_or_else(|| anyhow!("User not found"))?;
+        if user.passkey.take().is_none() {
+            return Ok(false);
+        }
+        user.session_generation += 1;
+        save(&db)?;
+        Ok(true)
+    }
+
+    /// Ferme toutes les sessions du compte, ouvertes avant cette nouvelle génération
+    pub fn new_session_generation(email: &str) -> Result<()> {
+        let mut db = DB.write().or(Err(anyhow!("DB poisoned")))?;
+        let user = db.get_mut(email).ok_or_else(|| anyhow!("User not found"))?;
+        user.session_generation += 1;
+        save(&db)?;
+        Ok(())
+    }
+
+    /// Remplace les codes de récupération d'un compte par de nouvelles empreintes
+    pub fn set_recovery_codes(email: &str, hashes: Vec<String>) -> Result<()> {
+        let mut db = DB.write().or(Err(anyhow!("DB poisoned")))?;
+        let user = db.get_mut(email).ok_or_else(|| anyhow!("User not found"))?;
+        user.recovery_codes = hashes;
+        save(&db)
+    }
+
+    /// Consomme un code de récupération ; retourne `false` si le compte ou le code est inconnu
+    pub fn redeem_recovery_code(email: &str, hash: &str) -> Result<bool> {
+        let mut db = DB.write().or(Err(anyhow!("DB poisoned")))?;
+        let Some(user) = db.get_mut(email) else {
+            return Ok(false);
+        };
+        let Some(index) = user.recovery_codes.iter().position(|stored| stored == hash) else {
+            return Ok(false);
+        };
+        user.recovery_codes.swap_remove(index);
+        save(&db)?;
+        Ok(true)
+    }
+
+    /// Change le rôle d'un compte
+    pub fn set_role(email: &str, role: Role) -> Result<()> {
+        let mut db = DB.write().or(Err(anyhow!("DB poisoned")))?;
+        let user = db.get_mut(email).ok_or_else(|| anyhow!("User not found"))?;
+        user.role = role;
+        save(&db)
+    }
+
+    /// Suspend ou rétablit un compte
+    pub fn set_suspended(email: &str, suspended: bool) -> Result<()> {
+        let mut db = DB.write().or(Err(anyhow!("DB poisoned")))?;
+        let user = db.get_mut(email).ok_or_else(|| anyhow!("User not found"))?;
+        user.suspended = suspended;
+        save(&db)
+    }
+
+    /// Active ou désactive la connexion par lien pour un compte
+    pub fn set_magic_link(email: &str, enabled: bool) -> Result<()> {
+        let mut db = DB.write().or(Err(anyhow!("DB poisoned")))?;
+        let user = db.get_mut(email).ok_or_else(|| anyhow!("User not found"))?;
+        user.magic_link = enabled;
+        save(&db)
+    }
+
+    /// Enregistre un post en tête des posts enregistrés du compte, ou l'en retire
+    pub fn set_bookmark(email: &str, post_id: &str, saved: bool) -> Result<()> {
+        let mut db = DB.write().or(Err(anyhow!("DB poisoned")))?;
+        let user = db.get_mut(email).ok_or_else(|| anyhow!("User not found"))?;
+        let present = user.stash.iter().any(|id| id == post_id);
+        if present == saved {
+            return Ok(());
+        }
+        if saved {
+            user.stash.insert(0, post_id.to_string());
+        } else {
+            user.stash.retain(|id| id != post_id);
+        }
+        save(&db)
+    }
+
+    /// Retire des posts enregistrés de tous les comptes et retourne le nombre d'entrées retirées
+    pub fn remove_bookmarks(post_ids: &[String]) -> Result<usize> {
+        let mut db = DB.write().or(Err(anyhow!("DB poisoned")))?;
+        let mut removed = 0;
+        for user in db.values_mut() {
+            let before = user.stash.len();
+            user.stash.retain(|id| !post_ids.contains(id));
+            removed += before - user.stash.len();
+        }
+        if removed > 0 {
+            save(&db)?;
+        }
+        Ok(removed)
+    }
+
+    /// Total des votes d'un post
+    pub fn likes(post_id: uuid::Uuid) -> i32 {
+        LIKES.read().ok().and_then(|likes| likes.get(&post_id).copied()).unwrap_or_default()
+    }
+
+    /// Vote `vote` (1 ou -1) pour un post ; le même vote une seconde fois l'annule.
+    /// Retourne le nouveau total du post et si c'est le premier like du compte sur ce post.
+    pub fn react(email: &str, post_id: uuid::Uuid, vote: i32) -> Result<(i32, bool)> {
+        let mut db = DB.write().or(Err(anyhow!("DB poisoned")))?;
+        let user = db.get_mut(email).ok_or_else(|| anyhow!("User not found"))?;
+        let reaction = user.liked_posts.entry(post_id).or_default();
+        let previous = reaction.vote;
+        reaction.vote = if previous == vote { 0 } else { vote };
+        let first_like = reaction.vote == 1 && !reaction.liked;
+        reaction.liked |= reaction.vote == 1;
+        let delta = reaction.vote - previous;
+        save(&db)?;
+
+        let mut likes = LIKES.write().or(Err(anyhow!("DB poisoned")))?;
+        let total = likes.entry(post_id).or_default();
+        *total += delta;
+        Ok((*total, first_like))
+    }
+
+    /// Retrouve le compte propriétaire d'un identifiant de credential (connexion sans email)
+    pub fn find_by_credential(cred_id: &[u8]) -> Result<Option<(String, Passkey)>> {
         let db = DB.read().or(Err(anyhow!("DB poisoned")))?;
-        let user = db.get(email).ok_or_else(|| anyhow!("User not found"))?;
-        Ok(user.passkey.clone())
+        Ok(db.values().find_map(|user| {
+            let passkey = user.passkey.as_ref()?;
+            (passkey.cred_id().as_ref() == cred_id).then(|| (user.email.clone(), passkey.clone()))
+        }))
     }
 
-    pub fn get(email: &str) -> Option<User> {
-        DB.read().ok()?.get(email).cloned()
+    /// Supprime un compte
+    pub fn delete(email: &str) -> Result<bool> {
+        let mut db = DB.write().or(Err(anyhow!("DB poisoned")))?;
+        if db.remove(email).is_none() {
+            return Ok(false);
+        }
+        save(&db)?;
+        reindex(&db)?;
+        Ok(true)
+    }
+
+    /// Supprime les comptes non vérifiés créés avant `created_before` et retourne leurs emails
+    pub fn purge_unverified(created_before: u64) -> Result<Vec<String>> {
+        let mut db = DB.write().or(Err(anyhow!("DB poisoned")))?;
+        let purged: Vec<String> = db
+            .values()
+            .filter(|user| !user.verified && user.created_at < created_before)
+            .map(|user| user.email.clone())
+            .collect();
+        if purged.is_empty() {
+            return Ok(purged);
+        }
+        for email in &purged {
+            db.remove(email);
+        }
+        save(&db)?;
+        reindex(&db)?;
+        Ok(purged)
+    }
+
+    #[cfg(test)]
+    pub fn set_created_at(email: &str, created_at: u64) -> Result<()> {
+        let mut db = DB.write().or(Err(anyhow!("DB poisoned")))?;
+        db.get_mut(email).ok_or_else(|| anyhow!("User not found"))?.created_at = created_at;
+        save(&db)
+    }
+
+    #[cfg(test)]
+    pub fn set_name(email: &str, first_name: &str, last_name: &str) -> Result<()> {
+        let mut db = DB.write().or(Err(anyhow!("DB poisoned")))?;
+        let user = db.get_mut(email).ok_or_else(|| anyhow!("User not found"))?;
+        user.first_name = first_name.to_string();
+        user.last_name = last_name.to_string();
+        save(&db)
     }
 
     pub fn exists(email: &str) -> Result<bool> {
@@ -89,32 +402,122 @@ pub mod user {
     }
 
     pub fn load() -> Result<()> {
-        super::load(&DB, consts::USERS_DB_PATH)
+        super::load(&DB, Store::Users)?;
+        let db = DB.read().or(Err(anyhow!("DB poisoned")))?;
+        reindex(&db)
     }
 
     fn save(db: &Db) -> Result<()> {
-        super::save(db, consts::USERS_DB_PATH)
+        super::save(db, Store::Users)
     }
 }
 
-/// Gestion des tokens
+/// Gestion des tokens (liens de validation et de récupération)
 pub mod token {
     use super::*;
     use once_cell::sync::Lazy;
+    use crate::consts;
+
+    /// Usage d'un token : un lien n'est accepté que par la route qui l'a émis
+    #[derive(Clone, Copy, Serialize, Deserialize, Debug, PartialEq, Eq)]
+    #[serde(rename_all = "snake_case")]
+    pub enum Purpose {
+        Validation,
+        Recovery,
+        MagicLink,
+    }
+
+    impl Purpose {
+        /// Durée de validité d'un lien émis pour cet usage, en secondes
+        pub fn ttl(self) -> u64 {
+            match self {
+                Purpose::Validation | Purpose::Recovery => consts::TOKEN_TTL_SECS,
+                Purpose::MagicLink => consts::MAGIC_LINK_TTL_SECS,
+            }
+        }
+    }
 
-    type Db = HashMap<String, String>;
+    #[derive(Clone, Serialize, Deserialize, Debug)]
+    pub struct Token {
+        pub email: String,
+        pub purpose: Purpose,
+        /// Date d'expiration, en secondes Unix
+        pub expires_at: u64,
+    }
+
+    impl Token {
+        pub fn is_expired(&self) -> bool {
+            unix_now() >= self.expires_at
+        }
+    }
+
+    pub(crate) type Db = HashMap<String, Token>;
     static DB: Lazy<RwLock<Db>> = Lazy::new(Default::default);
 
-    pub fn generate(email: &str) -> Result<String> {
+    pub fn generate(email: &str, purpose: Purpose) -> Result<String> {
         let token = uuid::Uuid::new_v4().to_string();
         let mut db = DB.write().or(Err(anyhow!("DB poisoned")))?;
-        db.insert(token.clone(), email.to_string());
+        let stored = Token { email: email.to_string(), purpose, expires_at: unix_now() + purpose.ttl() };
+        db.insert(token.clone(), stored);
+        save(&db)?;
         Ok(token)
     }
 
-    pub fn consume(token: &str) -> Result<String> {
+    /// Consomme un token émis pour `purpose` et retourne l'email associé
+    pub fn consume(token: &str, purpose: Purpose) -> Result<String> {
         let mut db = DB.write().or(Err(anyhow!("DB poisoned")))?;
-        db.remove(token).ok_or_else(|| anyhow!("Token not found"))
+        match db.get(token) {
+            Some(stored) if stored.purpose == purpose => {}
+            _ => return Err(anyhow!("Token not found")),
+        }
+        let stored = db.remove(token).ok_or_else(|| anyhow!("Token not found"))?;
+        save(&db)?;
+        if stored.is_expired() {
+            return Err(anyhow!("Token expired"));
+        }
+        Ok(stored.email)
+    }
+
+    /// Invalide les tokens d'un compte pour un usage, ou pour tous si `purpose` est `None`
+    pub fn revoke(email: &str, purpose: Option<Purpose>) -> Result<usize> {
+        let mut db = DB.write().or(Err(anyhow!("DB poisoned")))?;
+        let before = db.len();
+        db.retain(|_, stored| stored.email != email || purpose.is_some_and(|purpose| stored.purpose != purpose));
+        let revoked = before - db.len();
+        if revoked > 0 {
+            save(&db)?;
+        }
+        Ok(revoked)
+    }
+
+    /// Tokens encore en attente pour un compte
+    pub fn for_email(email: &str) -> Result<Vec<(String, Token)>> {
+        let db = DB.read().or(Err(anyhow!("DB poisoned")))?;
+        Ok(db
+            .iter()
+            .filter(|(_, stored)| stored.email == email)
+            .map(|(token, stored)| (token.clone(), stored.clone()))
+            .collect())
+    }
+
+    /// Supprime les tokens expirés et retourne leur nombre
+    pub fn purge_expired() -> Result<usize> {
+        let mut db = DB.write().or(Err(anyhow!("DB poisoned")))?;
+        let before = db.len();
+        db.retain(|_, stored| !stored.is_expired());
+        let purged = before - db.len();
+        if purged > 0 {
+            save(&db)?;
+        }
+        Ok(purged)
+    }
+
+    pub fn load() -> Result<()> {
+        super::load(&DB, Store::Tokens)
+    }
+
+    fn save(db: &Db) -> Result<()> {
+        super::save(db, Store::Tokens)
     }
 }
 
@@ -132,7 +535,7 @@ pub mod email {
     }
 
     #[derive(Default, Serialize, Deserialize)]
-    struct Db {
+    pub(crate) struct Db {
         pub next_pk: u64,
         pub emails: HashMap<u64, Email>,
     }
@@ -156,30 +559,859 @@ pub mod email {
         Ok(())
     }
 
+    /// Tous les emails, du plus ancien au plus récent
+    pub fn all() -> Result<Vec<Email>> {
+        let db = DB.read().or(Err(anyhow!("DB poisoned")))?;
+        let mut emails: Vec<Email> = db.emails.values().cloned().collect();
+        emails.sort_by_key(|email| email.pk);
+        Ok(emails)
+    }
+
+    pub fn get(pk: u64) -> Result<Option<Email>> {
+        Ok(DB.read().or(Err(anyhow!("DB poisoned")))?.emails.get(&pk).cloned())
+    }
+
+    /// Emails envoyés à un destinataire, du plus ancien au plus récent
+    pub fn sent_to(to: &str) -> Result<Vec<Email>> {
+        let db = DB.read().or(Err(anyhow!("DB poisoned")))?;
+        let mut emails: Vec<Email> = db.emails.values().filter(|email| email.to == to).cloned().collect();
+        emails.sort_by_key(|email| email.pk);
+        Ok(emails)
+    }
+
+    /// Nombre d'emails dans la boîte d'envoi
+    pub fn count() -> Result<usize> {
+        Ok(DB.read().or(Err(anyhow!("DB poisoned")))?.emails.len())
+    }
+
     pub fn load() -> Result<()> {
-        super::load(&DB, consts::EMAILS_DB_PATH)
+        super::load(&DB, Store::Emails)
     }
 
     fn save(db: &Db) -> Result<()> {
-        super::save(db, consts::EMAILS_DB_PATH)
+        super::save(db, Store::Emails)
     }
 }
 
+// Gestion des posts
 pub mod post {
     use super::*;
+    use once_cell::sync::Lazy;
+    use uuid::Uuid;
+    use super::search::{self, Query};
+
+    /// Public visé par un post publié
+    #[derive(Clone, Copy, Serialize, Deserialize, Debug, Default, PartialEq, Eq)]
+    #[serde(rename_all = "snake_case")]
+    pub enum Visibility {
+        /// Tous les comptes connectés
+        #[default]
+        Public,
+        /// Les abonnés de l'auteur
+        Followers,
+        /// L'auteur seul
+        Private,
+    }
 
+    impl Visibility {
+        pub const ALL: [Visibility; 3] = [Visibility::Public, Visibility::Followers, Visibility::Private];
+
+        pub fn as_str(self) -> &'static str {
+            match self {
+                Visibility::Public => "public",
+                Visibility::Followers => "followers",
+                Visibility::Private => "private",
+            }
+        }
+    }
+
+    impl std::str::FromStr for Visibility {
+        type Err = anyhow::Error;
+
+        fn from_str(value: &str) -> Result<Self> {
+            Visibility::ALL
+                .into_iter()
+                .find(|visibility| visibility.as_str() == value)
+                .ok_or_else(|| anyhow!("Unknown visibility {:?}", value))
+        }
+    }
+
+    /// Étape de publication d'un post. Seul l'auteur voit ses brouillons et ses posts programmés.
+    #[derive(Clone, Copy, Serialize, Deserialize, Debug, PartialEq, Eq)]
+    #[serde(rename_all = "snake_case")]
+    pub enum Status {
+        Draft,
+        /// Publié par une tâche de fond à la date `publish_at`
+        Scheduled,
+        Published,
+    }
+
+    /// Modèle représentant un post. Ses likes sont comptés depuis les réactions des comptes (`user::likes`).
     #[derive(Clone, Serialize, Deserialize, Debug)]
     pub struct Post {
-        pub id: String,
-        pub text: String,
-        pub image_path: String,
-        pub likes: i32,
+        pub id: Uuid,
+        /// Email de l'auteur, `None` pour les posts publiés avant le suivi des auteurs
+        pub author: Option<String>,
+        pub content: String,
+        pub image_path: Option<String>,
+        /// Masqué par la modération : absent du fil, conservé pour pouvoir le rétablir
+        pub hidden: bool,
+        pub visibility: Visibility,
+        pub status: Status,
+        /// Date de publication, prévue ou effective, en secondes Unix
+        pub publish_at: Option<u64>,
+    }
+
+    impl Post {
+        pub fn is_published(&self) -> bool {
+            self.status == Status::Published
+        }
+
+        /// Indique si `viewer` peut voir ce post, et son image. L'auteur voit tous ses posts
+        /// non masqués ; les autres comptes ne voient que les posts publiés qui leur sont destinés.
+        pub fn can_view(&self, viewer: &str) -> bool {
+            if self.hidden {
+                return false;
+            }
+            if self.author.as_deref() == Some(viewer) {
+                return true;
+            }
+            if !self.is_published() {
+                return false;
+            }
+            match self.visibility {
+                Visibility::Public => true,
+                Visibility::Followers => self
+                    .author
+                    .as_deref()
+                    .is_some_and(|author| super::follow::is_following(viewer, author).unwrap_or(false)),
+                Visibility::Private => false,
+            }
+        }
+
+        /// Post publié que `viewer` peut voir dans un fil
+        pub fn in_feed_of(&self, viewer: &str) -> bool {
+            self.is_published() && self.can_view(viewer)
+        }
+    }
+
+    pub(crate) type Db = Vec<Post>;
+    static DB: Lazy<RwLock<Db>> = Lazy::new(Default::default);
+    /// Positions des posts de chaque auteur dans `DB`, dans l'ordre du fil (ordre de publication).
+    /// Toujours verrouillé après `DB` et reconstruit quand des positions changent.
+    static BY_AUTHOR: Lazy<RwLock<HashMap<String, Vec<usize>>>> = Lazy::new(Default::default);
+
+    fn reindex(db: &Db) -> Result<()> {
+        let mut index: HashMap<String, Vec<usize>> = HashMap::new();
+        for (position, post) in db.iter().enumerate() {
+            if let Some(author) = &post.author {
+                index.entry(author.clone()).or_default().push(position);
+            }
+        }
+        *BY_AUTHOR.write().or(Err(anyhow!("DB poisoned")))? = index;
+        Ok(())
+    }
+
+    /// Posts des auteurs donnés retenus par `keep`, dans l'ordre du fil, sans parcourir les autres posts
+    fn by_authors<'a>(authors: impl IntoIterator<Item = &'a String>, keep: impl Fn(&Post) -> bool) -> Result<Vec<Post>> {
+        let db = DB.read().or(Err(anyhow!("DB poisoned")))?;
+        let index = BY_AUTHOR.read().or(Err(anyhow!("DB poisoned")))?;
+        let mut positions: Vec<usize> = authors
+            .into_iter()
+            .filter_map(|author| index.get(author))
+            .flatten()
+            .copied()
+            .collect();
+        positions.sort_unstable();
+        Ok(positions.into_iter().map(|position| &db[position]).filter(|post| keep(post)).cloned().collect())
+    }
+
+    /// Posts des auteurs donnés que `viewer` peut voir dans un fil
+    pub fn visible_by_authors<'a>(authors: impl IntoIterator<Item = &'a String>, viewer: &str) -> Result<Vec<Post>> {
+        by_authors(authors, |post| post.in_feed_of(viewer))
+    }
+
+    /// Brouillons et posts programmés d'un auteur
+    pub fn unpublished(author: &str) -> Result<Vec<Post>> {
+        by_authors([&author.to_string()], |post| !post.hidden && !post.is_published())
+    }
+
+    /// Retourne une copie de tous les posts
+    pub fn all() -> Result<Vec<Post>> {
+        Ok(DB.read().or(Err(anyhow!("DB poisoned")))?.clone())
+    }
+
+    /// Posts que `viewer` peut voir dans le fil global
+    pub fn visible_to(viewer: &str) -> Result<Vec<Post>> {
+        Ok(DB.read().or(Err(anyhow!("DB poisoned")))?.iter().filter(|post| post.in_feed_of(viewer)).cloned().collect())
+    }
+
+    /// Retourne une copie du post `id`
+    pub fn get(id: Uuid) -> Result<Option<Post>> {
+        Ok(DB.read().or(Err(anyhow!("DB poisoned")))?.iter().find(|post| post.id == id).cloned())
+    }
+
+    /// Ajoute un nouveau post et retourne son identifiant.
+    /// Sans date, le post est un brouillon ; une date future le programme.
+    pub fn create(author: &str, content: &str, image_path: Option<&str>, visibility: Visibility, publish_at: Option<u64>) -> Result<Uuid> {
+        let status = match publish_at {
+            None => Status::Draft,
+            Some(at) if at > unix_now() => Status::Scheduled,
+            Some(_) => Status::Published,
+        };
+        let post = Post {
+            id: Uuid::new_v4(),
+            author: Some(author.to_string()),
+            content: content.to_string(),
+            image_path: image_path.map(str::to_string),
+            hidden: false,
+            visibility,
+            status,
+            publish_at,
+        };
+        let id = post.id;
+
+        let mut db = DB.write().or(Err(anyhow!("DB poisoned")))?;
+        db.push(post);
+        save(&db)?;
+        let mut index = BY_AUTHOR.write().or(Err(anyhow!("DB poisoned")))?;
+        index.entry(author.to_string()).or_default().push(db.len() - 1);
+        search::index_post(id, content)?;
+        Ok(id)
+    }
+
+    /// Applique `f` au post `id` puis persiste la base. Retourne `None` si le post n'existe pas.
+    pub fn update<R>(id: Uuid, f: impl FnOnce(&mut Post) -> R) -> Result<Option<R>> {
+        let mut db = DB.write().or(Err(anyhow!("DB poisoned")))?;
+        let Some(post) = db.iter_mut().find(|post| post.id == id) else {
+            return Ok(None);
+        };
+
+        let content = post.content.clone();
+        let result = f(post);
+        let edited = (post.content != content).then(|| post.content.clone());
+        save(&db)?;
+        if let Some(content) = edited {
+            search::index_post(id, &content)?;
+        }
+        Ok(Some(result))
+    }
+
+    /// Publie maintenant un brouillon ou un post programmé de `author`. Le fil suit l'ordre de
+    /// publication : le post quitte sa place de création pour la fin du fil.
+    /// Retourne `false` si l'auteur n'a pas de tel post.
+    pub fn publish(id: Uuid, author: &str, now: u64) -> Result<bool> {
+        let mut db = DB.write().or(Err(anyhow!("DB poisoned")))?;
+        let Some(position) = db
+            .iter()
+            .position(|post| post.id == id && post.author.as_deref() == Some(author) && !post.hidden && !post.is_published())
+        else {
+            return Ok(false);
+        };
+        let mut post = db.remove(position);
+        post.status = Status::Published;
+        post.publish_at = Some(now);
+        db.push(post);
+        save(&db)?;
+        reindex(&db)?;
+        Ok(true)
+    }
+
+    /// Publie les posts programmés dont la date est passée, les place en fin de fil dans l'ordre
+    /// de leur date et retourne leurs identifiants dans cet ordre
+    pub fn publish_due(now: u64) -> Result<Vec<Uuid>> {
+        let mut db = DB.write().or(Err(anyhow!("DB poisoned")))?;
+        let is_due = |post: &Post| post.status == Status::Scheduled && post.publish_at.is_some_and(|at| at <= now);
+        if !db.iter().any(is_due) {
+            return Ok(vec![]);
+        }
+        let (mut due, kept): (Vec<Post>, Vec<Post>) = std::mem::take(&mut *db).into_iter().partition(is_due);
+        due.sort_by_key(|post| post.publish_at);
+        for post in &mut due {
+            post.status = Status::Published;
+        }
+        let published = due.iter().map(|post| post.id).collect();
+        *db = kept;
+        db.extend(due);
+        save(&db)?;
+        reindex(&db)?;
+        Ok(published)
+    }
+
+    /// Supprime un post et retourne son contenu, `None` s'il n'existe pas
+    pub fn delete(id: Uuid) -> Result<Option<Post>> {
+        let mut db = DB.write().or(Err(anyhow!("DB poisoned")))?;
+        let Some(index) = db.iter().position(|post| post.id == id) else {
+            return Ok(None);
+        };
+        let post = db.remove(index);
+        save(&db)?;
+        reindex(&db)?;
+        search::remove_post(id)?;
+        Ok(Some(post))
+    }
+
+    /// Supprime tous les posts d'un auteur, brouillons et posts masqués compris, et les retourne
+    pub fn remove_author(author: &str) -> Result<Vec<Post>> {
+        let mut db = DB.write().or(Err(anyhow!("DB poisoned")))?;
+        let (removed, kept): (Vec<Post>, Vec<Post>) =
+            std::mem::take(&mut *db).into_iter().partition(|post| post.author.as_deref() == Some(author));
+        *db = kept;
+        if removed.is_empty() {
+            return Ok(removed);
+        }
+        save(&db)?;
+        reindex(&db)?;
+        for post in &removed {
+            search::remove_post(post.id)?;
+        }
+        Ok(removed)
+    }
+
+    /// Posts trouvés par une recherche que `viewer` peut voir dans un fil, les plus pertinents
+    /// d'abord puis les derniers publiés, comme dans le fil
+    pub fn search(query: &Query, viewer: &str) -> Result<Vec<Post>> {
+        if query.is_empty() {
+            return Ok(vec![]);
+        }
+        let db = DB.read().or(Err(anyhow!("DB poisoned")))?;
+        let matches = search::INDEX.read().or(Err(anyhow!("DB poisoned")))?.matches(query);
+        let mut found: Vec<(u32, usize, &Post)> = db
+            .iter()
+            .enumerate()
+            .filter_map(|(position, post)| {
+                let score = match &matches {
+                    Some(matches) => *matches.get(&post.id)?,
+                    None => 0,
+                };
+                Some((score, position, post))
+            })
+            .filter(|(_, _, post)| query.author.is_none() || post.author == query.author)
+            .filter(|(_, _, post)| post.in_feed_of(viewer))
+            .collect();
+        found.sort_by_key(|(score, position, _)| std::cmp::Reverse((*score, *position)));
+        Ok(found.into_iter().map(|(_, _, post)| post.clone()).collect())
+    }
+
+    /// Hashtags qui commencent par `prefix` et nombre de posts que `viewer` voit avec chacun,
+    /// les plus utilisés d'abord
+    pub fn search_tags(prefix: &str, viewer: &str) -> Result<Vec<(String, usize)>> {
+        let db = DB.read().or(Err(anyhow!("DB poisoned")))?;
+        let index = search::INDEX.read().or(Err(anyhow!("DB poisoned")))?;
+        let posts: HashMap<Uuid, &Post> = db.iter().map(|post| (post.id, post)).collect();
+        let mut tags: Vec<(String, usize)> = index
+            .tags_with_prefix(prefix)
+            .map(|(tag, ids)| {
+                let count = ids.iter().filter(|id| posts.get(id).is_some_and(|post| post.in_feed_of(viewer))).count();
+                (tag.clone(), count)
+            })
+            .filter(|(_, count)| *count > 0)
+            .collect();
+        tags.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        Ok(tags)
+    }
+
+    /// Reconstruit l'index de recherche depuis la base et retourne le nombre de posts indexés
+    pub fn rebuild_search_index() -> Result<usize> {
+        let db = DB.read().or(Err(anyhow!("DB poisoned")))?;
+        search::rebuild(&db)?;
+        Ok(db.len())
+    }
+
+    pub fn load() -> Result<()> {
+        super::load(&DB, Store::Posts)?;
+        let db = DB.read().or(Err(anyhow!("DB poisoned")))?;
+        reindex(&db)?;
+        search::load(&db)
+    }
+
+    fn save(db: &Db) -> Result<()> {
+        super::save(db, Store::Posts)
+    }
+}
+
+/// Signalements des posts par les utilisateurs
+pub mod report {
+    use super::*;
+    use once_cell::sync::Lazy;
+    use uuid::Uuid;
+
+    /// Motif d'un signalement
+    #[derive(Clone, Copy, Serialize, Deserialize, Debug, PartialEq, Eq)]
+    #[serde(rename_all = "snake_case")]
+    pub enum Category {
+        Spam,
+        Harassment,
+        Illegal,
+        Other,
+    }
+
+    impl Category {
+        pub const ALL: [Category; 4] = [Category::Spam, Category::Harassment, Category::Illegal, Category::Other];
+
+        pub fn as_str(self) -> &'static str {
+            match self {
+                Category::Spam => "spam",
+                Category::Harassment => "harassment",
+                Category::Illegal => "illegal",
+                Category::Other => "other",
+            }
+        }
+    }
+
+    impl std::str::FromStr for Category {
+        type Err = anyhow::Error;
+
+        fn from_str(value: &str) -> Result<Self> {
+            Category::ALL
+                .into_iter()
+                .find(|category| category.as_str() == value)
+                .ok_or_else(|| anyhow!("Unknown report category {:?}", value))
+        }
+    }
+
+    /// Décision de la modération sur un post signalé
+    #[derive(Clone, Copy, Serialize, Deserialize, Debug, PartialEq, Eq)]
+    #[serde(rename_all = "snake_case")]
+    pub enum Outcome {
+        /// Le post reste masqué
+        Removed,
+        /// Le signalement est rejeté, le post est visible
+        Dismissed,
+    }
+
+    #[derive(Clone, Serialize, Deserialize, Debug)]
+    pub struct Report {
+        pub post_id: Uuid,
+        pub reporter: String,
+        pub category: Category,
+        pub details: String,
+        /// Date du signalement, en secondes Unix
+        pub at: u64,
+        /// `None` tant que la modération n'a pas traité le signalement
+        pub outcome: Option<Outcome>,
+    }
+
+    pub(crate) type Db = Vec<Report>;
+    static DB: Lazy<RwLock<Db>> = Lazy::new(Default::default);
+
+    /// Enregistre un signalement et retourne le nombre de signalements ouverts sur le post,
+    /// ou `None` si ce compte a déjà signalé ce post, même si la modération a tranché depuis :
+    /// un signalement rejeté ne peut pas être renouvelé pour masquer le post de nouveau
+    pub fn add(post_id: Uuid, reporter: &str, category: Category, details: &str) -> Result<Option<usize>> {
+        let mut db = DB.write().or(Err(anyhow!("DB poisoned")))?;
+        if db.iter().any(|report| report.post_id == post_id && report.reporter == reporter) {
+            return Ok(None);
+        }
+        let open_on_post = |report: &&Report| report.post_id == post_id && report.outcome.is_none();
+
+        db.push(Report {
+            post_id,
+            reporter: reporter.to_string(),
+            category,
+            details: details.to_string(),
+            at: unix_now(),
+            outcome: None,
+        });
+        let count = db.iter().filter(open_on_post).count();
+        save(&db)?;
+        Ok(Some(count))
+    }
+
+    /// Signalements en attente de traitement
+    pub fn open() -> Result<Vec<Report>> {
+        let db = DB.read().or(Err(anyhow!("DB poisoned")))?;
+        Ok(db.iter().filter(|report| report.outcome.is_none()).cloned().collect())
+    }
+
+    /// Clôture les signalements ouverts d'un post et retourne leurs auteurs
+    pub fn resolve(post_id: Uuid, outcome: Outcome) -> Result<Vec<String>> {
+        let mut db = DB.write().or(Err(anyhow!("DB poisoned")))?;
+        let mut reporters = vec![];
+        for report in db.iter_mut().filter(|report| report.post_id == post_id && report.outcome.is_none()) {
+            report.outcome = Some(outcome);
+            reporters.push(report.reporter.clone());
+        }
+        if !reporters.is_empty() {
+            save(&db)?;
+        }
+        Ok(reporters)
+    }
+
+    /// Oublie les signalements faits par un compte supprimé et ceux qui visent ses posts
+    pub fn remove_account(email: &str, post_ids: &[Uuid]) -> Result<()> {
+        let mut db = DB.write().or(Err(anyhow!("DB poisoned")))?;
+        let before = db.len();
+        db.retain(|report| report.reporter != email && !post_ids.contains(&report.post_id));
+        if db.len() != before {
+            save(&db)?;
+        }
+        Ok(())
+    }
+
+    pub fn load() -> Result<()> {
+        super::load(&DB, Store::Reports)
+    }
+
+    fn save(db: &Db) -> Result<()> {
+        super::save(db, Store::Reports)
+    }
+}
+
+/// Abonnements entre comptes
+pub mod follow {
+    use super::*;
+    use once_cell::sync::Lazy;
+
+    /// Comptes suivis, par compte abonné
+    pub(crate) type Db = HashMap<String, BTreeSet<String>>;
+    static DB: Lazy<RwLock<Db>> = Lazy::new(Default::default);
+    /// Index inverse de `DB` : abonnés de chaque compte, reconstruit au chargement.
+    /// Toujours verrouillé après `DB`.
+    static FOLLOWERS: Lazy<RwLock<Db>> = Lazy::new(Default::default);
+
+    /// Abonne `follower` à `followee` ; retourne `false` s'il l'était déjà
+    pub fn follow(follower: &str, followee: &str) -> Result<bool> {
+        let mut db = DB.write().or(Err(anyhow!("DB poisoned")))?;
+        if !db.entry(follower.to_string()).or_default().insert(followee.to_string()) {
+            return Ok(false);
+        }
+        save(&db)?;
+        let mut followers = FOLLOWERS.write().or(Err(anyhow!("DB poisoned")))?;
+        followers.entry(followee.to_string()).or_default().insert(follower.to_string());
+        Ok(true)
+    }
+
+    /// Désabonne `follower` de `followee` ; retourne `false` s'il ne le suivait pas
+    pub fn unfollow(follower: &str, followee: &str) -> Result<bool> {
+        let mut db = DB.write().or(Err(anyhow!("DB poisoned")))?;
+        let removed = db.get_mut(follower).is_some_and(|following| following.remove(followee));
+        if !removed {
+            return Ok(false);
+        }
+        db.retain(|_, following| !following.is_empty());
+        save(&db)?;
+        let mut followers = FOLLOWERS.write().or(Err(anyhow!("DB poisoned")))?;
+        if let Some(set) = followers.get_mut(followee) {
+            set.remove(follower);
+            if set.is_empty() {
+                followers.remove(followee);
+            }
+        }
+        Ok(true)
+    }
+
+    /// Comptes suivis par `email`
+    pub fn following(email: &str) -> Result<BTreeSet<String>> {
+        Ok(DB.read().or(Err(anyhow!("DB poisoned")))?.get(email).cloned().unwrap_or_default())
+    }
+
+    /// Abonnés de `email`
+    pub fn followers(email: &str) -> Result<BTreeSet<String>> {
+        Ok(FOLLOWERS.read().or(Err(anyhow!("DB poisoned")))?.get(email).cloned().unwrap_or_default())
+    }
+
+    pub fn is_following(follower: &str, followee: &str) -> Result<bool> {
+        Ok(DB.read().or(Err(anyhow!("DB poisoned")))?.get(follower).is_some_and(|following| following.contains(followee)))
+    }
+
+    /// Retire un compte supprimé du graphe, dans les deux sens
+    pub fn remove_account(email: &str) -> Result<()> {
+        let mut db = DB.write().or(Err(anyhow!("DB poisoned")))?;
+        let mut changed = db.remove(email).is_some();
+        for following in db.values_mut() {
+            changed |= following.remove(email);
+        }
+        if !changed {
+            return Ok(());
+        }
+        db.retain(|_, following| !following.is_empty());
+        save(&db)?;
+        reindex(&db)
+    }
+
+    fn reindex(db: &Db) -> Result<()> {
+        let mut followers = Db::new();
+        for (follower, following) in db {
+            for followee in following {
+                followers.entry(followee.clone()).or_default().insert(follower.clone());
+            }
+        }
+        *FOLLOWERS.write().or(Err(anyhow!("DB poisoned")))? = followers;
+        Ok(())
+    }
+
+    pub fn load() -> Result<()> {
+        super::load(&DB, Store::Follows)?;
+        let db = DB.read().or(Err(anyhow!("DB poisoned")))?;
+        reindex(&db)
+    }
+
+    fn save(db: &Db) -> Result<()> {
+        super::save(db, Store::Follows)
+    }
+}
+
+/// Notifications de chaque compte et préférences de résumé par email
+pub mod notification {
+    use super::*;
+    use once_cell::sync::Lazy;
+    use uuid::Uuid;
+
+    /// Événement notifié au compte concerné
+    #[derive(Clone, Copy, Serialize, Deserialize, Debug, PartialEq, Eq, PartialOrd, Ord)]
+    #[serde(rename_all = "snake_case")]
+    pub enum Kind {
+        /// Un post du compte a été liké
+        Like,
+        /// Le compte est mentionné (`@email`) dans un post publié qu'il peut voir
+        Mention,
+        /// Un compte s'est abonné
+        Follow,
+    }
+
+    impl Kind {
+        pub const ALL: [Kind; 3] = [Kind::Like, Kind::Mention, Kind::Follow];
+
+        pub fn as_str(self) -> &'static str {
+            match self {
+                Kind::Like => "like",
+                Kind::Mention => "mention",
+                Kind::Follow => "follow",
+            }
+        }
+    }
+
+    impl std::str::FromStr for Kind {
+        type Err = anyhow::Error;
+
+        fn from_str(value: &str) -> Result<Self> {
+            Kind::ALL
+                .into_iter()
+                .find(|kind| kind.as_str() == value)
+                .ok_or_else(|| anyhow!("Unknown notification kind {:?}", value))
+        }
+    }
+
+    #[derive(Clone, Serialize, Deserialize, Debug)]
+    pub struct Notification {
+        pub id: Uuid,
+        pub kind: Kind,
+        /// Compte à l'origine de l'événement
+        pub actor: String,
+        pub post_id: Option<Uuid>,
+        pub created_at: u64,
+        pub read: bool,
+        /// Déjà inclus dans un résumé par email
+        pub emailed: bool,
+    }
+
+    /// Notifications d'un compte, de la plus ancienne à la plus récente, et ses préférences
+    #[derive(Clone, Default, Serialize, Deserialize, Debug)]
+    pub struct Inbox {
+        pub notifications: Vec<Notification>,
+        /// Événements repris dans le résumé quotidien par email, aucun par défaut
+        pub digest: BTreeSet<Kind>,
+        /// Date du dernier résumé envoyé
+        pub digest_sent_at: Option<u64>,
+    }
+
+    pub(crate) type Db = HashMap<String, Inbox>;
+    static DB: Lazy<RwLock<Db>> = Lazy::new(Default::default);
+
+    /// Notifie `recipient` d'un événement causé par `actor`. Un compte n'est pas notifié de ses
+    /// propres actions ni d'un événement identique encore non lu ; retourne `false` dans ce cas.
+    pub fn add(recipient: &str, kind: Kind, actor: &str, post_id: Option<Uuid>) -> Result<bool> {
+        if recipient == actor {
+            return Ok(false);
+        }
+        let mut db = DB.write().or(Err(anyhow!("DB poisoned")))?;
+        let inbox = db.entry(recipient.to_string()).or_default();
+        let repeated = inbox
+            .notifications
+            .iter()
+            .any(|n| !n.read && n.kind == kind && n.actor == actor && n.post_id == post_id);
+        if repeated {
+            return Ok(false);
+        }
+
+        inbox.notifications.push(Notification {
+            id: Uuid::new_v4(),
+            kind,
+            actor: actor.to_string(),
+            post_id,
+            created_at: unix_now(),
+            read: false,
+            emailed: false,
+        });
+        // Les plus anciennes sont oubliées au-delà de la limite
+        let excess = inbox.notifications.len().saturating_sub(crate::consts::NOTIFICATIONS_PER_ACCOUNT);
+        inbox.notifications.drain(..excess);
+        save(&db)?;
+        Ok(true)
+    }
+
+    /// Notifications et préférences d'un compte
+    pub fn inbox(email: &str) -> Result<Inbox> {
+        Ok(DB.read().or(Err(anyhow!("DB poisoned")))?.get(email).cloned().unwrap_or_default())
+    }
+
+    pub fn unread_count(email: &str) -> Result<usize> {
+        let db = DB.read().or(Err(anyhow!("DB poisoned")))?;
+        Ok(db.get(email).map_or(0, |inbox| inbox.notifications.iter().filter(|n| !n.read).count()))
+    }
+
+    /// Marque comme lue la notification `id`, ou toutes si `id` vaut `None`.
+    /// Retourne le nombre de notifications qui n'étaient pas encore lues.
+    pub fn mark_read(email: &str, id: Option<Uuid>) -> Result<usize> {
+        let mut db = DB.write().or(Err(anyhow!("DB poisoned")))?;
+        let Some(inbox) = db.get_mut(email) else {
+            return Ok(0);
+        };
+        let mut marked = 0;
+        for notification in inbox.notifications.iter_mut().filter(|n| !n.read && id.is_none_or(|id| n.id == id)) {
+            notification.read = true;
+            marked += 1;
+        }
+        if marked > 0 {
+            save(&db)?;
+        }
+        Ok(marked)
+    }
+
+    /// Choisit les événements repris dans le résumé quotidien ; vide pour ne plus en recevoir
+    pub fn set_digest(email: &str, kinds: BTreeSet<Kind>) -> Result<()> {
+        let mut db = DB.write().or(Err(anyhow!("DB poisoned")))?;
+        db.entry(email.to_string()).or_default().digest = kinds;
+        save(&db)
+    }
+
+    /// Résumés dus à `now` : pour chaque compte dont le dernier résumé date d'au moins
+    /// `period` secondes, ses notifications non lues, pas encore envoyées, des types choisis.
+    /// Rien n'est marqué ici : `mark_emailed` le fait une fois le résumé envoyé.
+    pub fn due_digests(now: u64, period: u64) -> Result<Vec<(String, Vec<Notification>)>> {
+        let db = DB.read().or(Err(anyhow!("DB poisoned")))?;
+        Ok(db
+            .iter()
+            .filter(|(_, inbox)| inbox.digest_sent_at.is_none_or(|at| now.saturating_sub(at) >= period))
+            .map(|(email, inbox)| {
+                let pending: Vec<Notification> = inbox
+                    .notifications
+                    .iter()
+                    .filter(|n| !n.read && !n.emailed && inbox.digest.contains(&n.kind))
+                    .cloned()
+                    .collect();
+                (email.clone(), pending)
+            })
+            .filter(|(_, pending)| !pending.is_empty())
+            .collect())
+    }
+
+    /// Marque les notifications `ids` comme envoyées et date le résumé du compte à `now`
+    pub fn mark_emailed(email: &str, ids: &[Uuid], now: u64) -> Result<()> {
+        let mut db = DB.write().or(Err(anyhow!("DB poisoned")))?;
+        let Some(inbox) = db.get_mut(email) else {
+            return Ok(());
+        };
+        for notification in inbox.notifications.iter_mut().filter(|n| ids.contains(&n.id)) {
+            notification.emailed = true;
+        }
+        inbox.digest_sent_at = Some(now);
+        save(&db)
+    }
+
+    /// Oublie les notifications d'un compte supprimé et celles qu'il a causées
+    pub fn remove_account(email: &str) -> Result<()> {
+        let mut db = DB.write().or(Err(anyhow!("DB poisoned")))?;
+        let mut changed = db.remove(email).is_some();
+        for inbox in db.values_mut() {
+            let before = inbox.notifications.len();
+            inbox.notifications.retain(|n| n.actor != email);
+            changed |= inbox.notifications.len() != before;
+        }
+        if changed {
+            save(&db)?;
+        }
+        Ok(())
+    }
+
+    pub fn load() -> Result<()> {
+        super::load(&DB, Store::Notifications)
+    }
+
+    fn save(db: &Db) -> Result<()> {
+        super::save(db, Store::Notifications)
+    }
+}
+
+/// Journal des actions de modération
+pub mod audit {
+    use super::*;
+    use once_cell::sync::Lazy;
+
+    #[derive(Clone, Copy, Serialize, Deserialize, Debug, PartialEq, Eq)]
+    #[serde(rename_all = "snake_case")]
+    pub enum Action {
+        HidePost,
+        RestorePost,
+        SuspendUser,
+        RestoreUser,
+        SetRole,
+        ResolveReports,
+    }
+
+    #[derive(Clone, Serialize, Deserialize, Debug)]
+    pub struct Entry {
+        /// Date de l'action, en secondes Unix
+        pub at: u64,
+        /// Compte qui a agi
+        pub actor: String,
+        pub action: Action,
+        /// Post ou compte visé
+        pub target: String,
+        /// Précision sur l'action, par exemple le nouveau rôle
+        pub detail: Option<String>,
+        pub reason: String,
+    }
+
+    pub(crate) type Db = Vec<Entry>;
+    static DB: Lazy<RwLock<Db>> = Lazy::new(Default::default);
+
+    /// Ajoute une action au journal
+    pub fn record(actor: &str, action: Action, target: &str, detail: Option<&str>, reason: &str) -> Result<()> {
+        let entry = Entry {
+            at: unix_now(),
+            actor: actor.to_string(),
+            action,
+            target: target.to_string(),
+            detail: detail.map(str::to_string),
+            reason: reason.to_string(),
+        };
+        let mut db = DB.write().or(Err(anyhow!("DB poisoned")))?;
+        db.push(entry);
+        save(&db)
+    }
+
+    /// Dernières actions, de la plus récente à la plus ancienne
+    pub fn recent(limit: usize) -> Result<Vec<Entry>> {
+        let db = DB.read().or(Err(anyhow!("DB poisoned")))?;
+        Ok(db.iter().rev().take(limit).cloned().collect())
+    }
+
+    pub fn load() -> Result<()> {
+        super::load(&DB, Store::Audit)
+    }
+
+    fn save(db: &Db) -> Result<()> {
+        super::save(db, Store::Audit)
     }
 }
 
 /// Fonctions de sauvegarde et chargement YAML
-fn save<T: Serialize>(db: &T, path: &str) -> Result<()> {
-    let path_obj = Path::new(path);
+fn save<T: Serialize>(db: &T, store: Store) -> Result<()> {
+    let started = Instant::now();
+    let path = store.path();
+    let path_obj = path.as_path();
 
     // Crée le dossier parent s'il n'existe pas
     if let Some(parent_dir) = path_obj.parent() {
@@ -189,19 +1421,27 @@ fn save<T: Serialize>(db: &T, path: &str) -> Result<()> {
     }
 
     let file = File::create(path_obj)?;
-    to_writer(file, db).or(Err(anyhow!("Failed to serialize DB")))?;
+    let versioned = Versioned { version: store.current_version(), data: db };
+    to_writer(file, &versioned).or(Err(anyhow!("Failed to serialize DB")))?;
+    metrics::observe_persist(store.name(), started);
     Ok(())
 }
 
-fn load<T: for<'de> Deserialize<'de> + Default>(db: &RwLock<T>, path: &str) -> Result<()> {
-    // Chargement de la base de données depuis le fichier YAML
-    if let Ok(file) = File::open(path) {
-        let db_content: T = serde_yaml::from_reader(file).unwrap_or_default();
-        let mut db = db.write().or(Err(anyhow!("DB poisoned")))?;
-        *db = db_content;
-    } else {
-        let mut db = db.write().or(Err(anyhow!("DB poisoned")))?;
-        *db = T::default();
-    }
+fn load<T: for<'de> Deserialize<'de> + Default>(db: &RwLock<T>, store: Store) -> Result<()> {
+    // Chargement de la base de données depuis le fichier YAML, mise à niveau en mémoire si besoin
+    let path = store.path();
+    let db_content = match File::open(&path) {
+        Ok(file) => {
+            let raw: serde_yaml::Value = serde_yaml::from_reader(file)
+                .map_err(|e| anyhow!("Failed to parse {}: {}", path.display(), e))?;
+            let (_, data) = migrations::upgrade(store, raw)?;
+            serde_yaml::from_value(data)
+                .map_err(|e| anyhow!("Failed to deserialize {}: {}", path.display(), e))?
+        }
+        Err(_) => T::default(),
+    };
+
+    let mut db = db.write().or(Err(anyhow!("DB poisoned")))?;
+    *db = db_content;
     Ok(())
 }
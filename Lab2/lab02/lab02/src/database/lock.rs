@@ -0,0 +1,50 @@
+//! Verrou exclusif sur le dossier de données.
+//! Le serveur le tient pendant toute son exécution : les bases sont gardées en mémoire et
+//! réécrites entièrement, une modification faite par un autre processus serait écrasée.
+//! L'outil d'administration le prend aussi avant toute écriture.
+
+use std::fs::{create_dir_all, File, OpenOptions, TryLockError};
+use anyhow::{anyhow, Result};
+use crate::{config::CONFIG, consts};
+
+/// Verrou relâché à la destruction
+pub struct DataDirLock {
+    _file: File,
+}
+
+impl DataDirLock {
+    /// Prend le verrou, ou échoue si un autre processus le tient déjà
+    pub fn acquire() -> Result<Self> {
+        create_dir_all(&CONFIG.data_dir)
+            .map_err(|e| anyhow!("Failed to create {}: {}", CONFIG.data_dir.display(), e))?;
+        let path = CONFIG.data_dir.join(consts::LOCK_FILE);
+        let file = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(&path)
+            .map_err(|e| anyhow!("Failed to open {}: {}", path.display(), e))?;
+
+        match file.try_lock() {
+            Ok(()) => Ok(DataDirLock { _file: file }),
+            Err(TryLockError::WouldBlock) => Err(anyhow!(
+                "{} is in use by another process (is the server running?)",
+                CONFIG.data_dir.display()
+            )),
+            Err(TryLockError::Error(e)) => Err(anyhow!("Failed to lock {}: {}", path.display(), e)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lock_is_exclusive() {
+        let lock = DataDirLock::acquire().unwrap();
+        assert!(DataDirLock::acquire().is_err());
+        drop(lock);
+        assert!(DataDirLock::acquire().is_ok());
+    }
+}
@@ -0,0 +1,500 @@
+//! Versionnage du schéma des fichiers de données et migrations au démarrage.
+//!
+//! Chaque base est écrite sous la forme `{ version: N, data: ... }`. Les fichiers
+//! écrits avant l'introduction de cet en-tête sont considérés comme étant en version 0.
+//! Au chargement, les migrations enregistrées pour la base sont appliquées une à une
+//! jusqu'à la version courante.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+use anyhow::{anyhow, bail, Result};
+use log::info;
+use serde::{Deserialize, Serialize};
+use serde_yaml::{Mapping, Value};
+use crate::{config::CONFIG, consts};
+
+/// En-tête de version écrit dans chaque fichier de données
+#[derive(Serialize, Deserialize)]
+pub struct Versioned<T> {
+    pub version: u32,
+    pub data: T,
+}
+
+/// Une étape de migration qui fait passer une base de `from` à `from + 1`
+pub struct Migration {
+    pub from: u32,
+    pub description: &'static str,
+    pub apply: fn(Value) -> Result<Value>,
+}
+
+/// Bases persistées sur disque
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Store {
+    Users,
+    Emails,
+    Posts,
+    Tokens,
+    Audit,
+    Reports,
+    Follows,
+    Notifications,
+}
+
+impl Store {
+    pub const ALL: [Store; 8] = [
+        Store::Users,
+        Store::Emails,
+        Store::Posts,
+        Store::Tokens,
+        Store::Audit,
+        Store::Reports,
+        Store::Follows,
+        Store::Notifications,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Store::Users => "users",
+            Store::Emails => "emails",
+            Store::Posts => "posts",
+            Store::Tokens => "tokens",
+            Store::Audit => "audit",
+            Store::Reports => "reports",
+            Store::Follows => "follows",
+            Store::Notifications => "notifications",
+        }
+    }
+
+    pub fn path(self) -> PathBuf {
+        let file = match self {
+            Store::Users => consts::USERS_DB_FILE,
+            Store::Emails => consts::EMAILS_DB_FILE,
+            Store::Posts => consts::POSTS_DB_FILE,
+            Store::Tokens => consts::TOKENS_DB_FILE,
+            Store::Audit => consts::AUDIT_DB_FILE,
+            Store::Reports => consts::REPORTS_DB_FILE,
+            Store::Follows => consts::FOLLOWS_DB_FILE,
+            Store::Notifications => consts::NOTIFICATIONS_DB_FILE,
+        };
+        CONFIG.data_dir.join(file)
+    }
+
+    /// Registre des migrations de la base, trié par version de départ
+    pub fn migrations(self) -> &'static [Migration] {
+        match self {
+            Store::Users => USERS_MIGRATIONS,
+            Store::Emails => EMAILS_MIGRATIONS,
+            Store::Posts => POSTS_MIGRATIONS,
+            Store::Tokens => TOKENS_MIGRATIONS,
+            Store::Audit => AUDIT_MIGRATIONS,
+            Store::Reports => REPORTS_MIGRATIONS,
+            Store::Follows => FOLLOWS_MIGRATIONS,
+            Store::Notifications => NOTIFICATIONS_MIGRATIONS,
+        }
+    }
+
+    /// Version écrite par ce binaire
+    pub fn current_version(self) -> u32 {
+        self.migrations().len() as u32
+    }
+}
+
+static USERS_MIGRATIONS: &[Migration] = &[
+    Migration { from: 0, description: "add version header", apply: Ok },
+    Migration { from: 1, description: "add account creation date", apply: add_user_created_at },
+    Migration { from: 2, description: "add recovery codes", apply: add_user_recovery_codes },
+    Migration { from: 3, description: "add magic-link opt-in", apply: add_user_magic_link },
+    Migration { from: 4, description: "add role and suspension", apply: add_user_role },
+    Migration { from: 5, description: "add session generation", apply: add_user_session_generation },
+    Migration { from: 6, description: "add public handle", apply: add_user_handle },
+    Migration { from: 7, description: "replace liked posts by per-post reactions", apply: reset_user_liked_posts },
+];
+
+static EMAILS_MIGRATIONS: &[Migration] = &[
+    Migration { from: 0, description: "add version header", apply: Ok },
+];
+
+static POSTS_MIGRATIONS: &[Migration] = &[
+    Migration { from: 0, description: "add version header", apply: Ok },
+    Migration { from: 1, description: "add moderation flag", apply: add_post_hidden },
+    Migration { from: 2, description: "add author", apply: add_post_author },
+    Migration { from: 3, description: "add visibility and publication status", apply: add_post_visibility },
+    Migration { from: 4, description: "drop the shared like counter", apply: drop_post_likes },
+];
+
+// Base introduite avec l'en-tête : aucun fichier en version 0 n'existe
+static TOKENS_MIGRATIONS: &[Migration] = &[
+    Migration { from: 0, description: "add version header", apply: Ok },
+    Migration { from: 1, description: "drop tokens without purpose", apply: drop_tokens_without_purpose },
+];
+
+// Base introduite avec l'en-tête : aucun fichier en version 0 n'existe
+static AUDIT_MIGRATIONS: &[Migration] = &[
+    Migration { from: 0, description: "add version header", apply: Ok },
+];
+
+// Base introduite avec l'en-tête : aucun fichier en version 0 n'existe
+static REPORTS_MIGRATIONS: &[Migration] = &[
+    Migration { from: 0, description: "add version header", apply: Ok },
+];
+
+// Base introduite avec l'en-tête : aucun fichier en version 0 n'existe
+static FOLLOWS_MIGRATIONS: &[Migration] = &[
+    Migration { from: 0, description: "add version header", apply: Ok },
+];
+
+// Base introduite avec l'en-tête : aucun fichier en version 0 n'existe
+static NOTIFICATIONS_MIGRATIONS: &[Migration] = &[
+    Migration { from: 0, description: "add version header", apply: Ok },
+];
+
+/// Ajoute le champ `key` avec la valeur `default` à chaque enregistrement d'une base, table
+/// (comptes) ou liste (posts). Un champ déjà présent est gardé.
+fn add_field(mut data: Value, key: &str, default: Value) -> Result<Value> {
+    let records: Vec<&mut Value> = match &mut data {
+        Value::Mapping(records) => records.values_mut().collect(),
+        Value::Sequence(records) => records.iter_mut().collect(),
+        _ => vec![],
+    };
+    for record in records {
+        if let Value::Mapping(record) = record {
+            record.entry(Value::from(key)).or_insert_with(|| default.clone());
+        }
+    }
+    Ok(data)
+}
+
+/// Les comptes existants reçoivent la date de migration : le délai de vérification
+/// repart de zéro pour eux
+fn add_user_created_at(data: Value) -> Result<Value> {
+    add_field(data, "created_at", Value::from(crate::database::unix_now()))
+}
+
+/// Les comptes existants n'ont aucun code de récupération
+fn add_user_recovery_codes(data: Value) -> Result<Value> {
+    add_field(data, "recovery_codes", Value::Sequence(vec![]))
+}
+
+/// La connexion par lien reste désactivée tant que le titulaire ne l'a pas choisie
+fn add_user_magic_link(data: Value) -> Result<Value> {
+    add_field(data, "magic_link", Value::from(false))
+}
+
+/// Les comptes existants sont de simples utilisateurs, aucun n'est suspendu
+fn add_user_role(data: Value) -> Result<Value> {
+    add_field(add_field(data, "role", Value::from("user"))?, "suspended", Value::from(false))
+}
+
+/// Les sessions ouvertes restent valides
+fn add_user_session_generation(data: Value) -> Result<Value> {
+    add_field(data, "session_generation", Value::from(0))
+}
+
+/// Chaque compte existant reçoit son propre identifiant public aléatoire
+fn add_user_handle(mut data: Value) -> Result<Value> {
+    if let Value::Mapping(users) = &mut data {
+        for user in users.values_mut() {
+            if let Value::Mapping(user) = user {
+                user.entry(Value::from("handle")).or_insert_with(|| Value::from(crate::database::user::new_handle()));
+            }
+        }
+    }
+    Ok(data)
+}
+
+/// L'ancienne liste n'était jamais remplie et ses entrées ne désignaient aucun post
+fn reset_user_liked_posts(mut data: Value) -> Result<Value> {
+    if let Value::Mapping(users) = &mut data {
+        for user in users.values_mut() {
+            if let Value::Mapping(user) = user {
+                user.insert(Value::from("liked_posts"), Value::Mapping(Mapping::new()));
+            }
+        }
+    }
+    Ok(data)
+}
+
+/// Les posts existants restent visibles
+fn add_post_hidden(data: Value) -> Result<Value> {
+    add_field(data, "hidden", Value::from(false))
+}
+
+/// L'auteur des posts existants n'a pas été enregistré
+fn add_post_author(data: Value) -> Result<Value> {
+    add_field(data, "author", Value::Null)
+}
+
+/// Les posts existants sont publiés et publics
+fn add_post_visibility(data: Value) -> Result<Value> {
+    let data = add_field(data, "visibility", Value::from("public"))?;
+    let data = add_field(data, "status", Value::from("published"))?;
+    add_field(data, "publish_at", Value::Null)
+}
+
+/// Le compteur partagé ne disait pas qui avait voté : les likes repartent des réactions des comptes
+fn drop_post_likes(mut data: Value) -> Result<Value> {
+    if let Value::Sequence(posts) = &mut data {
+        for post in posts {
+            if let Value::Mapping(post) = post {
+                post.remove("likes");
+            }
+        }
+    }
+    Ok(data)
+}
+
+/// L'usage d'un ancien lien est inconnu : il est supprimé, un nouveau lien peut être demandé
+fn drop_tokens_without_purpose(mut data: Value) -> Result<Value> {
+    if let Value::Mapping(tokens) = &mut data {
+        tokens.retain(|_, token| token.get("purpose").is_some());
+    }
+    Ok(data)
+}
+
+/// Sépare l'en-tête de version des données. Un fichier sans en-tête est en version 0.
+fn split_version(raw: Value) -> Result<(u32, Value)> {
+    if let Value::Mapping(map) = &raw {
+        let version_key = Value::from("version");
+        let data_key = Value::from("data");
+        if map.len() == 2 && map.contains_key(&data_key) {
+            if let Some(version) = map.get(&version_key).and_then(Value::as_u64) {
+                let mut map: Mapping = map.clone();
+                let data = map.remove(&data_key).unwrap_or_default();
+                return Ok((u32::try_from(version)?, data));
+            }
+        }
+    }
+    Ok((0, raw))
+}
+
+/// Met à niveau le contenu brut d'un fichier jusqu'à la version courante.
+/// Retourne la version lue et les données migrées (sans en-tête).
+pub fn upgrade(store: Store, raw: Value) -> Result<(u32, Value)> {
+    let (found, mut data) = split_version(raw)?;
+    let current = store.current_version();
+
+    if found > current {
+        bail!(
+            "{} store is at version {} but this binary only supports up to {}",
+            store.name(), found, current
+        );
+    }
+
+    for migration in &store.migrations()[found as usize..] {
+        data = (migration.apply)(data).map_err(|e| {
+            anyhow!("{} migration v{} ({}) failed: {}", store.name(), migration.from, migration.description, e)
+        })?;
+    }
+
+    Ok((found, data))
+}
+
+/// Résultat de la migration d'un fichier
+#[derive(Debug)]
+pub struct Report {
+    pub store: Store,
+    pub from: u32,
+    pub to: u32,
+    pub steps: Vec<&'static str>,
+}
+
+/// Migre le fichier d'une base sur disque. En mode `dry_run`, rien n'est écrit.
+/// L'ancien fichier est conservé à côté avec le suffixe `.v<N>.bak`.
+pub fn migrate_file(store: Store, path: &Path, dry_run: bool) -> Result<Option<Report>> {
+    let Ok(content) = fs::read_to_string(path) else {
+        return Ok(None);
+    };
+
+    let raw: Value = serde_yaml::from_str(&content)
+        .map_err(|e| anyhow!("Failed to parse {}: {}", path.display(), e))?;
+    let (from, data) = upgrade(store, raw)?;
+    let to = store.current_version();
+
+    if from == to {
+        return Ok(None);
+    }
+
+    let steps = store.migrations()[from as usize..]
+        .iter()
+        .map(|migration| migration.description)
+        .collect();
+
+    if !dry_run {
+        let backup = format!("{}.v{}.bak", path.display(), from);
+        fs::write(&backup, &content)?;
+        let versioned = Versioned { version: to, data };
+        fs::write(path, serde_yaml::to_string(&versioned)?)?;
+    }
+
+    Ok(Some(Report { store, from, to, steps }))
+}
+
+/// Migre toutes les bases au démarrage
+pub fn run(dry_run: bool) -> Result<Vec<Report>> {
+    let mut reports = vec![];
+
+    for store in Store::ALL {
+        if let Some(report) = migrate_file(store, &store.path(), dry_run)? {
+            info!(
+                "{}{} store: v{} -> v{} ({})",
+                if dry_run { "[dry-run] " } else { "" },
+                report.store.name(), report.from, report.to, report.steps.join(", ")
+            );
+            reports.push(report);
+        }
+    }
+
+    Ok(reports)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::{email, post, token, user};
+
+    fn fixture(name: &str) -> String {
+        let path = format!("{}/tests/fixtures/migrations/{}", env!("CARGO_MANIFEST_DIR"), name);
+        fs::read_to_string(&path).unwrap_or_else(|_| panic!("missing fixture {}", path))
+    }
+
+    #[test]
+    fn test_registries_are_contiguous() {
+        for store in Store::ALL {
+            for (index, migration) in store.migrations().iter().enumerate() {
+                assert_eq!(migration.from, index as u32, "{} registry has a gap", store.name());
+            }
+        }
+    }
+
+    fn users(data: Value) -> user::Db {
+        serde_yaml::from_value(data).unwrap()
+    }
+
+    fn posts(data: Value) -> post::Db {
+        serde_yaml::from_value(data).unwrap()
+    }
+
+    /// Base, fixture, version attendue de la fixture et vérification des données mises à niveau
+    type Case = (Store, &'static str, u32, fn(Value));
+
+    /// Chaque fixture est mise à niveau depuis sa version vers la version courante
+    #[test]
+    fn test_upgrade_fixtures() {
+        let cases: &[Case] = &[
+            (Store::Users, "users_v0.yaml", 0, |data| {
+                let alice = &users(data)["alice@example.com"];
+                assert_eq!(alice.first_name, "Alice");
+                assert!(alice.verified);
+                assert!(alice.passkey.is_none());
+            }),
+            (Store::Users, "users_v1.yaml", 1, |data| assert!(users(data)["alice@example.com"].created_at > 0)),
+            (Store::Users, "users_v2.yaml", 2, |data| {
+                let alice = &users(data)["alice@example.com"];
+                assert_eq!(alice.created_at, 1_700_000_000);
+                assert!(alice.recovery_codes.is_empty());
+            }),
+            (Store::Users, "users_v3.yaml", 3, |data| {
+                let alice = &users(data)["alice@example.com"];
+                assert_eq!(alice.recovery_codes.len(), 1);
+                assert!(!alice.magic_link);
+            }),
+            (Store::Users, "users_v4.yaml", 4, |data| {
+                let alice = &users(data)["alice@example.com"];
+                assert!(alice.magic_link);
+                assert_eq!(alice.role, user::Role::User);
+                assert!(!alice.suspended);
+            }),
+            (Store::Users, "users_v5.yaml", 5, |data| {
+                let alice = &users(data)["alice@example.com"];
+                assert_eq!(alice.role, user::Role::Admin);
+                assert_eq!(alice.session_generation, 0);
+            }),
+            (Store::Users, "users_v6.yaml", 6, |data| {
+                let users = users(data);
+                assert_eq!(users["alice@example.com"].session_generation, 2);
+                assert_eq!(users["alice@example.com"].handle.len(), 32);
+                assert_ne!(users["alice@example.com"].handle, users["bob@example.com"].handle);
+            }),
+            (Store::Users, "users_v7.yaml", 7, |data| {
+                let alice = &users(data)["alice@example.com"];
+                assert_eq!(alice.handle, "5b3e0b1c9d2a4f6e8a7c1d0e2f3a4b5c");
+                assert!(alice.liked_posts.is_empty());
+            }),
+            // Version courante : rien n'est modifié
+            (Store::Users, "users_v8.yaml", Store::Users.current_version(), |data| {
+                let reactions = &users(data)["alice@example.com"].liked_posts;
+                assert_eq!(reactions.values().map(|reaction| reaction.vote).collect::<Vec<_>>(), vec![1]);
+            }),
+            (Store::Emails, "emails_v0.yaml", 0, |data| {
+                let emails: email::Db = serde_yaml::from_value(data).unwrap();
+                assert_eq!(emails.next_pk, 2);
+                assert_eq!(emails.emails[&1].subject, "Account Recovery");
+            }),
+            (Store::Posts, "posts_v0.yaml", 0, |data| {
+                let posts = posts(data);
+                assert_eq!(posts.len(), 2);
+                assert_eq!(posts[1].image_path, None);
+            }),
+            (Store::Posts, "posts_v1.yaml", 1, |data| assert!(posts(data).iter().all(|post| !post.hidden))),
+            (Store::Posts, "posts_v2.yaml", 2, |data| {
+                let posts = posts(data);
+                assert!(posts[0].hidden);
+                assert!(posts.iter().all(|post| post.author.is_none()));
+            }),
+            (Store::Posts, "posts_v3.yaml", 3, |data| {
+                let posts = posts(data);
+                assert_eq!(posts[0].author.as_deref(), Some("alice@example.com"));
+                assert!(posts.iter().all(|post| post.is_published() && post.visibility == post::Visibility::Public));
+            }),
+            (Store::Posts, "posts_v4.yaml", 4, |data| {
+                assert!(data[0].get("likes").is_none());
+                assert_eq!(posts(data)[0].author.as_deref(), Some("alice@example.com"));
+            }),
+            (Store::Tokens, "tokens_v1.yaml", 1, |data| {
+                let tokens: token::Db = serde_yaml::from_value(data).unwrap();
+                assert!(tokens.is_empty());
+            }),
+        ];
+
+        for (store, name, expected_from, check) in cases {
+            let raw: Value = serde_yaml::from_str(&fixture(name)).unwrap();
+            let (from, data) = upgrade(*store, raw).unwrap();
+            assert_eq!(from, *expected_from, "{}", name);
+            check(data);
+        }
+    }
+
+    #[test]
+    fn test_newer_version_is_rejected() {
+        let raw: Value = serde_yaml::from_str("version: 999\ndata: {}").unwrap();
+        assert!(upgrade(Store::Users, raw).is_err());
+    }
+
+    #[test]
+    fn test_migrate_file_dry_run_and_write() {
+        let dir = std::env::temp_dir().join(format!("lab02-migrations-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("posts.yaml");
+        let original = fixture("posts_v0.yaml");
+        fs::write(&path, &original).unwrap();
+
+        // Le dry-run décrit la migration sans toucher au fichier
+        let report = migrate_file(Store::Posts, &path, true).unwrap().unwrap();
+        assert_eq!((report.from, report.to), (0, Store::Posts.current_version()));
+        assert_eq!(fs::read_to_string(&path).unwrap(), original);
+
+        // La vraie migration écrit l'en-tête et garde une sauvegarde
+        migrate_file(Store::Posts, &path, false).unwrap().unwrap();
+        let migrated: Versioned<post::Db> = serde_yaml::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(migrated.version, Store::Posts.current_version());
+        assert_eq!(migrated.data.len(), 2);
+        assert_eq!(fs::read_to_string(dir.join("posts.yaml.v0.bak")).unwrap(), original);
+
+        // Une seconde exécution n'a plus rien à faire
+        assert!(migrate_file(Store::Posts, &path, false).unwrap().is_none());
+
+        fs::remove_dir_all(&dir).ok();
+    }
+}
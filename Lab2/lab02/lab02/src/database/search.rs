@@ -0,0 +1,393 @@
+//! Index plein texte des posts.
+//!
+//! Le contenu est découpé en mots (minuscules, séparés par tout caractère non alphanumérique),
+//! les mots vides du français et de l'anglais sont écartés sans décaler les positions, puis
+//! chaque mot est réduit à sa racine (Snowball) dans la langue détectée du post. Une recherche
+//! essaie les racines des deux langues. Les positions permettent les expressions entre
+//! guillemets ; les hashtags sont indexés à part.
+//!
+//! L'index est dérivé de la base des posts : `post` le tient à jour à la création, à la
+//! modification et à la suppression d'un post. Il est écrit dans son propre fichier et
+//! reconstruit en mémoire au chargement s'il manque, change de format ou ne couvre pas
+//! exactement les posts. `lab02-admin search rebuild` le reconstruit et le réécrit.
+
+use std::{
+    borrow::Cow,
+    collections::{BTreeSet, HashMap, HashSet},
+    fs::{create_dir_all, File},
+    path::PathBuf,
+    sync::RwLock,
+    time::Instant,
+};
+use anyhow::{anyhow, Result};
+use log::info;
+use once_cell::sync::Lazy;
+use rust_stemmers::{Algorithm, Stemmer};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+use crate::config::CONFIG;
+use crate::{consts, metrics};
+use crate::utils::markdown;
+use super::migrations::Versioned;
+use super::post::Post;
+
+/// Version du format de l'index : un index d'une autre version est reconstruit
+const INDEX_VERSION: u32 = 1;
+/// Les mots plus longs (URL, données collées) ne sont pas indexés
+const MAX_WORD_LEN: usize = 40;
+
+const ENGLISH_STOPWORDS: &[&str] = &[
+    "a", "an", "and", "are", "as", "at", "be", "but", "by", "for", "from", "has", "have", "he", "her", "his",
+    "i", "in", "is", "it", "its", "me", "my", "of", "on", "or", "our", "she", "so", "that", "the", "their",
+    "them", "there", "they", "this", "to", "was", "we", "were", "what", "which", "who", "will", "with", "you", "your",
+];
+const FRENCH_STOPWORDS: &[&str] = &[
+    "a", "au", "aux", "avec", "c", "ce", "ces", "d", "dans", "de", "des", "du", "elle", "en", "est", "et",
+    "il", "ils", "j", "je", "l", "la", "le", "les", "leur", "lui", "m", "ma", "mais", "me", "mes", "mon",
+    "n", "ne", "nous", "on", "ou", "par", "pas", "pour", "qu", "que", "qui", "s", "sa", "se", "ses", "son",
+    "sur", "t", "ta", "te", "tu", "un", "une", "vous",
+];
+
+static ENGLISH: Lazy<Stemmer> = Lazy::new(|| Stemmer::create(Algorithm::English));
+static FRENCH: Lazy<Stemmer> = Lazy::new(|| Stemmer::create(Algorithm::French));
+
+/// Index des posts, toujours verrouillé après la base des posts
+pub(crate) static INDEX: Lazy<RwLock<Index>> = Lazy::new(Default::default);
+
+/// Mots d'un texte en minuscules, avec leur position ; les mots vides gardent leur place
+fn words(text: &str) -> Vec<(u32, String)> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .zip(0..)
+        .map(|(word, position)| (position, word.to_lowercase()))
+        .filter(|(_, word)| {
+            word.chars().count() <= MAX_WORD_LEN
+                && !ENGLISH_STOPWORDS.contains(&word.as_str())
+                && !FRENCH_STOPWORDS.contains(&word.as_str())
+        })
+        .collect()
+}
+
+/// Langue d'un texte, d'après ses mots vides ; l'anglais en cas d'égalité
+fn stemmer_for(text: &str) -> &'static Stemmer {
+    let (mut english, mut french) = (0, 0);
+    for word in text.split(|c: char| !c.is_alphanumeric()).map(str::to_lowercase) {
+        english += ENGLISH_STOPWORDS.contains(&word.as_str()) as usize;
+        french += FRENCH_STOPWORDS.contains(&word.as_str()) as usize;
+    }
+    if french > english { &FRENCH } else { &ENGLISH }
+}
+
+/// Racines possibles d'un mot recherché, une par langue
+fn stems(word: &str) -> BTreeSet<String> {
+    [ENGLISH.stem(word), FRENCH.stem(word)].into_iter().map(Cow::into_owned).collect()
+}
+
+/// Recherche analysée : mots, expressions entre guillemets, hashtags et auteur
+#[derive(Debug, Default, PartialEq)]
+pub struct Query {
+    /// Mots hors guillemets, qui doivent tous apparaître
+    pub words: Vec<String>,
+    /// Expressions entre guillemets, dont les mots doivent se suivre
+    pub phrases: Vec<String>,
+    /// Hashtags, en minuscules et sans `#`
+    pub tags: Vec<String>,
+    /// Email de l'auteur
+    pub author: Option<String>,
+}
+
+impl Query {
+    /// Analyse le texte d'une recherche : `"…"` délimite une expression, `#tag` filtre par hashtag
+    pub fn parse(text: &str) -> Query {
+        let mut query = Query::default();
+        for (i, segment) in text.split('"').enumerate() {
+            if i % 2 == 1 {
+                if !words(segment).is_empty() {
+                    query.phrases.push(segment.trim().to_string());
+                }
+                continue;
+            }
+            for term in segment.split_whitespace() {
+                match term.strip_prefix('#') {
+                    Some(tag) => query.add_tag(tag),
+                    None => query.words.extend(words(term).into_iter().map(|(_, word)| word)),
+                }
+            }
+        }
+        query
+    }
+
+    /// Ajoute un filtre par hashtag, ignoré s'il ne contient aucun caractère de mot
+    pub fn add_tag(&mut self, tag: &str) {
+        let tag: String = tag.trim_start_matches('#').chars().filter(|c| c.is_alphanumeric() || *c == '_').collect();
+        let tag = tag.to_lowercase();
+        if !tag.is_empty() && !self.tags.contains(&tag) {
+            self.tags.push(tag);
+        }
+    }
+
+    /// Une recherche vide ne retourne rien
+    pub fn is_empty(&self) -> bool {
+        self.words.is_empty() && self.phrases.is_empty() && self.tags.is_empty() && self.author.is_none()
+    }
+
+    /// Indique si la recherche filtre sur le contenu (et pas seulement sur l'auteur)
+    fn has_content_filter(&self) -> bool {
+        !self.words.is_empty() || !self.phrases.is_empty() || !self.tags.is_empty()
+    }
+}
+
+/// Racines et hashtags d'un post, pour pouvoir le retirer de l'index
+#[derive(Default, Serialize, Deserialize)]
+struct Document {
+    terms: Vec<String>,
+    tags: Vec<String>,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+pub(crate) struct Index {
+    /// Racine → post → positions des mots
+    terms: HashMap<String, HashMap<Uuid, Vec<u32>>>,
+    /// Hashtag → posts
+    tags: HashMap<String, BTreeSet<Uuid>>,
+    docs: HashMap<Uuid, Document>,
+}
+
+impl Index {
+    fn add(&mut self, id: Uuid, content: &str) {
+        self.remove(id);
+        let stemmer = stemmer_for(content);
+        let mut document = Document::default();
+        for (position, word) in words(content) {
+            let stem = stemmer.stem(&word).into_owned();
+            let positions = self.terms.entry(stem.clone()).or_default().entry(id).or_default();
+            if positions.is_empty() {
+                document.terms.push(stem);
+            }
+            positions.push(position);
+        }
+        for tag in markdown::hashtags(content) {
+            self.tags.entry(tag.clone()).or_default().insert(id);
+            document.tags.push(tag);
+        }
+        self.docs.insert(id, document);
+    }
+
+    fn remove(&mut self, id: Uuid) {
+        let Some(document) = self.docs.remove(&id) else {
+            return;
+        };
+        for term in document.terms {
+            if let Some(postings) = self.terms.get_mut(&term) {
+                postings.remove(&id);
+                if postings.is_empty() {
+                    self.terms.remove(&term);
+                }
+            }
+        }
+        for tag in document.tags {
+            if let Some(posts) = self.tags.get_mut(&tag) {
+                posts.remove(&id);
+                if posts.is_empty() {
+                    self.tags.remove(&tag);
+                }
+            }
+        }
+    }
+
+    /// Positions d'un mot recherché dans chaque post, toutes langues confondues
+    fn postings(&self, word: &str) -> HashMap<Uuid, BTreeSet<u32>> {
+        let mut postings: HashMap<Uuid, BTreeSet<u32>> = HashMap::new();
+        for stem in stems(word) {
+            for (id, positions) in self.terms.get(&stem).into_iter().flatten() {
+                postings.entry(*id).or_default().extend(positions);
+            }
+        }
+        postings
+    }
+
+    /// Occurrences d'une expression dans chaque post qui la contient
+    fn phrase_matches(&self, phrase: &str) -> HashMap<Uuid, u32> {
+        let words = words(phrase);
+        let postings: Vec<_> = words.iter().map(|(_, word)| self.postings(word)).collect();
+        let Some((first, rest)) = postings.split_first() else {
+            return HashMap::new();
+        };
+        let offsets: Vec<u32> = words.iter().map(|(position, _)| position - words[0].0).collect();
+
+        first
+            .iter()
+            .filter_map(|(id, starts)| {
+                let following: Vec<_> = rest.iter().map(|postings| postings.get(id)).collect::<Option<_>>()?;
+                let count = starts
+                    .iter()
+                    .filter(|start| following.iter().zip(&offsets[1..]).all(|(positions, offset)| positions.contains(&(*start + offset))))
+                    .count() as u32;
+                (count > 0).then_some((*id, count))
+            })
+            .collect()
+    }
+
+    /// Posts qui satisfont les critères de contenu de la recherche, avec leur score
+    /// (occurrences des mots et des expressions). `None` si la recherche ne porte pas sur le contenu.
+    pub(crate) fn matches(&self, query: &Query) -> Option<HashMap<Uuid, u32>> {
+        if !query.has_content_filter() {
+            return None;
+        }
+        let mut found: Option<HashMap<Uuid, u32>> = None;
+        let mut narrow = |matches: HashMap<Uuid, u32>| {
+            found = Some(match found.take() {
+                None => matches,
+                Some(mut found) => {
+                    found.retain(|id, _| matches.contains_key(id));
+                    for (id, score) in found.iter_mut() {
+                        *score += matches[id];
+                    }
+                    found
+                }
+            });
+        };
+
+        for word in &query.words {
+            narrow(self.postings(word).into_iter().map(|(id, positions)| (id, positions.len() as u32)).collect());
+        }
+        for phrase in &query.phrases {
+            narrow(self.phrase_matches(phrase));
+        }
+        for tag in &query.tags {
+            narrow(self.tags.get(tag).into_iter().flatten().map(|id| (*id, 0)).collect());
+        }
+        found
+    }
+
+    /// Hashtags qui commencent par `prefix`, avec leurs posts
+    pub(crate) fn tags_with_prefix<'a>(&'a self, prefix: &'a str) -> impl Iterator<Item = (&'a String, &'a BTreeSet<Uuid>)> {
+        self.tags.iter().filter(move |(tag, _)| tag.starts_with(prefix))
+    }
+
+    fn build(posts: &[Post]) -> Index {
+        let mut index = Index::default();
+        for post in posts {
+            index.add(post.id, &post.content);
+        }
+        index
+    }
+}
+
+pub fn path() -> PathBuf {
+    CONFIG.data_dir.join(consts::SEARCH_INDEX_FILE)
+}
+
+fn save(index: &Index) -> Result<()> {
+    let started = Instant::now();
+    let path = path();
+    if let Some(parent) = path.parent() {
+        create_dir_all(parent).or(Err(anyhow!("Failed to create directory")))?;
+    }
+    let versioned = Versioned { version: INDEX_VERSION, data: index };
+    serde_yaml::to_writer(File::create(&path)?, &versioned).or(Err(anyhow!("Failed to serialize the search index")))?;
+    metrics::observe_persist("search_index", started);
+    Ok(())
+}
+
+/// Index écrit sur disque, s'il est lisible, du format courant et couvre exactement `posts`
+fn read(posts: &[Post]) -> Option<Index> {
+    let versioned: Versioned<Index> = serde_yaml::from_reader(File::open(path()).ok()?).ok()?;
+    let ids: HashSet<Uuid> = posts.iter().map(|post| post.id).collect();
+    let indexed: HashSet<Uuid> = versioned.data.docs.keys().copied().collect();
+    (versioned.version == INDEX_VERSION && ids == indexed).then_some(versioned.data)
+}
+
+/// Charge l'index des posts. Un index périmé est reconstruit en mémoire seulement : le
+/// chargement n'écrit rien, le fichier est réécrit à la prochaine modification.
+pub(crate) fn load(posts: &[Post]) -> Result<()> {
+    let index = read(posts).unwrap_or_else(|| {
+        info!("Search index missing or stale, rebuilding it from {} posts", posts.len());
+        Index::build(posts)
+    });
+    *INDEX.write().or(Err(anyhow!("DB poisoned")))? = index;
+    Ok(())
+}
+
+/// Reconstruit l'index depuis les posts et l'écrit sur disque
+pub(crate) fn rebuild(posts: &[Post]) -> Result<()> {
+    let index = Index::build(posts);
+    save(&index)?;
+    *INDEX.write().or(Err(anyhow!("DB poisoned")))? = index;
+    Ok(())
+}
+
+/// Indexe le contenu, nouveau ou modifié, d'un post
+pub(crate) fn index_post(id: Uuid, content: &str) -> Result<()> {
+    let mut index = INDEX.write().or(Err(anyhow!("DB poisoned")))?;
+    index.add(id, content);
+    save(&index)
+}
+
+/// Retire un post de l'index
+pub(crate) fn remove_post(id: Uuid) -> Result<()> {
+    let mut index = INDEX.write().or(Err(anyhow!("DB poisoned")))?;
+    index.remove(id);
+    save(&index)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn index(posts: &[&str]) -> (Index, Vec<Uuid>) {
+        let mut index = Index::default();
+        let ids: Vec<Uuid> = posts.iter().map(|_| Uuid::new_v4()).collect();
+        for (id, content) in ids.iter().zip(posts) {
+            index.add(*id, content);
+        }
+        (index, ids)
+    }
+
+    fn found(index: &Index, query: &str) -> BTreeSet<Uuid> {
+        index.matches(&Query::parse(query)).unwrap_or_default().into_keys().collect()
+    }
+
+    #[test]
+    fn test_tokenisation_and_stemming() {
+        assert_eq!(
+            words("L'été, les chats"),
+            vec![(1, "été".to_string()), (3, "chats".to_string())]
+        );
+        assert_eq!(stemmer_for("le chat et la souris").stem("chats"), "chat");
+        assert_eq!(stemmer_for("the cats and the dogs").stem("running"), "run");
+
+        let (index, ids) = index(&["Les chats mangeaient des souris", "The dog was running in the park"]);
+        assert_eq!(found(&index, "chat"), BTreeSet::from([ids[0]]));
+        assert_eq!(found(&index, "MANGER"), BTreeSet::from([ids[0]]));
+        assert_eq!(found(&index, "runs dogs"), BTreeSet::from([ids[1]]));
+        assert_eq!(found(&index, "dog chat"), BTreeSet::new());
+    }
+
+    #[test]
+    fn test_phrases_and_hashtags() {
+        let (index, ids) = index(&[
+            "Le chat du voisin dort #Chats",
+            "Le voisin a un chat #chats #nuit",
+            "Chat noir, chat blanc",
+        ]);
+        assert_eq!(found(&index, "\"chat du voisin\""), BTreeSet::from([ids[0]]));
+        assert_eq!(found(&index, "voisin chat"), BTreeSet::from([ids[0], ids[1]]));
+        assert_eq!(found(&index, "\"voisin chat\""), BTreeSet::new());
+        assert_eq!(found(&index, "#chats"), BTreeSet::from([ids[0], ids[1]]));
+        assert_eq!(found(&index, "chat #nuit"), BTreeSet::from([ids[1]]));
+        assert_eq!(index.matches(&Query::parse("chat")).unwrap()[&ids[2]], 2);
+        assert_eq!(Query::parse("  \"le\" "), Query::default());
+    }
+
+    #[test]
+    fn test_removed_posts_leave_no_trace() {
+        let (mut index, ids) = index(&["Bonjour #monde", "Bonjour"]);
+        index.add(ids[0], "Au revoir");
+        assert_eq!(found(&index, "bonjour"), BTreeSet::from([ids[1]]));
+        assert_eq!(found(&index, "#monde"), BTreeSet::new());
+        index.remove(ids[0]);
+        index.remove(ids[1]);
+        assert!(index.terms.is_empty() && index.tags.is_empty() && index.docs.is_empty());
+    }
+}
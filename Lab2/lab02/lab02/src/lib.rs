@@ -0,0 +1,34 @@
+//! Bibliothèque de l'application, partagée par le serveur (`main.rs`)
+//! et l'outil d'administration (`bin/lab02-admin.rs`).
+//! Configure Handlebars pour le rendu des templates et construit le routeur Axum.
+
+pub mod admin;
+pub mod backend;
+pub mod config;
+pub mod database;
+pub mod utils;
+pub mod email;
+pub mod metrics;
+pub mod consts;
+pub mod server;
+#[cfg(test)]
+mod test_support;
+
+use std::sync::Arc;
+use axum::{Extension, Router};
+use handlebars::Handlebars;
+use once_cell::sync::Lazy;
+
+// Initialisation de Handlebars pour le rendu des templates
+static HBS: Lazy<Handlebars> = Lazy::new(|| {
+    let mut hbs = Handlebars::new();
+    hbs.register_templates_directory(".hbs", "templates/")
+        .expect("Could not register template directory");
+    hbs
+});
+
+/// Construit l'application complète, avec Handlebars comme extension pour le routeur
+pub fn app() -> Router {
+    let hbs = Arc::new(HBS.clone());
+    backend::router::get_router().layer(Extension(hbs))
+}
@@ -1,31 +1,10 @@
 //! Point d'entrée principal de l'application.
-//! Initialise les bases de données, configure Handlebars pour le rendu des templates,
+//! Verrouille le dossier de données, initialise les bases de données
 //! et démarre le serveur web avec Axum.
 
-mod backend;
-mod database;
-mod utils;
-mod email;
-mod consts;
-
-use std::{net::SocketAddr, sync::Arc};
-use axum::Extension;
 use dotenv::dotenv;
-use handlebars::Handlebars;
-use log::info;
-use once_cell::sync::Lazy;
-use crate::{
-    consts::HTTP_PORT,
-    backend::handlers_auth::{load_posts_from_file, save_posts_to_file},
-};
-
-// Initialisation de Handlebars pour le rendu des templates
-static HBS: Lazy<Handlebars> = Lazy::new(|| {
-    let mut hbs = Handlebars::new();
-    hbs.register_templates_directory(".hbs", "templates/")
-        .expect("Could not register template directory");
-    hbs
-});
+use lab02::{app, backend, config, database, server, utils};
+use log::error;
 
 #[tokio::main]
 async fn main() {
@@ -35,36 +14,62 @@ async fn main() {
         .filter_level(log::LevelFilter::Info)
         .init();
 
-    // Charger les données des posts
-    if let Err(e) = load_posts_from_file() {
-        eprintln!("Erreur lors du chargement des posts: {}", e);
+    // Refuser de démarrer avec une variable d'environnement invalide
+    if let Err(e) = config::validate() {
+        error!("Configuration invalide: {}", e);
+        std::process::exit(1);
     }
 
-    // Charger les autres bases de données
-    database::user::load().ok();
-    database::email::load().ok();
+    // Un seul processus à la fois modifie le dossier de données
+    let _lock = match database::lock::DataDirLock::acquire() {
+        Ok(lock) => lock,
+        Err(e) => {
+            error!("Dossier de données indisponible: {}", e);
+            std::process::exit(1);
+        }
+    };
 
-    // Configurer Handlebars comme extension pour le routeur
-    let hbs = Arc::new(HBS.clone());
-    let app = backend::router::get_router().layer(Extension(hbs));
+    // Mettre à niveau les fichiers de données vers la version courante du schéma
+    let dry_run = std::env::args().any(|arg| arg == "--migrate-dry-run");
+    if let Err(e) = database::migrations::run(dry_run) {
+        error!("Migration des données impossible: {}", e);
+        std::process::exit(1);
+    }
+    if dry_run {
+        return;
+    }
 
-    // Ajouter une gestion de fin pour sauvegarder les posts
-    tokio::spawn(async {
-        tokio::signal::ctrl_c().await.unwrap();
-        if let Err(e) = save_posts_to_file() {
-            eprintln!("Erreur lors de la sauvegarde des posts: {}", e);
-        }
-    });
+    // Charger les bases de données
+    if let Err(e) = database::load_all() {
+        error!("Chargement des données impossible: {}", e);
+        std::process::exit(1);
+    }
+    if let Err(e) = utils::webauthn::load_credentials().await {
+        error!("Chargement des passkeys impossible: {}", e);
+        std::process::exit(1);
+    }
 
-    // Démarrer le serveur web
-    let addr = SocketAddr::from(([0, 0, 0, 0], HTTP_PORT));
-    info!("Listening on {}", addr);
+    // Valider la politique d'enregistrement WebAuthn
+    if let Err(e) = utils::registration_policy::load() {
+        error!("Politique WebAuthn invalide: {}", e);
+        std::process::exit(1);
+    }
 
-    let listener = tokio::net::TcpListener::bind(addr)
-        .await
-        .expect("Failed to open web server listener");
+    // Retirer régulièrement les cérémonies WebAuthn expirées
+    tokio::spawn(backend::ceremonies::sweep_periodically());
+    // Retirer régulièrement les sessions expirées
+    tokio::spawn(backend::session_store::delete_expired_periodically());
+    tokio::spawn(backend::sessions::sweep_periodically());
+    // Supprimer les comptes jamais vérifiés
+    tokio::spawn(backend::verification::purge_unverified_periodically());
+    // Publier les posts programmés
+    tokio::spawn(backend::drafts::publish_scheduled_periodically());
+    // Envoyer les résumés quotidiens des notifications
+    tokio::spawn(backend::notifications::send_digests_periodically());
 
-    axum::serve(listener, app)
-        .await
-        .expect("Failed to bind Axum to listener");
+    // Démarrer le serveur web (HTTP ou HTTPS selon la configuration)
+    if let Err(e) = server::run(app()).await {
+        error!("Serveur arrêté sur une erreur: {}", e);
+        std::process::exit(1);
+    }
 }
@@ -0,0 +1,215 @@
+//! Métriques Prometheus de l'application.
+//! Les compteurs HTTP sont alimentés par le middleware `track_requests`, les autres
+//! par les modules concernés (WebAuthn, persistance des bases).
+
+use std::time::Instant;
+use axum::{
+    extract::{MatchedPath, Request},
+    middleware::Next,
+    response::Response,
+};
+use once_cell::sync::Lazy;
+use prometheus::{
+    register_histogram_vec_with_registry, register_int_counter_vec_with_registry,
+    register_int_gauge_vec_with_registry, register_int_gauge_with_registry, Encoder, HistogramVec,
+    IntCounterVec, IntGauge, IntGaugeVec, Registry, TextEncoder,
+};
+
+static REGISTRY: Lazy<Registry> = Lazy::new(|| Registry::new_custom(Some("lab02".to_string()), None).unwrap());
+
+static HTTP_REQUESTS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec_with_registry!(
+        "http_requests_total",
+        "HTTP requests by route, method and status",
+        &["route", "method", "status"],
+        REGISTRY
+    )
+    .unwrap()
+});
+
+static HTTP_LATENCY: Lazy<HistogramVec> = Lazy::new(|| {
+    register_histogram_vec_with_registry!(
+        "http_request_duration_seconds",
+        "HTTP request latency by route and method",
+        &["route", "method"],
+        REGISTRY
+    )
+    .unwrap()
+});
+
+static WEBAUTHN_CEREMONIES: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec_with_registry!(
+        "webauthn_ceremonies_total",
+        "Completed WebAuthn ceremonies by kind and outcome",
+        &["ceremony", "outcome"],
+        REGISTRY
+    )
+    .unwrap()
+});
+
+static PENDING_STATES: Lazy<IntGaugeVec> = Lazy::new(|| {
+    register_int_gauge_vec_with_registry!(
+        "webauthn_pending_states",
+        "WebAuthn ceremonies started but not completed",
+        &["ceremony"],
+        REGISTRY
+    )
+    .unwrap()
+});
+
+static EMAIL_QUEUE_DEPTH: Lazy<IntGauge> = Lazy::new(|| {
+    register_int_gauge_with_registry!("email_queue_depth", "Emails waiting in the outbox", REGISTRY).unwrap()
+});
+
+static LIVE_CONNECTIONS: Lazy<IntGauge> = Lazy::new(|| {
+    register_int_gauge_with_registry!("live_connections", "Open live feed connections", REGISTRY).unwrap()
+});
+
+static STORE_PERSIST_LATENCY: Lazy<HistogramVec> = Lazy::new(|| {
+    register_histogram_vec_with_registry!(
+        "store_persist_duration_seconds",
+        "Time spent writing a store to disk",
+        &["store"],
+        REGISTRY
+    )
+    .unwrap()
+});
+
+static STATE_EVICTIONS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec_with_registry!(
+        "webauthn_state_evictions_total",
+        "Pending WebAuthn states dropped by ceremony and reason",
+        &["ceremony", "reason"],
+        REGISTRY
+    )
+    .unwrap()
+});
+
+static RATE_LIMITED: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec_with_registry!(
+        "rate_limited_requests_total",
+        "Requests rejected by the rate limiter by route",
+        &["route"],
+        REGISTRY
+    )
+    .unwrap()
+});
+
+/// Type de cérémonie WebAuthn
+#[derive(Clone, Copy)]
+pub enum Ceremony {
+    Registration,
+    Authentication,
+}
+
+impl Ceremony {
+    fn label(self) -> &'static str {
+        match self {
+            Ceremony::Registration => "registration",
+            Ceremony::Authentication => "authentication",
+        }
+    }
+}
+
+/// Enregistre l'issue d'une cérémonie WebAuthn
+pub fn record_ceremony(ceremony: Ceremony, success: bool) {
+    let outcome = if success { "success" } else { "failure" };
+    WEBAUTHN_CEREMONIES.with_label_values(&[ceremony.label(), outcome]).inc();
+}
+
+/// Met à jour le nombre de cérémonies en attente
+pub fn set_pending_states(ceremony: Ceremony, count: usize) {
+    PENDING_STATES.with_label_values(&[ceremony.label()]).set(count as i64);
+}
+
+/// Compte un état de cérémonie abandonné (expiré ou évincé par une limite)
+pub fn record_state_eviction(ceremony: Ceremony, reason: &str) {
+    STATE_EVICTIONS.with_label_values(&[ceremony.label(), reason]).inc();
+}
+
+/// Compte une requête refusée par la limitation de débit
+pub fn record_rate_limited(route: &str) {
+    RATE_LIMITED.with_label_values(&[route]).inc();
+}
+
+pub fn set_email_queue_depth(depth: usize) {
+    EMAIL_QUEUE_DEPTH.set(depth as i64);
+}
+
+pub fn set_live_connections(count: usize) {
+    LIVE_CONNECTIONS.set(count as i64);
+}
+
+/// Mesure la durée d'écriture d'une base
+pub fn observe_persist(store: &str, started: Instant) {
+    STORE_PERSIST_LATENCY
+        .with_label_values(&[store])
+        .observe(started.elapsed().as_secs_f64());
+}
+
+/// Middleware comptant les requêtes et leur latence par route.
+/// Le libellé de route est le motif axum (`/validate/:token`) pour borner la cardinalité.
+pub async fn track_requests(request: Request, next: Next) -> Response {
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|path| path.as_str().to_string())
+        .unwrap_or_else(|| "unmatched".to_string());
+    let method = request.method().to_string();
+    let started = Instant::now();
+
+    let response = next.run(request).await;
+
+    HTTP_LATENCY
+        .with_label_values(&[&route, &method])
+        .observe(started.elapsed().as_secs_f64());
+    HTTP_REQUESTS
+        .with_label_values(&[&route, &method, response.status().as_str()])
+        .inc();
+
+    response
+}
+
+/// Exporte toutes les métriques au format texte Prometheus
+pub fn render() -> String {
+    // Force l'enregistrement des familles pour qu'elles apparaissent même sans valeur
+    Lazy::force(&HTTP_REQUESTS);
+    Lazy::force(&HTTP_LATENCY);
+    Lazy::force(&WEBAUTHN_CEREMONIES);
+    Lazy::force(&PENDING_STATES);
+    Lazy::force(&STATE_EVICTIONS);
+    Lazy::force(&RATE_LIMITED);
+    Lazy::force(&EMAIL_QUEUE_DEPTH);
+    Lazy::force(&STORE_PERSIST_LATENCY);
+
+    let mut buffer = vec![];
+    TextEncoder::new()
+        .encode(&REGISTRY.gather(), &mut buffer)
+        .expect("Failed to encode metrics");
+    String::from_utf8(buffer).expect("Metrics are not valid UTF-8")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{body::Body, routing::get, Router};
+    use tower::ServiceExt;
+
+    #[tokio::test]
+    async fn test_requests_are_labelled_by_route_pattern() {
+        let app = Router::new()
+            .route("/metrics-test/:token", get(|| async { "ok" }))
+            .layer(axum::middleware::from_fn(track_requests));
+
+        app.oneshot(Request::builder().uri("/metrics-test/secret").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        record_ceremony(Ceremony::Registration, false);
+
+        let exported = render();
+        assert!(exported.contains(r#"lab02_http_requests_total{method="GET",route="/metrics-test/:token",status="200"} 1"#));
+        assert!(!exported.contains("secret"));
+        assert!(exported.contains(r#"lab02_webauthn_ceremonies_total{ceremony="registration",outcome="failure"}"#));
+        assert!(exported.contains("lab02_email_queue_depth"));
+    }
+}
@@ -0,0 +1,207 @@
+//! Démarrage des listeners HTTP et HTTPS.
+//! Sans configuration TLS, le routeur est servi en HTTP comme auparavant. Avec TLS, il est servi
+//! en HTTPS (rustls) et un listener HTTP optionnel redirige vers l'URL publique.
+//! Le certificat est rechargé à la réception de SIGHUP, sans couper les connexions établies.
+
+use std::{io, net::SocketAddr, time::Duration};
+use axum::{
+    extract::Request,
+    response::{IntoResponse, Redirect},
+    Router,
+};
+use axum_server::{tls_rustls::RustlsConfig, Handle};
+use log::{error, info};
+use crate::config::{TlsConfig, CONFIG};
+
+/// Délai laissé aux requêtes en cours lors de l'arrêt
+const SHUTDOWN_GRACE_PERIOD: Duration = Duration::from_secs(10);
+
+/// Sert l'application selon la configuration, jusqu'à Ctrl-C
+pub async fn run(app: Router) -> io::Result<()> {
+    let handle = Handle::new();
+    tokio::spawn(shutdown_on_ctrl_c(handle.clone()));
+
+    let http_addr = SocketAddr::from(([0, 0, 0, 0], CONFIG.http_port));
+
+    let Some(tls) = CONFIG.tls.clone() else {
+        info!("Listening on http://{}", http_addr);
+        return axum_server::bind(http_addr)
+            .handle(handle)
+            .serve(app.into_make_service_with_connect_info::<SocketAddr>())
+            .await;
+    };
+
+    let rustls_config = load_tls(&tls).await?;
+    tokio::spawn(reload_on_sighup(rustls_config.clone(), tls.clone()));
+
+    if tls.redirect_http {
+        info!("Redirecting http://{} to HTTPS", http_addr);
+        let redirect = axum_server::bind(http_addr)
+            .handle(handle.clone())
+            .serve(redirect_router().into_make_service());
+        tokio::spawn(async move {
+            if let Err(e) = redirect.await {
+                error!("HTTP redirect listener stopped: {}", e);
+            }
+        });
+    }
+
+    let https_addr = SocketAddr::from(([0, 0, 0, 0], tls.https_port));
+    info!("Listening on https://{}", https_addr);
+    axum_server::bind_rustls(https_addr, rustls_config)
+        .handle(handle)
+        .serve(app.into_make_service_with_connect_info::<SocketAddr>())
+        .await
+}
+
+/// Charge le certificat et la clé PEM configurés
+pub async fn load_tls(tls: &TlsConfig) -> io::Result<RustlsConfig> {
+    // Le fournisseur peut déjà être installé (rechargement, tests), l'erreur est alors sans effet
+    let _ = rustls::crypto::ring::default_provider().install_default();
+    RustlsConfig::from_pem_file(&tls.cert_path, &tls.key_path).await
+}
+
+/// Remplace le certificat servi pour les nouvelles connexions
+pub async fn reload_tls(config: &RustlsConfig, tls: &TlsConfig) -> io::Result<()> {
+    config.reload_from_pem_file(&tls.cert_path, &tls.key_path).await
+}
+
+#[cfg(unix)]
+async fn reload_on_sighup(config: RustlsConfig, tls: TlsConfig) {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut hangup = match signal(SignalKind::hangup()) {
+        Ok(hangup) => hangup,
+        Err(e) => {
+            error!("Failed to listen for SIGHUP: {}", e);
+            return;
+        }
+    };
+
+    while hangup.recv().await.is_some() {
+        // En cas d'échec, l'ancien certificat reste en service
+        match reload_tls(&config, &tls).await {
+            Ok(()) => info!("TLS certificate reloaded"),
+            Err(e) => error!("Failed to reload TLS certificate: {}", e),
+        }
+    }
+}
+
+#[cfg(not(unix))]
+async fn reload_on_sighup(_config: RustlsConfig, _tls: TlsConfig) {}
+
+/// Routeur du listener HTTP qui redirige toutes les requêtes vers l'URL publique HTTPS.
+/// L'hôte de la requête est ignoré pour ne pas servir de redirection ouverte.
+pub fn redirect_router() -> Router {
+    Router::new().fallback(|request: Request| async move {
+        let path = request.uri().path_and_query().map(|pq| pq.as_str()).unwrap_or("/");
+        Redirect::permanent(&CONFIG.link(path)).into_response()
+    })
+}
+
+async fn shutdown_on_ctrl_c(handle: Handle) {
+    tokio::signal::ctrl_c().await.ok();
+    info!("Shutting down");
+    handle.graceful_shutdown(Some(SHUTDOWN_GRACE_PERIOD));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{path::Path, sync::Arc};
+    use axum::{body::Body, routing::get};
+    use http::{header, StatusCode};
+    use rustls::pki_types::{CertificateDer, ServerName};
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpStream,
+    };
+    use tokio_rustls::{client::TlsStream, TlsConnector};
+    use tower::ServiceExt;
+
+    /// Génère un certificat auto-signé pour `localhost` et l'écrit au format PEM
+    fn write_self_signed(dir: &Path) -> CertificateDer<'static> {
+        let generated = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+        std::fs::write(dir.join("cert.pem"), generated.cert.pem()).unwrap();
+        std::fs::write(dir.join("key.pem"), generated.key_pair.serialize_pem()).unwrap();
+        generated.cert.der().clone()
+    }
+
+    async fn connect(addr: SocketAddr, trusted: &CertificateDer<'static>) -> io::Result<TlsStream<TcpStream>> {
+        let mut roots = rustls::RootCertStore::empty();
+        roots.add(trusted.clone()).unwrap();
+        let client = rustls::ClientConfig::builder().with_root_certificates(roots).with_no_client_auth();
+
+        let tcp = TcpStream::connect(addr).await?;
+        TlsConnector::from(Arc::new(client))
+            .connect(ServerName::try_from("localhost").unwrap(), tcp)
+            .await
+    }
+
+    async fn fetch(stream: &mut TlsStream<TcpStream>) -> String {
+        stream
+            .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .await
+            .unwrap();
+        let mut buf = vec![0; 1024];
+        let read = stream.read(&mut buf).await.unwrap();
+        String::from_utf8_lossy(&buf[..read]).to_string()
+    }
+
+    #[tokio::test]
+    async fn test_https_and_reload_keeps_connections() {
+        let dir = std::env::temp_dir().join(format!("lab02-tls-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let tls = TlsConfig {
+            cert_path: dir.join("cert.pem"),
+            key_path: dir.join("key.pem"),
+            https_port: 0,
+            redirect_http: false,
+        };
+
+        let first_cert = write_self_signed(&dir);
+        let config = load_tls(&tls).await.unwrap();
+        let handle = Handle::new();
+        let app = Router::new().route("/", get(|| async { "hello" }));
+        let server = axum_server::bind_rustls(SocketAddr::from(([127, 0, 0, 1], 0)), config.clone())
+            .handle(handle.clone())
+            .serve(app.into_make_service_with_connect_info::<SocketAddr>());
+        tokio::spawn(server);
+        let addr = handle.listening().await.unwrap();
+
+        let mut established = connect(addr, &first_cert).await.unwrap();
+        assert!(fetch(&mut established).await.contains("hello"));
+
+        // Nouveau certificat : les nouvelles connexions l'utilisent, l'ancienne reste ouverte
+        let second_cert = write_self_signed(&dir);
+        reload_tls(&config, &tls).await.unwrap();
+
+        assert!(connect(addr, &first_cert).await.is_err());
+        let mut fresh = connect(addr, &second_cert).await.unwrap();
+        assert!(fetch(&mut fresh).await.contains("hello"));
+        assert!(fetch(&mut established).await.contains("hello"));
+
+        handle.shutdown();
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[tokio::test]
+    async fn test_redirect_ignores_host_header() {
+        let response = redirect_router()
+            .oneshot(
+                Request::builder()
+                    .uri("/login?validated=true")
+                    .header(header::HOST, "evil.example")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::PERMANENT_REDIRECT);
+        assert_eq!(
+            response.headers()[header::LOCATION],
+            CONFIG.link("/login?validated=true").as_str()
+        );
+    }
+}
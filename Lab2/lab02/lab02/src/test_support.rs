@@ -0,0 +1,21 @@
+//! Harnais commun des tests : les bases et les uploads sont écrits dans un dossier
+//! temporaire propre au processus de test, supprimé à la fin de l'exécution.
+
+use std::{env, fs, path::PathBuf, process};
+use crate::config::Config;
+
+/// Dossier de données du processus de test
+fn data_dir() -> PathBuf {
+    env::temp_dir().join(format!("lab02-test-{}", process::id()))
+}
+
+extern "C" fn remove_data_dir() {
+    let _ = fs::remove_dir_all(data_dir());
+}
+
+/// Configuration des tests : celle de l'environnement avec le dossier de données temporaire
+pub(crate) fn config() -> Config {
+    // Appelé une seule fois, par l'initialisation de `CONFIG`
+    unsafe { libc::atexit(remove_data_dir) };
+    Config { data_dir: data_dir(), ..Config::from_env().expect("Invalid test configuration") }
+}
@@ -1,4 +1,7 @@
 //! Modules utilitaires pour diverses fonctionnalités.
 
 pub(crate) mod input;
-pub(crate) mod webauthn;
+pub mod markdown;
+pub(crate) mod pagination;
+pub mod registration_policy;
+pub mod webauthn;
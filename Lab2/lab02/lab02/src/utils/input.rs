@@ -7,6 +7,11 @@ pub fn valid_email(em: &str) -> bool {
         .is_match(em)
 }
 
+/// Canonical form of an email address, used for every lookup and by the rate limiter.
+pub fn normalize_email(em: &str) -> String {
+    em.trim().to_lowercase()
+}
+
 /// Ensures the provided name consists only of alphabetic characters, spaces, and dashes, adhering to a length constraint.
 pub fn valid_name(nm: &str) -> bool {
     !nm.is_empty()
@@ -32,11 +37,6 @@ pub fn valid_bool(val: Option<&serde_json::Value>) -> bool {
     }
 }
 
-/// Validates text fields to ensure they are non-empty and within a specified maximum length.
-pub fn valid_text(txt: &str, max_len: usize) -> bool {
-    !txt.is_empty() && txt.len() <= max_len
-}
-
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -51,6 +51,12 @@ mod tests {
         assert!(!valid_email("username@domain@domain.com"));
     }
 
+    #[test]
+    fn test_normalize_email() {
+        assert_eq!(normalize_email(" Alice@Example.COM "), "alice@example.com");
+        assert_eq!(normalize_email("bob@example.com"), "bob@example.com");
+    }
+
     #[test]
     fn test_valid_name() {
         assert!(valid_name("John Doe"));
@@ -76,12 +82,4 @@ mod tests {
         assert!(!valid_bool(Some(&serde_json::Value::String("true".to_string()))));
         assert!(!valid_bool(None));
     }
-
-    #[test]
-    fn test_valid_text() {
-        assert!(valid_text("This is a valid text.", 50));
-        assert!(!valid_text("", 50));
-        assert!(!valid_text("A".repeat(51).as_str(), 50));
-        assert!(valid_text("Short text", 10));
-    }
 }
\ No newline at end of file
@@ -0,0 +1,244 @@
+//! Rendu Markdown du contenu des posts.
+//! Le sous-ensemble accepté couvre l'emphase, les liens, les listes, le code et les citations.
+//! Le HTML brut du texte source est affiché tel quel, puis le HTML produit passe par une
+//! liste blanche (`ammonia`) : seuls ces éléments, les URL `http`, `https` et `mailto` et les
+//! chemins du site (`/…`) survivent. Les autres liens relatifs, dont `//hôte` qui mène à un
+//! autre site, perdent leur cible. Les hashtags deviennent des liens internes, comme les mentions (`@email`) d'un
+//! compte public, qui mènent à son profil par son identifiant public.
+
+use std::{borrow::Cow, collections::HashSet};
+use ammonia::{Builder, UrlRelative};
+use once_cell::sync::Lazy;
+use pulldown_cmark::{html, CowStr, Event, Options, Parser, Tag, TagEnd};
+use regex::Regex;
+use crate::database::user;
+use crate::utils::input::normalize_email;
+
+/// Balises produites par le sous-ensemble Markdown
+const ALLOWED_TAGS: [&str; 13] = ["p", "br", "em", "strong", "del", "a", "ul", "ol", "li", "code", "pre", "blockquote", "hr"];
+/// Schémas d'URL acceptés dans les liens
+const ALLOWED_SCHEMES: [&str; 3] = ["http", "https", "mailto"];
+
+static SANITIZER: Lazy<Builder<'static>> = Lazy::new(|| {
+    let mut builder = Builder::empty();
+    builder
+        .tags(HashSet::from(ALLOWED_TAGS))
+        .generic_attributes(HashSet::new())
+        .tag_attributes([("a", HashSet::from(["href"])), ("ol", HashSet::from(["start"]))].into())
+        .url_schemes(HashSet::from(ALLOWED_SCHEMES))
+        .url_relative(UrlRelative::Custom(Box::new(site_path)))
+        .link_rel(Some("nofollow noopener"));
+    builder
+});
+
+/// Garde un lien relatif seulement s'il s'agit d'un chemin du site. Les navigateurs lisent
+/// `\` comme `/` et ignorent les tabulations et retours à la ligne : `/\hôte` ou `/<tab>/hôte`
+/// mèneraient aussi à `//hôte`.
+fn site_path(url: &str) -> Option<Cow<'_, str>> {
+    let valid = url.starts_with('/')
+        && !url.starts_with("//")
+        && !url.chars().any(|c| c == '\\' || c.is_control() || c.is_whitespace());
+    valid.then_some(Cow::Borrowed(url))
+}
+
+static HASHTAG: Lazy<Regex> = Lazy::new(|| Regex::new(r"(^|[^\w&])#(\w{1,50})").unwrap());
+static MENTION: Lazy<Regex> = Lazy::new(|| Regex::new(r"(^|[^\w.+-])@([\w.+-]+@[\w-]+(?:\.[\w-]+)+)").unwrap());
+
+/// Rend le contenu d'un post en HTML sûr
+pub fn render(content: &str) -> String {
+    let mut in_link = false;
+    let mut in_code = false;
+    let events = Parser::new_ext(content, Options::ENABLE_STRIKETHROUGH).flat_map(|event| match event {
+        // Le HTML brut n'est jamais interprété
+        Event::Html(raw) | Event::InlineHtml(raw) => vec![Event::Text(raw)],
+        // Les images ne font pas partie du sous-ensemble : seul leur texte est gardé
+        Event::Start(Tag::Image { .. }) | Event::End(TagEnd::Image) => vec![],
+        Event::Start(Tag::Link { .. }) => {
+            in_link = true;
+            vec![event]
+        }
+        Event::End(TagEnd::Link) => {
+            in_link = false;
+            vec![event]
+        }
+        Event::Start(Tag::CodeBlock(_)) => {
+            in_code = true;
+            vec![event]
+        }
+        Event::End(TagEnd::CodeBlock) => {
+            in_code = false;
+            vec![event]
+        }
+        Event::Text(text) if !in_link && !in_code => autolink(text),
+        event => vec![event],
+    });
+
+    let mut unsafe_html = String::new();
+    html::push_html(&mut unsafe_html, events);
+    SANITIZER.clean(&unsafe_html).to_string()
+}
+
+/// Remplace les hashtags et les mentions d'un texte par des liens
+fn autolink(text: CowStr<'_>) -> Vec<Event<'_>> {
+    let mut links: Vec<(usize, usize, String)> = HASHTAG
+        .captures_iter(&text)
+        .map(|captures| {
+            let whole = captures.get(0).unwrap();
+            let tag = &captures[2];
+            let start = whole.start() + captures[1].len();
+            (start, whole.end(), format!(r#"<a href="/tags/{}">#{}</a>"#, tag.to_lowercase(), tag))
+        })
+        .collect();
+    links.extend(MENTION.captures_iter(&text).filter_map(|captures| {
+        let whole = captures.get(0).unwrap();
+        let email = &captures[2];
+        let start = whole.start() + captures[1].len();
+        // Un hashtag déjà trouvé dans l'adresse l'emporte
+        if links.iter().any(|(s, e, _)| *s < whole.end() && start < *e) {
+            return None;
+        }
+        let handle = user::get(&normalize_email(email)).filter(user::User::is_public)?.handle;
+        Some((start, whole.end(), format!(r#"<a href="/users/{}">@{}</a>"#, handle, email)))
+    }).collect::<Vec<_>>());
+    if links.is_empty() {
+        return vec![Event::Text(text)];
+    }
+    links.sort_by_key(|(start, _, _)| *start);
+
+    // Les captures ne contiennent que des caractères de mot, `.`, `+`, `-` et `@` : rien à échapper
+    let mut events = vec![];
+    let mut cursor = 0;
+    for (start, end, link) in links {
+        if start > cursor {
+            events.push(Event::Text(text[cursor..start].to_string().into()));
+        }
+        events.push(Event::InlineHtml(link.into()));
+        cursor = end;
+    }
+    if cursor < text.len() {
+        events.push(Event::Text(text[cursor..].to_string().into()));
+    }
+    events
+}
+
+/// Hashtags d'un contenu, en minuscules et sans doublon
+pub fn hashtags(content: &str) -> Vec<String> {
+    let mut tags = vec![];
+    for captures in HASHTAG.captures_iter(content) {
+        let tag = captures[2].to_lowercase();
+        if !tags.contains(&tag) {
+            tags.push(tag);
+        }
+    }
+    tags
+}
+
+/// Comptes mentionnés dans un contenu, sans doublon
+pub fn mentions(content: &str) -> Vec<String> {
+    let mut emails = vec![];
+    for captures in MENTION.captures_iter(content) {
+        let email = captures[2].to_string();
+        if !emails.contains(&email) {
+            emails.push(email);
+        }
+    }
+    emails
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_markdown_subset() {
+        assert_eq!(render("*a* **b** `c`"), "<p><em>a</em> <strong>b</strong> <code>c</code></p>\n");
+        assert_eq!(render("> quote"), "<blockquote>\n<p>quote</p>\n</blockquote>\n");
+        assert_eq!(render("- one\n- two"), "<ul>\n<li>one</li>\n<li>two</li>\n</ul>\n");
+        assert_eq!(
+            render("[site](https://example.com)"),
+            "<p><a href=\"https://example.com\" rel=\"nofollow noopener\">site</a></p>\n"
+        );
+        // Un chemin du site est gardé, un lien vers un autre hôte sans schéma perd sa cible
+        assert_eq!(render("[tags](/tags/rust)"), "<p><a href=\"/tags/rust\" rel=\"nofollow noopener\">tags</a></p>\n");
+        assert_eq!(render("[x](//evil.example)"), "<p><a rel=\"nofollow noopener\">x</a></p>\n");
+        // Les titres et les images ne gardent que leur texte
+        assert_eq!(render("# Title"), "Title\n");
+        assert_eq!(render("![alt](https://example.com/x.png)"), "<p>alt</p>\n");
+    }
+
+    #[test]
+    fn test_hashtags_and_mentions() {
+        // Un compte inconnu n'est pas lié
+        assert_eq!(
+            render("Hello @nobody@example.com #Rust"),
+            "<p>Hello @nobody@example.com <a href=\"/tags/rust\" rel=\"nofollow noopener\">#Rust</a></p>\n"
+        );
+        // Ni dans le code, ni dans un lien, ni au milieu d'un mot
+        assert_eq!(render("`#no` a#no"), "<p><code>#no</code> a#no</p>\n");
+        assert!(!render("[#no](https://example.com)").contains("/tags/"));
+        assert_eq!(hashtags("#Rust and #rust, #web"), vec!["rust", "web"]);
+        assert_eq!(mentions("@bob@example.com, bob@example.com"), vec!["bob@example.com"]);
+    }
+
+    /// Aucun contenu exécutable ne doit survivre au rendu
+    #[test]
+    fn test_xss_payloads_are_neutralised() {
+        let payloads = [
+            "<script>alert(1)</script>",
+            "<img src=x onerror=alert(1)>",
+            "<svg onload=alert(1)>",
+            "<iframe src=\"javascript:alert(1)\"></iframe>",
+            "<a href=\"javascript:alert(1)\">x</a>",
+            "[x](javascript:alert(1))",
+            "[x](JaVaScRiPt:alert(1))",
+            "[x](java\tscript:alert(1))",
+            "[x](vbscript:msgbox(1))",
+            "[x](data:text/html;base64,PHNjcmlwdD5hbGVydCgxKTwvc2NyaXB0Pg==)",
+            "![x](javascript:alert(1))",
+            "![x\" onerror=\"alert(1)](https://example.com/x.png)",
+            "[x](https://example.com \"title\\\" onmouseover=\\\"alert(1)\")",
+            "<div style=\"background:url(javascript:alert(1))\">x</div>",
+            "<body onload=alert(1)>",
+            "<math><mtext><table><mglyph><style><img src=x onerror=alert(1)>",
+            "<<script>script>alert(1)<</script>/script>",
+            "`<script>alert(1)</script>`",
+            "```\n<script>alert(1)</script>\n```",
+            "<details open ontoggle=alert(1)>",
+            "<form action=\"javascript:alert(1)\"><button>x</button></form>",
+            "<object data=\"javascript:alert(1)\"></object>",
+            "<embed src=\"javascript:alert(1)\">",
+            "<meta http-equiv=\"refresh\" content=\"0;url=javascript:alert(1)\">",
+            "<base href=\"javascript:alert(1)//\">",
+            "[x](&#106;avascript:alert(1))",
+            "<a href=\"&#x6A;avascript:alert(1)\">x</a>",
+            "#tag\"><script>alert(1)</script>",
+            "@a\"onmouseover=alert(1)@example.com",
+            "[x](//evil.example)",
+            "[x](//evil.example/path?q=1)",
+            "[x](/\\evil.example)",
+            "[x](\\\\evil.example)",
+            "[x](</\t/evil.example>)",
+        ];
+        let tag = Regex::new(r"<(/?)([a-zA-Z0-9]+)([^>]*)>").unwrap();
+        let attribute = Regex::new(r#"([a-zA-Z-]+)="([^"]*)""#).unwrap();
+        for payload in payloads {
+            let rendered = render(payload);
+            // Le texte échappé (`&lt;script&gt;`) reste affichable : seules les balises produites comptent
+            for captures in tag.captures_iter(&rendered) {
+                assert!(ALLOWED_TAGS.contains(&&captures[2]), "<{}> survived {:?}: {}", &captures[2], payload, rendered);
+                let attributes = &captures[3];
+                let names: Vec<_> = attribute.captures_iter(attributes).map(|attr| attr[1].to_string()).collect();
+                assert!(names.iter().all(|name| ["href", "rel", "start"].contains(&name.as_str())), "{:?}: {}", payload, rendered);
+                assert_eq!(attribute.replace_all(attributes, "").trim(), "", "{:?}: {}", payload, rendered);
+                for href in attribute.captures_iter(attributes).filter(|attr| &attr[1] == "href") {
+                    let href = href[2].to_lowercase();
+                    let site_path = href.starts_with('/') && !href.starts_with("//") && !href.contains('\\');
+                    assert!(
+                        site_path || href.starts_with("http://") || href.starts_with("https://") || href.starts_with("mailto:"),
+                        "{:?}: {}", payload, rendered
+                    );
+                }
+            }
+        }
+    }
+}
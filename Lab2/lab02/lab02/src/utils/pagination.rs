@@ -0,0 +1,22 @@
+//! Découpage des listes affichées par pages.
+
+/// Page `page` (à partir de 1) et présence d'une page suivante
+pub(crate) fn paginate<T>(items: Vec<T>, page: usize, page_size: usize) -> (Vec<T>, bool) {
+    let start = page.saturating_sub(1).saturating_mul(page_size);
+    let has_next = items.len() > start.saturating_add(page_size);
+    (items.into_iter().skip(start).take(page_size).collect(), has_next)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_paginate() {
+        let items: Vec<u32> = (0..5).collect();
+        assert_eq!(paginate(items.clone(), 1, 2), (vec![0, 1], true));
+        assert_eq!(paginate(items.clone(), 3, 2), (vec![4], false));
+        assert_eq!(paginate(items.clone(), 4, 2), (vec![], false));
+        assert_eq!(paginate(items, usize::MAX, 2), (vec![], false));
+    }
+}
@@ -0,0 +1,241 @@
+//! Politique d'acceptation des authentificateurs lors de l'enregistrement.
+//! Construite depuis la configuration au démarrage : préférence d'attestation, autorités
+//! d'attestation de confiance, listes d'AAGUID et exigence de resident key. La vérification
+//! de l'utilisateur n'est pas configurable : les passkeys l'exigent toujours.
+//!
+//! Les listes d'AAGUID ne sont appliquées qu'avec une attestation vérifiée : sans autorité
+//! de confiance, l'AAGUID est déclaré librement par le client (et mis à zéro par les
+//! navigateurs en l'absence d'attestation).
+
+use std::{fmt, fs};
+use anyhow::{anyhow, bail, Result};
+use once_cell::sync::OnceCell;
+use uuid::Uuid;
+use webauthn_rs::prelude::{AttestationCaList, AttestationMetadata, ParsedAttestation};
+use webauthn_rs_proto::{AttestationConveyancePreference, CredProps, ResidentKeyRequirement};
+use crate::config::{WebauthnPolicy, CONFIG};
+
+static POLICY: OnceCell<RegistrationPolicy> = OnceCell::new();
+
+/// Politique validée
+pub struct RegistrationPolicy {
+    pub attestation: AttestationConveyancePreference,
+    /// Autorités de confiance ; leur présence impose une attestation vérifiée
+    pub ca_list: Option<AttestationCaList>,
+    pub aaguid_allow: Vec<Uuid>,
+    pub aaguid_deny: Vec<Uuid>,
+    pub resident_key: Option<ResidentKeyRequirement>,
+}
+
+/// Enregistrement refusé par la politique
+#[derive(Debug)]
+pub struct PolicyViolation(pub String);
+
+impl fmt::Display for PolicyViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Authenticator not allowed by policy: {}", self.0)
+    }
+}
+
+impl std::error::Error for PolicyViolation {}
+
+/// Valide la politique configurée ; à appeler au démarrage pour échouer tôt
+pub fn load() -> Result<()> {
+    let policy = RegistrationPolicy::from_config(&CONFIG.webauthn)?;
+    let _ = POLICY.set(policy);
+    Ok(())
+}
+
+/// Politique courante
+pub fn get() -> &'static RegistrationPolicy {
+    POLICY.get_or_init(|| RegistrationPolicy::from_config(&CONFIG.webauthn).expect("Invalid WebAuthn policy"))
+}
+
+impl RegistrationPolicy {
+    pub fn from_config(config: &WebauthnPolicy) -> Result<Self> {
+        let mut ca_list: Option<AttestationCaList> = None;
+        for path in &config.attestation_ca_files {
+            let pem = fs::read(path).map_err(|e| anyhow!("Failed to read attestation CA {}: {}", path.display(), e))?;
+            let cas = AttestationCaList::try_from(pem.as_slice())
+                .map_err(|e| anyhow!("Invalid attestation CA {}: {}", path.display(), e))?;
+            match ca_list.as_mut() {
+                Some(list) => list.union(&cas),
+                None => ca_list = Some(cas),
+            }
+        }
+
+        let attestation = match config.attestation.as_deref() {
+            None if ca_list.is_some() => AttestationConveyancePreference::Direct,
+            None | Some("none") => AttestationConveyancePreference::None,
+            Some("indirect") => AttestationConveyancePreference::Indirect,
+            Some("direct") => AttestationConveyancePreference::Direct,
+            Some(other) => bail!("Unknown attestation preference '{}'", other),
+        };
+        if ca_list.is_some() && !matches!(attestation, AttestationConveyancePreference::Direct) {
+            bail!("Attestation CAs require the 'direct' attestation preference");
+        }
+
+        let aaguid_allow = parse_aaguids(&config.aaguid_allow)?;
+        let aaguid_deny = parse_aaguids(&config.aaguid_deny)?;
+        if ca_list.is_none() && !(aaguid_allow.is_empty() && aaguid_deny.is_empty()) {
+            bail!("AAGUID lists require attestation CAs, the AAGUID cannot be trusted otherwise");
+        }
+
+        let resident_key = match config.resident_key.as_deref() {
+            None => None,
+            Some("discouraged") => Some(ResidentKeyRequirement::Discouraged),
+            Some("preferred") => Some(ResidentKeyRequirement::Preferred),
+            Some("required") => Some(ResidentKeyRequirement::Required),
+            Some(other) => bail!("Unknown resident key requirement '{}'", other),
+        };
+
+        Ok(RegistrationPolicy { attestation, ca_list, aaguid_allow, aaguid_deny, resident_key })
+    }
+
+    /// Exigence de resident key pour un enregistrement, selon le choix de l'utilisateur
+    /// si la politique n'en impose pas
+    pub fn resident_key(&self, discoverable: bool) -> ResidentKeyRequirement {
+        match &self.resident_key {
+            Some(requirement) => *requirement,
+            None if discoverable => ResidentKeyRequirement::Required,
+            None => ResidentKeyRequirement::Discouraged,
+        }
+    }
+
+    /// Vérifie le modèle d'authentificateur déclaré par une attestation vérifiée
+    pub fn check_attestation(&self, attestation: &ParsedAttestation) -> Result<(), PolicyViolation> {
+        let aaguid = match &attestation.metadata {
+            AttestationMetadata::Packed { aaguid } | AttestationMetadata::Tpm { aaguid, .. } => Some(*aaguid),
+            _ => None,
+        };
+        self.check_aaguid(aaguid)
+    }
+
+    fn check_aaguid(&self, aaguid: Option<Uuid>) -> Result<(), PolicyViolation> {
+        if self.aaguid_allow.is_empty() && self.aaguid_deny.is_empty() {
+            return Ok(());
+        }
+        let Some(aaguid) = aaguid else {
+            return Err(PolicyViolation("authenticator model could not be identified".to_string()));
+        };
+        if self.aaguid_deny.contains(&aaguid) {
+            return Err(PolicyViolation(format!("AAGUID {} is denied", aaguid)));
+        }
+        if !self.aaguid_allow.is_empty() && !self.aaguid_allow.contains(&aaguid) {
+            return Err(PolicyViolation(format!("AAGUID {} is not in the allow list", aaguid)));
+        }
+        Ok(())
+    }
+}
+
+/// Vérifie qu'une resident key exigée a bien été créée, d'après l'extension `credProps`.
+/// Elle est rapportée par le navigateur sans signature : le contrôle écarte les
+/// authentificateurs qui se replient sur un credential non découvrable, pas un client malveillant.
+pub fn check_resident_key(requirement: ResidentKeyRequirement, cred_props: Option<&CredProps>) -> Result<(), PolicyViolation> {
+    match (requirement, cred_props) {
+        (ResidentKeyRequirement::Required, Some(CredProps { rk: true })) => Ok(()),
+        (ResidentKeyRequirement::Required, _) => {
+            Err(PolicyViolation("a discoverable credential (resident key) is required".to_string()))
+        }
+        _ => Ok(()),
+    }
+}
+
+fn parse_aaguids(values: &[String]) -> Result<Vec<Uuid>> {
+    values
+        .iter()
+        .map(|value| Uuid::parse_str(value).map_err(|_| anyhow!("Invalid AAGUID '{}'", value)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const YUBIKEY_5: &str = "cb69481e-8ff7-4039-93ec-0a2729a154a8";
+    const SOLOKEY: &str = "8876631b-d4a0-427f-5773-0ec71c9e0279";
+
+    fn policy(attestation: Option<&str>, resident_key: Option<&str>) -> Result<RegistrationPolicy> {
+        RegistrationPolicy::from_config(&WebauthnPolicy {
+            attestation: attestation.map(str::to_string),
+            resident_key: resident_key.map(str::to_string),
+            ..Default::default()
+        })
+    }
+
+    fn with_lists(allow: &[&str], deny: &[&str]) -> RegistrationPolicy {
+        RegistrationPolicy {
+            aaguid_allow: parse_aaguids(&allow.iter().map(|s| s.to_string()).collect::<Vec<_>>()).unwrap(),
+            aaguid_deny: parse_aaguids(&deny.iter().map(|s| s.to_string()).collect::<Vec<_>>()).unwrap(),
+            ..policy(None, None).unwrap()
+        }
+    }
+
+    fn packed(aaguid: &str) -> ParsedAttestation {
+        ParsedAttestation {
+            metadata: AttestationMetadata::Packed { aaguid: Uuid::parse_str(aaguid).unwrap() },
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_default_policy_is_permissive() {
+        let policy = policy(None, None).unwrap();
+        assert!(matches!(policy.attestation, AttestationConveyancePreference::None));
+        assert!(policy.ca_list.is_none());
+        assert!(policy.check_attestation(&ParsedAttestation::default()).is_ok());
+        assert_eq!(policy.resident_key(false), ResidentKeyRequirement::Discouraged);
+        assert_eq!(policy.resident_key(true), ResidentKeyRequirement::Required);
+    }
+
+    #[test]
+    fn test_invalid_configuration_is_rejected() {
+        assert!(policy(Some("enterprise"), None).is_err());
+        assert!(policy(None, Some("always")).is_err());
+
+        let missing_ca = RegistrationPolicy::from_config(&WebauthnPolicy {
+            attestation_ca_files: vec!["/nonexistent/ca.pem".into()],
+            ..Default::default()
+        });
+        assert!(missing_ca.is_err());
+
+        let untrusted_aaguid = RegistrationPolicy::from_config(&WebauthnPolicy {
+            attestation: Some("direct".to_string()),
+            aaguid_allow: vec![YUBIKEY_5.to_string()],
+            ..Default::default()
+        });
+        assert!(untrusted_aaguid.is_err());
+    }
+
+    #[test]
+    fn test_forced_resident_key_overrides_user_choice() {
+        let policy = policy(Some("indirect"), Some("preferred")).unwrap();
+        assert!(matches!(policy.attestation, AttestationConveyancePreference::Indirect));
+        assert_eq!(policy.resident_key(false), ResidentKeyRequirement::Preferred);
+        assert_eq!(policy.resident_key(true), ResidentKeyRequirement::Preferred);
+    }
+
+    #[test]
+    fn test_required_resident_key_must_be_reported() {
+        let created = CredProps { rk: true };
+        let not_created = CredProps { rk: false };
+        assert!(check_resident_key(ResidentKeyRequirement::Required, Some(&created)).is_ok());
+        assert!(check_resident_key(ResidentKeyRequirement::Required, Some(&not_created)).is_err());
+        assert!(check_resident_key(ResidentKeyRequirement::Required, None).is_err());
+        assert!(check_resident_key(ResidentKeyRequirement::Preferred, Some(&not_created)).is_ok());
+        assert!(check_resident_key(ResidentKeyRequirement::Discouraged, None).is_ok());
+    }
+
+    #[test]
+    fn test_aaguid_allow_and_deny_lists() {
+        let allow = with_lists(&[YUBIKEY_5], &[]);
+        assert!(allow.check_attestation(&packed(YUBIKEY_5)).is_ok());
+        assert!(allow.check_attestation(&packed(SOLOKEY)).is_err());
+        assert!(allow.check_attestation(&ParsedAttestation::default()).is_err());
+
+        let deny = with_lists(&[], &[SOLOKEY]);
+        assert!(deny.check_attestation(&packed(YUBIKEY_5)).is_ok());
+        let violation = deny.check_attestation(&packed(SOLOKEY)).unwrap_err();
+        assert!(violation.to_string().contains("is denied"));
+    }
+}
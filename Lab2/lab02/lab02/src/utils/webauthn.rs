@@ -2,27 +2,30 @@
 //! Fournit des fonctions pour démarrer et compléter les processus d'enregistrement et d'authentification.
 //! Inclut également des mécanismes pour la gestion sécurisée des passkeys et des tokens de récupération.
 
-use std::collections::HashMap;
-use anyhow::{Result, Context};
+use std::{collections::HashMap, fs};
+use anyhow::{anyhow, bail, Context, Result};
 use webauthn_rs::prelude::*;
+use webauthn_rs_proto::{AllowCredentials, ResidentKeyRequirement};
+use log::warn;
+use ring::{hmac, rand::{SecureRandom, SystemRandom}};
 use once_cell::sync::Lazy;
-use url::Url;
 use tokio::sync::RwLock;
 use serde_json::{json, Value};
 use uuid::Uuid;
-use crate::backend::handlers_unauth::{REGISTRATION_STATES, AUTHENTICATION_STATES, TimedStoredState};
-use  base64;
-use crate::utils::input::valid_email;
-use crate::backend::handlers_unauth::register_begin;
-use axum::Json;
+use base64::{engine::general_purpose::STANDARD, Engine};
+use crate::config::CONFIG;
+use crate::consts;
+use crate::database::user;
+use crate::metrics::{self, Ceremony};
+use crate::utils::registration_policy::{self, PolicyViolation};
 
 
 // Initialisation globale de WebAuthn
 static WEBAUTHN: Lazy<Webauthn> = Lazy::new(|| {
-    let rp_id = "localhost";
-    let rp_origin = Url::parse("http://localhost:8080").expect("Invalid RP origin URL");
+    let rp_origin = &CONFIG.public_url;
+    let rp_id = rp_origin.host_str().expect("Public URL has no host");
 
-    WebauthnBuilder::new(rp_id, &rp_origin)
+    WebauthnBuilder::new(rp_id, rp_origin)
         .expect("Failed to initialize WebAuthn")
         .build()
         .expect("Failed to build WebAuthn instance")
@@ -31,35 +34,118 @@ static WEBAUTHN: Lazy<Webauthn> = Lazy::new(|| {
 // Store sécurisé pour les passkeys
 pub static CREDENTIAL_STORE: Lazy<RwLock<HashMap<String, Passkey>>> = Lazy::new(Default::default);
 
+/// Clé des identifiants factices, gardée dans le dossier de données pour que les
+/// identifiants d'un email inconnu restent les mêmes après un redémarrage
+static DECOY_KEY: Lazy<hmac::Key> = Lazy::new(|| {
+    let key = load_decoy_key().unwrap_or_else(|e| {
+        warn!("Decoy credential key not persisted: {}", e);
+        let mut key = [0u8; 32];
+        SystemRandom::new().fill(&mut key).expect("Failed to generate random bytes");
+        key.to_vec()
+    });
+    hmac::Key::new(hmac::HMAC_SHA256, &key)
+});
+
+fn load_decoy_key() -> Result<Vec<u8>> {
+    let path = CONFIG.data_dir.join(consts::LOGIN_DECOY_KEY_FILE);
+    if let Ok(key) = fs::read(&path) {
+        if key.len() == 32 {
+            return Ok(key);
+        }
+    }
+    let mut key = [0u8; 32];
+    SystemRandom::new().fill(&mut key).map_err(|_| anyhow!("Failed to generate random bytes"))?;
+    fs::create_dir_all(&CONFIG.data_dir)?;
+    fs::write(&path, key)?;
+    Ok(key.to_vec())
+}
+
+/// Credentials annoncés au client. Les transports sont retirés : les credentials factices
+/// n'en ont pas et les navigateurs essaient tous les transports sans cette indication.
+fn allow_credentials(credentials: Vec<AllowCredentials>) -> Value {
+    let credentials: Vec<_> = credentials
+        .into_iter()
+        .map(|credential| AllowCredentials { transports: None, ..credential })
+        .collect();
+    json!(credentials)
+}
+
+/// Remplit le store des passkeys depuis la base des utilisateurs, au démarrage
+pub async fn load_credentials() -> Result<usize> {
+    let passkeys: HashMap<String, Passkey> = user::all()?
+        .into_iter()
+        .filter_map(|user| Some((user.email, user.passkey?)))
+        .collect();
+    let count = passkeys.len();
+    *CREDENTIAL_STORE.write().await = passkeys;
+    Ok(count)
+}
+
+/// Cérémonie d'enregistrement en cours, selon la politique d'attestation
+#[derive(Clone)]
+pub enum RegistrationCeremony {
+    Passkey(PasskeyRegistration),
+    /// L'attestation doit être signée par une autorité de confiance
+    Attested(AttestedPasskeyRegistration),
+}
+
 // Structure pour stocker l'état d'enregistrement
-pub(crate) struct StoredRegistrationState {
-    pub registration_state: PasskeyRegistration,
-    pub challenge: String,
+pub struct StoredRegistrationState {
+    pub registration_state: RegistrationCeremony,
+    /// Email pour lequel l'enregistrement a été démarré
+    pub email: String,
+    /// Exigence de resident key demandée, vérifiée à la fin de l'enregistrement
+    pub resident_key: ResidentKeyRequirement,
 }
 
-/// Démarrer l'enregistrement WebAuthn
-pub async fn begin_registration(
+/// Cérémonie de connexion en attente
+pub enum PendingAuthentication {
+    /// Connexion par email : seul le credential du compte est accepté
+    Account {
+        email: String,
+        state: PasskeyAuthentication,
+    },
+    /// Connexion sans email : le compte est retrouvé depuis le credential renvoyé
+    Discoverable(DiscoverableAuthentication),
+    /// Ré-authentification d'un utilisateur déjà connecté (voir `backend::step_up`)
+    StepUp {
+        email: String,
+        state: PasskeyAuthentication,
+    },
+}
+
+/// Démarrer l'enregistrement WebAuthn selon la politique configurée.
+/// Avec `discoverable`, l'authentificateur doit stocker le credential (resident key)
+/// pour permettre la connexion sans saisir d'email, sauf si la politique impose un autre choix.
+pub(crate) async fn begin_registration(
     user_email: &str,
     user_display_name: &str,
-) -> Result<(Value, PasskeyRegistration)> {
+    discoverable: bool,
+) -> Result<(Value, StoredRegistrationState)> {
     let user_id = Uuid::new_v4();
+    let policy = registration_policy::get();
 
     // Generate registration challenge
-    let (challenge_response, registration_state) = WEBAUTHN
-        .start_passkey_registration(user_id, user_email, user_display_name, None)
-        .context("Failed to start passkey registration")?;
-
-    // Store registration state
-    let encoded_challenge = base64::encode(challenge_response.public_key.challenge.to_vec());
-
-    let mut states = REGISTRATION_STATES.write().await;
-    states.insert(
-        user_email.to_string(),
-        StoredRegistrationState {
-            registration_state: registration_state.clone(),
-            challenge: encoded_challenge.clone(),
-        },
-    );
+    let (mut challenge_response, registration_state) = match &policy.ca_list {
+        Some(ca_list) => WEBAUTHN
+            .start_attested_passkey_registration(user_id, user_email, user_display_name, None, ca_list.clone(), None)
+            .map(|(challenge, state)| (challenge, RegistrationCeremony::Attested(state))),
+        None => WEBAUTHN
+            .start_passkey_registration(user_id, user_email, user_display_name, None)
+            .map(|(challenge, state)| (challenge, RegistrationCeremony::Passkey(state))),
+    }
+    .context("Failed to start passkey registration")?;
+
+    // L'attestation est vérifiée par webauthn-rs, la resident key par `complete_registration`
+    // grâce à l'extension credProps déjà demandée dans les options
+    challenge_response.public_key.attestation = Some(policy.attestation.clone());
+    let resident_key = policy.resident_key(discoverable);
+    if let Some(selection) = challenge_response.public_key.authenticator_selection.as_mut() {
+        selection.require_resident_key = resident_key == ResidentKeyRequirement::Required;
+        selection.resident_key = Some(resident_key);
+    }
+
+    let encoded_challenge = STANDARD.encode(&challenge_response.public_key.challenge);
 
     // Return client registration options
     Ok((
@@ -75,8 +161,9 @@ pub async fn begin_registration(
             "timeout": challenge_response.public_key.timeout,
             "authenticatorSelection": challenge_response.public_key.authenticator_selection,
             "attestation": challenge_response.public_key.attestation,
+            "extensions": challenge_response.public_key.extensions,
         }),
-        registration_state,
+        StoredRegistrationState { registration_state, email: user_email.to_string(), resident_key },
     ))
 }
 
@@ -87,9 +174,22 @@ pub async fn complete_registration(
     response: &RegisterPublicKeyCredential,
     stored_state: &StoredRegistrationState,
 ) -> Result<()> {
-    let passkey = WEBAUTHN
-        .finish_passkey_registration(response, &stored_state.registration_state)
-        .context("Failed to complete passkey registration")?;
+    let result = match &stored_state.registration_state {
+        RegistrationCeremony::Passkey(state) => WEBAUTHN
+            .finish_passkey_registration(response, state)
+            .map_err(|e| registration_error(user_email, e)),
+        RegistrationCeremony::Attested(state) => finish_attested_registration(user_email, response, state),
+    }
+    .and_then(|passkey| {
+        let cred_props = response.extensions.cred_props.as_ref();
+        if let Err(violation) = registration_policy::check_resident_key(stored_state.resident_key, cred_props) {
+            warn!("Registration for {} rejected: {}", user_email, violation);
+            return Err(violation.into());
+        }
+        Ok(passkey)
+    });
+    metrics::record_ceremony(Ceremony::Registration, result.is_ok());
+    let passkey = result?;
 
     // Save passkey
     let mut store = CREDENTIAL_STORE.write().await;
@@ -98,24 +198,54 @@ pub async fn complete_registration(
     Ok(())
 }
 
+/// Erreur de fin d'enregistrement : les refus de la politique deviennent une `PolicyViolation`
+/// journalisée, les autres erreurs viennent d'une réponse invalide du client
+fn registration_error(user_email: &str, error: WebauthnError) -> anyhow::Error {
+    let violation = match error {
+        WebauthnError::UserNotVerified => "user verification is required",
+        WebauthnError::AttestationNotSupported
+        | WebauthnError::AttestationNotVerifiable
+        | WebauthnError::AttestationTrustFailure
+        | WebauthnError::AttestationChainNotTrusted(_)
+        | WebauthnError::AttestationUntrustedAaguid
+        | WebauthnError::AttestationFormatMissingAaguid => "attestation is not signed by a trusted authority",
+        error => return anyhow!(error).context("Failed to complete passkey registration"),
+    };
+    let violation = PolicyViolation(violation.to_string());
+    warn!("Registration for {} rejected: {} ({:?})", user_email, violation, error);
+    violation.into()
+}
+
+/// Termine un enregistrement attesté et applique la politique sur le modèle d'authentificateur
+fn finish_attested_registration(
+    user_email: &str,
+    response: &RegisterPublicKeyCredential,
+    state: &AttestedPasskeyRegistration,
+) -> Result<Passkey> {
+    let attested = WEBAUTHN
+        .finish_attested_passkey_registration(response, state)
+        .map_err(|e| registration_error(user_email, e))?;
+
+    if let Err(violation) = registration_policy::get().check_attestation(attested.attestation()) {
+        warn!("Registration for {} rejected: {}", user_email, violation);
+        return Err(violation.into());
+    }
+
+    Ok(attested.into())
+}
+
 /// Démarrer l'authentification WebAuthn
 pub async fn begin_authentication(user_email: &str) -> Result<(Value, PasskeyAuthentication)> {
     let store = CREDENTIAL_STORE.read().await;
     let passkey = store.get(user_email)
-        .ok_or_else(|| anyhow::anyhow!("No passkey found for user"))?;
+        .ok_or_else(|| anyhow!("No passkey found for user"))?;
 
     // Generate authentication challenge
     let (challenge_response, auth_state) = WEBAUTHN
-        .start_passkey_authentication(&[passkey.clone()])
+        .start_passkey_authentication(std::slice::from_ref(passkey))
         .context("Failed to start authentication")?;
 
-    // Store authentication state
-    let encoded_challenge = base64::encode(challenge_response.public_key.challenge.to_vec());
-    let mut states = AUTHENTICATION_STATES.write().await;
-    states.insert(
-        user_email.to_string(),
-        TimedStoredState::new(auth_state.clone(), encoded_challenge.clone()),
-    );
+    let encoded_challenge = STANDARD.encode(&challenge_response.public_key.challenge);
 
     // Return client authentication options
     Ok((
@@ -123,22 +253,93 @@ pub async fn begin_authentication(user_email: &str) -> Result<(Value, PasskeyAut
             "challenge": encoded_challenge, // Encoded challenge
             "timeout": challenge_response.public_key.timeout,
             "rpId": challenge_response.public_key.rp_id,
+            "allowCredentials": allow_credentials(challenge_response.public_key.allow_credentials),
+            "userVerification": challenge_response.public_key.user_verification,
+        }),
+        auth_state,
+    ))
+}
+
+/// Démarrer une authentification pour un email sans compte. Les options ont la même forme que
+/// pour un compte existant, avec un identifiant de passkey factice dérivé de l'email : il est
+/// le même à chaque demande, comme celui d'un vrai compte, et aucun appareil ne le possède.
+pub fn begin_decoy_authentication(user_email: &str) -> Result<(Value, DiscoverableAuthentication)> {
+    let (mut options, auth_state) = begin_discoverable_authentication()?;
+    let tag = hmac::sign(&DECOY_KEY, user_email.as_bytes());
+    let decoy = AllowCredentials {
+        type_: "public-key".to_string(),
+        id: tag.as_ref()[..consts::DECOY_CREDENTIAL_ID_LEN].to_vec().into(),
+        transports: None,
+    };
+    options["allowCredentials"] = allow_credentials(vec![decoy]);
+    Ok((options, auth_state))
+}
+
+/// Démarrer une authentification sans email (credentials découvrables, conditional UI)
+pub fn begin_discoverable_authentication() -> Result<(Value, DiscoverableAuthentication)> {
+    let (challenge_response, auth_state) = WEBAUTHN
+        .start_discoverable_authentication()
+        .context("Failed to start discoverable authentication")?;
+
+    let encoded_challenge = STANDARD.encode(&challenge_response.public_key.challenge);
+
+    Ok((
+        json!({
+            "challenge": encoded_challenge,
+            "timeout": challenge_response.public_key.timeout,
+            "rpId": challenge_response.public_key.rp_id,
             "allowCredentials": challenge_response.public_key.allow_credentials,
+            "userVerification": challenge_response.public_key.user_verification,
         }),
         auth_state,
     ))
 }
 
+/// Compléter une authentification sans email et retourner l'email du compte.
+/// Le compte est retrouvé par l'identifiant du credential ; le user handle n'est pas signé
+/// par l'authentificateur et n'est donc pas utilisé pour choisir le compte.
+pub async fn complete_discoverable_authentication(
+    response: &PublicKeyCredential,
+    state: DiscoverableAuthentication,
+) -> Result<String> {
+    let Some((user_email, passkey)) = user::find_by_credential(response.get_credential_id())? else {
+        metrics::record_ceremony(Ceremony::Authentication, false);
+        bail!("Unknown credential");
+    };
+
+    let result = WEBAUTHN.finish_discoverable_authentication(response, state, &[(&passkey).into()]);
+    metrics::record_ceremony(Ceremony::Authentication, result.is_ok());
+    let auth_result = result.context("Failed to complete authentication")?;
+
+    // Persist the updated signature counter
+    let mut passkey = passkey;
+    if passkey.update_credential(&auth_result) == Some(true) {
+        user::set_passkey(&user_email, passkey.clone())?;
+    }
+    CREDENTIAL_STORE.write().await.insert(user_email.clone(), passkey);
+
+    Ok(user_email)
+}
+
 /// Compléter l'authentification WebAuthn
 pub async fn complete_authentication(
+    user_email: &str,
     response: &PublicKeyCredential,
     state: &PasskeyAuthentication,
-    server_challenge: &str,
+    _server_challenge: &str,
 ) -> Result<()> {
     // Validate response against the challenge
-    WEBAUTHN
-        .finish_passkey_authentication(response, state)
-        .context("Failed to complete authentication")?;
+    let result = WEBAUTHN.finish_passkey_authentication(response, state);
+    metrics::record_ceremony(Ceremony::Authentication, result.is_ok());
+    let auth_result = result.context("Failed to complete authentication")?;
+
+    // Persist the updated signature counter
+    let mut store = CREDENTIAL_STORE.write().await;
+    if let Some(passkey) = store.get_mut(user_email) {
+        if passkey.update_credential(&auth_result) == Some(true) {
+            user::set_passkey(user_email, passkey.clone())?;
+        }
+    }
 
     Ok(())
 }
@@ -146,9 +347,7 @@ pub async fn complete_authentication(
 #[cfg(test)]
 mod tests {
     use super::*;
-    use axum::http::StatusCode;
-    use serde_json::json;
-    use uuid::Uuid;
+    use crate::utils::input::valid_email;
 
     #[tokio::test]
     async fn test_valid_email() {
@@ -164,7 +363,7 @@ mod tests {
         let display_name = "Test User";
     
         // Call `begin_registration`
-        let result = begin_registration(email, display_name).await;
+        let result = begin_registration(email, display_name, false).await;
     
         // Assertions
         assert!(result.is_ok());
@@ -172,6 +371,31 @@ mod tests {
         assert!(public_key_options["challenge"].is_string());
         assert!(public_key_options["rp"].is_object());
         assert!(public_key_options["user"].is_object());
+        assert_eq!(public_key_options["authenticatorSelection"]["residentKey"], "discouraged");
+        assert_eq!(public_key_options["authenticatorSelection"]["userVerification"], "required");
+    }
+
+    #[test]
+    fn test_registration_errors_distinguish_policy_from_client() {
+        let is_violation = |error: WebauthnError| registration_error("test@example.com", error).is::<PolicyViolation>();
+        assert!(is_violation(WebauthnError::UserNotVerified));
+        assert!(is_violation(WebauthnError::AttestationTrustFailure));
+        assert!(!is_violation(WebauthnError::ParseNOMFailure));
+        assert!(!is_violation(WebauthnError::InvalidClientDataType));
+    }
+
+    #[tokio::test]
+    async fn test_begin_registration_discoverable() {
+        let (public_key_options, _reg_state) = begin_registration("rk@example.com", "RK User", true).await.unwrap();
+        assert_eq!(public_key_options["authenticatorSelection"]["residentKey"], "required");
+        assert_eq!(public_key_options["authenticatorSelection"]["requireResidentKey"], true);
+    }
+
+    #[test]
+    fn test_discoverable_authentication_allows_any_credential() {
+        let (options, _state) = begin_discoverable_authentication().unwrap();
+        assert_eq!(options["allowCredentials"], json!([]));
+        assert_eq!(options["userVerification"], "required");
     }
 }
 